@@ -14,7 +14,7 @@ use uuid::Uuid;
 
 use autopush_common::notification::Notification;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(untagged)]
 pub enum BroadcastValue {
     Value(String),
@@ -30,10 +30,11 @@ pub enum ServerNotification {
     Disconnect,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "messageType", rename_all = "snake_case")]
 pub enum ClientMessage {
     Hello {
+        #[serde(skip_serializing_if = "Option::is_none")]
         uaid: Option<String>,
         #[serde(rename = "channelIDs", skip_serializing_if = "Option::is_none")]
         channel_ids: Option<Vec<Uuid>>,
@@ -71,6 +72,21 @@ pub enum ClientMessage {
     Ping,
 }
 
+impl ClientMessage {
+    /// The message's `messageType`
+    pub fn message_type(&self) -> &'static str {
+        match self {
+            ClientMessage::Hello { .. } => "hello",
+            ClientMessage::Register { .. } => "register",
+            ClientMessage::Unregister { .. } => "unregister",
+            ClientMessage::BroadcastSubscribe { .. } => "broadcast_subscribe",
+            ClientMessage::Ack { .. } => "ack",
+            ClientMessage::Nack { .. } => "nack",
+            ClientMessage::Ping => "ping",
+        }
+    }
+}
+
 impl FromStr for ClientMessage {
     type Err = serde_json::error::Error;
 
@@ -82,14 +98,14 @@ impl FromStr for ClientMessage {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ClientAck {
     #[serde(rename = "channelID")]
     pub channel_id: Uuid,
     pub version: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "messageType", rename_all = "snake_case")]
 pub enum ServerMessage {
     Hello {
@@ -106,6 +122,9 @@ pub enum ServerMessage {
         status: u32,
         #[serde(rename = "pushEndpoint")]
         push_endpoint: String,
+        /// When the endpoint expires, in milliseconds since the epoch
+        #[serde(rename = "expirationTime", skip_serializing_if = "Option::is_none")]
+        expiration_time: Option<u64>,
     },
 
     Unregister {
@@ -133,3 +152,57 @@ impl ServerMessage {
         }
     }
 }
+
+impl FromStr for ServerMessage {
+    type Err = serde_json::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // parse empty object "{}" as a Ping
+        serde_json::from_str::<HashMap<(), ()>>(s)
+            .map(|_| ServerMessage::Ping)
+            .or_else(|_| serde_json::from_str(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use uuid::Uuid;
+
+    use super::{ClientMessage, ServerMessage};
+
+    #[test]
+    fn client_message_round_trip() {
+        let channel_id = Uuid::new_v4();
+        let json = serde_json::to_string(&ClientMessage::Register {
+            channel_id: channel_id.to_string(),
+            key: None,
+        })
+        .unwrap();
+        match ClientMessage::from_str(&json).unwrap() {
+            ClientMessage::Register { channel_id: id, .. } => {
+                assert_eq!(id, channel_id.to_string())
+            }
+            message => panic!("Unexpected message: {message:?}"),
+        }
+    }
+
+    #[test]
+    fn server_message_from_str() {
+        assert!(matches!(
+            ServerMessage::from_str("{}").unwrap(),
+            ServerMessage::Ping
+        ));
+
+        let json = r#"{"messageType": "notification", "channelID": "deadbeef-0000-4000-8000-000000000000",
+                       "version": "abc", "data": "AAAA", "headers": {"encoding": "aes128gcm"}}"#;
+        match ServerMessage::from_str(json).unwrap() {
+            ServerMessage::Notification(notification) => {
+                assert_eq!(notification.version, "abc");
+                assert_eq!(notification.data.as_deref(), Some("AAAA"));
+            }
+            message => panic!("Unexpected message: {message:?}"),
+        }
+    }
+}
@@ -8,6 +8,7 @@ extern crate serde_derive;
 use std::io;
 use std::net::ToSocketAddrs;
 
+use autopush_common::metrics::MetricsBackend;
 use config::{Config, ConfigError, Environment, File};
 use fernet::Fernet;
 use lazy_static::lazy_static;
@@ -74,12 +75,21 @@ pub struct Settings {
     pub endpoint_port: u16,
     /// The seed key to use for endpoint encryption
     pub crypto_key: String,
+    /// The maximum lifetime of a new subscription endpoint in seconds (0 for
+    /// endpoints that never expire)
+    pub max_subscription_lifetime: u64,
     /// The host name to send recorded metrics
     pub statsd_host: Option<String>,
     /// The port number to send recorded metrics
     pub statsd_port: u16,
     /// The root label to apply to metrics.
     pub statsd_label: String,
+    /// Where to send metrics: `statsd`, `prometheus` (served on
+    /// `/__metrics__` at `metrics_port`) or `both`
+    pub metrics_backend: MetricsBackend,
+    /// The port to serve Prometheus metrics on. This shouldn't be exposed
+    /// publicly.
+    pub metrics_port: u16,
     /// The DSN to connect to the storage engine (Used to select between storage systems)
     pub db_dsn: Option<String>,
     /// JSON set of specific database settings (See data storage engines)
@@ -99,6 +109,15 @@ pub struct Settings {
     /// (if a given [RegisteredClient] receives more than this number, the calling
     /// thread will lock.)
     pub max_pending_notification_queue: u32,
+    /// How long each `/__heartbeat__` check may take before it fails, in
+    /// milliseconds
+    pub health_check_timeout_millis: u64,
+    /// Where to export trace spans (as JSON): `stdout`, `stderr`, or the path
+    /// of a file. Tracing is disabled when this is empty.
+    pub tracing_exporter: String,
+    /// The fraction of new traces to record (0.0 to 1.0). Requests with a
+    /// `traceparent` header follow the caller's decision.
+    pub tracing_sample_rate: f64,
 }
 
 impl Default for Settings {
@@ -120,9 +139,12 @@ impl Default for Settings {
             endpoint_hostname: "localhost".to_owned(),
             endpoint_port: 8082,
             crypto_key: format!("[{}]", Fernet::generate_key()),
+            max_subscription_lifetime: 0,
             statsd_host: Some("localhost".to_owned()),
             statsd_label: ENV_PREFIX.to_owned(),
             statsd_port: 8125,
+            metrics_backend: MetricsBackend::Statsd,
+            metrics_port: 8083,
             db_dsn: None,
             db_settings: "".to_owned(),
             megaphone_api_url: None,
@@ -131,6 +153,9 @@ impl Default for Settings {
             human_logs: false,
             msg_limit: 100,
             max_pending_notification_queue: 10,
+            health_check_timeout_millis: 2000,
+            tracing_exporter: "".to_owned(),
+            tracing_sample_rate: 1.0,
         }
     }
 }
@@ -10,7 +10,7 @@ use autoconnect_common::registry::ClientRegistry;
 use autopush_common::db::{client::DbClient, dynamodb::DdbClientImpl, DbSettings, StorageType};
 use autopush_common::{
     errors::{ApcErrorKind, Result},
-    metrics::new_metrics,
+    metrics::{metrics_builder, PrometheusSink},
 };
 
 fn ito_dur(seconds: u32) -> Option<Duration> {
@@ -40,6 +40,9 @@ pub struct AppState {
     /// Encryption object for the endpoint URL
     pub fernet: MultiFernet,
     pub metrics: Arc<StatsdClient>,
+    /// The metrics to serve on `metrics_port`, if enabled
+    pub prometheus: Option<PrometheusSink>,
+    pub metrics_port: u16,
     /// Handle to the data storage object
     pub db_client: Box<dyn DbClient>,
     pub ssl_key: Option<PathBuf>,
@@ -52,6 +55,7 @@ pub struct AppState {
     pub close_handshake_timeout: Option<Duration>,
     pub router_url: String,
     pub endpoint_url: String,
+    pub max_subscription_lifetime: Option<Duration>,
     pub statsd_host: Option<String>,
     pub statsd_port: u16,
     pub megaphone_api_url: Option<String>,
@@ -61,6 +65,7 @@ pub struct AppState {
     pub msg_limit: u32,
     pub registry: Arc<ClientRegistry>,
     pub max_pending_notification_queue: usize,
+    pub health_check_timeout: Duration,
 }
 
 impl AppState {
@@ -85,10 +90,17 @@ impl AppState {
             })
             .collect();
         let fernet = MultiFernet::new(fernets);
-        let metrics = Arc::new(new_metrics(
-            settings.statsd_host.clone(),
+        let (metrics, prometheus) = metrics_builder(
+            "autopush",
+            settings.statsd_host.as_deref(),
             settings.statsd_port,
-        )?);
+            settings.metrics_backend,
+        )?;
+        let metrics = Arc::new(
+            metrics
+                .with_error_handler(|err| error!("Metrics send error: {}", err))
+                .build(),
+        );
 
         let router_url = settings.router_url();
         let endpoint_url = settings.endpoint_url();
@@ -105,12 +117,19 @@ impl AppState {
             port: settings.port,
             fernet,
             metrics,
+            prometheus,
+            metrics_port: settings.metrics_port,
             db_client,
             router_port: settings.router_port,
             statsd_host: settings.statsd_host.clone(),
             statsd_port: settings.statsd_port,
             router_url,
             endpoint_url,
+            max_subscription_lifetime: if settings.max_subscription_lifetime == 0 {
+                None
+            } else {
+                Some(Duration::from_secs(settings.max_subscription_lifetime))
+            },
             ssl_key: settings.router_ssl_key.clone().map(PathBuf::from),
             ssl_cert: settings.router_ssl_cert.clone().map(PathBuf::from),
             ssl_dh_param: settings.router_ssl_dh_param.clone().map(PathBuf::from),
@@ -133,6 +152,7 @@ impl AppState {
             msg_limit: settings.msg_limit,
             registry: Arc::new(ClientRegistry::default()),
             max_pending_notification_queue: settings.max_pending_notification_queue as usize,
+            health_check_timeout: Duration::from_millis(settings.health_check_timeout_millis),
         })
     }
 }
@@ -1,14 +1,17 @@
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use actix_web::web::Payload;
+use actix_web::web::{Data, Payload};
 use actix_web::{HttpRequest, HttpResponse};
 use autopush_common::util::user_agent::UserAgentInfo;
 use bytes::Bytes;
 use cadence::{CountedExt, StatsdClient};
+use fernet::MultiFernet;
 use futures_util::StreamExt;
 use serde_json::json;
+use tracing::{field::Empty, Instrument};
 use uuid::Uuid;
 
 use autoconnect_common::{
@@ -17,10 +20,11 @@ use autoconnect_common::{
     registry::RegisteredClient,
 };
 use autoconnect_settings::options::AppState;
-use autopush_common::db::{self, User};
+use autopush_common::db::{self, ChannelMeta, User};
+use autopush_common::endpoint::{key_digest, make_endpoint_with_expiry};
 use autopush_common::errors::{ApcError, ApcErrorKind, Result};
 use autopush_common::notification::Notification;
-use autopush_common::util::ms_since_epoch;
+use autopush_common::util::{b64_encode_url, ms_since_epoch, sec_since_epoch};
 
 /// Client & Registry functions.
 /// These are common functions run by connected WebSocket clients.
@@ -93,14 +97,27 @@ pub struct Client {
     deferred_user_registration: Option<User>,
     /// The router URL for this client.
     router_url: String,
+    /// The root URL for push endpoints
+    endpoint_url: String,
+    /// Encryption for push endpoint tokens
+    fernet: MultiFernet,
+    /// How long new push endpoints remain valid, if they expire
+    max_subscription_lifetime: Option<Duration>,
 }
 
 impl Client {
     pub async fn ws_handler(req: HttpRequest, body: Payload) -> Result<HttpResponse> {
-        let state = req.app_data::<AppState>().unwrap().clone();
+        let state = req
+            .app_data::<Data<AppState>>()
+            .expect("No server state found")
+            .clone();
         let client_metrics = state.metrics.clone();
         let db_client = state.db_client.clone();
-        let clients = req.app_data::<ClientChannels>().unwrap().clone();
+        let clients = req
+            .app_data::<Data<ClientChannels>>()
+            .expect("No client channels found")
+            .get_ref()
+            .clone();
         let ua_string = if let Some(header) = req.headers().get(actix_web::http::header::USER_AGENT)
         {
             header
@@ -130,6 +147,9 @@ impl Client {
                 // channel: tx,
                 clients: clients.clone(),
                 router_url: state.router_url.clone(),
+                endpoint_url: state.endpoint_url.clone(),
+                fernet: state.fernet.clone(),
+                max_subscription_lifetime: state.max_subscription_lifetime,
                 unacked_direct_notifs: Default::default(),
                 unacked_stored_notifs: Default::default(),
                 unacked_stored_highest: Default::default(),
@@ -214,6 +234,31 @@ impl Client {
             warn!("Invalid message: {}", String::from_utf8_lossy(bytes));
             ApcErrorKind::InvalidClientMessage(e.to_string())
         })?)?;
+
+        // The connection outlives the request which opened it, so each
+        // message starts its own trace
+        let span = tracing::info_span!(
+            parent: None,
+            "ws.message",
+            otel.name = %format!("ws.{}", msg.message_type()),
+            otel.kind = "server",
+            otel.status_code = Empty,
+            otel.status_message = Empty,
+            uaid = Empty,
+        );
+        let result = self.dispatch_message(msg).instrument(span.clone()).await;
+        if let Some(uaid) = self.uaid {
+            span.record("uaid", uaid.to_string().as_str());
+        }
+        if let Err(e) = &result {
+            span.record("otel.status_code", "ERROR");
+            span.record("otel.status_message", e.kind.to_string().as_str());
+        }
+        result
+    }
+
+    /// Call the sub function for the message
+    async fn dispatch_message(&mut self, msg: ClientMessage) -> Result<Option<ServerMessage>> {
         match msg {
             ClientMessage::Hello {
                 uaid,
@@ -301,10 +346,16 @@ impl Client {
             user_record.uaid = uaid;
             user_record.connected_at = ms_since_epoch();
             user_record.last_connect = Some(ms_since_epoch());
+            if user_record.current_month.is_none() {
+                user_record.current_month = Some(self.db.message_table().to_owned());
+            }
 
-            // Don't record the anonymous user if we're deferring registration
+            // Don't record the anonymous user if we're deferring registration:
+            // it's saved when the first channel is registered
             if !self.flags.defer_registration {
                 self.db.update_user(&user_record).await?;
+            } else {
+                self.deferred_user_registration = Some(user_record);
             }
         }
 
@@ -326,7 +377,7 @@ impl Client {
     pub async fn register_channel(
         &mut self,
         channel_id_string: String,
-        _key: Option<String>,
+        key: Option<String>,
     ) -> Result<Option<ServerMessage>> {
         debug!("Got a register command"; "uaid"=>self.uaid.map(|v| v.to_string()), "channel_id" => &channel_id_string );
 
@@ -340,16 +391,42 @@ impl Client {
             .into());
         }
 
-        let _uaid = self.uaid;
+        let uaid = self.uaid.ok_or_else(|| {
+            ApcErrorKind::InvalidClientMessage("Register requires a Hello first".to_owned())
+        })?;
 
         let status = 200;
 
-        let push_endpoint = "REPLACE ME".to_owned();
+        let expiry = self
+            .max_subscription_lifetime
+            .map(|lifetime| sec_since_epoch() + lifetime.as_secs());
+        let push_endpoint = make_endpoint_with_expiry(
+            &uaid,
+            &channel_id,
+            key.as_deref(),
+            expiry,
+            &self.endpoint_url,
+            &self.fernet,
+        )?;
+
+        if let Some(user) = self.deferred_user_registration.take() {
+            debug!("Saving the deferred user registration"; "uaid" => uaid.to_string());
+            self.db.add_user(&user).await?;
+            self.flags.defer_registration = false;
+        }
+        let key_hash = key
+            .as_deref()
+            .map(|key| key_digest(key).map(|digest| b64_encode_url(&digest)))
+            .transpose()?;
+        self.db
+            .add_channel_with_meta(&uaid, &channel_id, &ChannelMeta::new(key_hash))
+            .await?;
 
         Ok(Some(ServerMessage::Register {
             channel_id,
             status,
             push_endpoint,
+            expiration_time: expiry.map(|expiry| expiry * 1000),
         }))
     }
 
@@ -359,11 +436,17 @@ impl Client {
         channel_id: Uuid,
         _key: Option<u32>,
     ) -> Result<Option<ServerMessage>> {
-        let status = 200;
-
-        let _push_endpoint = "REPLACE ME".to_owned();
+        let uaid = self.uaid.ok_or_else(|| {
+            ApcErrorKind::InvalidClientMessage("Unregister requires a Hello first".to_owned())
+        })?;
+        // Unregistering an unknown (or already removed) channel succeeds:
+        // either way the channel is gone
+        self.db.remove_channel(&uaid, &channel_id).await?;
 
-        Ok(Some(ServerMessage::Unregister { channel_id, status }))
+        Ok(Some(ServerMessage::Unregister {
+            channel_id,
+            status: 200,
+        }))
     }
 
     /// Return a Ping / Set of broadcast updates
@@ -719,3 +802,133 @@ impl NotifManager {
         Ok(HttpResponse::NotFound().body::<Bytes>(body))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+
+    use autopush_common::db::{client::DbClient, mock::MockDbClient, User};
+    use autopush_common::util::user_agent::UserAgentInfo;
+    use cadence::{NopMetricSink, StatsdClient};
+    use fernet::{Fernet, MultiFernet};
+    use mockall::predicate;
+    use uuid::Uuid;
+
+    use autoconnect_common::protocol::ServerMessage;
+
+    use super::{Client, ClientFlags};
+
+    /// A client which has sent a Hello as `uaid`
+    fn make_client(uaid: Uuid, db: Box<dyn DbClient>) -> Client {
+        Client {
+            uaid: Some(uaid),
+            uid: Uuid::new_v4(),
+            flags: ClientFlags::default(),
+            ua_info: UserAgentInfo::from(""),
+            db,
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            metrics: Arc::new(StatsdClient::builder("", NopMetricSink).build()),
+            unacked_direct_notifs: Default::default(),
+            unacked_stored_notifs: Default::default(),
+            unacked_stored_highest: Default::default(),
+            connected_at: Default::default(),
+            sent_from_storage: Default::default(),
+            last_ping: Default::default(),
+            stats: Default::default(),
+            deferred_user_registration: Default::default(),
+            router_url: "http://localhost:8081/".to_owned(),
+            endpoint_url: "http://localhost:8080/".to_owned(),
+            fernet: MultiFernet::new(vec![Fernet::new(&Fernet::generate_key()).unwrap()]),
+            max_subscription_lifetime: None,
+        }
+    }
+
+    /// A new user is saved, with the channel, when it registers its first
+    /// channel
+    #[actix_rt::test]
+    async fn register_saves_deferred_user() {
+        let uaid = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+        let mut db = MockDbClient::new();
+        db.expect_add_user()
+            .withf(move |user| user.uaid == uaid)
+            .times(1)
+            .return_once(|_| Ok(()));
+        db.expect_add_channel_with_meta()
+            .with(
+                predicate::eq(uaid),
+                predicate::eq(channel_id),
+                predicate::always(),
+            )
+            .times(1)
+            .return_once(|_, _, _| Ok(()));
+        let mut client = make_client(uaid, db.into_boxed_arc());
+        client.flags.defer_registration = true;
+        client.deferred_user_registration = Some(User {
+            uaid,
+            ..Default::default()
+        });
+
+        let response = client
+            .register_channel(channel_id.as_hyphenated().to_string(), None)
+            .await
+            .unwrap();
+        assert!(
+            matches!(response, Some(ServerMessage::Register { status: 200, .. })),
+            "response = {response:?}"
+        );
+        assert!(client.deferred_user_registration.is_none());
+        assert!(!client.flags.defer_registration);
+    }
+
+    /// An existing user only has the channel added
+    #[actix_rt::test]
+    async fn register_existing_user() {
+        let uaid = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+        let mut db = MockDbClient::new();
+        db.expect_add_user().never();
+        db.expect_add_channel_with_meta()
+            .with(
+                predicate::eq(uaid),
+                predicate::eq(channel_id),
+                predicate::always(),
+            )
+            .times(1)
+            .return_once(|_, _, _| Ok(()));
+        let mut client = make_client(uaid, db.into_boxed_arc());
+
+        let response = client
+            .register_channel(channel_id.as_hyphenated().to_string(), None)
+            .await
+            .unwrap();
+        assert!(
+            matches!(response, Some(ServerMessage::Register { status: 200, .. })),
+            "response = {response:?}"
+        );
+    }
+
+    /// Unregistering removes the channel, and succeeds even if the channel
+    /// was unknown
+    #[actix_rt::test]
+    async fn unregister_unknown_channel() {
+        let uaid = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+        let mut db = MockDbClient::new();
+        db.expect_remove_channel()
+            .with(predicate::eq(uaid), predicate::eq(channel_id))
+            .times(1)
+            .return_once(|_, _| Ok(false));
+        let mut client = make_client(uaid, db.into_boxed_arc());
+
+        let response = client.unregister(channel_id, None).await.unwrap();
+        assert!(
+            matches!(
+                response,
+                Some(ServerMessage::Unregister { status: 200, channel_id: id }) if id == channel_id
+            ),
+            "response = {response:?}"
+        );
+    }
+}
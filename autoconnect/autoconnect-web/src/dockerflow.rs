@@ -3,18 +3,35 @@ use std::thread;
 
 use actix_web::web::{Data, Json};
 use actix_web::HttpResponse;
+use futures::join;
 use reqwest::StatusCode;
 use serde_json::json;
 
 use autoconnect_settings::options::AppState;
+use autopush_common::health::{probe, ComponentHealth, HealthReport};
 
 /// Handle the `/health` and `/__heartbeat__` routes
-pub async fn health_route(_state: Data<AppState>) -> Json<serde_json::Value> {
-    //TODO: query local state and report results
-    Json(json!({
-        "status": "OK",
-        "version": env!("CARGO_PKG_VERSION"),
-    }))
+pub async fn health_route(state: Data<AppState>) -> HttpResponse {
+    let timeout = state.health_check_timeout;
+    let database = probe(timeout, async {
+        match state.db_client.health_check().await {
+            Ok(()) => ComponentHealth::ok(),
+            Err(e) => ComponentHealth::error(e.to_string()),
+        }
+    });
+    let message_table = probe(timeout, async {
+        match state.db_client.message_table_exists().await {
+            Ok(true) => ComponentHealth::ok(),
+            Ok(false) => ComponentHealth::error("Nonexistent table"),
+            Err(e) => ComponentHealth::error(e.to_string()),
+        }
+    });
+    let (database, message_table) = join!(database, message_table);
+
+    let mut report = HealthReport::default();
+    report.add("database", database);
+    report.add("message_table", message_table);
+    HttpResponse::build(report.http_status()).json(report.to_json(env!("CARGO_PKG_VERSION")))
 }
 
 /// Handle the `/status` route
@@ -8,4 +8,28 @@ extern crate slog_scope;
 
 pub mod client;
 pub mod dockerflow;
-pub mod metrics;
+
+use actix_web::web;
+
+use crate::client::Client;
+
+/// Register the websocket and Dockerflow routes. The app data must include
+/// the `AppState`, the `ClientChannels` and the metrics client.
+pub fn config(config: &mut web::ServiceConfig) {
+    config
+        // Websocket Handler
+        .route("/ws/", web::get().to(Client::ws_handler))
+        // TODO: Internode Message handler
+        //.service(web::resource("/push/{uaid}").route(web::push().to(route::InterNode::put))
+        .service(web::resource("/status").route(web::get().to(dockerflow::status_route)))
+        .service(web::resource("/health").route(web::get().to(dockerflow::health_route)))
+        .service(web::resource("/v1/err").route(web::get().to(dockerflow::log_check)))
+        // standardized
+        .service(web::resource("/__error__").route(web::get().to(dockerflow::log_check)))
+        // Dockerflow
+        .service(web::resource("/__heartbeat__").route(web::get().to(dockerflow::health_route)))
+        .service(
+            web::resource("/__lbheartbeat__").route(web::get().to(dockerflow::lb_heartbeat_route)),
+        )
+        .service(web::resource("/__version__").route(web::get().to(dockerflow::version_route)));
+}
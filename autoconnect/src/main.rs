@@ -16,15 +16,9 @@ use serde::Deserialize;
 use std::sync::RwLock;
 
 use autoconnect_settings::{options::AppState, Settings};
-use autoconnect_web::{
-    client::{Client, ClientChannels},
-    dockerflow,
-};
-use autopush_common::errors::{render_404, ApcErrorKind, Result};
-
-mod middleware;
-
-pub type LocalError = autopush_common::errors::ApcError;
+use autoconnect_web::client::ClientChannels;
+use autopush_common::errors::{render_404, ApcError, ApcErrorKind, Result};
+use autopush_common::middleware::{sentry::SentryWrapper, telemetry::TelemetryWrapper};
 
 const USAGE: &str = "
 Usage: autopush_rs [options]
@@ -57,6 +51,11 @@ async fn main() -> Result<()> {
     }
     let settings =
         Settings::with_env_and_config_files(&filenames).map_err(ApcErrorKind::ConfigError)?;
+    autopush_common::telemetry::init(
+        "autoconnect",
+        &settings.tracing_exporter,
+        settings.tracing_sample_rate,
+    )?;
 
     //TODO: Eventually this will match between the various storage engines that
     // we support. For now, it's just the one, DynamoDB.
@@ -86,6 +85,9 @@ async fn main() -> Result<()> {
     });
 
     let app_state = AppState::from_settings(&settings)?;
+    if let Some(prometheus) = &app_state.prometheus {
+        actix_rt::spawn(prometheus.serve(("0.0.0.0", app_state.metrics_port))?);
+    }
 
     info!("Starting autoconnect on port {:?}", &settings.port);
     HttpServer::new(move || {
@@ -102,27 +104,16 @@ async fn main() -> Result<()> {
             // internally, it uses Arc
             .app_data(web::Data::new(app_state.clone()))
             .app_data(web::Data::new(client_channels))
+            // For the `Metrics` extractor
+            .app_data(web::Data::from(app_state.metrics.clone()))
             .wrap(ErrorHandlers::new().handler(StatusCode::NOT_FOUND, render_404))
-            .wrap(crate::middleware::sentry::SentryWrapper::new(
+            .wrap(SentryWrapper::<ApcError>::new(
                 app_state.metrics.clone(),
                 "error".to_owned(),
             ))
-            // Websocket Handler
-            .route("/ws/", web::get().to(Client::ws_handler))
-            // TODO: Internode Message handler
-            //.service(web::resource("/push/{uaid}").route(web::push().to(autoconnect_web::route::InterNode::put))
-            .service(web::resource("/status").route(web::get().to(dockerflow::status_route)))
-            .service(web::resource("/health").route(web::get().to(dockerflow::health_route)))
-            .service(web::resource("/v1/err").route(web::get().to(dockerflow::log_check)))
-            // standardized
-            .service(web::resource("/__error__").route(web::get().to(dockerflow::log_check)))
-            // Dockerflow
-            .service(web::resource("/__heartbeat__").route(web::get().to(dockerflow::health_route)))
-            .service(
-                web::resource("/__lbheartbeat__")
-                    .route(web::get().to(dockerflow::lb_heartbeat_route)),
-            )
-            .service(web::resource("/__version__").route(web::get().to(dockerflow::version_route)))
+            // Outermost, so the request span covers the other middleware
+            .wrap(TelemetryWrapper)
+            .configure(autoconnect_web::config)
     })
     .bind(("0.0.0.0", settings.port))?
     .run()
@@ -130,6 +121,7 @@ async fn main() -> Result<()> {
     .map_err(|e| e.into())
     .map(|v| {
         info!("Shutting down autoconnect");
+        autopush_common::telemetry::shutdown();
         v
     })
 }
@@ -1,7 +1,14 @@
+use crate::error::{ApiErrorKind, ApiResult};
+use autopush_common::util::{b64_decode_url, b64_encode_url};
 use openssl::error::ErrorStack;
 use openssl::hash::MessageDigest;
 use openssl::pkey::PKey;
 use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The prefix of a scoped registration secret
+const SCOPED_SECRET_PREFIX: &str = "v2.";
 
 /// Sign some data with a key and return the hex representation
 pub fn sign_with_key(key: &[u8], data: &[u8]) -> Result<String, ErrorStack> {
@@ -11,3 +18,102 @@ pub fn sign_with_key(key: &[u8], data: &[u8]) -> Result<String, ErrorStack> {
     signer.update(data)?;
     Ok(hex::encode(signer.sign_to_vec()?))
 }
+
+/// An operation a registration secret may allow
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretScope {
+    /// List the registration's channels
+    Read,
+    /// Add a channel
+    Subscribe,
+    /// Remove a channel
+    Unsubscribe,
+    /// Change the bridge token or router
+    UpdateToken,
+    /// Delete the registration
+    Unregister,
+    /// Issue new secrets, or revoke the existing ones
+    Secrets,
+}
+
+impl SecretScope {
+    /// Every operation. Legacy secrets, and secrets issued at registration,
+    /// have this scope.
+    pub const ALL: [SecretScope; 6] = [
+        SecretScope::Read,
+        SecretScope::Subscribe,
+        SecretScope::Unsubscribe,
+        SecretScope::UpdateToken,
+        SecretScope::Unregister,
+        SecretScope::Secrets,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SecretScope::Read => "read",
+            SecretScope::Subscribe => "subscribe",
+            SecretScope::Unsubscribe => "unsubscribe",
+            SecretScope::UpdateToken => "update_token",
+            SecretScope::Unregister => "unregister",
+            SecretScope::Secrets => "secrets",
+        }
+    }
+}
+
+/// The claims of a scoped registration secret. The secret is
+/// `v2.<base64 JSON claims>.<HMAC-SHA256 of the preceding text>`, signed with
+/// one of the auth keys.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SecretClaims {
+    pub uaid: Uuid,
+    pub scope: Vec<SecretScope>,
+    /// When the secret was issued, in milliseconds
+    pub iat: u64,
+    /// When the secret expires, in milliseconds (never if unset)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+}
+
+impl SecretClaims {
+    /// If the token looks like a scoped secret (instead of a legacy one)
+    pub fn is_scoped(token: &str) -> bool {
+        token.starts_with(SCOPED_SECRET_PREFIX)
+    }
+
+    /// Sign the claims, creating a secret
+    pub fn sign(&self, auth_key: &str) -> Result<String, ErrorStack> {
+        let claims = serde_json::to_vec(self).expect("Secret claims are serializable");
+        let payload = format!("{}{}", SCOPED_SECRET_PREFIX, b64_encode_url(&claims));
+        let signature = sign_with_key(auth_key.as_bytes(), payload.as_bytes())?;
+
+        Ok(format!("{payload}.{signature}"))
+    }
+
+    /// Get the claims of a secret signed with one of the auth keys. The
+    /// claims (e.g. expiry) are not checked.
+    pub fn verify(token: &str, auth_keys: &[String]) -> ApiResult<Self> {
+        let invalid = || ApiErrorKind::InvalidLocalAuth("invalid scoped secret".to_owned());
+        let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let claims = payload
+            .strip_prefix(SCOPED_SECRET_PREFIX)
+            .ok_or_else(invalid)?;
+
+        for key in auth_keys {
+            let expected = sign_with_key(key.as_bytes(), payload.as_bytes())
+                .map_err(ApiErrorKind::RegistrationSecretHash)?;
+            if expected.len() == signature.len()
+                && openssl::memcmp::eq(expected.as_bytes(), signature.as_bytes())
+            {
+                let claims = b64_decode_url(claims).map_err(|_| invalid())?;
+                return serde_json::from_slice(&claims).map_err(|_| invalid().into());
+            }
+        }
+        Err(ApiErrorKind::InvalidLocalAuth("incorrect auth token".to_owned()).into())
+    }
+
+    /// If the claims allow the operation
+    pub fn allows(&self, scope: SecretScope) -> bool {
+        self.scope.contains(&scope)
+    }
+}
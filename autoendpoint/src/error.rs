@@ -19,7 +19,7 @@ use thiserror::Error;
 use validator::{ValidationErrors, ValidationErrorsKind};
 
 use autopush_common::db::error::DbError;
-use autopush_common::errors::{ApcError, ApcErrorKind};
+use autopush_common::errors::{ApcError, ApcErrorKind, ReportableError};
 
 /// Common `Result` type.
 pub type ApiResult<T> = Result<T, ApiError>;
@@ -43,6 +43,24 @@ impl ApiError {
     }
 }
 
+impl ReportableError for ApiError {
+    fn reportable_source(&self) -> &(dyn Error + 'static) {
+        &self.kind
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        Some(&self.backtrace)
+    }
+
+    fn is_sentry_event(&self) -> bool {
+        self.kind.is_sentry_event()
+    }
+
+    fn metric_label(&self) -> Option<&'static str> {
+        self.kind.metric_label()
+    }
+}
+
 impl From<ApiError> for ApcError {
     fn from(err: ApiError) -> ApcError {
         ApcError {
@@ -97,6 +115,17 @@ pub enum ApiErrorKind {
     #[error("No such subscription")]
     NoSubscription,
 
+    #[error("Subscription has expired")]
+    SubscriptionExpired,
+
+    #[error("No such dead letter")]
+    NoDeadLetter,
+
+    /// The user's connection node couldn't disconnect their client (only
+    /// the legacy server supports it)
+    #[error("Could not disconnect the client: {0}")]
+    DisconnectFailed(String),
+
     /// A specific issue with the encryption headers
     #[error("{0}")]
     InvalidEncryption(String),
@@ -120,12 +149,27 @@ pub enum ApiErrorKind {
     #[error("Invalid Authentication")]
     InvalidAuthentication,
 
+    /// A batch request or one of its items is malformed
+    #[error("Invalid batch: {0}")]
+    InvalidBatch(String),
+
     #[error("Invalid Local Auth {0}")]
     InvalidLocalAuth(String),
 
+    /// The registration secret is valid, but doesn't allow the operation
+    #[error("Registration secret does not allow {0}")]
+    InsufficientScope(&'static str),
+
     #[error("General error {0}")]
     General(String),
 
+    /// The sender exceeded a rate limit. `retry_after` is in seconds.
+    #[error("Rate limit exceeded for {scope}")]
+    RateLimited {
+        scope: &'static str,
+        retry_after: u64,
+    },
+
     #[error("ERROR:Success")]
     LogCheck,
 }
@@ -142,7 +186,8 @@ impl ApiErrorKind {
             | ApiErrorKind::NoTTL
             | ApiErrorKind::InvalidRouterType
             | ApiErrorKind::InvalidRouterToken
-            | ApiErrorKind::InvalidMessageId => StatusCode::BAD_REQUEST,
+            | ApiErrorKind::InvalidMessageId
+            | ApiErrorKind::InvalidBatch(_) => StatusCode::BAD_REQUEST,
 
             ApiErrorKind::VapidError(_)
             | ApiErrorKind::Jwt(_)
@@ -150,9 +195,19 @@ impl ApiErrorKind {
             | ApiErrorKind::InvalidAuthentication
             | ApiErrorKind::InvalidLocalAuth(_) => StatusCode::UNAUTHORIZED,
 
-            ApiErrorKind::InvalidToken | ApiErrorKind::InvalidApiVersion => StatusCode::NOT_FOUND,
+            ApiErrorKind::InsufficientScope(_) => StatusCode::FORBIDDEN,
+
+            ApiErrorKind::InvalidToken
+            | ApiErrorKind::InvalidApiVersion
+            | ApiErrorKind::NoDeadLetter => StatusCode::NOT_FOUND,
+
+            ApiErrorKind::NoUser
+            | ApiErrorKind::NoSubscription
+            | ApiErrorKind::SubscriptionExpired => StatusCode::GONE,
+
+            ApiErrorKind::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
 
-            ApiErrorKind::NoUser | ApiErrorKind::NoSubscription => StatusCode::GONE,
+            ApiErrorKind::DisconnectFailed(_) => StatusCode::BAD_GATEWAY,
 
             ApiErrorKind::LogCheck => StatusCode::IM_A_TEAPOT,
 
@@ -177,18 +232,25 @@ impl ApiErrorKind {
             ApiErrorKind::InvalidRouterType => "invalid_router_type",
             ApiErrorKind::InvalidRouterToken => "invalid_router_token",
             ApiErrorKind::InvalidMessageId => "invalid_message_id",
+            ApiErrorKind::InvalidBatch(_) => "invalid_batch",
 
             ApiErrorKind::VapidError(_) => "vapid_error",
             ApiErrorKind::Jwt(_) => "jwt",
             ApiErrorKind::TokenHashValidation(_) => "token_hash_validation",
             ApiErrorKind::InvalidAuthentication => "invalid_authentication",
             ApiErrorKind::InvalidLocalAuth(_) => "invalid_local_auth",
+            ApiErrorKind::InsufficientScope(_) => "insufficient_scope",
 
             ApiErrorKind::InvalidToken => "invalid_token",
             ApiErrorKind::InvalidApiVersion => "invalid_api_version",
 
             ApiErrorKind::NoUser => "no_user",
             ApiErrorKind::NoSubscription => "no_subscription",
+            ApiErrorKind::SubscriptionExpired => "subscription_expired",
+            ApiErrorKind::NoDeadLetter => "no_dead_letter",
+            ApiErrorKind::DisconnectFailed(_) => "disconnect_failed",
+
+            ApiErrorKind::RateLimited { .. } => "rate_limited",
 
             ApiErrorKind::LogCheck => "log_check",
 
@@ -212,9 +274,13 @@ impl ApiErrorKind {
             | ApiErrorKind::Jwt(_)
             | ApiErrorKind::TokenHashValidation(_)
             | ApiErrorKind::InvalidAuthentication
-            | ApiErrorKind::InvalidLocalAuth(_) |
+            | ApiErrorKind::InvalidLocalAuth(_)
+            | ApiErrorKind::InsufficientScope(_) |
             // Ignore missing or invalid user errors
             ApiErrorKind::NoUser | ApiErrorKind::NoSubscription |
+            ApiErrorKind::SubscriptionExpired |
+            // Ignore rate limited senders
+            ApiErrorKind::RateLimited { .. } |
             // Ignore overflow errors
             ApiErrorKind::Router(RouterError::TooMuchData(_)),
         )
@@ -238,7 +304,7 @@ impl ApiErrorKind {
                 Some(104)
             }
 
-            ApiErrorKind::NoSubscription => Some(106),
+            ApiErrorKind::NoSubscription | ApiErrorKind::SubscriptionExpired => Some(106),
 
             ApiErrorKind::InvalidRouterType => Some(108),
 
@@ -246,7 +312,8 @@ impl ApiErrorKind {
             | ApiErrorKind::TokenHashValidation(_)
             | ApiErrorKind::Jwt(_)
             | ApiErrorKind::InvalidAuthentication
-            | ApiErrorKind::InvalidLocalAuth(_) => Some(109),
+            | ApiErrorKind::InvalidLocalAuth(_)
+            | ApiErrorKind::InsufficientScope(_) => Some(109),
 
             ApiErrorKind::InvalidEncryption(_) => Some(110),
 
@@ -262,7 +329,20 @@ impl ApiErrorKind {
             | ApiErrorKind::InvalidRouterToken
             | ApiErrorKind::RegistrationSecretHash(_)
             | ApiErrorKind::EndpointUrl(_)
-            | ApiErrorKind::InvalidMessageId => None,
+            | ApiErrorKind::InvalidMessageId
+            | ApiErrorKind::InvalidBatch(_)
+            | ApiErrorKind::NoDeadLetter
+            | ApiErrorKind::DisconnectFailed(_)
+            | ApiErrorKind::RateLimited { .. } => None,
+        }
+    }
+
+    /// How many seconds the caller should wait before retrying, if known
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            ApiErrorKind::RateLimited { retry_after, .. } => Some(*retry_after),
+            ApiErrorKind::Router(e) => e.retry_after(),
+            _ => None,
         }
     }
 }
@@ -291,6 +371,12 @@ impl From<ApiErrorKind> for ApcErrorKind {
             ApiErrorKind::NoSubscription => {
                 ApcErrorKind::EndpointError("NoSubscription", "".to_string())
             }
+            ApiErrorKind::SubscriptionExpired => {
+                ApcErrorKind::EndpointError("SubscriptionExpired", "".to_string())
+            }
+            ApiErrorKind::NoDeadLetter => {
+                ApcErrorKind::EndpointError("NoDeadLetter", "".to_string())
+            }
             ApiErrorKind::InvalidEncryption(e) => {
                 ApcErrorKind::EndpointError("InvalidEncryption", e)
             }
@@ -307,10 +393,17 @@ impl From<ApiErrorKind> for ApcErrorKind {
             ApiErrorKind::InvalidMessageId => {
                 ApcErrorKind::EndpointError("InvalidMessageId", "".to_string())
             }
+            ApiErrorKind::InvalidBatch(e) => ApcErrorKind::EndpointError("InvalidBatch", e),
             ApiErrorKind::InvalidAuthentication => {
                 ApcErrorKind::EndpointError("InvalidAuthentication", "".to_string())
             }
             ApiErrorKind::InvalidLocalAuth(e) => ApcErrorKind::EndpointError("InvalidLocalAuth", e),
+            ApiErrorKind::InsufficientScope(scope) => {
+                ApcErrorKind::EndpointError("InsufficientScope", scope.to_string())
+            }
+            ApiErrorKind::RateLimited { scope, .. } => {
+                ApcErrorKind::EndpointError("RateLimited", scope.to_string())
+            }
             ApiErrorKind::LogCheck => {
                 ApcErrorKind::EndpointError("LogCheck", "testing 1,2,3".to_string())
             }
@@ -366,6 +459,10 @@ impl ResponseError for ApiError {
             builder.insert_header(("Cache-Control", "max-age=86400"));
         }
 
+        if let Some(retry_after) = self.kind.retry_after() {
+            builder.insert_header(("Retry-After", retry_after.to_string()));
+        }
+
         builder.json(self)
     }
 }
@@ -411,3 +508,62 @@ fn errno_from_validation_errors(e: &ValidationErrors) -> Option<usize> {
         })
         .next()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiError, ApiErrorKind};
+    use crate::routers::fcm::error::FcmError;
+    use crate::routers::RouterError;
+    use actix_web::ResponseError;
+
+    fn retry_after_header(kind: ApiErrorKind) -> Option<String> {
+        let response = ApiError::from(kind).error_response();
+        response
+            .headers()
+            .get("Retry-After")
+            .map(|value| value.to_str().unwrap().to_owned())
+    }
+
+    /// Errors which know when to retry send a Retry-After header
+    #[test]
+    fn retry_after() {
+        assert_eq!(
+            retry_after_header(ApiErrorKind::RateLimited {
+                scope: "vapid",
+                retry_after: 10,
+            }),
+            Some("10".to_owned())
+        );
+        assert_eq!(
+            retry_after_header(ApiErrorKind::Router(RouterError::Fcm(
+                FcmError::QuotaExceeded {
+                    retry_after: Some(30)
+                }
+            ))),
+            Some("30".to_owned())
+        );
+        assert_eq!(
+            retry_after_header(ApiErrorKind::Router(RouterError::Unavailable {
+                status: "503".to_owned(),
+                message: "Service Unavailable".to_owned(),
+                retry_after: Some(60),
+            })),
+            Some("60".to_owned())
+        );
+    }
+
+    /// No Retry-After header is sent when the bridge didn't say when to retry
+    #[test]
+    fn no_retry_after() {
+        assert_eq!(
+            retry_after_header(ApiErrorKind::Router(RouterError::Fcm(
+                FcmError::QuotaExceeded { retry_after: None }
+            ))),
+            None
+        );
+        assert_eq!(
+            retry_after_header(ApiErrorKind::Router(RouterError::RequestTimeout)),
+            None
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use crate::error::{ApiError, ApiErrorKind};
+use crate::headers::util::get_header;
+use crate::server::AppState;
+use actix_web::dev::Payload;
+use actix_web::{web::Data, FromRequest, HttpRequest};
+use futures::future::{self, Ready};
+
+/// Verifies that the request carries one of the admin API keys as a bearer
+/// token. The admin keys are separate from the `auth_keys` used to sign
+/// registration secrets.
+pub struct AdminAuth;
+
+impl AdminAuth {
+    pub fn validate_token(token: &str, admin_keys: &[String]) -> Result<Self, ApiError> {
+        for key in admin_keys {
+            if key.len() == token.len() && openssl::memcmp::eq(key.as_bytes(), token.as_bytes()) {
+                return Ok(Self);
+            }
+        }
+        Err(ApiErrorKind::InvalidLocalAuth("incorrect admin token".to_owned()).into())
+    }
+}
+
+impl FromRequest for AdminAuth {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let state: Data<AppState> = Data::extract(req)
+            .into_inner()
+            .expect("No server state found");
+        let token = get_header(req, "Authorization")
+            .and_then(|header| header.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token)
+            .ok_or_else(|| ApiErrorKind::InvalidLocalAuth("missing admin token".to_owned()));
+
+        future::ready(
+            token
+                .map_err(ApiError::from)
+                .and_then(|token| Self::validate_token(token, &state.settings.admin_keys())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AdminAuth;
+
+    #[test]
+    fn validate_token() {
+        let keys = vec!["admin-key-1".to_owned(), "admin-key-2".to_owned()];
+        assert!(AdminAuth::validate_token("admin-key-2", &keys).is_ok());
+        assert!(AdminAuth::validate_token("admin-key", &keys).is_err());
+        assert!(AdminAuth::validate_token("admin-key-1", &[]).is_err());
+    }
+}
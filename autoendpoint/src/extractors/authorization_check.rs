@@ -1,9 +1,12 @@
-use crate::auth::sign_with_key;
-use crate::error::{ApiError, ApiErrorKind};
+use crate::auth::{sign_with_key, SecretClaims, SecretScope};
+use crate::error::{ApiError, ApiErrorKind, ApiResult};
 use crate::headers::util::get_header;
 use crate::server::AppState;
+use crate::settings::Settings;
 use actix_web::dev::Payload;
 use actix_web::{web::Data, FromRequest, HttpRequest};
+use autopush_common::util::ms_since_epoch;
+use cadence::CountedExt;
 use futures::future::LocalBoxFuture;
 use futures::FutureExt;
 use openssl::error::ErrorStack;
@@ -11,18 +14,117 @@ use uuid::Uuid;
 
 /// Verifies the request authorization via the authorization header.
 ///
-/// The expected token is the HMAC-SHA256 hash of the UAID, signed with one of
-/// the available keys (allows for key rotation).
+/// The token is either a scoped secret (signed claims listing the UAID, the
+/// allowed operations and an expiry), or a legacy secret: the HMAC-SHA256
+/// hash of the UAID, which allows every operation. Both are signed with one
+/// of the available keys (allows for key rotation). Routes must `require`
+/// the operation they perform.
+///
+/// Checking whether the secret was revoked costs a user lookup on every
+/// authorized request. This is deliberate: registration requests are rare
+/// next to notifications, and legacy secrets carry no issue time, so the
+/// user's `secrets_not_before` is the only way to revoke them.
 /// NOTE: This is *ONLY* for internal calls that require authorization and should
 ///      NOT be used by calls that are using VAPID authentication (e.g.
 ///      subscription provider endpoints)
-pub struct AuthorizationCheck;
+pub struct AuthorizationCheck {
+    scope: Vec<SecretScope>,
+    /// When the secret was issued, in milliseconds (unknown for legacy
+    /// secrets)
+    issued_at: Option<u64>,
+    /// When the secret expires, in milliseconds (never for legacy secrets)
+    expires_at: Option<u64>,
+}
 
 impl AuthorizationCheck {
+    /// Generate a legacy secret for the user
     pub fn generate_token(auth_key: &str, user: &Uuid) -> Result<String, ErrorStack> {
         sign_with_key(auth_key.as_bytes(), user.as_simple().to_string().as_bytes())
     }
 
+    /// Generate a scoped secret for the user. The secret expires after `ttl`
+    /// seconds, or never if `ttl` is 0.
+    pub fn generate_scoped_secret(
+        auth_key: &str,
+        user: &Uuid,
+        scope: Vec<SecretScope>,
+        ttl: u64,
+    ) -> Result<String, ErrorStack> {
+        let now = ms_since_epoch();
+        SecretClaims {
+            uaid: *user,
+            scope,
+            iat: now,
+            exp: (ttl > 0).then(|| now + ttl * 1000),
+        }
+        .sign(auth_key)
+    }
+
+    /// Generate a secret allowing every operation, scoped or legacy depending
+    /// on the settings
+    pub fn generate_secret(settings: &Settings, user: &Uuid) -> ApiResult<String> {
+        if settings.issue_scoped_secrets {
+            return Self::generate_full_scoped_secret(settings, user);
+        }
+        Self::generate_token(&Self::auth_key(settings), user)
+            .map_err(|e| ApiErrorKind::RegistrationSecretHash(e).into())
+    }
+
+    /// Generate a scoped secret allowing every operation, whatever the
+    /// settings. Legacy secrets are revoked along with the others, so this is
+    /// the only kind of secret which survives a revocation.
+    pub fn generate_full_scoped_secret(settings: &Settings, user: &Uuid) -> ApiResult<String> {
+        Self::generate_scoped_secret(
+            &Self::auth_key(settings),
+            user,
+            SecretScope::ALL.to_vec(),
+            settings.scoped_secret_ttl,
+        )
+        .map_err(|e| ApiErrorKind::RegistrationSecretHash(e).into())
+    }
+
+    /// Generate a scoped secret for the user on behalf of the caller. The
+    /// secret expires after `ttl` seconds (defaulting to the
+    /// `scoped_secret_ttl` setting, 0 for never), but never after the
+    /// caller's secret. An expiring secret can't ask for a secret which
+    /// never expires.
+    pub fn generate_delegated_secret(
+        &self,
+        settings: &Settings,
+        user: &Uuid,
+        scope: Vec<SecretScope>,
+        ttl: Option<u64>,
+    ) -> ApiResult<String> {
+        if ttl == Some(0) && self.expires_at.is_some() {
+            return Err(ApiErrorKind::InsufficientScope("secrets which never expire").into());
+        }
+        let ttl = ttl.unwrap_or(settings.scoped_secret_ttl);
+        let now = ms_since_epoch();
+        let exp = match ((ttl > 0).then(|| now + ttl * 1000), self.expires_at) {
+            (Some(exp), Some(max_exp)) => Some(exp.min(max_exp)),
+            (exp, max_exp) => exp.or(max_exp),
+        };
+
+        SecretClaims {
+            uaid: *user,
+            scope,
+            iat: now,
+            exp,
+        }
+        .sign(&Self::auth_key(settings))
+        .map_err(|e| ApiErrorKind::RegistrationSecretHash(e).into())
+    }
+
+    /// The key new secrets are signed with
+    fn auth_key(settings: &Settings) -> String {
+        settings
+            .auth_keys()
+            .into_iter()
+            .next()
+            .expect("At least one auth key must be provided in the settings")
+    }
+
+    /// Validate a legacy secret
     pub fn validate_token(
         token: &str,
         uaid: &Uuid,
@@ -38,11 +140,68 @@ impl AuthorizationCheck {
             if expected_token.len() == token.len()
                 && openssl::memcmp::eq(expected_token.as_bytes(), token.as_bytes())
             {
-                return Ok(Self);
+                return Ok(Self {
+                    scope: SecretScope::ALL.to_vec(),
+                    issued_at: None,
+                    expires_at: None,
+                });
             }
         }
         Err(ApiErrorKind::InvalidLocalAuth("incorrect auth token".to_owned()).into())
     }
+
+    /// Validate a scoped or legacy secret. Revocation is checked separately
+    /// (see `check_revoked`), as it needs the user's record.
+    pub fn validate_secret(token: &str, uaid: &Uuid, settings: &Settings) -> ApiResult<Self> {
+        if !SecretClaims::is_scoped(token) {
+            if !settings.accept_legacy_secrets {
+                return Err(ApiErrorKind::InvalidLocalAuth(
+                    "legacy secrets are disabled".to_owned(),
+                )
+                .into());
+            }
+            return Self::validate_token(token, uaid, &settings.auth_keys());
+        }
+
+        let claims = SecretClaims::verify(token, &settings.auth_keys())?;
+        if claims.uaid != *uaid {
+            return Err(ApiErrorKind::InvalidLocalAuth("incorrect auth token".to_owned()).into());
+        }
+        if claims.exp.map_or(false, |exp| exp <= ms_since_epoch()) {
+            return Err(ApiErrorKind::InvalidLocalAuth("expired secret".to_owned()).into());
+        }
+
+        Ok(Self {
+            scope: claims.scope,
+            issued_at: Some(claims.iat),
+            expires_at: claims.exp,
+        })
+    }
+
+    /// Check that the secret was not revoked. Secrets issued before the
+    /// user's `secrets_not_before` are revoked. Legacy secrets can't be told
+    /// apart, so they are all revoked once any secret is.
+    pub fn check_revoked(&self, secrets_not_before: Option<u64>) -> ApiResult<()> {
+        match (self.issued_at, secrets_not_before) {
+            (_, None) => Ok(()),
+            (Some(issued_at), Some(not_before)) if issued_at >= not_before => Ok(()),
+            _ => Err(ApiErrorKind::InvalidLocalAuth("revoked secret".to_owned()).into()),
+        }
+    }
+
+    /// Check that the secret allows the operation
+    pub fn require(&self, scope: SecretScope) -> ApiResult<()> {
+        if self.scope.contains(&scope) {
+            Ok(())
+        } else {
+            Err(ApiErrorKind::InsufficientScope(scope.as_str()).into())
+        }
+    }
+
+    /// The operations the secret allows
+    pub fn scope(&self) -> &[SecretScope] {
+        &self.scope
+    }
 }
 
 impl FromRequest for AuthorizationCheck {
@@ -67,7 +226,19 @@ impl FromRequest for AuthorizationCheck {
             let token = get_token_from_auth_header(auth_header)
                 .ok_or_else(|| ApiErrorKind::InvalidLocalAuth("missing auth token".to_owned()))?;
 
-            Self::validate_token(token, &uaid, &state.settings.auth_keys())
+            let auth = Self::validate_secret(token, &uaid, &state.settings)?;
+            if auth.issued_at.is_none() {
+                // Track how many clients still use legacy secrets
+                state.metrics.incr("registration.secret.legacy").ok();
+            }
+
+            let secrets_not_before = state
+                .db
+                .get_user(&uaid)
+                .await?
+                .and_then(|user| user.secrets_not_before);
+            auth.check_revoked(secrets_not_before)?;
+            Ok(auth)
         }
         .boxed_local()
     }
@@ -133,4 +304,128 @@ mod test {
         assert!(get_token_from_auth_header(&format!("random {}", &token)).is_none());
         Ok(())
     }
+
+    #[test]
+    fn test_scoped_secret() -> ApiResult<()> {
+        let uaid: Uuid = "729e5104f5f04abc9196085340317dea".parse().unwrap();
+        let settings = Settings::default();
+        let auth_key = settings.auth_keys().remove(0);
+        let secret = AuthorizationCheck::generate_scoped_secret(
+            &auth_key,
+            &uaid,
+            vec![SecretScope::Read, SecretScope::Subscribe],
+            60,
+        )
+        .unwrap();
+
+        let auth = AuthorizationCheck::validate_secret(&secret, &uaid, &settings)?;
+        auth.require(SecretScope::Subscribe)?;
+        assert!(matches!(
+            auth.require(SecretScope::Unregister).unwrap_err().kind,
+            ApiErrorKind::InsufficientScope("unregister")
+        ));
+
+        // The secret is only valid for its UAID, and can't be tampered with
+        let other_uaid = Uuid::new_v4();
+        assert!(AuthorizationCheck::validate_secret(&secret, &other_uaid, &settings).is_err());
+        let tampered = secret.replacen("v2.", "v2.e", 1);
+        assert!(AuthorizationCheck::validate_secret(&tampered, &uaid, &settings).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired_secret() {
+        let uaid = Uuid::new_v4();
+        let settings = Settings::default();
+        let secret = SecretClaims {
+            uaid,
+            scope: SecretScope::ALL.to_vec(),
+            iat: 0,
+            exp: Some(1),
+        }
+        .sign(&settings.auth_keys()[0])
+        .unwrap();
+
+        assert!(AuthorizationCheck::validate_secret(&secret, &uaid, &settings).is_err());
+    }
+
+    #[test]
+    fn test_delegated_secret_expiry() -> ApiResult<()> {
+        let uaid = Uuid::new_v4();
+        let settings = Settings::default();
+        let caller = AuthorizationCheck {
+            scope: SecretScope::ALL.to_vec(),
+            issued_at: Some(ms_since_epoch()),
+            expires_at: Some(ms_since_epoch() + 60_000),
+        };
+        let expires_at = |secret: &str| -> ApiResult<Option<u64>> {
+            Ok(AuthorizationCheck::validate_secret(secret, &uaid, &settings)?.expires_at)
+        };
+
+        // The new secret doesn't outlive the caller's
+        let secret = caller.generate_delegated_secret(
+            &settings,
+            &uaid,
+            vec![SecretScope::Read],
+            Some(3600),
+        )?;
+        assert_eq!(expires_at(&secret)?, caller.expires_at);
+        let secret = caller.generate_delegated_secret(
+            &settings,
+            &uaid,
+            vec![SecretScope::Read],
+            Some(10),
+        )?;
+        assert!(expires_at(&secret)? < caller.expires_at);
+
+        // An expiring secret can't issue one which never expires
+        assert!(matches!(
+            caller
+                .generate_delegated_secret(&settings, &uaid, vec![SecretScope::Read], Some(0))
+                .unwrap_err()
+                .kind,
+            ApiErrorKind::InsufficientScope(_)
+        ));
+
+        // A secret which never expires can
+        let caller = AuthorizationCheck {
+            expires_at: None,
+            ..caller
+        };
+        let secret =
+            caller.generate_delegated_secret(&settings, &uaid, vec![SecretScope::Read], Some(0))?;
+        assert_eq!(expires_at(&secret)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_revoked_secret() -> ApiResult<()> {
+        let uaid = Uuid::new_v4();
+        let settings = Settings::default();
+        let secret = AuthorizationCheck::generate_full_scoped_secret(&settings, &uaid)?;
+        let auth = AuthorizationCheck::validate_secret(&secret, &uaid, &settings)?;
+
+        auth.check_revoked(None)?;
+        auth.check_revoked(Some(auth.issued_at.unwrap()))?;
+        assert!(auth.check_revoked(Some(ms_since_epoch() + 1000)).is_err());
+
+        // Legacy secrets are revoked along with every other secret
+        let legacy = AuthorizationCheck::generate_token(&settings.auth_keys()[0], &uaid).unwrap();
+        let auth = AuthorizationCheck::validate_secret(&legacy, &uaid, &settings)?;
+        auth.check_revoked(None)?;
+        assert!(auth.check_revoked(Some(0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_secrets_disabled() {
+        let uaid = Uuid::new_v4();
+        let settings = Settings {
+            accept_legacy_secrets: false,
+            ..Default::default()
+        };
+        let legacy = AuthorizationCheck::generate_token(&settings.auth_keys()[0], &uaid).unwrap();
+
+        assert!(AuthorizationCheck::validate_secret(&legacy, &uaid, &settings).is_err());
+    }
 }
@@ -0,0 +1,10 @@
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// A list of channel IDs provided when managing several channels of an
+/// existing user at once. Extract from the request via the `Json` extractor.
+#[derive(serde::Deserialize)]
+pub struct ChannelListData {
+    #[serde(rename = "channelIDs")]
+    pub channel_ids: HashSet<Uuid>,
+}
@@ -1,9 +1,12 @@
 //! Actix extractors (`FromRequest`). These extractors transform and validate
 //! the incoming request data.
 
+pub mod admin_auth;
 pub mod authorization_check;
+pub mod channel_list_data;
 pub mod message_id;
 pub mod new_channel_data;
+pub mod new_secret_data;
 pub mod notification;
 pub mod notification_headers;
 pub mod registration_path_args;
@@ -0,0 +1,14 @@
+use crate::auth::SecretScope;
+
+/// The data provided when issuing a new registration secret. Extract from the
+/// request via the `Json` extractor.
+#[derive(serde::Deserialize, Default)]
+pub struct NewSecretData {
+    /// The operations the new secret allows. Defaults to the operations the
+    /// caller's secret allows, and may not exceed them.
+    pub scope: Option<Vec<SecretScope>>,
+    /// The lifetime of the new secret in seconds, 0 for never. Defaults to
+    /// the `scoped_secret_ttl` setting. The new secret never outlives the
+    /// caller's, so 0 is refused for callers whose secret expires.
+    pub ttl: Option<u64>,
+}
@@ -4,11 +4,14 @@ use crate::extractors::{
 };
 use crate::server::AppState;
 use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use autopush_common::audit::{AuditEvent, AuditRecord};
+use autopush_common::db::{DeadLetter, User};
 use autopush_common::util::{b64_encode_url, ms_since_epoch, sec_since_epoch};
 use cadence::CountedExt;
 use fernet::MultiFernet;
 use futures::{future, FutureExt};
 use std::collections::HashMap;
+use tracing::Instrument;
 use uuid::Uuid;
 
 /// Extracts notification data from `Subscription` and request data
@@ -58,35 +61,10 @@ impl FromRequest for Notification {
             };
 
             let headers = NotificationHeaders::from_request(&req, data.is_some())?;
-            let timestamp = sec_since_epoch();
-            let sort_key_timestamp = ms_since_epoch();
-            let message_id = Self::generate_message_id(
-                &app_state.fernet,
-                subscription.user.uaid,
-                subscription.channel_id,
-                headers.topic.as_deref(),
-                sort_key_timestamp,
-            );
-
-            // Record the encoding if we have an encrypted payload
-            if let Some(encoding) = &headers.encoding {
-                if data.is_some() {
-                    app_state
-                        .metrics
-                        .incr(&format!("updates.notification.encoding.{encoding}"))
-                        .ok();
-                }
-            }
 
-            Ok(Notification {
-                message_id,
-                subscription,
-                headers,
-                timestamp,
-                sort_key_timestamp,
-                data,
-            })
+            Ok(Notification::new(subscription, headers, data, &app_state))
         }
+        .instrument(tracing::info_span!("extract.notification"))
         .boxed_local()
     }
 }
@@ -114,6 +92,100 @@ impl From<Notification> for autopush_common::notification::Notification {
 }
 
 impl Notification {
+    /// Build a notification from already validated parts
+    pub fn new(
+        subscription: Subscription,
+        headers: NotificationHeaders,
+        data: Option<String>,
+        app_state: &AppState,
+    ) -> Self {
+        let timestamp = sec_since_epoch();
+        let sort_key_timestamp = ms_since_epoch();
+        let message_id = Self::generate_message_id(
+            &app_state.fernet,
+            subscription.user.uaid,
+            subscription.channel_id,
+            headers.topic.as_deref(),
+            sort_key_timestamp,
+        );
+
+        // Record the encoding if we have an encrypted payload
+        if let Some(encoding) = &headers.encoding {
+            if data.is_some() {
+                app_state
+                    .metrics
+                    .incr(&format!("updates.notification.encoding.{encoding}"))
+                    .ok();
+            }
+        }
+
+        Notification {
+            message_id,
+            subscription,
+            headers,
+            timestamp,
+            sort_key_timestamp,
+            data,
+        }
+    }
+
+    /// Start an audit log event for the notification
+    pub fn audit(&self, event: AuditEvent) -> AuditRecord<'_> {
+        AuditRecord::new(event, &self.message_id, &self.subscription.user.uaid)
+            .channel_id(&self.subscription.channel_id)
+    }
+
+    /// Record the notification as a dead letter, after a bridge failed to
+    /// deliver it
+    pub fn to_dead_letter(&self, reason: String) -> DeadLetter {
+        DeadLetter {
+            id: Uuid::new_v4().as_simple().to_string(),
+            uaid: self.subscription.user.uaid,
+            channel_id: self.subscription.channel_id,
+            message_id: self.message_id.clone(),
+            router_type: self.subscription.user.router_type.clone(),
+            timestamp: self.timestamp,
+            ttl: self.headers.ttl as u64,
+            topic: self.headers.topic.clone(),
+            urgency: self.headers.urgency.clone(),
+            headers: self.headers.clone().into(),
+            data: self.data.clone(),
+            failed_at: sec_since_epoch(),
+            reason,
+        }
+    }
+
+    /// Rebuild a dead-lettered notification to the user, so it may be
+    /// replayed. The TTL is reduced to the time remaining.
+    pub fn from_dead_letter(dead_letter: DeadLetter, user: User) -> Self {
+        let timestamp = sec_since_epoch();
+        let ttl = dead_letter.ttl_remaining(timestamp) as i64;
+        let mut headers = dead_letter.headers;
+
+        Notification {
+            message_id: dead_letter.message_id,
+            subscription: Subscription {
+                user,
+                channel_id: dead_letter.channel_id,
+                vapid: None,
+                vapid_sub: None,
+                reissued_endpoint: None,
+            },
+            headers: NotificationHeaders {
+                ttl,
+                topic: dead_letter.topic,
+                urgency: dead_letter.urgency,
+                encoding: headers.remove("encoding"),
+                encryption: headers.remove("encryption"),
+                encryption_key: headers.remove("encryption_key"),
+                crypto_key: headers.remove("crypto_key"),
+            },
+            timestamp,
+            sort_key_timestamp: ms_since_epoch(),
+            data: dead_letter.data,
+        }
+    }
+
     /// Generate a message-id suitable for accessing the message
     ///
     /// For topic messages, a sort_key version of 01 is used, and the topic
@@ -1,6 +1,6 @@
 use crate::error::{ApiError, ApiErrorKind, ApiResult};
 use crate::headers::crypto_key::CryptoKeyHeader;
-use crate::headers::util::{get_header, get_owned_header};
+use crate::headers::util::get_owned_header;
 use actix_web::HttpRequest;
 use autopush_common::util::InsertOpt;
 use lazy_static::lazy_static;
@@ -19,6 +19,9 @@ lazy_static! {
 /// 60 days
 const MAX_TTL: i64 = 60 * 60 * 24 * 60;
 
+/// The `Urgency` header values defined by RFC 8030
+const URGENCIES: [&str; 4] = ["very-low", "low", "normal", "high"];
+
 /// Extractor and validator for notification headers
 #[derive(Clone, Debug, Eq, PartialEq, Validate)]
 pub struct NotificationHeaders {
@@ -40,6 +43,9 @@ pub struct NotificationHeaders {
     )]
     pub topic: Option<String>,
 
+    /// The lowercased `Urgency` header, if it's one of `URGENCIES`
+    pub urgency: Option<String>,
+
     // These fields are validated separately, because the validation is complex
     // and based upon the content encoding
     pub encoding: Option<String>,
@@ -67,28 +73,44 @@ impl NotificationHeaders {
     /// know if the payload has data, without actually advancing the payload
     /// stream.
     pub fn from_request(req: &HttpRequest, has_data: bool) -> ApiResult<Self> {
+        Self::from_headers(|name| get_owned_header(req, name), has_data)
+    }
+
+    /// Extract the notification headers using a header lookup function. The
+    /// function is given lowercase header names.
+    pub fn from_headers(
+        get_header: impl Fn(&str) -> Option<String>,
+        has_data: bool,
+    ) -> ApiResult<Self> {
         // Collect raw headers
-        let ttl = get_header(req, "ttl")
+        let ttl = get_header("ttl")
             .and_then(|ttl| ttl.parse().ok())
             // Enforce a maximum TTL, but don't error
             .map(|ttl| min(ttl, MAX_TTL))
             .ok_or(ApiErrorKind::NoTTL)?;
-        let topic = get_owned_header(req, "topic");
+        let topic = get_header("topic");
+        // Unknown urgencies are ignored rather than rejected, like other
+        // optional headers
+        let urgency = get_header("urgency")
+            .map(|urgency| urgency.trim().to_lowercase())
+            .filter(|urgency| URGENCIES.contains(&urgency.as_str()));
 
         let headers = if has_data {
             NotificationHeaders {
                 ttl,
                 topic,
-                encoding: get_owned_header(req, "content-encoding"),
-                encryption: get_owned_header(req, "encryption").map(Self::strip_header),
-                encryption_key: get_owned_header(req, "encryption-key"),
-                crypto_key: get_owned_header(req, "crypto-key").map(Self::strip_header),
+                urgency,
+                encoding: get_header("content-encoding"),
+                encryption: get_header("encryption").map(Self::strip_header),
+                encryption_key: get_header("encryption-key"),
+                crypto_key: get_header("crypto-key").map(Self::strip_header),
             }
         } else {
             // Messages without a body shouldn't pass along unnecessary headers
             NotificationHeaders {
                 ttl,
                 topic,
+                urgency,
                 encoding: None,
                 encryption: None,
                 encryption_key: None,
@@ -333,6 +355,24 @@ mod tests {
         );
     }
 
+    /// Urgencies are case-insensitive, and unknown urgencies are ignored
+    #[test]
+    fn urgency() {
+        let urgency = |value: &str| {
+            let req = TestRequest::post()
+                .insert_header(("TTL", "10"))
+                .insert_header(("Urgency", value))
+                .to_http_request();
+            NotificationHeaders::from_request(&req, false)
+                .unwrap()
+                .urgency
+        };
+
+        assert_eq!(urgency("very-low"), Some("very-low".to_string()));
+        assert_eq!(urgency("High"), Some("high".to_string()));
+        assert_eq!(urgency("urgent"), None);
+    }
+
     /// If there is a payload, there must be a content encoding header
     #[test]
     fn payload_without_content_encoding() {
@@ -361,6 +401,7 @@ mod tests {
             NotificationHeaders {
                 ttl: 10,
                 topic: None,
+                urgency: None,
                 encoding: Some("aesgcm".to_string()),
                 encryption: Some("salt=foo".to_string()),
                 encryption_key: None,
@@ -386,6 +427,7 @@ mod tests {
             NotificationHeaders {
                 ttl: 10,
                 topic: None,
+                urgency: None,
                 encoding: Some("aes128gcm".to_string()),
                 encryption: Some("notsalt=foo".to_string()),
                 encryption_key: None,
@@ -412,6 +454,7 @@ mod tests {
             NotificationHeaders {
                 ttl: 10,
                 topic: None,
+                urgency: None,
                 encoding: Some("aesgcm".to_string()),
                 encryption: Some("salt=foo".to_string()),
                 encryption_key: None,
@@ -1,11 +1,12 @@
 use crate::error::{ApiError, ApiErrorKind};
-use crate::extractors::routers::RouterType;
-use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use crate::server::AppState;
+use actix_web::{dev::Payload, web::Data, FromRequest, HttpRequest};
 use futures::future;
 
 /// Extracts and validates the `router_type` and `app_id` path arguments
 pub struct RegistrationPathArgs {
-    pub router_type: RouterType,
+    /// The name of a router in the `RouterRegistry`
+    pub router_type: String,
     pub app_id: String,
 }
 
@@ -14,15 +15,17 @@ impl FromRequest for RegistrationPathArgs {
     type Future = future::Ready<Result<Self, Self::Error>>;
 
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let app_state = Data::<AppState>::extract(req)
+            .into_inner()
+            .expect("No server state found");
         let match_info = req.match_info();
-        let router_type = match match_info
+        let router_type = match_info
             .get("router_type")
             .expect("{router_type} must be part of the path")
-            .parse::<RouterType>()
-        {
-            Ok(router_type) => router_type,
-            Err(_) => return future::err(ApiErrorKind::InvalidRouterType.into()),
-        };
+            .to_lowercase();
+        if !app_state.routers.contains(&router_type) {
+            return future::err(ApiErrorKind::InvalidRouterType.into());
+        }
         let app_id = match_info
             .get("app_id")
             .expect("{app_id} must be part of the path")
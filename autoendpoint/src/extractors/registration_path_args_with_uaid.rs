@@ -1,6 +1,5 @@
 use crate::error::{ApiError, ApiErrorKind};
 use crate::extractors::registration_path_args::RegistrationPathArgs;
-use crate::extractors::routers::RouterType;
 use crate::server::AppState;
 use actix_web::dev::Payload;
 use actix_web::web::Data;
@@ -12,7 +11,7 @@ use uuid::Uuid;
 /// An extension of `RegistrationPathArgs` which requires a `uaid` path arg.
 /// The `uaid` is verified by checking if the user exists in the database.
 pub struct RegistrationPathArgsWithUaid {
-    pub router_type: RouterType,
+    pub router_type: String,
     pub app_id: String,
     pub uaid: Uuid,
 }
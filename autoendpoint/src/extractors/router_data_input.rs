@@ -1,17 +1,9 @@
 use crate::error::{ApiError, ApiErrorKind};
-use crate::extractors::{registration_path_args::RegistrationPathArgs, routers::RouterType};
+use crate::extractors::{registration_path_args::RegistrationPathArgs, routers::Routers};
 use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
 use futures::{future::LocalBoxFuture, FutureExt};
-use lazy_static::lazy_static;
-use regex::Regex;
 use uuid::Uuid;
 
-lazy_static! {
-    static ref VALID_TOKEN: Regex = Regex::new(r"^[^ ]{8,}$").unwrap();
-    static ref VALID_ADM_TOKEN: Regex =
-        Regex::new(r"^amzn1.adm-registration.v3.[^ ]{256,}$").unwrap();
-}
-
 /// Extracts the router data from the request body and validates the token
 /// against the given router's token schema (taken from request path params).
 #[derive(serde::Deserialize)]
@@ -21,6 +13,8 @@ pub struct RouterDataInput {
     pub channel_id: Option<Uuid>,
     pub key: Option<String>,
     pub aps: Option<serde_json::Value>,
+    /// The shared secret used to sign webhook callbacks
+    pub secret: Option<String>,
 }
 
 impl FromRequest for RouterDataInput {
@@ -38,15 +32,11 @@ impl FromRequest for RouterDataInput {
                 .map_err(ApiErrorKind::PayloadError)?;
 
             // Validate the token according to each router's token schema
-            let is_valid = match path_args.router_type {
-                RouterType::WebPush => true,
-                RouterType::FCM | RouterType::GCM | RouterType::APNS => {
-                    VALID_TOKEN.is_match(&data.token)
-                }
-                RouterType::ADM => VALID_ADM_TOKEN.is_match(&data.token),
-            };
-
-            if !is_valid {
+            let routers = Routers::extract(&req).into_inner()?;
+            if !routers
+                .get(&path_args.router_type)?
+                .validate_token(&data.token)
+            {
                 return Err(ApiErrorKind::InvalidRouterToken.into());
             }
 
@@ -1,8 +1,5 @@
 use crate::error::{ApiError, ApiResult};
-use crate::routers::adm::router::AdmRouter;
-use crate::routers::apns::router::ApnsRouter;
-use crate::routers::fcm::router::FcmRouter;
-use crate::routers::webpush::WebPushRouter;
+use crate::routers::registry::RouterRegistry;
 use crate::routers::Router;
 use crate::server::AppState;
 use actix_web::dev::Payload;
@@ -13,7 +10,8 @@ use std::fmt::{self, Display};
 use std::str::FromStr;
 use std::sync::Arc;
 
-/// Valid `DynamoDbUser::router_type` values
+/// The built-in `DynamoDbUser::router_type` values. Other router types may be
+/// added to the `RouterRegistry`.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum RouterType {
@@ -22,6 +20,7 @@ pub enum RouterType {
     GCM,
     APNS,
     ADM,
+    Webhook,
 }
 
 impl FromStr for RouterType {
@@ -34,6 +33,7 @@ impl FromStr for RouterType {
             "gcm" => Ok(RouterType::GCM),
             "apns" => Ok(RouterType::APNS),
             "adm" => Ok(RouterType::ADM),
+            "webhook" => Ok(RouterType::Webhook),
             _ => Err(()),
         }
     }
@@ -47,6 +47,7 @@ impl Display for RouterType {
             RouterType::GCM => "gcm",
             RouterType::APNS => "apns",
             RouterType::ADM => "adm",
+            RouterType::Webhook => "webhook",
         })
     }
 }
@@ -54,10 +55,7 @@ impl Display for RouterType {
 /// Holds the various notification routers. The routers use resources from the
 /// server state, which is why `Routers` is an extractor.
 pub struct Routers {
-    webpush: WebPushRouter,
-    fcm: Arc<FcmRouter>,
-    apns: Arc<ApnsRouter>,
-    adm: Arc<AdmRouter>,
+    registry: Arc<RouterRegistry>,
 }
 
 impl FromRequest for Routers {
@@ -70,27 +68,14 @@ impl FromRequest for Routers {
             .expect("No server state found");
 
         future::ok(Routers {
-            webpush: WebPushRouter {
-                db: app_state.db.clone(),
-                metrics: app_state.metrics.clone(),
-                http: app_state.http.clone(),
-                endpoint_url: app_state.settings.endpoint_url(),
-            },
-            fcm: app_state.fcm_router.clone(),
-            apns: app_state.apns_router.clone(),
-            adm: app_state.adm_router.clone(),
+            registry: app_state.routers.clone(),
         })
     }
 }
 
 impl Routers {
     /// Get the router which handles the router type
-    pub fn get(&self, router_type: RouterType) -> &dyn Router {
-        match router_type {
-            RouterType::WebPush => &self.webpush,
-            RouterType::FCM | RouterType::GCM => self.fcm.as_ref(),
-            RouterType::APNS => self.apns.as_ref(),
-            RouterType::ADM => self.adm.as_ref(),
-        }
+    pub fn get(&self, router_type: &str) -> ApiResult<&dyn Router> {
+        self.registry.get(router_type)
     }
 }
@@ -2,16 +2,20 @@ use std::borrow::Cow;
 use std::str::FromStr;
 
 use actix_web::{dev::Payload, web::Data, FromRequest, HttpRequest};
+use autopush_common::endpoint::reissue_endpoint;
 use autopush_common::{
     db::User,
+    metrics::Metrics,
     tags::Tags,
     util::{b64_decode_std, b64_decode_url, sec_since_epoch},
 };
 use cadence::{CountedExt, StatsdClient};
+use fernet::Fernet;
 use futures::{future::LocalBoxFuture, FutureExt};
 use jsonwebtoken::{Algorithm, DecodingKey, Validation};
 use openssl::hash::MessageDigest;
 use serde::{Deserialize, Serialize};
+use tracing::Instrument;
 use url::Url;
 use uuid::Uuid;
 
@@ -24,7 +28,6 @@ use crate::headers::{
     crypto_key::CryptoKeyHeader,
     vapid::{VapidError, VapidHeader, VapidHeaderWithKey, VapidVersionData},
 };
-use crate::metrics::Metrics;
 use crate::server::AppState;
 
 const ONE_DAY_IN_SECONDS: u64 = 60 * 60 * 24;
@@ -35,6 +38,11 @@ pub struct Subscription {
     pub user: User,
     pub channel_id: Uuid,
     pub vapid: Option<VapidHeaderWithKey>,
+    /// The `sub` claim of the validated VAPID token
+    pub vapid_sub: Option<String>,
+    /// The endpoint re-encrypted with the primary key, if the token was
+    /// encrypted with an older key and reissuing is enabled
+    pub reissued_endpoint: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -67,65 +75,124 @@ impl FromRequest for Subscription {
             trace!("Token info: {:?}", &token_info);
             let app_state: Data<AppState> =
                 Data::extract(&req).await.expect("No server state found");
-            let metrics = Metrics::from(&app_state);
-
-            // Decrypt the token
-            let token = app_state
-                .fernet
-                .decrypt(&repad_base64(&token_info.token))
-                .map_err(|e| {
-                    error!("fernet: {:?}", e);
-                    ApiErrorKind::InvalidToken
-                })?;
-
-            // Parse VAPID and extract public key.
-            let vapid: Option<VapidHeaderWithKey> = parse_vapid(&token_info, &app_state.metrics)?
-                .map(|vapid| extract_public_key(vapid, &token_info))
-                .transpose()?;
-
-            trace!("Vapid: {:?}", &vapid);
-
-            match token_info.api_version {
-                ApiVersion::Version1 => version_1_validation(&token)?,
-                ApiVersion::Version2 => version_2_validation(&token, vapid.as_ref())?,
-            }
 
-            // Load and validate user data.
-            // Note: It is safe to unwrap the Uuid result because an error is
-            // only returned if the slice length is not 16.
-            let uaid = Uuid::from_slice(&token[..16]).unwrap();
-            let channel_id = Uuid::from_slice(&token[16..32]).unwrap();
+            Subscription::from_token_info(&token_info, &app_state)
+                .instrument(tracing::info_span!("extract.subscription"))
+                .await
+        }
+        .boxed_local()
+    }
+}
+
+impl Subscription {
+    /// Decrypt and validate the subscription referenced by the token info.
+    /// This is shared by the `FromRequest` impl and the batch route, where the
+    /// token info comes from the request body instead of the path.
+    pub async fn from_token_info(
+        token_info: &TokenInfo,
+        app_state: &Data<AppState>,
+    ) -> ApiResult<Self> {
+        let metrics = Metrics::from(app_state.metrics.clone());
+
+        // Decrypt the token, recording which key was used so we know when
+        // old keys can be retired
+        let (key_index, token) = decrypt_token(&app_state.fernet_keys, &token_info.token)
+            .ok_or_else(|| {
+                error!("fernet: Token could not be decrypted with any key");
+                ApiErrorKind::InvalidToken
+            })?;
+        app_state
+            .metrics
+            .incr_with_tags("notification.token.decrypted")
+            .with_tag("key_index", &key_index.to_string())
+            .send();
+
+        // Parse VAPID and extract public key.
+        let vapid: Option<VapidHeaderWithKey> = parse_vapid(token_info, &app_state.metrics)?
+            .map(|vapid| extract_public_key(vapid, token_info))
+            .transpose()?;
+
+        trace!("Vapid: {:?}", &vapid);
+
+        match token_info.api_version {
+            ApiVersion::Version1 => version_1_validation(&token)?,
+            ApiVersion::Version2 => version_2_validation(&token, vapid.as_ref())?,
+            ApiVersion::Version3 => version_3_validation(&token, vapid.as_ref())?,
+        }
 
-            trace!("UAID: {:?}, CHID: {:?}", uaid, channel_id);
+        // Load and validate user data.
+        // Note: It is safe to unwrap the Uuid result because an error is
+        // only returned if the slice length is not 16.
+        let uaid = Uuid::from_slice(&token[..16]).unwrap();
+        let channel_id = Uuid::from_slice(&token[16..32]).unwrap();
 
-            let user = app_state
-                .db
-                .get_user(&uaid)
-                .await?
-                .ok_or(ApiErrorKind::NoSubscription)?;
+        trace!("UAID: {:?}, CHID: {:?}", uaid, channel_id);
 
-            trace!("user: {:?}", &user);
-            validate_user(&user, &channel_id, &app_state).await?;
+        let user = app_state
+            .db
+            .get_user(&uaid)
+            .await?
+            .ok_or(ApiErrorKind::NoSubscription)?;
 
-            // Validate the VAPID JWT token and record the version
-            if let Some(vapid) = &vapid {
-                validate_vapid_jwt(vapid, &app_state.settings.endpoint_url(), &metrics)?;
+        trace!("user: {:?}", &user);
+        validate_user(&user, &channel_id, app_state).await?;
 
-                app_state
-                    .metrics
-                    .incr(&format!("updates.vapid.draft{:02}", vapid.vapid.version()))?;
-            }
+        // Validate the VAPID JWT token and record the version
+        let mut vapid_sub = None;
+        if let Some(vapid) = &vapid {
+            let claims = validate_vapid_jwt(vapid, &app_state.settings.endpoint_url(), &metrics)?;
+            vapid_sub = Some(claims.sub);
 
-            Ok(Subscription {
-                user,
-                channel_id,
-                vapid,
-            })
+            app_state
+                .metrics
+                .incr(&format!("updates.vapid.draft{:02}", vapid.vapid.version()))?;
         }
-        .boxed_local()
+
+        // Only check the limits once the VAPID key is known to be genuine,
+        // so a sender can't exhaust another sender's bucket.
+        app_state
+            .rate_limiter
+            .check(
+                vapid.as_ref().map(|vapid| vapid.public_key.as_str()),
+                &uaid,
+                &channel_id,
+                &metrics,
+            )
+            .await?;
+
+        let reissued_endpoint = if key_index > 0 && app_state.settings.reissue_endpoints {
+            let primary = &app_state.fernet_keys[0];
+            reissue_endpoint(
+                &token_info.api_version.to_string(),
+                &token,
+                app_state.settings.endpoint_url().as_str(),
+                primary,
+            )
+            .map_err(|e| warn!("Could not reissue endpoint: {:?}", e))
+            .ok()
+        } else {
+            None
+        };
+
+        Ok(Subscription {
+            user,
+            channel_id,
+            vapid,
+            vapid_sub,
+            reissued_endpoint,
+        })
     }
 }
 
+/// Decrypt a token, returning the index of the key which decrypted it along
+/// with the decrypted data
+fn decrypt_token(keys: &[Fernet], token: &str) -> Option<(usize, Vec<u8>)> {
+    let token = repad_base64(token);
+    keys.iter()
+        .enumerate()
+        .find_map(|(index, key)| key.decrypt(&token).ok().map(|data| (index, data)))
+}
+
 /// Add back padding to a base64 string
 fn repad_base64(data: &str) -> Cow<'_, str> {
     let trailing_chars = data.len() % 4;
@@ -223,7 +290,33 @@ fn version_2_validation(token: &[u8], vapid: Option<&VapidHeaderWithKey>) -> Api
 
     // Verify that the sender is authorized to send notifications.
     // The last 32 bytes of the token is the hashed public key.
-    let token_key = &token[32..];
+    validate_key_hash(&token[32..], vapid)
+}
+
+/// `/webpush/v3/` validations
+fn version_3_validation(token: &[u8], vapid: Option<&VapidHeaderWithKey>) -> ApiResult<()> {
+    if token.len() != 40 && token.len() != 72 {
+        // Corrupted token
+        return Err(ApiErrorKind::InvalidToken.into());
+    }
+
+    // Bytes 32 to 40 are the expiry timestamp
+    let mut expiry = [0u8; 8];
+    expiry.copy_from_slice(&token[32..40]);
+    if u64::from_be_bytes(expiry) <= sec_since_epoch() {
+        return Err(ApiErrorKind::SubscriptionExpired.into());
+    }
+
+    // Restricted subscriptions end with the hashed public key
+    if token.len() == 72 {
+        validate_key_hash(&token[40..], vapid)?;
+    }
+
+    Ok(())
+}
+
+/// Verify that the VAPID public key hashes to the key hash stored in the token
+fn validate_key_hash(token_key: &[u8], vapid: Option<&VapidHeaderWithKey>) -> ApiResult<()> {
     let public_key = &vapid.ok_or(VapidError::MissingKey)?.public_key;
 
     // Hash the VAPID public key
@@ -244,12 +337,13 @@ fn version_2_validation(token: &[u8], vapid: Option<&VapidHeaderWithKey>) -> Api
 /// - Make sure it hasn't expired
 /// - Make sure the expiration isn't too far into the future
 ///
-/// This is mostly taken care of by the jsonwebtoken library
+/// This is mostly taken care of by the jsonwebtoken library. The validated
+/// claims are returned.
 fn validate_vapid_jwt(
     vapid: &VapidHeaderWithKey,
     domain: &Url,
     metrics: &Metrics,
-) -> ApiResult<()> {
+) -> ApiResult<VapidClaims> {
     let VapidHeaderWithKey { vapid, public_key } = vapid;
 
     let public_key = decode_public_key(public_key)?;
@@ -313,16 +407,16 @@ fn validate_vapid_jwt(
         return Err(VapidError::InvalidAudience.into());
     }
 
-    Ok(())
+    Ok(token_data.claims)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{validate_vapid_jwt, VapidClaims};
+    use super::{decrypt_token, validate_vapid_jwt, version_3_validation, VapidClaims};
     use crate::error::ApiErrorKind;
     use crate::extractors::subscription::repad_base64;
     use crate::headers::vapid::{VapidError, VapidHeader, VapidHeaderWithKey, VapidVersionData};
-    use crate::metrics::Metrics;
+    use autopush_common::metrics::Metrics;
     use autopush_common::util::{b64_decode_std, sec_since_epoch};
     use serde::{Deserialize, Serialize};
     use std::str::FromStr;
@@ -576,4 +670,46 @@ mod tests {
             ApiErrorKind::VapidError(VapidError::InvalidVapid(_))
         ])
     }
+
+    #[test]
+    fn version_3_expiry() {
+        let mut token = vec![0u8; 32];
+        token.extend((sec_since_epoch() + 60).to_be_bytes());
+        assert!(version_3_validation(&token, None).is_ok());
+
+        let mut token = vec![0u8; 32];
+        token.extend((sec_since_epoch() - 60).to_be_bytes());
+        assert!(matches!(
+            version_3_validation(&token, None).unwrap_err().kind,
+            ApiErrorKind::SubscriptionExpired
+        ));
+
+        // A restricted token requires the VAPID key
+        let mut token = vec![0u8; 32];
+        token.extend((sec_since_epoch() + 60).to_be_bytes());
+        token.extend([0u8; 32]);
+        assert!(matches!(
+            version_3_validation(&token, None).unwrap_err().kind,
+            ApiErrorKind::VapidError(VapidError::MissingKey)
+        ));
+
+        assert!(matches!(
+            version_3_validation(&[0u8; 32], None).unwrap_err().kind,
+            ApiErrorKind::InvalidToken
+        ));
+    }
+
+    #[test]
+    fn decrypt_token_key_index() {
+        let primary = fernet::Fernet::new(&fernet::Fernet::generate_key()).unwrap();
+        let old = fernet::Fernet::new(&fernet::Fernet::generate_key()).unwrap();
+        let token = old.encrypt(b"data");
+        let keys = vec![primary, old];
+
+        assert_eq!(
+            decrypt_token(&keys, token.trim_end_matches('=')),
+            Some((1, b"data".to_vec()))
+        );
+        assert_eq!(decrypt_token(&keys[..1], &token), None);
+    }
 }
@@ -2,6 +2,7 @@ use crate::error::{ApiError, ApiErrorKind};
 use crate::headers::util::get_owned_header;
 use actix_web::{dev::Payload, FromRequest, HttpRequest};
 use futures::future;
+use std::fmt::{self, Display};
 use std::str::FromStr;
 
 /// Extracts basic token data from the webpush request path and headers
@@ -42,6 +43,7 @@ impl FromRequest for TokenInfo {
 pub enum ApiVersion {
     Version1,
     Version2,
+    Version3,
 }
 
 impl FromStr for ApiVersion {
@@ -51,7 +53,18 @@ impl FromStr for ApiVersion {
         match s {
             "v1" => Ok(ApiVersion::Version1),
             "v2" => Ok(ApiVersion::Version2),
+            "v3" => Ok(ApiVersion::Version3),
             _ => Err(ApiErrorKind::InvalidApiVersion.into()),
         }
     }
 }
+
+impl Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ApiVersion::Version1 => "v1",
+            ApiVersion::Version2 => "v2",
+            ApiVersion::Version3 => "v3",
+        })
+    }
+}
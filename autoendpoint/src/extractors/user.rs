@@ -8,30 +8,21 @@ use cadence::{CountedExt, StatsdClient};
 use uuid::Uuid;
 
 /// Perform some validations on the user, including:
-/// - Validate router type
+/// - Validate router type (it must be in the `RouterRegistry`)
 /// - (WebPush) Check that the subscription/channel exists
 /// - (WebPush) Drop user if inactive
-///
-/// Returns an enum representing the user's router type.
-pub async fn validate_user(
-    user: &User,
-    channel_id: &Uuid,
-    app_state: &AppState,
-) -> ApiResult<RouterType> {
-    let router_type = match user.router_type.parse::<RouterType>() {
-        Ok(router_type) => router_type,
-        Err(_) => {
-            debug!("Unknown router type, dropping user"; "user" => ?user);
-            drop_user(user.uaid, app_state.db.as_ref(), &app_state.metrics).await?;
-            return Err(ApiErrorKind::NoSubscription.into());
-        }
-    };
+pub async fn validate_user(user: &User, channel_id: &Uuid, app_state: &AppState) -> ApiResult<()> {
+    if !app_state.routers.contains(&user.router_type) {
+        debug!("Unknown router type, dropping user"; "user" => ?user);
+        drop_user(user.uaid, app_state.db.as_ref(), &app_state.metrics).await?;
+        return Err(ApiErrorKind::NoSubscription.into());
+    }
 
-    if router_type == RouterType::WebPush {
+    if user.router_type.parse::<RouterType>() == Ok(RouterType::WebPush) {
         validate_webpush_user(user, channel_id, app_state.db.as_ref(), &app_state.metrics).await?;
     }
 
-    Ok(router_type)
+    Ok(())
 }
 
 /// Make sure the user is not inactive and the subscription channel exists
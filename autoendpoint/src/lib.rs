@@ -0,0 +1,23 @@
+//! The endpoint server, which accepts notifications from application servers
+//! and routes them to user agents, either via a connection server or a
+//! bridge.
+//!
+//! This is a library as well as a binary so the integration tests can run the
+//! server in-process, and so deployments can add their own routers (see
+//! `server::ServerBuilder::router`).
+#![warn(rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+#[macro_use]
+extern crate slog_scope;
+
+mod auth;
+pub mod error;
+pub mod extractors;
+mod headers;
+mod metrics;
+mod rate_limit;
+pub mod routers;
+mod routes;
+pub mod server;
+pub mod settings;
@@ -4,25 +4,13 @@
 #[macro_use]
 extern crate slog_scope;
 
-mod auth;
-mod error;
-mod extractors;
-mod headers;
-mod metrics;
-mod middleware;
-mod routers;
-mod routes;
-mod server;
-mod settings;
-
 use docopt::Docopt;
 use serde::Deserialize;
 use std::error::Error;
 
+use autoendpoint::{server, settings};
 use autopush_common::logging;
 
-pub type LocalError = crate::error::ApiError;
-
 const USAGE: &str = "
 Usage: autoendpoint [options]
 
@@ -44,6 +32,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let settings = settings::Settings::with_env_and_config_file(&args.flag_config)?;
     let host_port = format!("{}:{}", &settings.host, &settings.port);
     logging::init_logging(!settings.human_logs).expect("Logging failed to initialize");
+    autopush_common::telemetry::init(
+        "autoendpoint",
+        &settings.tracing_exporter,
+        settings.tracing_sample_rate,
+    )
+    .expect("Tracing failed to initialize");
+    autopush_common::audit::init(settings.audit_sample_rate);
     debug!("Starting up...");
 
     let _sentry = sentry::init(sentry::ClientOptions {
@@ -61,6 +56,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // Shutdown
     info!("Server closing");
+    autopush_common::telemetry::shutdown();
     logging::reset_logging();
     Ok(())
 }
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use async_trait::async_trait;
+
+use crate::error::ApiResult;
+use crate::rate_limit::settings::BucketLimit;
+use crate::rate_limit::{RateLimitBackend, RateLimitDecision};
+
+/// A single token bucket
+#[derive(Clone, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &BucketLimit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    /// Add the tokens accrued since the last update
+    fn refill(&mut self, limit: &BucketLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.updated = now;
+    }
+
+    /// Whether a token can be taken from the bucket, after refilling it
+    fn check(&mut self, limit: &BucketLimit, now: Instant) -> RateLimitDecision {
+        self.refill(limit, now);
+
+        if self.tokens >= 1.0 {
+            RateLimitDecision::Allowed
+        } else {
+            RateLimitDecision::Limited {
+                retry_after: limit.refill_time(1.0 - self.tokens),
+            }
+        }
+    }
+
+    fn is_full(&self, limit: &BucketLimit, now: Instant) -> bool {
+        let mut bucket = self.clone();
+        bucket.refill(limit, now);
+        bucket.tokens >= limit.burst as f64
+    }
+}
+
+/// Token buckets stored in the memory of this node. Limits are not shared
+/// with other nodes.
+pub struct InMemoryBackend {
+    buckets: Mutex<HashMap<String, (Bucket, BucketLimit)>>,
+    max_entries: usize,
+}
+
+impl InMemoryBackend {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+            max_entries,
+        }
+    }
+
+    fn acquire_at(
+        &self,
+        requests: &[(&str, &BucketLimit)],
+        now: Instant,
+    ) -> Vec<RateLimitDecision> {
+        let mut buckets = self.buckets.lock().expect("Rate limit buckets poisoned");
+
+        // The buckets to take a token from, if every bucket allows it
+        let mut tracked = Vec::with_capacity(requests.len());
+        let mut decisions = Vec::with_capacity(requests.len());
+        for (key, limit) in requests {
+            if buckets.len() >= self.max_entries && !buckets.contains_key(*key) {
+                // A full bucket behaves the same as a missing one, so they can
+                // be dropped without changing any decision.
+                buckets.retain(|_, (bucket, limit)| !bucket.is_full(limit, now));
+                if buckets.len() >= self.max_entries {
+                    warn!("Rate limit backend is full, allowing request"; "key" => *key);
+                    decisions.push(RateLimitDecision::Allowed);
+                    continue;
+                }
+            }
+
+            let (bucket, stored_limit) = buckets
+                .entry(key.to_string())
+                .or_insert_with(|| (Bucket::full(limit, now), **limit));
+            *stored_limit = **limit;
+            decisions.push(bucket.check(limit, now));
+            tracked.push(*key);
+        }
+
+        if decisions.iter().all(|d| *d == RateLimitDecision::Allowed) {
+            for key in tracked {
+                if let Some((bucket, _)) = buckets.get_mut(key) {
+                    bucket.tokens -= 1.0;
+                }
+            }
+        }
+        decisions
+    }
+
+    #[cfg(test)]
+    fn acquire_one(&self, key: &str, limit: &BucketLimit, now: Instant) -> RateLimitDecision {
+        self.acquire_at(&[(key, limit)], now)[0]
+    }
+}
+
+#[async_trait]
+impl RateLimitBackend for InMemoryBackend {
+    async fn acquire(&self, buckets: &[(&str, &BucketLimit)]) -> ApiResult<Vec<RateLimitDecision>> {
+        Ok(self.acquire_at(buckets, Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryBackend;
+    use crate::rate_limit::settings::BucketLimit;
+    use crate::rate_limit::RateLimitDecision;
+    use std::time::{Duration, Instant};
+
+    /// A bucket allows a burst, then limits until tokens are refilled
+    #[test]
+    fn burst_then_refill() {
+        let backend = InMemoryBackend::new(10);
+        let limit = BucketLimit::new(2, 1.0).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(
+            backend.acquire_one("key", &limit, now),
+            RateLimitDecision::Allowed
+        );
+        assert_eq!(
+            backend.acquire_one("key", &limit, now),
+            RateLimitDecision::Allowed
+        );
+        assert_eq!(
+            backend.acquire_one("key", &limit, now),
+            RateLimitDecision::Limited {
+                retry_after: Duration::from_secs(1)
+            }
+        );
+        assert_eq!(
+            backend.acquire_one("key", &limit, now + Duration::from_secs(1)),
+            RateLimitDecision::Allowed
+        );
+    }
+
+    /// Buckets are tracked separately per key
+    #[test]
+    fn separate_keys() {
+        let backend = InMemoryBackend::new(10);
+        let limit = BucketLimit::new(1, 1.0).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(
+            backend.acquire_one("a", &limit, now),
+            RateLimitDecision::Allowed
+        );
+        assert_eq!(
+            backend.acquire_one("b", &limit, now),
+            RateLimitDecision::Allowed
+        );
+        assert!(matches!(
+            backend.acquire_one("a", &limit, now),
+            RateLimitDecision::Limited { .. }
+        ));
+    }
+
+    /// No tokens are taken when any of the buckets is limited
+    #[test]
+    fn all_or_nothing() {
+        let backend = InMemoryBackend::new(10);
+        let limit = BucketLimit::new(1, 1.0).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(
+            backend.acquire_one("b", &limit, now),
+            RateLimitDecision::Allowed
+        );
+        let decisions = backend.acquire_at(&[("a", &limit), ("b", &limit)], now);
+        assert_eq!(decisions[0], RateLimitDecision::Allowed);
+        assert!(matches!(decisions[1], RateLimitDecision::Limited { .. }));
+        assert_eq!(
+            backend.acquire_one("a", &limit, now),
+            RateLimitDecision::Allowed
+        );
+    }
+
+    /// Idle buckets are pruned once the backend is full
+    #[test]
+    fn prunes_full_buckets() {
+        let backend = InMemoryBackend::new(1);
+        let limit = BucketLimit::new(1, 1.0).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(
+            backend.acquire_one("a", &limit, now),
+            RateLimitDecision::Allowed
+        );
+        let later = now + Duration::from_secs(5);
+        assert_eq!(
+            backend.acquire_one("b", &limit, later),
+            RateLimitDecision::Allowed
+        );
+        assert_eq!(backend.buckets.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,240 @@
+//! Token bucket rate limiting of incoming notifications, keyed on the sender's
+//! VAPID public key and on the target UAID and subscription.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use autopush_common::metrics::Metrics;
+use autopush_common::tags::Tags;
+use uuid::Uuid;
+
+use crate::error::{ApiErrorKind, ApiResult};
+use crate::rate_limit::memory::InMemoryBackend;
+use crate::rate_limit::settings::{BucketLimit, RateLimitSettings};
+
+pub mod memory;
+pub mod settings;
+
+/// The outcome of trying to take a token from a bucket
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitDecision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+/// Storage for token buckets. Implementations may be local to the node or
+/// shared between nodes.
+#[async_trait]
+pub trait RateLimitBackend: Send + Sync {
+    /// Take a token from each of the buckets identified by their keys,
+    /// creating them with the given limits if they do not exist yet. Tokens
+    /// are only taken if every bucket allows it, so a request rejected by one
+    /// bucket doesn't use up the others. Returns each bucket's decision, in
+    /// order.
+    async fn acquire(&self, buckets: &[(&str, &BucketLimit)]) -> ApiResult<Vec<RateLimitDecision>>;
+}
+
+/// Applies the configured limits to incoming notifications
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    backend: Box<dyn RateLimitBackend>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter using the backend named in the settings
+    pub fn new(settings: RateLimitSettings) -> ApiResult<Self> {
+        let backend: Box<dyn RateLimitBackend> = match settings.backend.as_str() {
+            "memory" => Box::new(InMemoryBackend::new(settings.max_entries)),
+            other => {
+                return Err(
+                    ApiErrorKind::General(format!("Invalid rate limit backend: {other}")).into(),
+                )
+            }
+        };
+
+        Ok(Self::with_backend(settings, backend))
+    }
+
+    /// Create a rate limiter with a specific backend
+    pub fn with_backend(settings: RateLimitSettings, backend: Box<dyn RateLimitBackend>) -> Self {
+        Self { settings, backend }
+    }
+
+    /// Check the limits for a notification to the given subscription. Returns
+    /// a `RateLimited` error if any limit has been exceeded.
+    pub async fn check(
+        &self,
+        vapid_public_key: Option<&str>,
+        uaid: &Uuid,
+        channel_id: &Uuid,
+        metrics: &Metrics,
+    ) -> ApiResult<()> {
+        if !self.settings.enabled {
+            return Ok(());
+        }
+
+        let vapid_key = vapid_public_key.map(|key| format!("vapid:{key}"));
+        let checks = [
+            ("vapid", vapid_key, self.settings.vapid_limit()),
+            (
+                "uaid",
+                Some(format!("uaid:{uaid}")),
+                self.settings.uaid_limit(),
+            ),
+            (
+                "subscription",
+                Some(format!("sub:{uaid}:{channel_id}")),
+                self.settings.subscription_limit(),
+            ),
+        ];
+
+        // The enabled limits which apply to this notification
+        let limits: Vec<_> = checks
+            .iter()
+            .filter_map(|(scope, key, limit)| Some((*scope, key.as_deref()?, limit.as_ref()?)))
+            .collect();
+        let buckets: Vec<_> = limits
+            .iter()
+            .map(|(_, key, limit)| (*key, *limit))
+            .collect();
+        let decisions = match self.backend.acquire(&buckets).await {
+            Ok(decisions) => decisions,
+            Err(e) => {
+                // Don't reject notifications because the backend is unavailable
+                warn!("Rate limit backend error: {}", e.kind);
+                return Ok(());
+            }
+        };
+
+        for (&(scope, _, _), decision) in limits.iter().zip(decisions) {
+            if let RateLimitDecision::Limited { retry_after } = decision {
+                let mut tags = Tags::default();
+                tags.tags.insert("scope".to_owned(), scope.to_owned());
+                metrics
+                    .clone()
+                    .incr_with_tags("notification.rate_limited", Some(tags));
+
+                return Err(ApiErrorKind::RateLimited {
+                    scope,
+                    // Round up so clients never retry before a token is available
+                    retry_after: retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0),
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use crate::error::ApiErrorKind;
+    use crate::rate_limit::settings::RateLimitSettings;
+    use autopush_common::metrics::Metrics;
+    use uuid::Uuid;
+
+    fn settings() -> RateLimitSettings {
+        RateLimitSettings {
+            enabled: true,
+            vapid_burst: 0,
+            uaid_burst: 0,
+            subscription_burst: 1,
+            subscription_per_second: 0.5,
+            ..Default::default()
+        }
+    }
+
+    /// A subscription is limited independently of other subscriptions
+    #[tokio::test]
+    async fn limits_subscription() {
+        let limiter = RateLimiter::new(settings()).unwrap();
+        let metrics = Metrics::noop();
+        let uaid = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+
+        limiter
+            .check(None, &uaid, &channel_id, &metrics)
+            .await
+            .unwrap();
+        let err = limiter
+            .check(None, &uaid, &channel_id, &metrics)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.kind,
+                ApiErrorKind::RateLimited {
+                    scope: "subscription",
+                    retry_after: 2
+                }
+            ),
+            "{}",
+            err
+        );
+
+        limiter
+            .check(None, &uaid, &Uuid::new_v4(), &metrics)
+            .await
+            .unwrap();
+    }
+
+    /// A notification rejected by one limit doesn't use up the others
+    #[tokio::test]
+    async fn limited_does_not_consume() {
+        let limiter = RateLimiter::new(RateLimitSettings {
+            uaid_burst: 2,
+            uaid_per_second: 0.5,
+            ..settings()
+        })
+        .unwrap();
+        let metrics = Metrics::noop();
+        let uaid = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+
+        limiter
+            .check(None, &uaid, &channel_id, &metrics)
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            let err = limiter
+                .check(None, &uaid, &channel_id, &metrics)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.kind,
+                ApiErrorKind::RateLimited {
+                    scope: "subscription",
+                    ..
+                }
+            ));
+        }
+
+        // The UAID's second token is still available
+        limiter
+            .check(None, &uaid, &Uuid::new_v4(), &metrics)
+            .await
+            .unwrap();
+    }
+
+    /// Nothing is limited when rate limiting is disabled
+    #[tokio::test]
+    async fn disabled() {
+        let limiter = RateLimiter::new(RateLimitSettings {
+            enabled: false,
+            ..settings()
+        })
+        .unwrap();
+        let metrics = Metrics::noop();
+        let uaid = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+
+        for _ in 0..3 {
+            limiter
+                .check(None, &uaid, &channel_id, &metrics)
+                .await
+                .unwrap();
+        }
+    }
+}
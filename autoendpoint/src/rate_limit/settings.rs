@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+/// Settings for the incoming notification rate limiter
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitSettings {
+    /// Whether incoming notifications should be rate limited at all
+    pub enabled: bool,
+    /// The storage backend for the token buckets. Only `memory` is
+    /// currently supported.
+    pub backend: String,
+    /// The maximum number of buckets the in-memory backend will track
+    /// before pruning idle ones
+    pub max_entries: usize,
+    /// Burst size allowed per VAPID public key (0 disables this limit)
+    pub vapid_burst: u32,
+    /// Sustained notifications per second allowed per VAPID public key
+    pub vapid_per_second: f64,
+    /// Burst size allowed per UAID (0 disables this limit)
+    pub uaid_burst: u32,
+    /// Sustained notifications per second allowed per UAID
+    pub uaid_per_second: f64,
+    /// Burst size allowed per subscription (UAID + channel ID, 0 disables
+    /// this limit)
+    pub subscription_burst: u32,
+    /// Sustained notifications per second allowed per subscription
+    pub subscription_per_second: f64,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backend: "memory".to_string(),
+            max_entries: 100_000,
+            vapid_burst: 1000,
+            vapid_per_second: 100.0,
+            uaid_burst: 100,
+            uaid_per_second: 5.0,
+            subscription_burst: 50,
+            subscription_per_second: 1.0,
+        }
+    }
+}
+
+impl RateLimitSettings {
+    /// The limit applied to a VAPID public key, if any
+    pub fn vapid_limit(&self) -> Option<BucketLimit> {
+        BucketLimit::new(self.vapid_burst, self.vapid_per_second)
+    }
+
+    /// The limit applied to a UAID, if any
+    pub fn uaid_limit(&self) -> Option<BucketLimit> {
+        BucketLimit::new(self.uaid_burst, self.uaid_per_second)
+    }
+
+    /// The limit applied to a single subscription, if any
+    pub fn subscription_limit(&self) -> Option<BucketLimit> {
+        BucketLimit::new(self.subscription_burst, self.subscription_per_second)
+    }
+}
+
+/// The capacity and refill rate of a token bucket
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BucketLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl BucketLimit {
+    /// Build a limit, returning `None` if the limit is disabled
+    pub fn new(burst: u32, per_second: f64) -> Option<Self> {
+        if burst == 0 || per_second <= 0.0 {
+            return None;
+        }
+
+        Some(Self { burst, per_second })
+    }
+
+    /// How long it takes to refill `tokens` tokens
+    pub fn refill_time(&self, tokens: f64) -> Duration {
+        Duration::from_secs_f64(tokens.max(0.0) / self.per_second)
+    }
+}
@@ -1,7 +1,8 @@
 use crate::routers::adm::error::AdmError;
 use crate::routers::adm::settings::{AdmProfile, AdmSettings};
-use crate::routers::common::message_size_check;
+use crate::routers::common::{message_size_check, retry_after};
 use crate::routers::RouterError;
+use autopush_common::health::ComponentHealth;
 use autopush_common::util::sec_since_epoch;
 use futures::lock::Mutex;
 use reqwest::StatusCode;
@@ -111,6 +112,15 @@ impl AdmClient {
         Ok(token_info.token.clone())
     }
 
+    /// Check that an access token can be obtained. The token is cached, so
+    /// ADM is only contacted when it needs refreshing.
+    pub async fn token_health(&self) -> ComponentHealth {
+        match self.get_access_token().await {
+            Ok(_) => ComponentHealth::ok(),
+            Err(e) => ComponentHealth::error(e.to_string()),
+        }
+    }
+
     /// Send the message data to ADM. The device's current registration ID is
     /// returned. If it is different than the current stored ID, the stored ID
     /// should be updated.
@@ -167,14 +177,22 @@ impl AdmClient {
         // Handle error
         let status = response.status();
         if status != 200 {
-            let response_error: AdmResponseError = response
-                .json()
-                .await
-                .map_err(AdmError::DeserializeResponse)?;
+            let retry_after = retry_after(response.headers());
+            let response_error: AdmResponseError = match response.json().await {
+                Ok(response_error) => response_error,
+                // Overloaded servers don't always send a JSON body
+                Err(_) if status.is_server_error() => AdmResponseError { reason: None },
+                Err(e) => return Err(AdmError::DeserializeResponse(e).into()),
+            };
 
             return Err(match (status, response_error.reason) {
                 (StatusCode::UNAUTHORIZED, _) => RouterError::Authentication,
                 (StatusCode::NOT_FOUND, _) => RouterError::NotFound,
+                (status, reason) if status.is_server_error() => RouterError::Unavailable {
+                    status: status.to_string(),
+                    message: reason.unwrap_or_else(|| "Unknown reason".to_string()),
+                    retry_after,
+                },
                 (status, reason) => RouterError::Upstream {
                     status: status.to_string(),
                     message: reason.unwrap_or_else(|| "Unknown reason".to_string()),
@@ -1,31 +1,114 @@
 use autopush_common::db::client::DbClient;
 
-use crate::error::ApiResult;
+use crate::error::{ApiError, ApiResult};
 use crate::extractors::notification::Notification;
 use crate::extractors::router_data_input::RouterDataInput;
 use crate::routers::adm::client::AdmClient;
 use crate::routers::adm::error::AdmError;
+use crate::routers::adm::settings::AdmProfile;
 use crate::routers::adm::settings::AdmSettings;
 use crate::routers::common::{build_message_data, handle_error, incr_success_metrics};
+use crate::routers::reload::Reloadable;
+use crate::routers::retry::{BridgeRetry, RetryJob};
 use crate::routers::{Router, RouterError, RouterResponse};
+use crate::settings::Settings;
 use async_trait::async_trait;
+use autopush_common::db::User;
+use autopush_common::health::ComponentHealth;
 use cadence::StatsdClient;
+use futures::future::join_all;
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 use url::Url;
 
+lazy_static! {
+    static ref VALID_ADM_TOKEN: Regex =
+        Regex::new(r"^amzn1.adm-registration.v3.[^ ]{256,}$").unwrap();
+}
+
 /// 31 days, specified by ADM
 const MAX_TTL: usize = 2419200;
 
+/// A notification to send to ADM, owning what's needed to retry it
+struct AdmJob {
+    clients: Arc<HashMap<String, AdmClient>>,
+    profile: String,
+    registration_id: String,
+    message_data: HashMap<&'static str, String>,
+    ttl: usize,
+    /// The user, whose registration ID is updated if ADM reports a new one
+    user: User,
+    metrics: Arc<StatsdClient>,
+    db: Box<dyn DbClient>,
+}
+
+#[async_trait]
+impl RetryJob for AdmJob {
+    type Error = RouterError;
+
+    fn app_id(&self) -> &str {
+        &self.profile
+    }
+
+    async fn send(&self) -> Result<(), RouterError> {
+        let client = self
+            .clients
+            .get(&self.profile)
+            .ok_or(AdmError::InvalidProfile)?;
+        let new_registration_id = client
+            .send(
+                self.message_data.clone(),
+                self.registration_id.clone(),
+                self.ttl,
+            )
+            .await?;
+
+        // If the returned registration ID is different than the old one,
+        // update the user.
+        if new_registration_id != self.registration_id {
+            trace!("ADM reports a new registration ID for user, updating our copy");
+            let mut user = self.user.clone();
+            if let Some(router_data) = user.router_data.as_mut() {
+                router_data.insert(
+                    "token".to_string(),
+                    serde_json::to_value(&new_registration_id).unwrap(),
+                );
+            }
+
+            if let Err(e) = self.db.update_user(&user).await {
+                warn!("Error while updating ADM registration ID: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_error(&self, error: RouterError) -> ApiError {
+        handle_error(
+            error,
+            &self.metrics,
+            self.db.as_ref(),
+            "adm",
+            &self.profile,
+            self.user.uaid,
+        )
+        .await
+    }
+}
+
 /// Amazon Device Messaging router
 pub struct AdmRouter {
     settings: AdmSettings,
     endpoint_url: Url,
     metrics: Arc<StatsdClient>,
     db: Box<dyn DbClient>,
-    /// A map from profile name to an authenticated ADM client
-    clients: HashMap<String, AdmClient>,
+    /// A map from profile name to an authenticated ADM client, rebuilt when
+    /// the credentials are reloaded
+    clients: Reloadable<HashMap<String, AdmClient>>,
+    http: reqwest::Client,
+    retry: BridgeRetry,
 }
 
 impl AdmRouter {
@@ -38,36 +121,62 @@ impl AdmRouter {
         db: Box<dyn DbClient>,
     ) -> Result<Self, AdmError> {
         let profiles = settings.profiles()?;
-
-        let clients: HashMap<String, AdmClient> = profiles
-            .into_iter()
-            .map(|(name, profile)| (name, AdmClient::new(&settings, profile, http.clone())))
-            .collect();
-        trace!("Initialized {} ADM clients", clients.len());
+        let clients = Self::create_clients(&settings, profiles, &http);
+        let retry = BridgeRetry::new(settings.retry.clone(), "adm", metrics.clone(), db.clone());
 
         Ok(Self {
             settings,
             endpoint_url,
             metrics,
             db,
-            clients,
+            clients: Reloadable::new(clients),
+            http,
+            retry,
         })
     }
 
-    /// if we have any clients defined, this connection is "active"
-    pub fn active(&self) -> bool {
-        !self.clients.is_empty()
+    /// Create an ADM client for each profile
+    fn create_clients(
+        settings: &AdmSettings,
+        profiles: HashMap<String, AdmProfile>,
+        http: &reqwest::Client,
+    ) -> HashMap<String, AdmClient> {
+        let clients: HashMap<String, AdmClient> = profiles
+            .into_iter()
+            .map(|(name, profile)| (name, AdmClient::new(settings, profile, http.clone())))
+            .collect();
+        trace!("Initialized {} ADM clients", clients.len());
+        clients
     }
 }
 
 #[async_trait(?Send)]
 impl Router for AdmRouter {
+    /// if we have any clients defined, this router is "active"
+    fn active(&self) -> bool {
+        !self.clients.load().is_empty()
+    }
+
+    /// Check each client's access token. The endpoint can run without the
+    /// bridge, so failures are only warnings.
+    async fn health(&self) -> ComponentHealth {
+        let clients = self.clients.load();
+        let checks = clients
+            .iter()
+            .map(|(name, client)| async move { (name, client.token_health().await) });
+        ComponentHealth::worst(join_all(checks).await).degraded()
+    }
+
+    fn validate_token(&self, token: &str) -> bool {
+        VALID_ADM_TOKEN.is_match(token)
+    }
+
     fn register(
         &self,
         router_input: &RouterDataInput,
         app_id: &str,
     ) -> Result<HashMap<String, Value>, RouterError> {
-        if !self.clients.contains_key(app_id) {
+        if !self.clients.load().contains_key(app_id) {
             return Err(AdmError::InvalidProfile.into());
         }
 
@@ -111,54 +220,52 @@ impl Router for AdmRouter {
         let message_data = build_message_data(notification)?;
 
         // Send the notification to ADM
-        let client = self.clients.get(profile).ok_or(AdmError::InvalidProfile)?;
+        let clients = self.clients.load();
+        if !clients.contains_key(profile) {
+            return Err(AdmError::InvalidProfile.into());
+        }
         trace!("Sending message to ADM: {:?}", message_data);
-        let new_registration_id = match client
-            .send(message_data, registration_id.to_string(), ttl)
-            .await
-        {
-            Ok(id) => id,
-            Err(e) => {
-                return Err(handle_error(
-                    e,
-                    &self.metrics,
-                    self.db.as_ref(),
-                    "adm",
-                    profile,
-                    notification.subscription.user.uaid,
-                )
-                .await)
-            }
-        };
+        let job = Arc::new(AdmJob {
+            clients,
+            profile: profile.to_owned(),
+            registration_id: registration_id.to_owned(),
+            message_data,
+            ttl,
+            user: notification.subscription.user.clone(),
+            metrics: self.metrics.clone(),
+            db: self.db.clone(),
+        });
+        let location = self
+            .endpoint_url
+            .join(&format!("/m/{}", notification.message_id))
+            .expect("Message ID is not URL-safe")
+            .to_string();
+        if let Err(e) = job.send().await {
+            return match self.retry.queue(notification, job.clone(), e).await {
+                Ok(()) => Ok(RouterResponse::accepted(
+                    location,
+                    notification.headers.ttl as usize,
+                )),
+                Err(e) => Err(job.handle_error(e).await),
+            };
+        }
 
         // Sent successfully, update metrics and make response
         trace!("ADM request was successful");
         incr_success_metrics(&self.metrics, "adm", profile, notification);
 
-        // If the returned registration ID is different than the old one, update
-        // the user.
-        if new_registration_id != registration_id {
-            trace!("ADM reports a new registration ID for user, updating our copy");
-            let mut user = notification.subscription.user.clone();
-            let mut router_data = router_data.clone();
-
-            router_data.insert(
-                "token".to_string(),
-                serde_json::to_value(&new_registration_id).unwrap(),
-            );
-            user.router_data = Some(router_data);
-
-            self.db.update_user(&user).await?;
-        }
-
         Ok(RouterResponse::success(
-            self.endpoint_url
-                .join(&format!("/m/{}", notification.message_id))
-                .expect("Message ID is not URL-safe")
-                .to_string(),
+            location,
             notification.headers.ttl as usize,
         ))
     }
+
+    async fn reload(&self, settings: &Settings) -> Result<bool, RouterError> {
+        let profiles = settings.adm.profiles().map_err(AdmError::from)?;
+        let clients = Self::create_clients(&self.settings, profiles, &self.http);
+        self.clients.store(clients);
+        Ok(true)
+    }
 }
 
 #[cfg(test)]
@@ -1,3 +1,4 @@
+use crate::routers::retry::settings::RetrySettings;
 use std::collections::HashMap;
 use url::Url;
 
@@ -17,6 +18,8 @@ pub struct AdmSettings {
     pub timeout: usize,
     /// The minimum TTL to use for ADM notifications
     pub min_ttl: usize,
+    /// How ADM requests which fail with transient errors are retried
+    pub retry: RetrySettings,
 }
 
 /// Settings for a specific ADM profile
@@ -36,6 +39,7 @@ impl Default for AdmSettings {
             base_url: Url::parse("https://api.amazon.com").unwrap(),
             timeout: 3,
             min_ttl: 60,
+            retry: RetrySettings::default(),
         }
     }
 }
@@ -13,7 +13,13 @@ pub enum ApnsError {
     Io(#[from] io::Error),
 
     #[error("Error while setting up APNS clients: {0}")]
-    ApnsClient(#[source] a2::Error),
+    ApnsClient(String),
+
+    #[error("Invalid APNS channel settings: {0}")]
+    ChannelSettings(String),
+
+    #[error("Invalid APNS signing key: {0}")]
+    TokenSigningKey(String),
 
     #[error("Error while checking the message size limit: {0}")]
     SizeLimit(#[source] a2::Error),
@@ -49,9 +55,11 @@ impl ApnsError {
                 StatusCode::GONE
             }
 
-            ApnsError::ChannelSettingsDecode(_) | ApnsError::Io(_) | ApnsError::ApnsClient(_) => {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
+            ApnsError::ChannelSettingsDecode(_)
+            | ApnsError::Io(_)
+            | ApnsError::ApnsClient(_)
+            | ApnsError::ChannelSettings(_)
+            | ApnsError::TokenSigningKey(_) => StatusCode::INTERNAL_SERVER_ERROR,
 
             ApnsError::ApnsUpstream(_) => StatusCode::BAD_GATEWAY,
         }
@@ -67,6 +75,8 @@ impl ApnsError {
             ApnsError::ChannelSettingsDecode(_)
             | ApnsError::Io(_)
             | ApnsError::ApnsClient(_)
+            | ApnsError::ChannelSettings(_)
+            | ApnsError::TokenSigningKey(_)
             | ApnsError::ApnsUpstream(_)
             | ApnsError::InvalidReleaseChannel
             | ApnsError::InvalidApsData
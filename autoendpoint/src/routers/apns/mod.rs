@@ -1,5 +1,7 @@
 //! A notification router for Apple devices, using Apple Push Notification Service
 
 pub mod error;
+pub mod http;
 pub mod router;
 pub mod settings;
+pub mod token;
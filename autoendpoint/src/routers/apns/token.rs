@@ -0,0 +1,256 @@
+//! An APNS client using token-based (JWT) authentication
+
+use crate::routers::apns::error::ApnsError;
+use crate::routers::apns::http;
+use crate::routers::apns::router::ApnsClient;
+use a2::request::payload::Payload;
+use a2::{ErrorBody, ErrorReason, Response};
+use async_trait::async_trait;
+use autopush_common::util::sec_since_epoch;
+use futures::lock::Mutex;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::time::Duration;
+use url::Url;
+
+/// The APNS production server
+pub const PRODUCTION_URL: &str = "https://api.push.apple.com";
+/// The APNS sandbox server
+pub const SANDBOX_URL: &str = "https://api.sandbox.push.apple.com";
+
+/// How long to use a provider token before generating a new one. APNS rejects
+/// tokens older than an hour, and refreshing more than once every 20 minutes
+/// is throttled.
+const TOKEN_LIFETIME: u64 = 50 * 60;
+
+/// Sends notifications to APNS, authenticating with a provider token signed by
+/// a `.p8` key. The token is regenerated automatically before it expires.
+pub struct TokenClient {
+    base_url: Url,
+    key_id: String,
+    team_id: String,
+    signing_key: EncodingKey,
+    http: reqwest::Client,
+    timeout: Duration,
+    token_info: Mutex<TokenInfo>,
+}
+
+/// Holds the cached provider token
+#[derive(Default)]
+struct TokenInfo {
+    token: String,
+    issued_at: u64,
+}
+
+/// The provider token claims
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    iat: u64,
+}
+
+impl TokenClient {
+    /// Create a `TokenClient`. `signing_key` is the PEM contents of the `.p8`
+    /// key file.
+    pub fn new(
+        base_url: Url,
+        key_id: String,
+        team_id: String,
+        signing_key: &[u8],
+        http: reqwest::Client,
+        timeout: Duration,
+    ) -> Result<Self, ApnsError> {
+        let signing_key = EncodingKey::from_ec_pem(signing_key)
+            .map_err(|e| ApnsError::TokenSigningKey(e.to_string()))?;
+
+        Ok(TokenClient {
+            base_url,
+            key_id,
+            team_id,
+            signing_key,
+            http,
+            timeout,
+            // The default TokenInfo is expired to trigger a token generation
+            token_info: Mutex::default(),
+        })
+    }
+
+    /// Get a provider token (from cache or sign a new one)
+    async fn get_token(&self) -> Result<String, a2::Error> {
+        let mut token_info = self.token_info.lock().await;
+        let now = sec_since_epoch();
+
+        if token_info.issued_at + TOKEN_LIFETIME > now {
+            trace!("Using cached APNS provider token");
+            return Ok(token_info.token.clone());
+        }
+
+        trace!("APNS provider token is out of date, signing a new one");
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.key_id.clone());
+        let claims = Claims {
+            iss: &self.team_id,
+            iat: now,
+        };
+        token_info.token =
+            jsonwebtoken::encode(&header, &claims, &self.signing_key).map_err(|e| {
+                warn!("Error while signing APNS provider token: {}", e);
+                a2::Error::InvalidOptions(format!("Could not sign provider token: {e}"))
+            })?;
+        token_info.issued_at = now;
+
+        Ok(token_info.token.clone())
+    }
+
+    /// Forget the cached provider token, so the next request signs a new one
+    async fn expire_token(&self) {
+        *self.token_info.lock().await = TokenInfo::default();
+    }
+}
+
+#[async_trait]
+impl ApnsClient for TokenClient {
+    async fn send(
+        &self,
+        payload: Payload<'_>,
+        aps_extra: &Map<String, Value>,
+    ) -> Result<Response, a2::Error> {
+        let token = self.get_token().await?;
+        let result = http::send(
+            &self.http,
+            &self.base_url,
+            Some(token),
+            self.timeout,
+            &payload,
+            aps_extra,
+        )
+        .await;
+        if let Err(a2::Error::ResponseError(Response {
+            error:
+                Some(ErrorBody {
+                    reason: ErrorReason::ExpiredProviderToken,
+                    ..
+                }),
+            ..
+        })) = &result
+        {
+            self.expire_token().await;
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::TokenClient;
+    use crate::routers::apns::router::ApnsClient;
+    use a2::request::payload::{Payload, APS};
+    use a2::{NotificationOptions, Priority};
+    use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use serde_json::Map;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+    use url::Url;
+
+    pub const KEY_ID: &str = "TESTKEYID1";
+    pub const TEAM_ID: &str = "TESTTEAMID";
+    pub const DEVICE_TOKEN: &str = "test-token";
+
+    /// Generate a `.p8` style (PKCS #8) signing key, returning the private and
+    /// public key PEM
+    pub fn make_signing_key() -> (Vec<u8>, Vec<u8>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        (
+            key.private_key_to_pem_pkcs8().unwrap(),
+            key.public_key_to_pem().unwrap(),
+        )
+    }
+
+    /// Start building a mock APNS endpoint for the device
+    pub fn mock_apns_endpoint_builder() -> mockito::Mock {
+        mockito::mock("POST", format!("/3/device/{DEVICE_TOKEN}").as_str())
+    }
+
+    fn make_client(signing_key: &[u8]) -> TokenClient {
+        TokenClient::new(
+            Url::parse(&mockito::server_url()).unwrap(),
+            KEY_ID.to_string(),
+            TEAM_ID.to_string(),
+            signing_key,
+            reqwest::Client::new(),
+            Duration::from_secs(3),
+        )
+        .unwrap()
+    }
+
+    fn make_payload() -> Payload<'static> {
+        Payload {
+            aps: APS::default(),
+            data: BTreeMap::new(),
+            device_token: DEVICE_TOKEN,
+            options: NotificationOptions {
+                apns_id: None,
+                apns_priority: Some(Priority::High),
+                apns_topic: Some("test-topic"),
+                apns_collapse_id: None,
+                apns_expiration: None,
+            },
+        }
+    }
+
+    /// The request carries a provider token signed by the key
+    #[tokio::test]
+    async fn signed_request() {
+        let (private_key, public_key) = make_signing_key();
+        let client = make_client(&private_key);
+        let mock = mock_apns_endpoint_builder()
+            .match_header("apns-topic", "test-topic")
+            .match_header("apns-priority", "10")
+            .with_header("apns-id", "test-apns-id")
+            .create();
+
+        let response = client.send(make_payload(), &Map::new()).await.unwrap();
+        assert_eq!(response.code, 200);
+        assert_eq!(response.apns_id.as_deref(), Some("test-apns-id"));
+        mock.assert();
+
+        // The cached token is valid for the team and key
+        let token = client.get_token().await.unwrap();
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        let token_data = jsonwebtoken::decode::<serde_json::Value>(
+            &token,
+            &DecodingKey::from_ec_pem(&public_key).unwrap(),
+            &validation,
+        )
+        .unwrap();
+        assert_eq!(token_data.header.kid.as_deref(), Some(KEY_ID));
+        assert_eq!(token_data.claims["iss"], TEAM_ID);
+    }
+
+    /// An expired token is regenerated for the next request
+    #[tokio::test]
+    async fn expired_token() {
+        let (private_key, _) = make_signing_key();
+        let client = make_client(&private_key);
+        let _mock = mock_apns_endpoint_builder()
+            .with_status(403)
+            .with_body(r#"{"reason": "ExpiredProviderToken"}"#)
+            .create();
+
+        client.get_token().await.unwrap();
+        let result = client.send(make_payload(), &Map::new()).await;
+        assert!(
+            matches!(result, Err(a2::Error::ResponseError(ref response)) if response.code == 403),
+            "result = {result:?}"
+        );
+        assert_eq!(client.token_info.lock().await.issued_at, 0);
+    }
+}
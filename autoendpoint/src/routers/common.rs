@@ -1,5 +1,6 @@
 use crate::error::{ApiError, ApiResult};
 use crate::extractors::notification::Notification;
+use crate::routers::fcm::error::FcmError;
 use crate::routers::RouterError;
 use actix_web::http::StatusCode;
 use autopush_common::db::client::DbClient;
@@ -36,6 +37,17 @@ pub fn message_size_check(data: &[u8], max_data: usize) -> Result<(), RouterErro
     }
 }
 
+/// Read a `Retry-After` header in seconds. HTTP dates are not supported.
+pub fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
 /// Handle a bridge error by logging, updating metrics, etc
 pub async fn handle_error(
     error: RouterError,
@@ -57,17 +69,6 @@ pub async fn handle_error(
                 error.errno(),
             );
         }
-        RouterError::GCMAuthentication => {
-            warn!("GCM Authentication error");
-            incr_error_metric(
-                metrics,
-                platform,
-                app_id,
-                "gcm authentication",
-                error.status(),
-                error.errno(),
-            );
-        }
         RouterError::RequestTimeout => {
             warn!("Bridge timeout");
             incr_error_metric(
@@ -105,6 +106,54 @@ pub async fn handle_error(
                 warn!("Error while removing user due to bridge not_found: {}", e);
             }
         }
+        RouterError::Fcm(FcmError::Unregistered) => {
+            debug!("FCM registration token is unregistered, removing user");
+            incr_error_metric(
+                metrics,
+                platform,
+                app_id,
+                "unregistered",
+                error.status(),
+                error.errno(),
+            );
+
+            if let Err(e) = db.remove_user(&uaid).await {
+                warn!("Error while removing user due to FCM unregistered: {}", e);
+            }
+        }
+        RouterError::Fcm(FcmError::QuotaExceeded { .. }) => {
+            warn!("FCM quota exceeded");
+            incr_error_metric(
+                metrics,
+                platform,
+                app_id,
+                "quota_exceeded",
+                error.status(),
+                error.errno(),
+            );
+        }
+        RouterError::Fcm(FcmError::SenderIdMismatch) => {
+            warn!("FCM sender ID mismatch");
+            incr_error_metric(
+                metrics,
+                platform,
+                app_id,
+                "sender_id_mismatch",
+                error.status(),
+                error.errno(),
+            );
+        }
+        RouterError::Unavailable { .. } => {
+            warn!("{}", error.to_string());
+            incr_error_metric(
+                metrics,
+                platform,
+                app_id,
+                "server_unavailable",
+                error.status(),
+                error.errno(),
+            );
+        }
         RouterError::Upstream { .. } => {
             warn!("{}", error.to_string());
             incr_error_metric(
@@ -217,10 +266,13 @@ pub mod tests {
                 },
                 channel_id: channel_id(),
                 vapid: None,
+                vapid_sub: None,
+                reissued_endpoint: None,
             },
             headers: NotificationHeaders {
                 ttl: 0,
                 topic: Some("test-topic".to_string()),
+                urgency: None,
                 encoding: Some("test-encoding".to_string()),
                 encryption: Some("test-encryption".to_string()),
                 encryption_key: Some("test-encryption-key".to_string()),
@@ -28,31 +28,57 @@ pub enum FcmError {
 
     #[error("User has invalid app ID {0}")]
     InvalidAppId(String),
+
+    #[error("FCM reports the registration token is no longer valid")]
+    Unregistered,
+
+    /// FCM is throttling messages to the app or device. `retry_after` is in
+    /// seconds.
+    #[error("FCM message rate quota exceeded")]
+    QuotaExceeded { retry_after: Option<u64> },
+
+    #[error("FCM registration token belongs to a different sender")]
+    SenderIdMismatch,
+
+    #[error("GCM is no longer supported")]
+    GcmRefused,
 }
 
 impl FcmError {
     /// Get the associated HTTP status code
     pub fn status(&self) -> StatusCode {
         match self {
-            FcmError::NoRegistrationToken | FcmError::NoAppId | FcmError::InvalidAppId(_) => {
-                StatusCode::GONE
-            }
+            FcmError::NoRegistrationToken
+            | FcmError::NoAppId
+            | FcmError::InvalidAppId(_)
+            | FcmError::Unregistered
+            | FcmError::GcmRefused => StatusCode::GONE,
+
+            FcmError::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
 
             FcmError::CredentialDecode(_)
             | FcmError::OAuthClientBuild(_)
             | FcmError::OAuthToken(_)
             | FcmError::NoOAuthToken => StatusCode::INTERNAL_SERVER_ERROR,
 
-            FcmError::DeserializeResponse(_) => StatusCode::BAD_GATEWAY,
+            FcmError::DeserializeResponse(_) | FcmError::SenderIdMismatch => {
+                StatusCode::BAD_GATEWAY
+            }
         }
     }
 
     /// Get the associated error number
     pub fn errno(&self) -> Option<usize> {
         match self {
-            FcmError::NoRegistrationToken | FcmError::NoAppId | FcmError::InvalidAppId(_) => {
-                Some(106)
-            }
+            FcmError::NoRegistrationToken
+            | FcmError::NoAppId
+            | FcmError::InvalidAppId(_)
+            | FcmError::Unregistered
+            | FcmError::GcmRefused => Some(106),
+
+            // 904 was the removed GCM authentication error, so it isn't reused
+            FcmError::QuotaExceeded { .. } => Some(907),
+            FcmError::SenderIdMismatch => Some(905),
 
             FcmError::CredentialDecode(_)
             | FcmError::OAuthClientBuild(_)
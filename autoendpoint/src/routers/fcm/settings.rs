@@ -2,6 +2,8 @@ use std::collections::HashMap;
 
 use url::Url;
 
+use crate::routers::retry::settings::RetrySettings;
+
 /// Settings for `FcmRouter`
 #[derive(Clone, Debug, serde::Deserialize)]
 #[serde(default)]
@@ -11,45 +13,59 @@ pub struct FcmSettings {
     pub min_ttl: usize,
     /// A JSON dict of `FcmCredential`s. This must be a `String` because
     /// environment variables cannot encode a `HashMap<String, FcmCredential>`
-    /// This contains both GCM and FCM credentials.
-    /// FCM (the more modern credential set) is specified as
     ///
     /// ```json
-    /// {"_project_id_":{"project_id": "_project_id_", "credential": "_key_"}, ...}
+    /// {"_app_id_":{"project_id": "_project_id_", "credential": "_key_"}, ...}
     /// ```
-    /// For FCM, `credential` keys can be either a serialized JSON string, or the
+    /// `credential` can be either a serialized JSON service account key, or the
     /// path to the JSON key file.
-    ///
-    /// GCM follows the same pattern, where
-    ///
-    /// `_project_id_` == senderID and `_key_` == credential
-    /// e.g. "bar-project" is via FCM, with a serialized JSON key,
-    /// e.g. "gorp-project" is via FCM, with a key path,
-    /// and a GCM project with SenderID of "f00" specifies the server key as credential
+    /// e.g. "bar-project" has a serialized JSON key and "gorp-project" has a
+    /// key path:
     ///
     /// ```json
     /// {"bar-project":{"project_id": "bar-project-1234", "credential": "{\"type\": ...}"},
     ///  "gorp-project":{"project_id": "gorp-project-abcd", "credential": "keys/gorp-project.json"},
-    ///  "f00": {"project_id": "f00", "credential": "abcd0123457"},
     ///  ...
     /// }
     /// ```
     #[serde(rename = "credentials")]
     pub server_credentials: String,
+    /// A JSON dict mapping legacy GCM sender IDs to the FCM app ID (a key of
+    /// `credentials`) which now sends their notifications. GCM users whose
+    /// sender ID is not listed use the app ID matching their sender ID.
+    ///
+    /// ```json
+    /// {"f00": "bar-project", ...}
+    /// ```
+    pub gcm_sender_ids: String,
+    /// Move GCM users to the FCM router after their next successful
+    /// notification or token update
+    pub migrate_gcm: bool,
+    /// Reject notifications for GCM users with a 410. Token updates still
+    /// migrate GCM users if `migrate_gcm` is enabled.
+    pub refuse_gcm: bool,
     /// The max size of notification data in bytes
     pub max_data: usize,
     /// The base URL to use for FCM requests
     pub base_url: Url,
     /// The number of seconds to wait for FCM requests to complete
     pub timeout: usize,
+    /// How FCM requests which fail with transient errors are retried
+    pub retry: RetrySettings,
 }
 
 /// Credential information for each application
-#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
 pub struct FcmServerCredential {
     pub project_id: String,
     #[serde(rename = "credential")]
     pub server_access_token: String,
+    /// Allow delivery while the device is in direct boot mode
+    #[serde(default)]
+    pub direct_boot_ok: bool,
+    /// Only deliver to the Android app with this package name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restricted_package_name: Option<String>,
 }
 
 impl Default for FcmSettings {
@@ -60,6 +76,10 @@ impl Default for FcmSettings {
             max_data: 4096,
             base_url: Url::parse("https://fcm.googleapis.com").unwrap(),
             timeout: 3,
+            gcm_sender_ids: "{}".to_string(),
+            migrate_gcm: false,
+            refuse_gcm: false,
+            retry: RetrySettings::default(),
         }
     }
 }
@@ -69,4 +89,9 @@ impl FcmSettings {
     pub fn credentials(&self) -> serde_json::Result<HashMap<String, FcmServerCredential>> {
         serde_json::from_str(&self.server_credentials)
     }
+
+    /// Read the GCM sender ID to FCM app ID mapping from the provided JSON
+    pub fn gcm_sender_ids(&self) -> serde_json::Result<HashMap<String, String>> {
+        serde_json::from_str(&self.gcm_sender_ids)
+    }
 }
@@ -6,13 +6,18 @@ use crate::extractors::router_data_input::RouterDataInput;
 use crate::routers::adm::error::AdmError;
 use crate::routers::apns::error::ApnsError;
 use crate::routers::fcm::error::FcmError;
+use crate::routers::webhook::error::WebhookError;
+use crate::settings::Settings;
 
 use autopush_common::db::error::DbError;
 use autopush_common::errors::ApcErrorKind;
+use autopush_common::health::ComponentHealth;
 
 use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use async_trait::async_trait;
+use lazy_static::lazy_static;
+use regex::Regex;
 use std::collections::HashMap;
 use thiserror::Error;
 
@@ -20,10 +25,41 @@ pub mod adm;
 pub mod apns;
 mod common;
 pub mod fcm;
+pub mod registry;
+pub mod reload;
+pub mod retry;
+pub mod webhook;
 pub mod webpush;
 
+lazy_static! {
+    static ref VALID_TOKEN: Regex = Regex::new(r"^[^ ]{8,}$").unwrap();
+}
+
 #[async_trait(?Send)]
-pub trait Router {
+pub trait Router: Send + Sync {
+    /// Whether the router is configured and able to route notifications
+    fn active(&self) -> bool {
+        true
+    }
+
+    /// Check the router's connection to its bridge, for `/__heartbeat__`.
+    /// Only called for active routers.
+    async fn health(&self) -> ComponentHealth {
+        ComponentHealth::ok()
+    }
+
+    /// Validate a registration token against the router's token schema
+    fn validate_token(&self, token: &str) -> bool {
+        VALID_TOKEN.is_match(token)
+    }
+
+    /// Get the router type to store for a user registering with this router
+    /// under `router_type`. Routers which replace a legacy router type may
+    /// move users to their own type, or refuse the legacy type.
+    fn registration_router_type(&self, router_type: &str) -> Result<String, RouterError> {
+        Ok(router_type.to_owned())
+    }
+
     /// Validate that the user can use this router, and return data to be stored in
     /// the user's `router_data` field.
     fn register(
@@ -34,6 +70,13 @@ pub trait Router {
 
     /// Route a notification to the user
     async fn route_notification(&self, notification: &Notification) -> ApiResult<RouterResponse>;
+
+    /// Rebuild the router's bridge clients from the credentials in the
+    /// settings. Notifications already being sent keep using the old clients.
+    /// Returns false if the router has no credentials to reload.
+    async fn reload(&self, _settings: &Settings) -> Result<bool, RouterError> {
+        Ok(false)
+    }
 }
 
 /// The response returned when a router routes a notification
@@ -58,6 +101,23 @@ impl RouterResponse {
             body: None,
         }
     }
+
+    /// Build a router response (202 Accepted) for a notification which will
+    /// be delivered later
+    pub fn accepted(location: String, ttl: usize) -> Self {
+        RouterResponse {
+            status: StatusCode::ACCEPTED,
+            ..Self::success(location, ttl)
+        }
+    }
+
+    /// Point the sender at a reissued endpoint, if there is one
+    pub fn add_reissued_endpoint(&mut self, reissued_endpoint: Option<&str>) {
+        if let Some(endpoint) = reissued_endpoint {
+            self.headers
+                .insert("Link", format!("<{endpoint}>; rel=\"alternate\""));
+        }
+    }
 }
 
 impl From<RouterResponse> for HttpResponse {
@@ -83,6 +143,9 @@ pub enum RouterError {
     #[error(transparent)]
     Fcm(#[from] FcmError),
 
+    #[error(transparent)]
+    Webhook(#[from] WebhookError),
+
     #[error("Database error while saving notification")]
     SaveDb(#[source] DbError),
 
@@ -98,9 +161,6 @@ pub enum RouterError {
     #[error("Bridge authentication error")]
     Authentication,
 
-    #[error("GCM Bridge authentication error")]
-    GCMAuthentication,
-
     #[error("Bridge request timeout")]
     RequestTimeout,
 
@@ -112,6 +172,14 @@ pub enum RouterError {
 
     #[error("Bridge error, {status}: {message}")]
     Upstream { status: String, message: String },
+
+    /// The bridge had a server error. `retry_after` is in seconds.
+    #[error("Bridge unavailable, {status}: {message}")]
+    Unavailable {
+        status: String,
+        message: String,
+        retry_after: Option<u64>,
+    },
 }
 
 impl RouterError {
@@ -121,6 +189,7 @@ impl RouterError {
             RouterError::Adm(e) => e.status(),
             RouterError::Apns(e) => e.status(),
             RouterError::Fcm(e) => e.status(),
+            RouterError::Webhook(e) => e.status(),
 
             RouterError::SaveDb(_) => StatusCode::SERVICE_UNAVAILABLE,
 
@@ -129,10 +198,10 @@ impl RouterError {
             RouterError::TooMuchData(_) => StatusCode::PAYLOAD_TOO_LARGE,
 
             RouterError::Authentication
-            | RouterError::GCMAuthentication
             | RouterError::RequestTimeout
             | RouterError::Connect(_)
-            | RouterError::Upstream { .. } => StatusCode::BAD_GATEWAY,
+            | RouterError::Upstream { .. }
+            | RouterError::Unavailable { .. } => StatusCode::BAD_GATEWAY,
         }
     }
 
@@ -142,6 +211,7 @@ impl RouterError {
             RouterError::Adm(e) => e.errno(),
             RouterError::Apns(e) => e.errno(),
             RouterError::Fcm(e) => e.errno(),
+            RouterError::Webhook(e) => e.errno(),
 
             RouterError::TooMuchData(_) => Some(104),
 
@@ -157,11 +227,20 @@ impl RouterError {
 
             RouterError::RequestTimeout => Some(903),
 
-            RouterError::GCMAuthentication => Some(904),
+            RouterError::Unavailable { .. } => Some(906),
 
             RouterError::Upstream { .. } => None,
         }
     }
+
+    /// How many seconds the bridge asked to wait before retrying, if it did
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            RouterError::Fcm(FcmError::QuotaExceeded { retry_after })
+            | RouterError::Unavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
 }
 
 impl From<RouterError> for ApcErrorKind {
@@ -170,6 +249,7 @@ impl From<RouterError> for ApcErrorKind {
             RouterError::Adm(e) => ApcErrorKind::EndpointError("Router:Adm", e.to_string()),
             RouterError::Apns(e) => ApcErrorKind::EndpointError("Router:APNS", e.to_string()),
             RouterError::Fcm(e) => ApcErrorKind::EndpointError("Router:FCM", e.to_string()),
+            RouterError::Webhook(e) => ApcErrorKind::EndpointError("Router:Webhook", e.to_string()),
             RouterError::TooMuchData(e) => ApcErrorKind::EndpointError("TooMucData", e.to_string()),
             RouterError::UserWasDeleted => {
                 ApcErrorKind::EndpointError("UserWasDeleted", err.to_string())
@@ -183,12 +263,12 @@ impl From<RouterError> for ApcErrorKind {
             RouterError::RequestTimeout => {
                 ApcErrorKind::EndpointError("RequestTimeout", err.to_string())
             }
-            RouterError::GCMAuthentication => {
-                ApcErrorKind::EndpointError("GCMAuthentication", err.to_string())
-            }
             RouterError::Upstream { .. } => {
                 ApcErrorKind::EndpointError("Upstream", err.to_string())
             }
+            RouterError::Unavailable { .. } => {
+                ApcErrorKind::EndpointError("Unavailable", err.to_string())
+            }
         }
     }
 }
@@ -0,0 +1,250 @@
+//! A registry of the available routers, keyed by router type name
+
+use crate::error::{ApiErrorKind, ApiResult};
+use crate::routers::adm::router::AdmRouter;
+use crate::routers::apns::router::ApnsRouter;
+use crate::routers::fcm::router::FcmRouter;
+use crate::routers::webhook::router::WebhookRouter;
+use crate::routers::webpush::WebPushRouter;
+use crate::routers::{Router, RouterError};
+use crate::settings::Settings;
+use autopush_common::db::client::DbClient;
+use cadence::StatsdClient;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Holds the routers which notifications may be routed through. A user's
+/// `router_type` names the router which handles their notifications.
+#[derive(Clone, Default)]
+pub struct RouterRegistry {
+    routers: BTreeMap<String, Arc<dyn Router>>,
+}
+
+impl RouterRegistry {
+    /// Create the built-in routers from the settings
+    pub async fn from_settings(
+        settings: &Settings,
+        http: reqwest::Client,
+        metrics: Arc<StatsdClient>,
+        db: Box<dyn DbClient>,
+    ) -> ApiResult<Self> {
+        let endpoint_url = settings.endpoint_url();
+        let mut registry = Self::default();
+
+        registry.register(
+            "webpush",
+            Arc::new(WebPushRouter {
+                db: db.clone(),
+                metrics: metrics.clone(),
+                http: http.clone(),
+                endpoint_url: endpoint_url.clone(),
+            }),
+        );
+        let fcm_router = Arc::new(
+            FcmRouter::new(
+                settings.fcm.clone(),
+                endpoint_url.clone(),
+                http.clone(),
+                metrics.clone(),
+                db.clone(),
+            )
+            .await?,
+        );
+        // GCM users are handled by the FCM router
+        registry.register("fcm", fcm_router.clone());
+        registry.register("gcm", fcm_router);
+        registry.register(
+            "apns",
+            Arc::new(
+                ApnsRouter::new(
+                    settings.apns.clone(),
+                    endpoint_url.clone(),
+                    http.clone(),
+                    metrics.clone(),
+                    db.clone(),
+                )
+                .await?,
+            ),
+        );
+        registry.register(
+            "adm",
+            Arc::new(AdmRouter::new(
+                settings.adm.clone(),
+                endpoint_url.clone(),
+                http,
+                metrics.clone(),
+                db.clone(),
+            )?),
+        );
+        registry.register(
+            "webhook",
+            Arc::new(WebhookRouter::new(
+                settings.webhook.clone(),
+                endpoint_url,
+                metrics,
+                db,
+            )?),
+        );
+
+        Ok(registry)
+    }
+
+    /// Add a router, replacing any router already registered under the name.
+    /// Names are case-insensitive.
+    pub fn register(&mut self, name: &str, router: Arc<dyn Router>) {
+        self.routers.insert(name.to_lowercase(), router);
+    }
+
+    /// Get the router registered under the name
+    pub fn get(&self, name: &str) -> ApiResult<&dyn Router> {
+        self.routers
+            .get(&name.to_lowercase())
+            .map(|router| router.as_ref())
+            .ok_or_else(|| ApiErrorKind::InvalidRouterType.into())
+    }
+
+    /// Check if a router is registered under the name
+    pub fn contains(&self, name: &str) -> bool {
+        self.routers.contains_key(&name.to_lowercase())
+    }
+
+    /// Reload each router's bridge credentials from the settings. A router
+    /// registered under several names is reloaded once, under its first name.
+    /// The result is returned for each router which has credentials.
+    pub async fn reload(&self, settings: &Settings) -> Vec<(&str, Result<(), RouterError>)> {
+        let mut reloaded: Vec<&Arc<dyn Router>> = Vec::new();
+        let mut results = Vec::new();
+
+        for (name, router) in &self.routers {
+            if reloaded.iter().any(|other| Arc::ptr_eq(other, router)) {
+                continue;
+            }
+            reloaded.push(router);
+
+            match router.reload(settings).await {
+                Ok(false) => {}
+                Ok(true) => results.push((name.as_str(), Ok(()))),
+                Err(e) => results.push((name.as_str(), Err(e))),
+            }
+        }
+
+        results
+    }
+
+    /// Iterate over the registered routers, ordered by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &dyn Router)> {
+        self.routers
+            .iter()
+            .map(|(name, router)| (name.as_str(), router.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RouterRegistry;
+    use crate::error::{ApiErrorKind, ApiResult};
+    use crate::extractors::notification::Notification;
+    use crate::extractors::router_data_input::RouterDataInput;
+    use crate::extractors::routers::RouterType;
+    use crate::routers::common::tests::make_notification;
+    use crate::routers::{Router, RouterError, RouterResponse};
+    use crate::settings::Settings;
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    struct TestRouter(bool);
+
+    #[async_trait(?Send)]
+    impl Router for TestRouter {
+        fn active(&self) -> bool {
+            self.0
+        }
+
+        fn register(
+            &self,
+            _router_input: &RouterDataInput,
+            _app_id: &str,
+        ) -> Result<HashMap<String, serde_json::Value>, RouterError> {
+            Ok(HashMap::new())
+        }
+
+        async fn route_notification(
+            &self,
+            notification: &Notification,
+        ) -> ApiResult<RouterResponse> {
+            Ok(RouterResponse::success(
+                format!("http://localhost/m/{}", notification.message_id),
+                notification.headers.ttl as usize,
+            ))
+        }
+
+        async fn reload(&self, _settings: &Settings) -> Result<bool, RouterError> {
+            Ok(self.0)
+        }
+    }
+
+    /// Routers are looked up by case-insensitive name
+    #[test]
+    fn lookup() {
+        let mut registry = RouterRegistry::default();
+        registry.register("Custom", Arc::new(TestRouter(true)));
+
+        assert!(registry.contains("custom"));
+        assert!(registry.get("CUSTOM").unwrap().active());
+        assert!(matches!(
+            registry.get("other").err().unwrap().kind,
+            ApiErrorKind::InvalidRouterType
+        ));
+    }
+
+    /// Notifications are routed by the router registered for the user's
+    /// router type
+    #[tokio::test]
+    async fn route() {
+        let mut registry = RouterRegistry::default();
+        registry.register("webpush", Arc::new(TestRouter(true)));
+        let notification = make_notification(HashMap::new(), None, RouterType::WebPush);
+
+        let response = registry
+            .get(&notification.subscription.user.router_type)
+            .unwrap()
+            .route_notification(&notification)
+            .await
+            .unwrap();
+        assert_eq!(
+            response,
+            RouterResponse::success("http://localhost/m/test-message-id".to_string(), 0)
+        );
+    }
+
+    /// Iteration is ordered by name
+    #[test]
+    fn iter() {
+        let mut registry = RouterRegistry::default();
+        registry.register("b", Arc::new(TestRouter(false)));
+        registry.register("a", Arc::new(TestRouter(true)));
+
+        let routers: Vec<_> = registry
+            .iter()
+            .map(|(name, router)| (name, router.active()))
+            .collect();
+        assert_eq!(routers, vec![("a", true), ("b", false)]);
+    }
+
+    /// A router registered under several names is reloaded once, and routers
+    /// without credentials aren't reported
+    #[tokio::test]
+    async fn reload() {
+        let mut registry = RouterRegistry::default();
+        let shared = Arc::new(TestRouter(true));
+        registry.register("gcm", shared.clone());
+        registry.register("fcm", shared);
+        registry.register("webpush", Arc::new(TestRouter(false)));
+
+        let results = registry.reload(&Settings::default()).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, "fcm");
+        assert!(results[0].1.is_ok());
+    }
+}
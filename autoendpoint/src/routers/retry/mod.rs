@@ -0,0 +1,536 @@
+//! A bounded queue per bridge, retrying notifications which fail with
+//! transient errors in the background, and dead-letter storage for
+//! notifications which exhaust their retries
+
+use crate::error::ApiError;
+use crate::extractors::notification::Notification;
+use crate::routers::apns::error::ApnsError;
+use crate::routers::common::incr_success_metrics;
+use crate::routers::fcm::error::FcmError;
+use crate::routers::retry::settings::RetrySettings;
+use crate::routers::RouterError;
+use async_trait::async_trait;
+use autopush_common::audit::AuditEvent;
+use autopush_common::db::client::DbClient;
+use autopush_common::util::sec_since_epoch;
+use cadence::{CountedExt, StatsdClient};
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub mod settings;
+
+/// An error which may be resolved by sending the request again
+pub trait TransientError: Display {
+    /// Whether the request may succeed if sent again
+    fn is_transient(&self) -> bool;
+
+    /// How long the bridge asked us to wait before sending again
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl TransientError for RouterError {
+    fn is_transient(&self) -> bool {
+        match self {
+            RouterError::RequestTimeout
+            | RouterError::Connect(_)
+            | RouterError::Unavailable { .. }
+            | RouterError::Fcm(FcmError::QuotaExceeded { .. }) => true,
+            RouterError::Apns(ApnsError::ApnsUpstream(e)) => e.is_transient(),
+            _ => false,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RouterError::Unavailable { retry_after, .. }
+            | RouterError::Fcm(FcmError::QuotaExceeded { retry_after }) => {
+                retry_after.map(Duration::from_secs)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A bridge request which owns everything needed to send it again later
+#[async_trait]
+pub trait RetryJob: Send + Sync + 'static {
+    type Error: TransientError + Send + 'static;
+
+    /// The app ID (or release channel, or profile) the request is sent for
+    fn app_id(&self) -> &str;
+
+    /// Send the request once
+    async fn send(&self) -> Result<(), Self::Error>;
+
+    /// Handle an error the request failed with by logging, updating metrics,
+    /// etc. Errors from retries in the background are handled after the
+    /// sender was told the notification was accepted.
+    async fn handle_error(&self, error: Self::Error) -> ApiError;
+}
+
+/// A bounded queue of notifications being retried for a bridge. The sender is
+/// told a queued notification was accepted, and its retries back off
+/// exponentially in the background, waiting at least as long as the bridge
+/// asked with `Retry-After`, until the notification's TTL runs out.
+#[derive(Clone)]
+pub struct BridgeRetry {
+    settings: RetrySettings,
+    platform: &'static str,
+    metrics: Arc<StatsdClient>,
+    db: Box<dyn DbClient>,
+    /// Bounds the number of notifications being retried in the background
+    queue: Arc<Semaphore>,
+}
+
+impl BridgeRetry {
+    pub fn new(
+        settings: RetrySettings,
+        platform: &'static str,
+        metrics: Arc<StatsdClient>,
+        db: Box<dyn DbClient>,
+    ) -> Self {
+        let queue = Arc::new(Semaphore::new(settings.queue_size));
+        BridgeRetry {
+            settings,
+            platform,
+            metrics,
+            db,
+            queue,
+        }
+    }
+
+    /// Queue a job which failed with `error` to be retried in the background.
+    /// If the queue is full, the notification is stored as a dead letter
+    /// instead (when enabled). The error is returned if the notification was
+    /// neither queued nor stored.
+    pub async fn queue<J: RetryJob>(
+        &self,
+        notification: &Notification,
+        job: Arc<J>,
+        error: J::Error,
+    ) -> Result<(), J::Error> {
+        if !error.is_transient() || self.delay(notification, &error, 1).is_none() {
+            return Err(error);
+        }
+        let permit = match self.reserve() {
+            Some(permit) => permit,
+            None => {
+                if self.settings.dead_letter && self.dead_letter(notification, &error).await {
+                    return Ok(());
+                }
+                return Err(error);
+            }
+        };
+        self.incr_metric("notification.bridge.retry");
+
+        let retry = self.clone();
+        let notification = notification.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let destination = notification.subscription.user.router_type.as_str();
+            match retry.retry(&notification, job.as_ref(), error).await {
+                Ok(()) => {
+                    incr_success_metrics(
+                        &retry.metrics,
+                        retry.platform,
+                        job.app_id(),
+                        &notification,
+                    );
+                    notification
+                        .audit(AuditEvent::Routed)
+                        .destination(destination)
+                        .log();
+                }
+                Err(e) => {
+                    let stored = e.is_transient()
+                        && retry.settings.dead_letter
+                        && retry.dead_letter(&notification, &e).await;
+                    if !stored {
+                        let e = job.handle_error(e).await;
+                        notification
+                            .audit(AuditEvent::Dropped)
+                            .reason(e.kind.metric_label().unwrap_or("error"))
+                            .destination(destination)
+                            .log();
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Reserve a place in the queue. Returns `None` if retries are disabled or
+    /// the queue is full.
+    fn reserve(&self) -> Option<OwnedSemaphorePermit> {
+        if self.settings.max_attempts <= 1 {
+            return None;
+        }
+        let permit = self.queue.clone().try_acquire_owned().ok();
+        if permit.is_none() {
+            self.incr_metric("notification.bridge.retry_queue_full");
+        }
+        permit
+    }
+
+    /// Retry a job which failed with `error`, until it succeeds, fails
+    /// permanently, runs out of attempts or would be sent after the
+    /// notification expires
+    async fn retry<J: RetryJob>(
+        &self,
+        notification: &Notification,
+        job: &J,
+        mut error: J::Error,
+    ) -> Result<(), J::Error> {
+        for retry in 1..self.settings.max_attempts {
+            let delay = match self.delay(notification, &error, retry) {
+                Some(delay) => delay,
+                None => {
+                    debug!(
+                        "Not retrying {} notification, it expires first",
+                        self.platform
+                    );
+                    self.incr_metric("notification.bridge.retry_expired");
+                    return Err(error);
+                }
+            };
+
+            debug!(
+                "Retrying {} notification in {:?}: {}",
+                self.platform, delay, error
+            );
+            tokio::time::sleep(delay).await;
+            match job.send().await {
+                Ok(()) => return Ok(()),
+                Err(e) if e.is_transient() => error = e,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(error)
+    }
+
+    /// The delay before the given retry: the backoff, or the bridge's
+    /// `Retry-After` if it's longer. Returns `None` if the notification
+    /// would expire first.
+    fn delay(
+        &self,
+        notification: &Notification,
+        error: &impl TransientError,
+        retry: u32,
+    ) -> Option<Duration> {
+        let backoff = self.settings.backoff(retry);
+        let delay = error
+            .retry_after()
+            .map_or(backoff, |retry_after| retry_after.max(backoff));
+        let expiry = notification.timestamp + notification.headers.ttl as u64;
+        (sec_since_epoch() + delay.as_secs() < expiry).then_some(delay)
+    }
+
+    /// Store the notification as a dead letter. Returns false if it was not
+    /// stored.
+    async fn dead_letter(&self, notification: &Notification, error: &impl Display) -> bool {
+        let dead_letter = notification.to_dead_letter(error.to_string());
+        if dead_letter.ttl_remaining(sec_since_epoch()) == 0 {
+            return false;
+        }
+
+        if let Err(e) = self.db.save_dead_letter(&dead_letter).await {
+            warn!(
+                "Error while storing dead letter for {}: {}",
+                notification.subscription.user.uaid, e
+            );
+            return false;
+        }
+        self.incr_metric("notification.bridge.dead_letter");
+        notification
+            .audit(AuditEvent::Dropped)
+            .reason("dead_letter")
+            .destination(&notification.subscription.user.router_type)
+            .log();
+        true
+    }
+
+    fn incr_metric(&self, name: &str) {
+        self.metrics
+            .incr_with_tags(name)
+            .with_tag("platform", self.platform)
+            .send();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ApiError;
+    use crate::extractors::notification::Notification;
+    use crate::extractors::routers::RouterType;
+    use crate::routers::common::tests::make_notification;
+    use crate::routers::retry::settings::RetrySettings;
+    use crate::routers::retry::{BridgeRetry, RetryJob};
+    use crate::routers::RouterError;
+    use async_trait::async_trait;
+    use autopush_common::db::mock::MockDbClient;
+    use autopush_common::util::sec_since_epoch;
+    use cadence::StatsdClient;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// A job which fails with the error `send_fn` returns for each attempt
+    /// (starting at 1), counting its attempts
+    struct TestJob {
+        send_fn: fn(usize) -> Result<(), RouterError>,
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl TestJob {
+        fn new(send_fn: fn(usize) -> Result<(), RouterError>) -> Self {
+            TestJob {
+                send_fn,
+                attempts: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl RetryJob for TestJob {
+        type Error = RouterError;
+
+        fn app_id(&self) -> &str {
+            "test-app"
+        }
+
+        async fn send(&self) -> Result<(), RouterError> {
+            let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            (self.send_fn)(attempt)
+        }
+
+        async fn handle_error(&self, error: RouterError) -> ApiError {
+            error.into()
+        }
+    }
+
+    fn make_retry(settings: RetrySettings, db: MockDbClient) -> BridgeRetry {
+        BridgeRetry::new(
+            settings,
+            "test",
+            Arc::new(StatsdClient::from_sink("autopush", cadence::NopMetricSink)),
+            db.into_boxed_arc(),
+        )
+    }
+
+    fn fast_settings() -> RetrySettings {
+        RetrySettings {
+            initial_backoff_millis: 1,
+            max_backoff_millis: 1,
+            ..Default::default()
+        }
+    }
+
+    /// A notification with a minute of TTL left
+    fn live_notification(data: Option<String>) -> Notification {
+        let mut notification = make_notification(HashMap::new(), data, RouterType::FCM);
+        notification.headers.ttl = 60;
+        notification.timestamp = sec_since_epoch();
+        notification
+    }
+
+    fn unavailable(retry_after: Option<u64>) -> RouterError {
+        RouterError::Unavailable {
+            status: "503 Service Unavailable".to_string(),
+            message: "try again".to_string(),
+            retry_after,
+        }
+    }
+
+    /// The backoff doubles with each retry, up to the maximum
+    #[test]
+    fn backoff() {
+        let settings = RetrySettings::default();
+        assert_eq!(settings.backoff(1), Duration::from_millis(200));
+        assert_eq!(settings.backoff(2), Duration::from_millis(400));
+        assert_eq!(settings.backoff(4), Duration::from_millis(1600));
+        assert_eq!(settings.backoff(5), Duration::from_millis(2000));
+        assert_eq!(settings.backoff(40), Duration::from_millis(2000));
+    }
+
+    /// Transient errors are retried until the request succeeds
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let retry = make_retry(fast_settings(), MockDbClient::new());
+        let job = TestJob::new(|attempt| {
+            if attempt < 2 {
+                Err(RouterError::RequestTimeout)
+            } else {
+                Ok(())
+            }
+        });
+
+        let result = retry
+            .retry(&live_notification(None), &job, RouterError::RequestTimeout)
+            .await;
+
+        assert!(result.is_ok(), "result = {result:?}");
+        assert_eq!(job.attempts.load(Ordering::SeqCst), 2);
+    }
+
+    /// Retries stop at the first permanent error
+    #[tokio::test]
+    async fn stops_at_permanent_error() {
+        let retry = make_retry(fast_settings(), MockDbClient::new());
+        let job = TestJob::new(|_| Err(RouterError::NotFound));
+
+        let result = retry
+            .retry(&live_notification(None), &job, RouterError::RequestTimeout)
+            .await;
+
+        assert!(matches!(result, Err(RouterError::NotFound)));
+        assert_eq!(job.attempts.load(Ordering::SeqCst), 1);
+    }
+
+    /// A notification isn't retried after a Retry-After which outlasts its
+    /// TTL
+    #[tokio::test]
+    async fn stops_at_expiry() {
+        let retry = make_retry(fast_settings(), MockDbClient::new());
+        let job = TestJob::new(|_| Ok(()));
+
+        let result = retry
+            .retry(&live_notification(None), &job, unavailable(Some(3600)))
+            .await;
+
+        assert!(matches!(result, Err(RouterError::Unavailable { .. })));
+        assert_eq!(job.attempts.load(Ordering::SeqCst), 0);
+    }
+
+    /// Permanent errors are not queued
+    #[tokio::test]
+    async fn does_not_queue_permanent_errors() {
+        let retry = make_retry(fast_settings(), MockDbClient::new());
+        let job = TestJob::new(|_| panic!("The job should not be retried"));
+
+        let result = retry
+            .queue(
+                &live_notification(None),
+                Arc::new(job),
+                RouterError::NotFound,
+            )
+            .await;
+
+        assert!(matches!(result, Err(RouterError::NotFound)));
+    }
+
+    /// Notifications which would expire before their first retry are not
+    /// queued
+    #[tokio::test]
+    async fn does_not_queue_expiring_notifications() {
+        let retry = make_retry(fast_settings(), MockDbClient::new());
+        let job = TestJob::new(|_| panic!("The job should not be retried"));
+        let mut notification = live_notification(None);
+        notification.headers.ttl = 0;
+
+        let result = retry
+            .queue(&notification, Arc::new(job), RouterError::RequestTimeout)
+            .await;
+
+        assert!(matches!(result, Err(RouterError::RequestTimeout)));
+    }
+
+    /// Queued notifications which exhaust their retries are stored as dead
+    /// letters in the background
+    #[tokio::test]
+    async fn dead_letters_exhausted_notifications() {
+        let (stored_tx, stored_rx) = tokio::sync::oneshot::channel();
+        let mut db = MockDbClient::new();
+        db.expect_save_dead_letter()
+            .withf(|dead_letter| {
+                dead_letter.ttl == 60 && dead_letter.data.as_deref() == Some("test-data")
+            })
+            .times(1)
+            .return_once(move |_| {
+                stored_tx.send(()).unwrap();
+                Ok(())
+            });
+        let retry = make_retry(
+            RetrySettings {
+                dead_letter: true,
+                ..fast_settings()
+            },
+            db,
+        );
+        let job = TestJob::new(|_| Err(unavailable(None)));
+        let attempts = job.attempts.clone();
+
+        let result = retry
+            .queue(
+                &live_notification(Some("test-data".to_string())),
+                Arc::new(job),
+                unavailable(None),
+            )
+            .await;
+
+        assert!(result.is_ok(), "result = {result:?}");
+        tokio::time::timeout(Duration::from_secs(5), stored_rx)
+            .await
+            .expect("The dead letter was not stored")
+            .unwrap();
+        // The first attempt was made before queueing
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    /// When the queue is full, notifications are stored as dead letters
+    /// straight away
+    #[tokio::test]
+    async fn dead_letters_when_queue_full() {
+        let mut db = MockDbClient::new();
+        db.expect_save_dead_letter()
+            .times(1)
+            .return_once(|_| Ok(()));
+        let retry = make_retry(
+            RetrySettings {
+                queue_size: 0,
+                dead_letter: true,
+                ..fast_settings()
+            },
+            db,
+        );
+        let job = TestJob::new(|_| panic!("The job should not be retried"));
+
+        let result = retry
+            .queue(
+                &live_notification(None),
+                Arc::new(job),
+                RouterError::RequestTimeout,
+            )
+            .await;
+
+        assert!(result.is_ok(), "result = {result:?}");
+    }
+
+    /// When the queue is full and dead letters are disabled, the error is
+    /// returned
+    #[tokio::test]
+    async fn returns_error_when_queue_full() {
+        let retry = make_retry(
+            RetrySettings {
+                queue_size: 0,
+                ..fast_settings()
+            },
+            MockDbClient::new(),
+        );
+        let job = TestJob::new(|_| panic!("The job should not be retried"));
+
+        let result = retry
+            .queue(
+                &live_notification(None),
+                Arc::new(job),
+                RouterError::RequestTimeout,
+            )
+            .await;
+
+        assert!(matches!(result, Err(RouterError::RequestTimeout)));
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+/// Settings for retrying failed bridge requests. The sender is told a
+/// notification was accepted once it's queued, and its retries are sent in
+/// the background.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct RetrySettings {
+    /// The maximum number of attempts to send a notification, including the
+    /// first one (1 disables retries)
+    pub max_attempts: u32,
+    /// The delay before the first retry, in milliseconds. The delay doubles
+    /// with each following retry, and is at least the bridge's `Retry-After`.
+    pub initial_backoff_millis: u64,
+    /// The longest backoff between retries, in milliseconds
+    pub max_backoff_millis: u64,
+    /// The maximum number of notifications being retried at once. When the
+    /// queue is full, notifications fail (or are stored as dead letters)
+    /// after their first attempt.
+    pub queue_size: usize,
+    /// Store notifications which exhaust their retries, and still have TTL
+    /// left, so they may be replayed later
+    pub dead_letter: bool,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_millis: 200,
+            max_backoff_millis: 2000,
+            queue_size: 100,
+            dead_letter: false,
+        }
+    }
+}
+
+impl RetrySettings {
+    /// The exponential backoff delay before the given retry (starting at 1)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let millis = self
+            .initial_backoff_millis
+            .saturating_mul(2u64.saturating_pow(retry.saturating_sub(1)));
+        Duration::from_millis(millis.min(self.max_backoff_millis))
+    }
+}
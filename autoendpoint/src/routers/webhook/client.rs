@@ -0,0 +1,324 @@
+use crate::auth::sign_with_key;
+use crate::routers::common::message_size_check;
+use crate::routers::webhook::error::WebhookError;
+use crate::routers::webhook::settings::WebhookSettings;
+use crate::routers::RouterError;
+use autopush_common::util::sec_since_epoch;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use url::Url;
+
+/// The header carrying the callback signature
+pub const SIGNATURE_HEADER: &str = "X-Autopush-Signature";
+/// The header carrying the signed timestamp
+pub const TIMESTAMP_HEADER: &str = "X-Autopush-Timestamp";
+
+/// The outcome of a single callback attempt
+pub enum Attempt {
+    Done(Result<(), RouterError>),
+    Retry(RouterError),
+}
+
+/// Whether the address is publicly routable. Callbacks may not be sent to
+/// loopback, private, link-local or other special purpose addresses, which
+/// could reach services inside the deployment.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "This network" (0.0.0.0/8)
+                || octets[0] == 0
+                // Shared address space (100.64.0.0/10)
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local (fc00::/7) and link-local (fe80::/10)
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Resolves callback hosts to their public addresses only, so a host can't
+/// be pointed at an internal service (including after registration)
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(resolve_public(name))
+    }
+}
+
+async fn resolve_public(name: Name) -> Result<Addrs, Box<dyn Error + Send + Sync>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+        .await?
+        .filter(|addr| is_public(addr.ip()))
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{} has no public addresses", name.as_str()).into());
+    }
+    Ok(Box::new(addrs.into_iter()))
+}
+
+/// Sends notifications to webhook callback URLs
+#[derive(Clone)]
+pub struct WebhookClient {
+    timeout: Duration,
+    max_data: usize,
+    max_retries: usize,
+    retry_delay: Duration,
+    /// Bounds the number of callbacks being retried in the background
+    retry_queue: Arc<Semaphore>,
+    http: reqwest::Client,
+}
+
+impl WebhookClient {
+    pub fn new(settings: &WebhookSettings) -> Result<Self, WebhookError> {
+        // Redirects could point anywhere, including at internal services
+        let mut builder = reqwest::Client::builder().redirect(Policy::none());
+        if !settings.allow_private_addresses {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        Ok(WebhookClient {
+            timeout: Duration::from_secs(settings.timeout as u64),
+            max_data: settings.max_data,
+            max_retries: settings.max_retries,
+            retry_delay: Duration::from_millis(settings.retry_delay_millis),
+            retry_queue: Arc::new(Semaphore::new(settings.retry_queue_size)),
+            http: builder.build().map_err(WebhookError::HttpClient)?,
+        })
+    }
+
+    /// Sign a callback body. The signature covers the timestamp as well as the
+    /// body so that callbacks can't be replayed later.
+    pub fn sign(secret: &str, timestamp: u64, body: &str) -> Result<String, WebhookError> {
+        sign_with_key(secret.as_bytes(), format!("{timestamp}.{body}").as_bytes())
+            .map_err(WebhookError::Sign)
+    }
+
+    /// Build the callback body, checking it isn't too large
+    pub fn message_json(
+        &self,
+        data: HashMap<&'static str, String>,
+        ttl: usize,
+    ) -> Result<String, RouterError> {
+        let message_json = serde_json::json!({
+            "data": data,
+            "ttl": ttl,
+        })
+        .to_string();
+        message_size_check(message_json.as_bytes(), self.max_data)?;
+        Ok(message_json)
+    }
+
+    /// Reserve a place in the retry queue for a callback which failed with a
+    /// transient error. Returns `None` if retries are disabled or the queue is
+    /// full.
+    pub fn reserve_retry(&self) -> Option<OwnedSemaphorePermit> {
+        if self.max_retries == 0 {
+            return None;
+        }
+        self.retry_queue.clone().try_acquire_owned().ok()
+    }
+
+    /// Retry a callback which failed with `error`, backing off exponentially,
+    /// until it succeeds, fails permanently or runs out of retries
+    pub async fn retry(
+        &self,
+        url: &Url,
+        secret: &str,
+        message_json: &str,
+        mut error: RouterError,
+    ) -> Result<(), RouterError> {
+        for retry in 0..self.max_retries {
+            let delay = self.retry_delay * 2u32.saturating_pow(retry as u32);
+            debug!("Retrying webhook callback in {:?}: {}", delay, error);
+            tokio::time::sleep(delay).await;
+
+            match self.send_once(url, secret, message_json).await? {
+                Attempt::Done(result) => return result,
+                Attempt::Retry(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Make a single callback request
+    pub async fn send_once(
+        &self,
+        url: &Url,
+        secret: &str,
+        message_json: &str,
+    ) -> Result<Attempt, RouterError> {
+        let timestamp = sec_since_epoch();
+        let signature = Self::sign(secret, timestamp, message_json)?;
+
+        let response = match self
+            .http
+            .post(url.clone())
+            .header("Content-Type", "application/json")
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, format!("sha256={signature}"))
+            .body(message_json.to_owned())
+            .timeout(self.timeout)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) if e.is_timeout() => return Ok(Attempt::Retry(RouterError::RequestTimeout)),
+            Err(e) => return Ok(Attempt::Retry(RouterError::Connect(e))),
+        };
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(Attempt::Done(Ok(())));
+        }
+
+        let upstream = RouterError::Upstream {
+            status: status.to_string(),
+            message: response.text().await.unwrap_or_default(),
+        };
+        Ok(match status {
+            StatusCode::NOT_FOUND | StatusCode::GONE => Attempt::Done(Err(RouterError::NotFound)),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Attempt::Done(Err(RouterError::Authentication))
+            }
+            StatusCode::TOO_MANY_REQUESTS => Attempt::Retry(upstream),
+            status if status.is_server_error() => Attempt::Retry(upstream),
+            _ => Attempt::Done(Err(upstream)),
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::routers::webhook::client::{is_public, Attempt, WebhookClient, SIGNATURE_HEADER};
+    use crate::routers::webhook::settings::WebhookSettings;
+    use crate::routers::RouterError;
+    use std::collections::HashMap;
+    use url::Url;
+
+    pub const CALLBACK_PATH: &str = "/callback";
+    pub const SECRET: &str = "test-shared-secret";
+
+    /// Start building a mock callback endpoint
+    pub fn mock_callback_builder() -> mockito::Mock {
+        mockito::mock("POST", CALLBACK_PATH).match_header(
+            SIGNATURE_HEADER,
+            mockito::Matcher::Regex("^sha256=[0-9a-f]{64}$".to_string()),
+        )
+    }
+
+    /// The URL of the mock callback endpoint
+    pub fn callback_url() -> Url {
+        Url::parse(&mockito::server_url())
+            .unwrap()
+            .join(CALLBACK_PATH)
+            .unwrap()
+    }
+
+    fn make_client() -> WebhookClient {
+        WebhookClient::new(&WebhookSettings {
+            retry_delay_millis: 1,
+            allow_private_addresses: true,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// The signature covers the timestamp and body
+    #[test]
+    fn signature() {
+        let signature = WebhookClient::sign(SECRET, 1234, "{}").unwrap();
+        assert_eq!(signature, WebhookClient::sign(SECRET, 1234, "{}").unwrap());
+        assert_ne!(signature, WebhookClient::sign(SECRET, 1235, "{}").unwrap());
+        assert_ne!(signature, WebhookClient::sign("other", 1234, "{}").unwrap());
+    }
+
+    /// Only publicly routable addresses are public
+    #[test]
+    fn public_addresses() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    /// Server errors are retried up to the limit
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let client = make_client();
+        let mock = mock_callback_builder().with_status(503).expect(3).create();
+        let message_json = client.message_json(HashMap::new(), 60).unwrap();
+
+        let error = match client
+            .send_once(&callback_url(), SECRET, &message_json)
+            .await
+            .unwrap()
+        {
+            Attempt::Retry(error) => error,
+            Attempt::Done(result) => panic!("Not retryable: {result:?}"),
+        };
+        let result = client
+            .retry(&callback_url(), SECRET, &message_json, error)
+            .await;
+        assert!(
+            matches!(result, Err(RouterError::Upstream { .. })),
+            "result = {result:?}"
+        );
+        mock.assert();
+    }
+
+    /// Other client errors are not retried
+    #[tokio::test]
+    async fn no_retry_client_errors() {
+        let client = make_client();
+        let mock = mock_callback_builder().with_status(400).expect(1).create();
+        let message_json = client.message_json(HashMap::new(), 60).unwrap();
+
+        let attempt = client
+            .send_once(&callback_url(), SECRET, &message_json)
+            .await
+            .unwrap();
+        assert!(matches!(
+            attempt,
+            Attempt::Done(Err(RouterError::Upstream { .. }))
+        ));
+        mock.assert();
+    }
+}
@@ -0,0 +1,76 @@
+use crate::error::ApiErrorKind;
+use crate::routers::RouterError;
+use actix_web::http::StatusCode;
+
+/// Errors that may occur in the webhook router
+#[derive(thiserror::Error, Debug)]
+pub enum WebhookError {
+    #[error("Failed to decode the webhook app settings")]
+    AppSettingsDecode(#[from] serde_json::Error),
+
+    #[error("Webhook app {0} has no allowed hosts")]
+    NoAllowedHosts(String),
+
+    #[error("Failed to build the webhook HTTP client")]
+    HttpClient(#[source] reqwest::Error),
+
+    #[error("Error while signing the callback request")]
+    Sign(#[source] openssl::error::ErrorStack),
+
+    #[error("Invalid callback URL: {0}")]
+    InvalidCallbackUrl(String),
+
+    #[error("The shared secret must be at least {0} characters")]
+    InvalidSecret(usize),
+
+    #[error("No callback URL found for user")]
+    NoCallbackUrl,
+
+    #[error("No shared secret found for user")]
+    NoSecret,
+
+    #[error("User has invalid webhook app")]
+    InvalidApp,
+}
+
+impl WebhookError {
+    /// Get the associated HTTP status code
+    pub fn status(&self) -> StatusCode {
+        match self {
+            WebhookError::InvalidCallbackUrl(_) | WebhookError::InvalidSecret(_) => {
+                StatusCode::BAD_REQUEST
+            }
+
+            WebhookError::NoCallbackUrl | WebhookError::NoSecret | WebhookError::InvalidApp => {
+                StatusCode::GONE
+            }
+
+            WebhookError::AppSettingsDecode(_)
+            | WebhookError::NoAllowedHosts(_)
+            | WebhookError::HttpClient(_)
+            | WebhookError::Sign(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Get the associated error number
+    pub fn errno(&self) -> Option<usize> {
+        match self {
+            WebhookError::NoCallbackUrl | WebhookError::NoSecret | WebhookError::InvalidApp => {
+                Some(106)
+            }
+
+            WebhookError::AppSettingsDecode(_)
+            | WebhookError::NoAllowedHosts(_)
+            | WebhookError::HttpClient(_)
+            | WebhookError::Sign(_)
+            | WebhookError::InvalidCallbackUrl(_)
+            | WebhookError::InvalidSecret(_) => None,
+        }
+    }
+}
+
+impl From<WebhookError> for ApiErrorKind {
+    fn from(e: WebhookError) -> Self {
+        ApiErrorKind::Router(RouterError::Webhook(e))
+    }
+}
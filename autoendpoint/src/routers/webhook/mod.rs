@@ -0,0 +1,7 @@
+//! A notification router for server-side daemons, delivering notifications as
+//! signed HTTP callbacks
+
+pub mod client;
+pub mod error;
+pub mod router;
+pub mod settings;
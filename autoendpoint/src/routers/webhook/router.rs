@@ -0,0 +1,451 @@
+use autopush_common::db::client::DbClient;
+
+use crate::error::ApiResult;
+use crate::extractors::notification::Notification;
+use crate::extractors::router_data_input::RouterDataInput;
+use crate::routers::common::{build_message_data, handle_error, incr_success_metrics};
+use crate::routers::webhook::client::{is_public, Attempt, WebhookClient};
+use crate::routers::webhook::error::WebhookError;
+use crate::routers::webhook::settings::{WebhookApp, WebhookSettings};
+use crate::routers::{Router, RouterError, RouterResponse};
+use async_trait::async_trait;
+use cadence::{CountedExt, StatsdClient};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::{Host, Url};
+use uuid::Uuid;
+
+/// The minimum length of a callback's shared secret
+const MIN_SECRET_LENGTH: usize = 16;
+
+/// Webhook router, delivering notifications to HTTP callback URLs
+pub struct WebhookRouter {
+    endpoint_url: Url,
+    metrics: Arc<StatsdClient>,
+    db: Box<dyn DbClient>,
+    settings: WebhookSettings,
+    /// A map from app ID to the app's settings
+    apps: HashMap<String, WebhookApp>,
+    client: WebhookClient,
+}
+
+impl WebhookRouter {
+    /// Create a new `WebhookRouter`
+    pub fn new(
+        settings: WebhookSettings,
+        endpoint_url: Url,
+        metrics: Arc<StatsdClient>,
+        db: Box<dyn DbClient>,
+    ) -> Result<Self, WebhookError> {
+        let apps = settings.apps()?;
+        // Callbacks to arbitrary hosts would let anyone make requests from
+        // inside the deployment
+        if let Some((app_id, _)) = apps.iter().find(|(_, app)| app.allowed_hosts.is_empty()) {
+            return Err(WebhookError::NoAllowedHosts(app_id.clone()));
+        }
+        trace!("Initialized {} webhook apps", apps.len());
+        let client = WebhookClient::new(&settings)?;
+
+        Ok(Self {
+            endpoint_url,
+            metrics,
+            db,
+            settings,
+            apps,
+            client,
+        })
+    }
+
+    /// Check that the callback URL is allowed for the app
+    fn validate_callback_url(&self, url: &str, app: &WebhookApp) -> Result<Url, WebhookError> {
+        let url = Url::parse(url).map_err(|e| WebhookError::InvalidCallbackUrl(e.to_string()))?;
+
+        match url.scheme() {
+            "https" => {}
+            "http" if self.settings.allow_insecure => {}
+            scheme => {
+                return Err(WebhookError::InvalidCallbackUrl(format!(
+                    "Unsupported scheme {scheme}"
+                )))
+            }
+        }
+
+        let host = url.host_str().unwrap_or_default();
+        if !app.allowed_hosts.iter().any(|h| h == host) {
+            return Err(WebhookError::InvalidCallbackUrl(format!(
+                "Host {host} is not allowed"
+            )));
+        }
+
+        // Host names are resolved to public addresses only by the client
+        let ip = match url.host() {
+            Some(Host::Ipv4(ip)) => Some(ip.into()),
+            Some(Host::Ipv6(ip)) => Some(ip.into()),
+            _ => None,
+        };
+        if let Some(ip) = ip {
+            if !self.settings.allow_private_addresses && !is_public(ip) {
+                return Err(WebhookError::InvalidCallbackUrl(format!(
+                    "Address {ip} is not allowed"
+                )));
+            }
+        }
+
+        Ok(url)
+    }
+
+    /// Retry a callback which failed with a transient error in the
+    /// background, so the sender isn't kept waiting. The error is returned if
+    /// the callback can't be retried.
+    fn queue_retry(
+        &self,
+        url: Url,
+        secret: &str,
+        message_json: String,
+        error: RouterError,
+        app_id: &str,
+        uaid: Uuid,
+    ) -> Result<(), RouterError> {
+        let permit = match self.client.reserve_retry() {
+            Some(permit) => permit,
+            None => return Err(error),
+        };
+        self.metrics
+            .incr_with_tags("notification.bridge.retry")
+            .with_tag("platform", "webhook")
+            .send();
+
+        let client = self.client.clone();
+        let secret = secret.to_owned();
+        let metrics = self.metrics.clone();
+        let db = self.db.clone();
+        let app_id = app_id.to_owned();
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) = client.retry(&url, &secret, &message_json, error).await {
+                handle_error(e, &metrics, db.as_ref(), "webhook", &app_id, uaid).await;
+            }
+        });
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl Router for WebhookRouter {
+    /// if we have any apps defined, this router is "active"
+    fn active(&self) -> bool {
+        !self.apps.is_empty()
+    }
+
+    /// The token is the callback URL
+    fn validate_token(&self, token: &str) -> bool {
+        Url::parse(token).is_ok()
+    }
+
+    fn register(
+        &self,
+        router_input: &RouterDataInput,
+        app_id: &str,
+    ) -> Result<HashMap<String, Value>, RouterError> {
+        let app = self.apps.get(app_id).ok_or(WebhookError::InvalidApp)?;
+        let url = self.validate_callback_url(&router_input.token, app)?;
+        let secret = router_input
+            .secret
+            .as_deref()
+            .filter(|secret| secret.len() >= MIN_SECRET_LENGTH)
+            .ok_or(WebhookError::InvalidSecret(MIN_SECRET_LENGTH))?;
+
+        let mut router_data = HashMap::new();
+        router_data.insert("url".to_string(), serde_json::to_value(url).unwrap());
+        router_data.insert("secret".to_string(), serde_json::to_value(secret).unwrap());
+        router_data.insert("app_id".to_string(), serde_json::to_value(app_id).unwrap());
+
+        Ok(router_data)
+    }
+
+    async fn route_notification(&self, notification: &Notification) -> ApiResult<RouterResponse> {
+        debug!(
+            "Sending webhook notification to UAID {}",
+            notification.subscription.user.uaid
+        );
+        trace!("Notification = {:?}", notification);
+
+        let router_data = notification
+            .subscription
+            .user
+            .router_data
+            .as_ref()
+            .ok_or(WebhookError::NoCallbackUrl)?;
+        let url = router_data
+            .get("url")
+            .and_then(Value::as_str)
+            .ok_or(WebhookError::NoCallbackUrl)?;
+        let secret = router_data
+            .get("secret")
+            .and_then(Value::as_str)
+            .ok_or(WebhookError::NoSecret)?;
+        let app_id = router_data
+            .get("app_id")
+            .and_then(Value::as_str)
+            .ok_or(WebhookError::InvalidApp)?;
+        let app = self.apps.get(app_id).ok_or(WebhookError::InvalidApp)?;
+        // The app's allowed hosts may have changed since registration
+        let url = self.validate_callback_url(url, app)?;
+        let ttl = notification.headers.ttl as usize;
+        let message_data = build_message_data(notification)?;
+        let message_json = self.client.message_json(message_data, ttl)?;
+        let uaid = notification.subscription.user.uaid;
+
+        trace!("Sending message to webhook: {}", message_json);
+        let result = match self.client.send_once(&url, secret, &message_json).await {
+            Ok(Attempt::Done(result)) => result,
+            Ok(Attempt::Retry(e)) => self.queue_retry(url, secret, message_json, e, app_id, uaid),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            return Err(
+                handle_error(e, &self.metrics, self.db.as_ref(), "webhook", app_id, uaid).await,
+            );
+        }
+
+        // Sent successfully, update metrics and make response
+        trace!("Webhook request was successful");
+        incr_success_metrics(&self.metrics, "webhook", app_id, notification);
+
+        Ok(RouterResponse::success(
+            self.endpoint_url
+                .join(&format!("/m/{}", notification.message_id))
+                .expect("Message ID is not URL-safe")
+                .to_string(),
+            notification.headers.ttl as usize,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ApiErrorKind;
+    use crate::extractors::router_data_input::RouterDataInput;
+    use crate::extractors::routers::RouterType;
+    use crate::routers::common::tests::{make_notification, CHANNEL_ID};
+    use crate::routers::webhook::client::tests::{callback_url, mock_callback_builder, SECRET};
+    use crate::routers::webhook::error::WebhookError;
+    use crate::routers::webhook::router::WebhookRouter;
+    use crate::routers::webhook::settings::WebhookSettings;
+    use crate::routers::{Router, RouterError, RouterResponse};
+    use autopush_common::db::{client::DbClient, mock::MockDbClient};
+    use cadence::StatsdClient;
+    use mockall::predicate;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use url::Url;
+
+    fn test_settings() -> WebhookSettings {
+        WebhookSettings {
+            apps: r#"{ "dev": { "allowed_hosts": ["example.com", "127.0.0.1"] } }"#.to_string(),
+            max_retries: 0,
+            allow_insecure: true,
+            allow_private_addresses: true,
+            ..Default::default()
+        }
+    }
+
+    fn make_router_with(
+        settings: WebhookSettings,
+        db: Box<dyn DbClient>,
+    ) -> Result<WebhookRouter, WebhookError> {
+        WebhookRouter::new(
+            settings,
+            Url::parse("http://localhost:8080/").unwrap(),
+            Arc::new(StatsdClient::from_sink("autopush", cadence::NopMetricSink)),
+            db,
+        )
+    }
+
+    /// Create a router for testing
+    fn make_router(db: Box<dyn DbClient>) -> WebhookRouter {
+        make_router_with(test_settings(), db).unwrap()
+    }
+
+    /// Create default user router data
+    fn default_router_data() -> HashMap<String, serde_json::Value> {
+        let mut map = HashMap::new();
+        map.insert(
+            "url".to_string(),
+            serde_json::to_value(callback_url().to_string()).unwrap(),
+        );
+        map.insert("secret".to_string(), serde_json::to_value(SECRET).unwrap());
+        map.insert("app_id".to_string(), serde_json::to_value("dev").unwrap());
+        map
+    }
+
+    fn router_input(url: &str, secret: Option<&str>) -> RouterDataInput {
+        RouterDataInput {
+            token: url.to_string(),
+            channel_id: None,
+            key: None,
+            aps: None,
+            secret: secret.map(str::to_string),
+        }
+    }
+
+    /// Registration stores the callback URL, secret and app ID
+    #[test]
+    fn register() {
+        let router = make_router(MockDbClient::new().into_boxed_arc());
+        let router_data = router
+            .register(
+                &router_input("https://example.com/push", Some(SECRET)),
+                "dev",
+            )
+            .unwrap();
+
+        assert_eq!(router_data["url"], "https://example.com/push");
+        assert_eq!(router_data["secret"], SECRET);
+        assert_eq!(router_data["app_id"], "dev");
+    }
+
+    /// Registration fails with a short secret or an unknown app
+    #[test]
+    fn register_invalid() {
+        let router = make_router(MockDbClient::new().into_boxed_arc());
+
+        assert!(matches!(
+            router.register(
+                &router_input("https://example.com/push", Some("short")),
+                "dev"
+            ),
+            Err(RouterError::Webhook(WebhookError::InvalidSecret(_)))
+        ));
+        assert!(matches!(
+            router.register(
+                &router_input("https://example.com/push", Some(SECRET)),
+                "other"
+            ),
+            Err(RouterError::Webhook(WebhookError::InvalidApp))
+        ));
+        assert!(matches!(
+            router.register(&router_input("ftp://example.com/push", Some(SECRET)), "dev"),
+            Err(RouterError::Webhook(WebhookError::InvalidCallbackUrl(_)))
+        ));
+        assert!(matches!(
+            router.register(&router_input("https://other.com/push", Some(SECRET)), "dev"),
+            Err(RouterError::Webhook(WebhookError::InvalidCallbackUrl(_)))
+        ));
+    }
+
+    /// Apps must restrict their callbacks to a list of hosts
+    #[test]
+    fn app_without_hosts() {
+        let result = make_router_with(
+            WebhookSettings {
+                apps: r#"{ "dev": {} }"#.to_string(),
+                ..test_settings()
+            },
+            MockDbClient::new().into_boxed_arc(),
+        );
+        assert!(matches!(result, Err(WebhookError::NoAllowedHosts(_))));
+    }
+
+    /// Callbacks may not point at private addresses, even if allowed
+    #[test]
+    fn register_private_address() {
+        let router = make_router_with(
+            WebhookSettings {
+                apps: r#"{ "dev": { "allowed_hosts": ["10.0.0.1"] } }"#.to_string(),
+                allow_private_addresses: false,
+                ..test_settings()
+            },
+            MockDbClient::new().into_boxed_arc(),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            router.register(&router_input("https://10.0.0.1/push", Some(SECRET)), "dev"),
+            Err(RouterError::Webhook(WebhookError::InvalidCallbackUrl(_)))
+        ));
+    }
+
+    /// A notification with data is POSTed to the callback
+    #[tokio::test]
+    async fn successful_routing_with_data() {
+        let router = make_router(MockDbClient::new().into_boxed_arc());
+        let mock = mock_callback_builder()
+            .match_body(
+                serde_json::json!({
+                    "data": {
+                        "chid": CHANNEL_ID,
+                        "body": "test-data",
+                        "con": "test-encoding",
+                        "enc": "test-encryption",
+                        "cryptokey": "test-crypto-key",
+                        "enckey": "test-encryption-key"
+                    },
+                    "ttl": 0
+                })
+                .to_string()
+                .as_str(),
+            )
+            .create();
+        let notification = make_notification(
+            default_router_data(),
+            Some("test-data".to_string()),
+            RouterType::Webhook,
+        );
+
+        let result = router.route_notification(&notification).await;
+        assert!(result.is_ok(), "result = {result:?}");
+        assert_eq!(
+            result.unwrap(),
+            RouterResponse::success("http://localhost:8080/m/test-message-id".to_string(), 0)
+        );
+        mock.assert();
+    }
+
+    /// Transient failures are retried in the background, after the
+    /// notification is accepted
+    #[tokio::test]
+    async fn retries_in_background() {
+        let router = make_router_with(
+            WebhookSettings {
+                max_retries: 1,
+                retry_delay_millis: 1,
+                ..test_settings()
+            },
+            MockDbClient::new().into_boxed_arc(),
+        )
+        .unwrap();
+        let mock = mock_callback_builder().with_status(503).expect(2).create();
+        let notification = make_notification(default_router_data(), None, RouterType::Webhook);
+
+        let result = router.route_notification(&notification).await;
+        assert!(result.is_ok(), "result = {result:?}");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        mock.assert();
+    }
+
+    /// If the callback reports the subscription is gone, drop the user
+    #[tokio::test]
+    async fn callback_gone() {
+        let notification = make_notification(default_router_data(), None, RouterType::Webhook);
+        let mut db = MockDbClient::new();
+        db.expect_remove_user()
+            .with(predicate::eq(notification.subscription.user.uaid))
+            .times(1)
+            .return_once(|_| Ok(()));
+
+        let router = make_router(db.into_boxed_arc());
+        let _mock = mock_callback_builder().with_status(410).create();
+
+        let result = router.route_notification(&notification).await;
+        assert!(
+            matches!(
+                result.as_ref().unwrap_err().kind,
+                ApiErrorKind::Router(RouterError::NotFound)
+            ),
+            "result = {result:?}"
+        );
+    }
+}
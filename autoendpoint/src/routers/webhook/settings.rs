@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+/// Settings for `WebhookRouter`
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookSettings {
+    /// A JSON dict of `WebhookApp`s. This must be a `String` because
+    /// environment variables cannot encode a `HashMap<String, WebhookApp>`
+    pub apps: String,
+    /// The max size of notification data in bytes
+    pub max_data: usize,
+    /// The number of seconds to wait for each callback request to complete
+    pub timeout: usize,
+    /// The number of times to retry a callback after a timeout, connection
+    /// error, 429 or 5xx response. Retries are sent in the background, after
+    /// the notification has been accepted.
+    pub max_retries: usize,
+    /// The delay before the first retry. Each following retry waits twice as
+    /// long as the previous one.
+    pub retry_delay_millis: u64,
+    /// The maximum number of callbacks waiting to be retried. Failures beyond
+    /// this are not retried.
+    pub retry_queue_size: usize,
+    /// Allow callback URLs using plain `http` (intended for testing)
+    pub allow_insecure: bool,
+    /// Allow callbacks to loopback, private and link-local addresses
+    /// (intended for testing)
+    pub allow_private_addresses: bool,
+}
+
+/// Settings for a specific webhook application
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookApp {
+    /// The hosts which callback URLs may point to. Apps must list at least
+    /// one host.
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            apps: "{}".to_string(),
+            max_data: 4096,
+            timeout: 3,
+            max_retries: 2,
+            retry_delay_millis: 100,
+            retry_queue_size: 100,
+            allow_insecure: false,
+            allow_private_addresses: false,
+        }
+    }
+}
+
+impl WebhookSettings {
+    /// Read the apps from the JSON string
+    pub fn apps(&self) -> serde_json::Result<HashMap<String, WebhookApp>> {
+        serde_json::from_str(&self.apps)
+    }
+}
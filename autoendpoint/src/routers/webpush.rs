@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use cadence::{Counted, CountedExt, StatsdClient, Timed};
-use reqwest::{Response, StatusCode};
+use reqwest::{RequestBuilder, Response, StatusCode};
 use serde_json::Value;
 use std::collections::{hash_map::RandomState, HashMap};
 use std::sync::Arc;
@@ -11,7 +11,10 @@ use crate::error::{ApiErrorKind, ApiResult};
 use crate::extractors::{notification::Notification, router_data_input::RouterDataInput};
 use crate::routers::{Router, RouterError, RouterResponse};
 
+use autopush_common::audit::AuditEvent;
 use autopush_common::db::{client::DbClient, User};
+use autopush_common::telemetry::{self, TRACEPARENT_HEADER};
+use tracing::{field::Empty, Instrument};
 
 /// The router for desktop user agents.
 ///
@@ -27,6 +30,11 @@ pub struct WebPushRouter {
 
 #[async_trait(?Send)]
 impl Router for WebPushRouter {
+    fn validate_token(&self, _token: &str) -> bool {
+        // WebPush registration doesn't use a token
+        true
+    }
+
     fn register(
         &self,
         _router_input: &RouterDataInput,
@@ -82,7 +90,8 @@ impl Router for WebPushRouter {
                 // TODO: include `internal` if meta is set.
                 .with_tag("topic", &topic)
                 .send();
-            return Ok(self.make_delivered_response(notification));
+            notification.audit(AuditEvent::Expired).ttl(0).log();
+            return Ok(self.make_response(notification, "Direct", StatusCode::CREATED));
         }
 
         // Save notification, node is not present or busy
@@ -158,7 +167,7 @@ impl WebPushRouter {
         let url = format!("{}/push/{}", node_id, notification.subscription.user.uaid);
         let notification = notification.serialize_for_delivery();
 
-        self.http.put(&url).json(&notification).send().await
+        send_traced("internode.push", self.http.put(&url).json(&notification)).await
     }
 
     /// Notify the node to check for notifications for the user
@@ -169,7 +178,7 @@ impl WebPushRouter {
     ) -> Result<Response, reqwest::Error> {
         let url = format!("{node_id}/notif/{uaid}");
 
-        self.http.put(&url).send().await
+        send_traced("internode.notif", self.http.put(&url)).await
     }
 
     /// Store a notification in the database
@@ -196,12 +205,20 @@ impl WebPushRouter {
     /// Update metrics and create a response for when a notification has been directly forwarded to
     /// an autopush server.
     fn make_delivered_response(&self, notification: &Notification) -> RouterResponse {
+        notification
+            .audit(AuditEvent::Routed)
+            .destination("Direct")
+            .log();
         self.make_response(notification, "Direct", StatusCode::CREATED)
     }
 
     /// Update metrics and create a response for when a notification has been stored in the database
     /// for future transmission.
     fn make_stored_response(&self, notification: &Notification) -> RouterResponse {
+        notification
+            .audit(AuditEvent::Routed)
+            .destination("Stored")
+            .log();
         self.make_response(notification, "Stored", StatusCode::CREATED)
     }
 
@@ -238,3 +255,44 @@ impl WebPushRouter {
         }
     }
 }
+
+/// Ask the connection node to disconnect the user's client. The node responds
+/// with a 404 if the client isn't connected to it.
+pub async fn disconnect_client(
+    http: &reqwest::Client,
+    uaid: &Uuid,
+    node_id: &str,
+) -> Result<Response, reqwest::Error> {
+    let url = format!("{node_id}/disconnect/{uaid}");
+
+    send_traced("internode.disconnect", http.put(&url)).await
+}
+
+/// Send a request to a connection node within a client span named `name`,
+/// propagating the trace context so the node's span joins the trace
+async fn send_traced(name: &str, mut request: RequestBuilder) -> Result<Response, reqwest::Error> {
+    let span = tracing::info_span!(
+        "internode",
+        otel.name = name,
+        otel.kind = "client",
+        otel.status_code = Empty,
+        otel.status_message = Empty,
+        http.method = "PUT",
+        http.status_code = Empty,
+    );
+    if let Some(traceparent) = telemetry::traceparent(&span) {
+        request = request.header(TRACEPARENT_HEADER, traceparent);
+    }
+
+    let result = request.send().instrument(span.clone()).await;
+    match &result {
+        Ok(response) => {
+            span.record("http.status_code", response.status().as_u16());
+        }
+        Err(e) => {
+            span.record("otel.status_code", "ERROR");
+            span.record("otel.status_message", e.to_string().as_str());
+        }
+    }
+    result
+}
@@ -0,0 +1,273 @@
+use crate::error::{ApiErrorKind, ApiResult};
+use crate::extractors::admin_auth::AdminAuth;
+use crate::extractors::notification::Notification;
+use crate::extractors::routers::Routers;
+use crate::routers::webpush::disconnect_client;
+use crate::server::AppState;
+use actix_web::web::{Data, Path, Query};
+use actix_web::HttpResponse;
+use autopush_common::db::{
+    channels_json, drop_user, fetch_stored_messages, purge_stored_messages, User,
+};
+use autopush_common::util::sec_since_epoch;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+/// The default number of dead letters to list
+const DEFAULT_DEAD_LETTER_LIMIT: usize = 100;
+/// The maximum number of dead letters to list
+const MAX_DEAD_LETTER_LIMIT: usize = 1000;
+/// The default number of stored messages to list (of each kind, topic and
+/// timestamped)
+const DEFAULT_MESSAGE_LIMIT: usize = 100;
+/// The maximum number of stored messages to list
+const MAX_MESSAGE_LIMIT: usize = 1000;
+/// Shown in place of `router_data` values, which hold bridge tokens and
+/// webhook shared secrets
+const REDACTED: &str = "[redacted]";
+/// The body of the legacy server's 404 response to a disconnect request,
+/// when the client isn't connected to it
+const CLIENT_NOT_AVAILABLE: &str = "Client not available.";
+
+#[derive(Debug, Deserialize)]
+pub struct DeadLetterQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageQuery {
+    pub limit: Option<usize>,
+}
+
+/// Handle the `GET /__admin__/dead_letters` route
+pub async fn list_dead_letters_route(
+    _auth: AdminAuth,
+    query: Query<DeadLetterQuery>,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DEAD_LETTER_LIMIT)
+        .min(MAX_DEAD_LETTER_LIMIT);
+    let dead_letters = app_state.db.fetch_dead_letters(limit).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "dead_letters": dead_letters })))
+}
+
+/// Handle the `POST /__admin__/dead_letters/{id}/replay` route. The
+/// notification is routed to the user again with the TTL it has left, and
+/// removed from dead-letter storage once the router accepts it.
+pub async fn replay_dead_letter_route(
+    _auth: AdminAuth,
+    id: Path<String>,
+    routers: Routers,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    let dead_letter = app_state
+        .db
+        .get_dead_letter(&id)
+        .await?
+        .ok_or(ApiErrorKind::NoDeadLetter)?;
+    if dead_letter.ttl_remaining(sec_since_epoch()) == 0 {
+        debug!("Dead letter {} has expired, removing it", dead_letter.id);
+        app_state.db.remove_dead_letter(&dead_letter.id).await?;
+        return Err(ApiErrorKind::NoDeadLetter.into());
+    }
+
+    let user = app_state
+        .db
+        .get_user(&dead_letter.uaid)
+        .await?
+        .ok_or(ApiErrorKind::NoUser)?;
+    let id = dead_letter.id.clone();
+    let notification = Notification::from_dead_letter(dead_letter, user);
+    debug!(
+        "Replaying dead letter {} to UAID {}",
+        id, notification.subscription.user.uaid
+    );
+
+    let router = routers.get(&notification.subscription.user.router_type)?;
+    // If the bridge is still unavailable, the router stores a new dead letter
+    let response = router.route_notification(&notification).await?;
+    app_state.db.remove_dead_letter(&id).await?;
+
+    Ok(response.into())
+}
+
+/// Handle the `GET /__admin__/uaid/{uaid}` route. Shows the user record,
+/// including the connection node (`node_id`), and the user's channels. The
+/// values in the user's `router_data` are redacted.
+pub async fn get_uaid_route(
+    _auth: AdminAuth,
+    uaid: Path<Uuid>,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    let mut user = get_user(&app_state, &uaid).await?;
+    redact_router_data(&mut user);
+    let channels = channels_json(app_state.db.get_channels_with_meta(&uaid).await?);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "user": user,
+        "connected": user.node_id.is_some(),
+        "channels": channels,
+    })))
+}
+
+/// Handle the `DELETE /__admin__/uaid/{uaid}` route. The user is removed
+/// along with their channels and stored messages, and their client (if
+/// connected) is disconnected so it registers again.
+pub async fn drop_uaid_route(
+    _auth: AdminAuth,
+    uaid: Path<Uuid>,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    let user = get_user(&app_state, &uaid).await?;
+    info!("Admin: dropping user"; "uaid" => uaid.to_string());
+    let removed = drop_user(&*app_state.db, &uaid).await?;
+    let disconnected = disconnect(&app_state, &user).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "disconnected": disconnected,
+        "removed": removed,
+    })))
+}
+
+/// Handle the `POST /__admin__/uaid/{uaid}/disconnect` route
+pub async fn disconnect_uaid_route(
+    _auth: AdminAuth,
+    uaid: Path<Uuid>,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    let user = get_user(&app_state, &uaid).await?;
+    info!("Admin: disconnecting user"; "uaid" => uaid.to_string());
+    let disconnected = disconnect(&app_state, &user).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "disconnected": disconnected })))
+}
+
+/// Handle the `GET /__admin__/uaid/{uaid}/messages` route. Lists the user's
+/// stored messages (not their data) with the TTL they have left, in seconds.
+pub async fn list_messages_route(
+    _auth: AdminAuth,
+    uaid: Path<Uuid>,
+    query: Query<MessageQuery>,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_MESSAGE_LIMIT)
+        .min(MAX_MESSAGE_LIMIT);
+    let now = sec_since_epoch();
+    let messages: Vec<serde_json::Value> = fetch_stored_messages(&*app_state.db, &uaid, limit)
+        .await?
+        .into_iter()
+        .map(|message| {
+            serde_json::json!({
+                "sortKey": message.sort_key(),
+                "channelID": message.channel_id,
+                "version": message.version,
+                "topic": message.topic,
+                "timestamp": message.timestamp,
+                "ttl": message.ttl,
+                "ttlRemaining": (message.timestamp + message.ttl).saturating_sub(now),
+                "size": message.data.as_ref().map_or(0, String::len),
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "messages": messages })))
+}
+
+/// Handle the `DELETE /__admin__/uaid/{uaid}/messages` route. Removes all of
+/// the user's stored messages.
+pub async fn purge_messages_route(
+    _auth: AdminAuth,
+    uaid: Path<Uuid>,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    info!("Admin: purging stored messages"; "uaid" => uaid.to_string());
+    let removed = purge_stored_messages(&*app_state.db, &uaid).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "removed": removed })))
+}
+
+/// Hide the credentials in a user's `router_data`, keeping the keys so it's
+/// clear which fields are set
+fn redact_router_data(user: &mut User) {
+    for value in user
+        .router_data
+        .iter_mut()
+        .flat_map(|data| data.values_mut())
+    {
+        *value = REDACTED.into();
+    }
+}
+
+async fn get_user(app_state: &AppState, uaid: &Uuid) -> ApiResult<User> {
+    app_state
+        .db
+        .get_user(uaid)
+        .await?
+        .ok_or_else(|| ApiErrorKind::NoUser.into())
+}
+
+/// Ask the user's connection node, if any, to disconnect their client.
+/// Returns whether the client was connected. Fails if the node couldn't
+/// disconnect it, e.g. an autoconnect node (which has no disconnect route).
+async fn disconnect(app_state: &AppState, user: &User) -> ApiResult<bool> {
+    let node_id = match &user.node_id {
+        Some(node_id) => node_id,
+        None => return Ok(false),
+    };
+
+    let response = match disconnect_client(&app_state.http, &user.uaid, node_id).await {
+        Ok(response) => response,
+        Err(e) => {
+            // Can't communicate with the node, so the client can't be
+            // connected to it
+            debug!("Error while disconnecting the client: {}", e);
+            app_state
+                .db
+                .remove_node_id(&user.uaid, node_id, user.connected_at)
+                .await?;
+            return Ok(false);
+        }
+    };
+    let status = response.status();
+    if status.is_success() {
+        return Ok(true);
+    }
+    // Other 404s mean the node doesn't support disconnecting clients
+    if status == StatusCode::NOT_FOUND
+        && matches!(response.text().await.as_deref(), Ok(CLIENT_NOT_AVAILABLE))
+    {
+        return Ok(false);
+    }
+
+    Err(ApiErrorKind::DisconnectFailed(format!("{node_id} responded with {status}")).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redact_router_data, REDACTED};
+    use autopush_common::db::User;
+    use std::collections::HashMap;
+
+    /// The router data's keys are shown, but not their values
+    #[test]
+    fn redacts_router_data() {
+        let mut user = User {
+            router_data: Some(HashMap::from([
+                ("url".to_owned(), "https://example.com/push".into()),
+                ("secret".to_owned(), "test-shared-secret".into()),
+            ])),
+            ..Default::default()
+        };
+        redact_router_data(&mut user);
+
+        let router_data = user.router_data.unwrap();
+        assert_eq!(router_data.len(), 2);
+        assert!(router_data.values().all(|value| value == REDACTED));
+    }
+}
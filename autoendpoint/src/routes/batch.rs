@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+
+use actix_web::web::{Data, Json};
+use actix_web::{HttpRequest, HttpResponse};
+use autopush_common::util::{b64_decode_url, b64_encode_url};
+use cadence::Counted;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ApiError, ApiErrorKind, ApiResult};
+use crate::extractors::notification::Notification;
+use crate::extractors::notification_headers::NotificationHeaders;
+use crate::extractors::routers::Routers;
+use crate::extractors::subscription::Subscription;
+use crate::extractors::token_info::TokenInfo;
+use crate::headers::util::get_owned_header;
+use crate::routers::RouterResponse;
+use crate::routes::webpush::send_notification;
+use crate::server::AppState;
+
+/// A single notification in a batch request
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchItem {
+    /// The token from the subscription endpoint (the last path segment)
+    pub token: String,
+    /// The API version from the subscription endpoint, `v1` or `v2`
+    #[serde(default = "default_api_version")]
+    pub api_version: String,
+    /// The notification headers (TTL, Topic, Content-Encoding, etc.)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// The encrypted notification body, base64url encoded
+    #[serde(default)]
+    pub data: Option<String>,
+}
+
+fn default_api_version() -> String {
+    "v1".to_string()
+}
+
+/// The outcome of routing a single notification in a batch request
+#[derive(Debug, Default, Eq, PartialEq, Serialize)]
+pub struct BatchItemResult {
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// A `Link` header pointing at a reissued endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errno: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl From<RouterResponse> for BatchItemResult {
+    fn from(response: RouterResponse) -> Self {
+        BatchItemResult {
+            status: response.status.as_u16(),
+            location: response.headers.get("Location").cloned(),
+            link: response.headers.get("Link").cloned(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ApiError> for BatchItemResult {
+    fn from(error: ApiError) -> Self {
+        BatchItemResult {
+            status: error.kind.status().as_u16(),
+            errno: error.kind.errno(),
+            message: Some(error.kind.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Handle the `POST /wpush/batch` route.
+///
+/// The request's `Authorization` (VAPID) header authenticates every item in
+/// the batch. Each item is validated and routed as if it were sent to its own
+/// endpoint, and the response lists the outcome of each item in order.
+pub async fn webpush_batch_route(
+    req: HttpRequest,
+    items: Json<Vec<BatchItem>>,
+    routers: Routers,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    let items = items.into_inner();
+    let settings = &app_state.settings;
+
+    let auth_header =
+        get_owned_header(&req, "authorization").ok_or(ApiErrorKind::InvalidAuthentication)?;
+    if items.len() > settings.batch_max_items {
+        return Err(ApiErrorKind::InvalidBatch(format!(
+            "A batch may contain at most {} notifications",
+            settings.batch_max_items
+        ))
+        .into());
+    }
+
+    let crypto_key_header = get_owned_header(&req, "crypto-key");
+    let results: Vec<BatchItemResult> = stream::iter(items)
+        .map(|item| {
+            let auth_header = auth_header.clone();
+            let crypto_key_header = crypto_key_header.clone();
+            let routers = &routers;
+            let app_state = &app_state;
+            async move {
+                route_batch_item(item, auth_header, crypto_key_header, routers, app_state)
+                    .await
+                    .map_or_else(BatchItemResult::from, BatchItemResult::from)
+            }
+        })
+        .buffered(settings.batch_concurrency.max(1))
+        .collect()
+        .await;
+
+    let failed = results.iter().filter(|r| r.status >= 400).count();
+    app_state
+        .metrics
+        .count_with_tags("notification.batch.items", results.len() as i64)
+        .with_tag("result", "total")
+        .send();
+    app_state
+        .metrics
+        .count_with_tags("notification.batch.items", failed as i64)
+        .with_tag("result", "failed")
+        .send();
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Validate and route one notification from a batch
+async fn route_batch_item(
+    item: BatchItem,
+    auth_header: String,
+    crypto_key_header: Option<String>,
+    routers: &Routers,
+    app_state: &Data<AppState>,
+) -> ApiResult<RouterResponse> {
+    // Header names are case-insensitive
+    let headers: HashMap<String, String> = item
+        .headers
+        .into_iter()
+        .map(|(name, value)| (name.to_lowercase(), value))
+        .collect();
+
+    let token_info = TokenInfo {
+        api_version: item.api_version.parse()?,
+        token: item.token,
+        crypto_key_header: headers.get("crypto-key").cloned().or(crypto_key_header),
+        auth_header: Some(auth_header),
+    };
+    let subscription = Subscription::from_token_info(&token_info, app_state).await?;
+
+    // Normalize the data to unpadded base64url, like the single notification route
+    let data = match item.data.as_deref() {
+        None | Some("") => None,
+        Some(data) => {
+            let data = b64_decode_url(data).map_err(|_| {
+                ApiErrorKind::InvalidBatch("Notification data must be base64url encoded".into())
+            })?;
+            if data.len() > app_state.settings.max_data_bytes {
+                return Err(ApiErrorKind::InvalidBatch(format!(
+                    "Notification data must be at most {} bytes",
+                    app_state.settings.max_data_bytes
+                ))
+                .into());
+            }
+            Some(b64_encode_url(&data))
+        }
+    };
+
+    let headers =
+        NotificationHeaders::from_headers(|name| headers.get(name).cloned(), data.is_some())?;
+    let notification = Notification::new(subscription, headers, data, app_state);
+
+    send_notification(&notification, routers, app_state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{webpush_batch_route, BatchItem, BatchItemResult};
+    use crate::error::{ApiError, ApiErrorKind, ApiResult};
+    use crate::extractors::notification::Notification;
+    use crate::extractors::router_data_input::RouterDataInput;
+    use crate::rate_limit::RateLimiter;
+    use crate::routers::registry::RouterRegistry;
+    use crate::routers::reload::ReloadStatus;
+    use crate::routers::{Router, RouterError, RouterResponse};
+    use crate::server::AppState;
+    use crate::settings::Settings;
+    use actix_web::web::{self, Data};
+    use actix_web::{test, App};
+    use async_trait::async_trait;
+    use autopush_common::db::mock::MockDbClient;
+    use autopush_common::db::User;
+    use autopush_common::metrics::Metrics;
+    use autopush_common::util::{b64_decode_std, b64_encode_url, sec_since_epoch};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    const PRIVATE_KEY: &str = "MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQgZImOgpRszunnU3j1\
+                               oX5UQiX8KU4X2OdbENuvc/t8wpmhRANCAATN21Y1v8LmQueGpSG6o022gTbbYa4l\
+                               bXWZXITsjknW1WHmELtouYpyXX7e41FiAMuDvcRwW2Nfehn/taHW/IXb";
+    const PUBLIC_KEY: &str =
+        "BM3bVjW_wuZC54alIbqjTbaBNtthriVtdZlchOyOSdbVYeYQu2i5inJdft7jUWIAy4O9xHBbY196Gf-1odb8hds";
+
+    /// Accepts notifications, except those with a `gone` topic, which the
+    /// bridge reports as unknown
+    struct TestRouter;
+
+    #[async_trait(?Send)]
+    impl Router for TestRouter {
+        fn register(
+            &self,
+            _router_input: &RouterDataInput,
+            _app_id: &str,
+        ) -> Result<HashMap<String, Value>, RouterError> {
+            Ok(HashMap::new())
+        }
+
+        async fn route_notification(
+            &self,
+            notification: &Notification,
+        ) -> ApiResult<RouterResponse> {
+            if notification.headers.topic.as_deref() == Some("gone") {
+                return Err(RouterError::NotFound.into());
+            }
+            Ok(RouterResponse::success(
+                format!("http://localhost/m/{}", notification.message_id),
+                notification.headers.ttl as usize,
+            ))
+        }
+    }
+
+    /// Server state with a single user, routed by `TestRouter`
+    fn app_state(settings: Settings, user: User) -> AppState {
+        let mut db = MockDbClient::new();
+        db.expect_get_user()
+            .returning(move |uaid| Ok((uaid == &user.uaid).then(|| user.clone())));
+        let mut routers = RouterRegistry::default();
+        routers.register("test", Arc::new(TestRouter));
+
+        AppState {
+            metrics: Arc::new(Metrics::sink()),
+            fernet: settings.make_fernet(),
+            fernet_keys: settings.fernet_keys(),
+            db: db.into_boxed_arc(),
+            http: reqwest::Client::new(),
+            routers: Arc::new(routers),
+            rate_limiter: Arc::new(RateLimiter::new(settings.rate_limit.clone()).unwrap()),
+            credential_reloads: Arc::new(ReloadStatus::default()),
+            settings,
+        }
+    }
+
+    /// A v1 endpoint token for the subscription
+    fn token(app_state: &AppState, uaid: &Uuid, channel_id: &Uuid) -> String {
+        let token = app_state
+            .fernet
+            .encrypt(&[uaid.as_bytes().as_slice(), channel_id.as_bytes().as_slice()].concat());
+        token.trim_end_matches('=').to_owned()
+    }
+
+    /// A VAPID `Authorization` header for the server
+    fn vapid_header(settings: &Settings) -> String {
+        let claims = json!({
+            "aud": settings.endpoint_url().as_str(),
+            "exp": sec_since_epoch() + 60 * 60,
+            "sub": "mailto:admin@example.com",
+        });
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_ec_der(&b64_decode_std(PRIVATE_KEY).unwrap()),
+        )
+        .unwrap();
+        format!("vapid t={token},k={PUBLIC_KEY}")
+    }
+
+    async fn send_batch(app_state: AppState, items: Value) -> (u16, Value) {
+        let auth_header = vapid_header(&app_state.settings);
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(app_state))
+                .service(web::resource("/wpush/batch").route(web::post().to(webpush_batch_route))),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/wpush/batch")
+            .insert_header(("Authorization", auth_header))
+            .set_json(items)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status().as_u16();
+        (status, test::read_body_json(resp).await)
+    }
+
+    /// Each item is routed independently, and the response lists the outcome
+    /// of each item in order
+    #[actix_rt::test]
+    async fn mixed_results() {
+        let user = User {
+            router_type: "test".to_owned(),
+            ..Default::default()
+        };
+        let uaid = user.uaid;
+        let app_state = app_state(Settings::default(), user);
+        let valid = token(&app_state, &uaid, &Uuid::new_v4());
+        let unknown_user = token(&app_state, &Uuid::new_v4(), &Uuid::new_v4());
+        let too_large = b64_encode_url(&vec![0; app_state.settings.max_data_bytes + 1]);
+
+        let (status, results) = send_batch(
+            app_state,
+            json!([
+                {"token": valid, "headers": {"TTL": "60"}},
+                {"token": valid, "headers": {"ttl": "60", "topic": "gone"}},
+                {"token": "not-a-token", "headers": {"ttl": "60"}},
+                {"token": unknown_user, "headers": {"ttl": "60"}},
+                {"token": valid, "headers": {"ttl": "60"}, "data": too_large},
+            ]),
+        )
+        .await;
+
+        assert_eq!(status, 200);
+        let statuses: Vec<_> = results
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, vec![200, 410, 404, 410, 400]);
+        assert!(results[0]["location"]
+            .as_str()
+            .unwrap()
+            .starts_with("http://localhost/m/"));
+        assert_eq!(results[1]["errno"], 106);
+        assert!(results[1].get("location").is_none());
+    }
+
+    /// A batch with too many items is rejected as a whole
+    #[actix_rt::test]
+    async fn too_many_items() {
+        let user = User {
+            router_type: "test".to_owned(),
+            ..Default::default()
+        };
+        let uaid = user.uaid;
+        let app_state = app_state(
+            Settings {
+                batch_max_items: 2,
+                ..Default::default()
+            },
+            user,
+        );
+        let valid = token(&app_state, &uaid, &Uuid::new_v4());
+        let item = json!({"token": valid, "headers": {"ttl": "60"}});
+
+        let (status, body) = send_batch(app_state, json!([item, item, item])).await;
+        assert_eq!(status, 400);
+        assert!(body["message"].as_str().unwrap().contains("at most 2"));
+    }
+
+    /// Items default to API v1 with no headers or data
+    #[test]
+    fn item_defaults() {
+        let item: BatchItem = serde_json::from_str(r#"{"token": "abc"}"#).unwrap();
+        assert_eq!(item.token, "abc");
+        assert_eq!(item.api_version, "v1");
+        assert!(item.headers.is_empty());
+        assert!(item.data.is_none());
+    }
+
+    /// Results carry the location on success and the errno on failure
+    #[test]
+    fn item_results() {
+        let success = BatchItemResult::from(RouterResponse::success(
+            "http://localhost/m/123".to_string(),
+            60,
+        ));
+        assert_eq!(
+            serde_json::to_value(success).unwrap(),
+            serde_json::json!({"status": 200, "location": "http://localhost/m/123"})
+        );
+
+        let failure = BatchItemResult::from(ApiError::from(ApiErrorKind::NoSubscription));
+        assert_eq!(
+            serde_json::to_value(failure).unwrap(),
+            serde_json::json!({"status": 410, "errno": 106, "message": "No such subscription"})
+        );
+    }
+}
@@ -1,50 +1,67 @@
 //! Health and Dockerflow routes
+use std::future::Future;
 use std::thread;
+use std::time::Duration;
 
 use actix_web::{
     web::{Data, Json},
     HttpResponse,
 };
+use futures::future::join_all;
+use futures::join;
 use reqwest::StatusCode;
 use serde_json::json;
 
 use autopush_common::db::error::DbResult;
+use autopush_common::health::{probe, ComponentHealth, HealthReport};
 
 use crate::error::{ApiErrorKind, ApiResult};
 use crate::server::AppState;
 
-/// Handle the `/health` and `/__heartbeat__` routes
-pub async fn health_route(state: Data<AppState>) -> Json<serde_json::Value> {
-    let router_health = interpret_table_health(state.db.router_table_exists().await);
-    let message_health = interpret_table_health(state.db.message_table_exists().await);
-
-    Json(json!({
-        "status": "OK",
-        "version": env!("CARGO_PKG_VERSION"),
-        "router_table": router_health,
-        "message_table": message_health,
-        "routers": {
-            "adm": state.adm_router.active(),
-            "apns": state.apns_router.active(),
-            "fcm": state.fcm_router.active(),
+/// Handle the `/health` and `/__heartbeat__` routes. The database must be
+/// usable, but the endpoint can run without its bridges, so their failures are
+/// only warnings.
+pub async fn health_route(state: Data<AppState>) -> HttpResponse {
+    let timeout = Duration::from_millis(state.settings.health_check_timeout_millis);
+    let database = probe(timeout, async {
+        match state.db.health_check().await {
+            Ok(()) => ComponentHealth::ok(),
+            Err(e) => ComponentHealth::error(e.to_string()),
         }
-    }))
+    });
+    let message_table = probe(
+        timeout,
+        interpret_table_health(state.db.message_table_exists()),
+    );
+    let routers = join_all(state.routers.iter().map(|(name, router)| async move {
+        let health = if router.active() {
+            probe(timeout, router.health()).await.degraded()
+        } else {
+            ComponentHealth::ok().with_detail("Not configured")
+        };
+        (format!("router.{name}"), health)
+    }));
+    let (database, message_table, routers) = join!(database, message_table, routers);
+
+    let mut report = HealthReport::default();
+    report.add("database", database);
+    report.add("message_table", message_table);
+    for (name, health) in routers {
+        report.add(name, health);
+    }
+    for (router, health) in state.credential_reloads.health() {
+        report.add(format!("credentials.{router}"), health);
+    }
+
+    HttpResponse::build(report.http_status()).json(report.to_json(env!("CARGO_PKG_VERSION")))
 }
 
-/// Convert the result of a DB health check to JSON
-fn interpret_table_health(health: DbResult<bool>) -> serde_json::Value {
-    match health {
-        Ok(true) => json!({
-            "status": "OK"
-        }),
-        Ok(false) => json!({
-            "status": "NOT OK",
-            "cause": "Nonexistent table"
-        }),
-        Err(e) => json!({
-            "status": "NOT OK",
-            "cause": e.to_string()
-        }),
+/// Convert the result of a table existence check to a component's health
+async fn interpret_table_health(exists: impl Future<Output = DbResult<bool>>) -> ComponentHealth {
+    match exists.await {
+        Ok(true) => ComponentHealth::ok(),
+        Ok(false) => ComponentHealth::error("Nonexistent table"),
+        Err(e) => ComponentHealth::error(e.to_string()),
     }
 }
 
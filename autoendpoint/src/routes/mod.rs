@@ -1,3 +1,5 @@
+pub mod admin;
+pub mod batch;
 pub mod health;
 pub mod registration;
 pub mod webpush;
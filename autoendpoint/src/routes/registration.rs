@@ -1,11 +1,15 @@
+use std::collections::HashSet;
+
 use actix_web::web::{Data, Json};
 use actix_web::{HttpRequest, HttpResponse};
 use cadence::{CountedExt, StatsdClient};
 use uuid::Uuid;
 
+use crate::auth::SecretScope;
 use crate::error::{ApiErrorKind, ApiResult};
 use crate::extractors::{
-    authorization_check::AuthorizationCheck, new_channel_data::NewChannelData,
+    authorization_check::AuthorizationCheck, channel_list_data::ChannelListData,
+    new_channel_data::NewChannelData, new_secret_data::NewSecretData,
     registration_path_args::RegistrationPathArgs,
     registration_path_args_with_uaid::RegistrationPathArgsWithUaid,
     router_data_input::RouterDataInput, routers::Routers,
@@ -13,8 +17,9 @@ use crate::extractors::{
 use crate::headers::util::get_header;
 use crate::server::AppState;
 
-use autopush_common::db::User;
-use autopush_common::endpoint::make_endpoint;
+use autopush_common::db::{channels_json, ChannelMeta, User};
+use autopush_common::endpoint::{key_digest, make_endpoint_with_expiry};
+use autopush_common::util::{b64_encode_url, ms_since_epoch};
 
 /// Handle the `POST /v1/{router_type}/{app_id}/registration` route
 pub async fn register_uaid_route(
@@ -30,30 +35,37 @@ pub async fn register_uaid_route(
         path_args.router_type
     );
     trace!("token = {}", router_data_input.token);
-    let router = routers.get(path_args.router_type);
+    let router = routers.get(&path_args.router_type)?;
+    let router_type = router.registration_router_type(&path_args.router_type)?;
     let router_data = router.register(&router_data_input, &path_args.app_id)?;
     incr_metric("ua.command.register", &app_state.metrics, &request);
 
     // Register user and channel in database
     let user = User {
-        router_type: path_args.router_type.to_string(),
+        router_type,
         router_data: Some(router_data),
         current_month: Some(app_state.db.message_table().to_string()),
         ..Default::default()
     };
     let channel_id = router_data_input.channel_id.unwrap_or_else(Uuid::new_v4);
+    let channel_meta = new_channel_meta(router_data_input.key.as_deref())?;
     trace!("Creating user with UAID {}", user.uaid);
     trace!("user = {:?}", user);
     trace!("channel_id = {}", channel_id);
     app_state.db.add_user(&user).await?;
-    app_state.db.add_channel(&user.uaid, &channel_id).await?;
+    app_state
+        .db
+        .add_channel_with_meta(&user.uaid, &channel_id, &channel_meta)
+        .await?;
 
     // Make the endpoint URL
     trace!("Creating endpoint for user");
-    let endpoint_url = make_endpoint(
+    let expiry = app_state.settings.subscription_expiry();
+    let endpoint_url = make_endpoint_with_expiry(
         &user.uaid,
         &channel_id,
         router_data_input.key.as_deref(),
+        expiry,
         app_state.settings.endpoint_url().as_str(),
         &app_state.fernet,
     )
@@ -62,28 +74,25 @@ pub async fn register_uaid_route(
 
     // Create the secret
     trace!("Creating secret for UAID {}", user.uaid);
-    let auth_keys = app_state.settings.auth_keys();
-    let auth_key = auth_keys
-        .get(0)
-        .expect("At least one auth key must be provided in the settings");
-    let secret = AuthorizationCheck::generate_token(auth_key, &user.uaid)
-        .map_err(ApiErrorKind::RegistrationSecretHash)?;
+    let secret = AuthorizationCheck::generate_secret(&app_state.settings, &user.uaid)?;
 
     trace!("Finished registering UAID {}", user.uaid);
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "uaid": user.uaid,
         "channelID": channel_id,
         "endpoint": endpoint_url,
-        "secret": secret
+        "secret": secret,
+        "expirationTime": expiry.map(|expiry| expiry * 1000),
     })))
 }
 
 /// Handle the `DELETE /v1/{router_type}/{app_id}/registration/{uaid}` route
 pub async fn unregister_user_route(
-    _auth: AuthorizationCheck,
+    auth: AuthorizationCheck,
     path_args: RegistrationPathArgsWithUaid,
     app_state: Data<AppState>,
 ) -> ApiResult<HttpResponse> {
+    auth.require(SecretScope::Unregister)?;
     debug!("Unregistering UAID {}", path_args.uaid);
     app_state.db.remove_user(&path_args.uaid).await?;
     Ok(HttpResponse::Ok().finish())
@@ -91,31 +100,44 @@ pub async fn unregister_user_route(
 
 /// Handle the `PUT /v1/{router_type}/{app_id}/registration/{uaid}` route
 pub async fn update_token_route(
-    _auth: AuthorizationCheck,
+    auth: AuthorizationCheck,
     path_args: RegistrationPathArgsWithUaid,
     router_data_input: RouterDataInput,
     routers: Routers,
     app_state: Data<AppState>,
 ) -> ApiResult<HttpResponse> {
+    auth.require(SecretScope::UpdateToken)?;
     // Re-register with router
     debug!(
         "Updating the token of UAID {} with the {} router",
         path_args.uaid, path_args.router_type
     );
     trace!("token = {}", router_data_input.token);
-    let router = routers.get(path_args.router_type);
+    let router = routers.get(&path_args.router_type)?;
+    let router_type = router.registration_router_type(&path_args.router_type)?;
     let router_data = router.register(&router_data_input, &path_args.app_id)?;
 
     // Update the user in the database
     let user = User {
         uaid: path_args.uaid,
-        router_type: path_args.router_type.to_string(),
+        router_type,
         router_data: Some(router_data),
         ..Default::default()
     };
     trace!("Updating user with UAID {}", user.uaid);
     trace!("user = {:?}", user);
-    app_state.db.update_user(&user).await?;
+    if user.router_type == path_args.router_type {
+        app_state.db.update_user(&user).await?;
+    } else {
+        debug!(
+            "Moving UAID {} from the {} router to the {} router",
+            user.uaid, path_args.router_type, user.router_type
+        );
+        app_state
+            .db
+            .migrate_user_router(&user, &path_args.router_type)
+            .await?;
+    }
 
     trace!("Finished updating token for UAID {}", user.uaid);
     Ok(HttpResponse::Ok().finish())
@@ -123,27 +145,31 @@ pub async fn update_token_route(
 
 /// Handle the `POST /v1/{router_type}/{app_id}/registration/{uaid}/subscription` route
 pub async fn new_channel_route(
-    _auth: AuthorizationCheck,
+    auth: AuthorizationCheck,
     path_args: RegistrationPathArgsWithUaid,
     channel_data: Option<Json<NewChannelData>>,
     app_state: Data<AppState>,
 ) -> ApiResult<HttpResponse> {
+    auth.require(SecretScope::Subscribe)?;
     // Add the channel
     debug!("Adding a channel to UAID {}", path_args.uaid);
     let channel_data = channel_data.map(Json::into_inner).unwrap_or_default();
     let channel_id = channel_data.channel_id.unwrap_or_else(Uuid::new_v4);
+    let channel_meta = new_channel_meta(channel_data.key.as_deref())?;
     trace!("channel_id = {}", channel_id);
     app_state
         .db
-        .add_channel(&path_args.uaid, &channel_id)
+        .add_channel_with_meta(&path_args.uaid, &channel_id, &channel_meta)
         .await?;
 
     // Make the endpoint URL
     trace!("Creating endpoint for the new channel");
-    let endpoint_url = make_endpoint(
+    let expiry = app_state.settings.subscription_expiry();
+    let endpoint_url = make_endpoint_with_expiry(
         &path_args.uaid,
         &channel_id,
         channel_data.key.as_deref(),
+        expiry,
         app_state.settings.endpoint_url().as_str(),
         &app_state.fernet,
     )
@@ -153,31 +179,129 @@ pub async fn new_channel_route(
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "channelID": channel_id,
         "endpoint": endpoint_url,
+        "expirationTime": expiry.map(|expiry| expiry * 1000),
     })))
 }
 
 /// Handle the `GET /v1/{router_type}/{app_id}/registration/{uaid}` route
 pub async fn get_channels_route(
-    _auth: AuthorizationCheck,
+    auth: AuthorizationCheck,
     path_args: RegistrationPathArgsWithUaid,
     app_state: Data<AppState>,
 ) -> ApiResult<HttpResponse> {
+    auth.require(SecretScope::Read)?;
     debug!("Getting channel IDs for UAID {}", path_args.uaid);
-    let channel_ids = app_state.db.get_channels(&path_args.uaid).await?;
+    let channels = app_state.db.get_channels_with_meta(&path_args.uaid).await?;
+    let mut channel_ids: Vec<Uuid> = channels.keys().copied().collect();
+    channel_ids.sort();
+    let channels = channels_json(channels);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "uaid": path_args.uaid,
+        "channelIDs": channel_ids,
+        "channels": channels,
+    })))
+}
+
+/// Handle the `PATCH /v1/{router_type}/{app_id}/registration/{uaid}/subscription`
+/// route, which replaces the user's channels. Endpoints are returned for the
+/// added channels.
+pub async fn replace_channels_route(
+    auth: AuthorizationCheck,
+    path_args: RegistrationPathArgsWithUaid,
+    channel_list: Json<ChannelListData>,
+    app_state: Data<AppState>,
+    request: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    auth.require(SecretScope::Subscribe)?;
+    auth.require(SecretScope::Unsubscribe)?;
+    let channel_ids = channel_list.into_inner().channel_ids;
+
+    debug!(
+        "Replacing the channels of UAID {} with {} channels",
+        path_args.uaid,
+        channel_ids.len()
+    );
+    incr_metric("ua.command.replace_channels", &app_state.metrics, &request);
+    let old_channel_ids = app_state.db.get_channels(&path_args.uaid).await?;
+    app_state
+        .db
+        .save_channels(
+            &path_args.uaid,
+            channel_ids.iter().collect(),
+            app_state.db.message_table(),
+        )
+        .await?;
+
+    // Make the endpoint URLs for the added channels
+    let expiry = app_state.settings.subscription_expiry();
+    let added = channel_ids
+        .difference(&old_channel_ids)
+        .map(|channel_id| {
+            let endpoint_url = make_endpoint_with_expiry(
+                &path_args.uaid,
+                channel_id,
+                None,
+                expiry,
+                app_state.settings.endpoint_url().as_str(),
+                &app_state.fernet,
+            )
+            .map_err(ApiErrorKind::EndpointUrl)?;
+            Ok(serde_json::json!({
+                "channelID": channel_id,
+                "endpoint": endpoint_url,
+                "expirationTime": expiry.map(|expiry| expiry * 1000),
+            }))
+        })
+        .collect::<ApiResult<Vec<_>>>()?;
+    let removed: Vec<&Uuid> = old_channel_ids.difference(&channel_ids).collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "uaid": path_args.uaid,
+        "channelIDs": channel_ids,
+        "added": added,
+        "removed": removed,
+    })))
+}
+
+/// Handle the `POST /v1/{router_type}/{app_id}/registration/{uaid}/subscription/delete`
+/// route, which removes several channels at once. Channels which don't exist
+/// are ignored.
+pub async fn unregister_channels_route(
+    auth: AuthorizationCheck,
+    path_args: RegistrationPathArgsWithUaid,
+    channel_list: Json<ChannelListData>,
+    app_state: Data<AppState>,
+    request: HttpRequest,
+) -> ApiResult<HttpResponse> {
+    auth.require(SecretScope::Unsubscribe)?;
+    let channel_ids = channel_list.into_inner().channel_ids;
+
+    debug!(
+        "Unregistering {} channels for UAID {}",
+        channel_ids.len(),
+        path_args.uaid
+    );
+    incr_metric("ua.command.unregister_bulk", &app_state.metrics, &request);
+    let removed: HashSet<Uuid> = app_state
+        .db
+        .remove_channels(&path_args.uaid, &channel_ids)
+        .await?;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "uaid": path_args.uaid,
-        "channelIDs": channel_ids
+        "removed": removed,
     })))
 }
 
 /// Handle the `DELETE /v1/{router_type}/{app_id}/registration/{uaid}/subscription/{chid}` route
 pub async fn unregister_channel_route(
-    _auth: AuthorizationCheck,
+    auth: AuthorizationCheck,
     path_args: RegistrationPathArgsWithUaid,
     app_state: Data<AppState>,
     request: HttpRequest,
 ) -> ApiResult<HttpResponse> {
+    auth.require(SecretScope::Unsubscribe)?;
     let channel_id = request
         .match_info()
         .get("chid")
@@ -204,6 +328,71 @@ pub async fn unregister_channel_route(
     }
 }
 
+/// Handle the `POST /v1/{router_type}/{app_id}/registration/{uaid}/secret` route
+pub async fn new_secret_route(
+    auth: AuthorizationCheck,
+    path_args: RegistrationPathArgsWithUaid,
+    secret_data: Option<Json<NewSecretData>>,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    auth.require(SecretScope::Secrets)?;
+    let secret_data = secret_data.map(Json::into_inner).unwrap_or_default();
+    let scope = secret_data.scope.unwrap_or_else(|| auth.scope().to_vec());
+    for operation in &scope {
+        auth.require(*operation)?;
+    }
+
+    debug!("Issuing a scoped secret for UAID {}", path_args.uaid);
+    trace!("scope = {:?}", scope);
+    let secret = auth.generate_delegated_secret(
+        &app_state.settings,
+        &path_args.uaid,
+        scope,
+        secret_data.ttl,
+    )?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "uaid": path_args.uaid,
+        "secret": secret,
+    })))
+}
+
+/// Handle the `DELETE /v1/{router_type}/{app_id}/registration/{uaid}/secret`
+/// route. Every secret issued for the UAID (including the caller's) is
+/// revoked, and a new scoped secret allowing every operation is returned.
+/// A legacy secret would be revoked too, so one is never returned here.
+pub async fn revoke_secrets_route(
+    auth: AuthorizationCheck,
+    path_args: RegistrationPathArgsWithUaid,
+    app_state: Data<AppState>,
+) -> ApiResult<HttpResponse> {
+    auth.require(SecretScope::Secrets)?;
+    let mut user = app_state
+        .db
+        .get_user(&path_args.uaid)
+        .await?
+        .ok_or(ApiErrorKind::NoUser)?;
+
+    debug!("Revoking the secrets of UAID {}", user.uaid);
+    user.secrets_not_before = Some(ms_since_epoch());
+    app_state.db.update_user(&user).await?;
+    let secret = AuthorizationCheck::generate_full_scoped_secret(&app_state.settings, &user.uaid)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "uaid": user.uaid,
+        "secret": secret,
+    })))
+}
+
+/// Make the metadata for a new channel, given its VAPID public key (if any)
+fn new_channel_meta(key: Option<&str>) -> ApiResult<ChannelMeta> {
+    let key_hash = key
+        .map(|key| key_digest(key).map(|digest| b64_encode_url(&digest)))
+        .transpose()
+        .map_err(ApiErrorKind::EndpointUrl)?;
+    Ok(ChannelMeta::new(key_hash))
+}
+
 /// Increment a metric with data from the request
 fn incr_metric(name: &str, metrics: &StatsdClient, request: &HttpRequest) {
     metrics
@@ -1,18 +1,20 @@
-use std::str::FromStr;
-
-use crate::error::{ApiErrorKind, ApiResult};
+use crate::error::ApiResult;
 use crate::extractors::message_id::MessageId;
 use crate::extractors::notification::Notification;
-use crate::extractors::routers::{RouterType, Routers};
+use crate::extractors::routers::Routers;
+use crate::routers::{Router, RouterResponse};
 use crate::server::AppState;
+use actix_web::http::StatusCode;
 use actix_web::web::Data;
 use actix_web::HttpResponse;
+use autopush_common::audit::AuditEvent;
+use tracing::{field::Empty, Instrument};
 
 /// Handle the `POST /wpush/{api_version}/{token}` and `POST /wpush/{token}` routes
 pub async fn webpush_route(
     notification: Notification,
     routers: Routers,
-    _app_state: Data<AppState>,
+    app_state: Data<AppState>,
 ) -> ApiResult<HttpResponse> {
     // TODO:
     sentry::configure_scope(|scope| {
@@ -21,12 +23,105 @@ pub async fn webpush_route(
             notification.subscription.user.uaid.to_string().into(),
         );
     });
-    let router = routers.get(
-        RouterType::from_str(&notification.subscription.user.router_type)
-            .map_err(|_| ApiErrorKind::InvalidRouterType)?,
+    Ok(send_notification(&notification, &routers, &app_state)
+        .await?
+        .into())
+}
+
+/// Route a validated notification through the user's router, recording the
+/// delivery and adding any reissued endpoint to the response. This is shared
+/// by the single notification and batch routes.
+pub async fn send_notification(
+    notification: &Notification,
+    routers: &Routers,
+    app_state: &AppState,
+) -> ApiResult<RouterResponse> {
+    let router = routers.get(&notification.subscription.user.router_type)?;
+
+    let mut response = route_notification(router, notification).await?;
+    record_delivery(notification, &response, app_state).await;
+    response.add_reissued_endpoint(notification.subscription.reissued_endpoint.as_deref());
+    Ok(response)
+}
+
+/// Route the notification within a span, recording it in the audit log. The
+/// WebPush router records where it routed the notification itself.
+async fn route_notification(
+    router: &dyn Router,
+    notification: &Notification,
+) -> ApiResult<RouterResponse> {
+    let router_type = notification.subscription.user.router_type.as_str();
+    notification
+        .audit(AuditEvent::Received)
+        .vapid_sub(notification.subscription.vapid_sub.as_deref())
+        // The data is base64 encoded
+        .size(
+            notification
+                .data
+                .as_ref()
+                .map_or(0, |data| data.len() as u64 * 3 / 4),
+        )
+        .ttl(notification.headers.ttl as u64)
+        .topic(notification.headers.topic.as_deref())
+        .log();
+
+    let span = tracing::info_span!(
+        "router.route_notification",
+        router.type = router_type,
+        http.status_code = Empty,
+        otel.status_code = Empty,
+        otel.status_message = Empty,
     );
+    let result = router
+        .route_notification(notification)
+        .instrument(span.clone())
+        .await;
+    match &result {
+        Ok(response) => {
+            span.record("http.status_code", response.status.as_u16());
+            // Accepted bridge notifications are being retried or were stored
+            // as dead letters, which the bridge's retry queue audits
+            if router_type != "webpush" && response.status != StatusCode::ACCEPTED {
+                notification
+                    .audit(AuditEvent::Routed)
+                    .destination(router_type)
+                    .log();
+            }
+        }
+        Err(e) => {
+            span.record("otel.status_code", "ERROR");
+            span.record("otel.status_message", e.kind.to_string().as_str());
+            notification
+                .audit(AuditEvent::Dropped)
+                .reason(e.kind.metric_label().unwrap_or("error"))
+                .log();
+        }
+    }
+    result
+}
+
+/// Record a successful delivery to a bridged channel, if enabled by the
+/// `record_channel_deliveries` setting. Failing to record it doesn't fail the
+/// notification.
+async fn record_delivery(
+    notification: &Notification,
+    response: &RouterResponse,
+    app_state: &AppState,
+) {
+    let user = &notification.subscription.user;
+    // Dead lettered notifications are accepted, but not delivered
+    if !app_state.settings.record_channel_deliveries
+        || user.router_type == "webpush"
+        || !response.status.is_success()
+        || response.status == StatusCode::ACCEPTED
+    {
+        return;
+    }
 
-    Ok(router.route_notification(&notification).await?.into())
+    let channel_id = notification.subscription.channel_id;
+    if let Err(e) = app_state.db.record_delivery(&user.uaid, &channel_id).await {
+        warn!("Could not record the delivery to {}: {}", channel_id, e);
+    }
 }
 
 /// Handle the `DELETE /m/{message_id}` route
@@ -1,5 +1,6 @@
 //! Main application server
 #![forbid(unsafe_code)]
+use std::net::TcpListener;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -8,19 +9,29 @@ use actix_web::{
     dev, http::StatusCode, middleware::ErrorHandlers, web, web::Data, App, HttpServer,
 };
 use cadence::StatsdClient;
-use fernet::MultiFernet;
+use fernet::{Fernet, MultiFernet};
 use serde_json::json;
 
 use autopush_common::db::{client::DbClient, dynamodb::DdbClientImpl, DbSettings, StorageType};
+use autopush_common::middleware::{sentry::SentryWrapper, telemetry::TelemetryWrapper};
 
 use crate::error::{ApiError, ApiErrorKind, ApiResult};
 use crate::metrics;
-use crate::routers::{adm::router::AdmRouter, apns::router::ApnsRouter, fcm::router::FcmRouter};
+use crate::rate_limit::RateLimiter;
+use crate::routers::registry::RouterRegistry;
+use crate::routers::reload::{CredentialReloader, ReloadStatus};
+use crate::routers::Router;
 use crate::routes::{
+    admin::{
+        disconnect_uaid_route, drop_uaid_route, get_uaid_route, list_dead_letters_route,
+        list_messages_route, purge_messages_route, replay_dead_letter_route,
+    },
+    batch::webpush_batch_route,
     health::{health_route, lb_heartbeat_route, log_check, status_route, version_route},
     registration::{
-        get_channels_route, new_channel_route, register_uaid_route, unregister_channel_route,
-        unregister_user_route, update_token_route,
+        get_channels_route, new_channel_route, new_secret_route, register_uaid_route,
+        replace_channels_route, revoke_secrets_route, unregister_channel_route,
+        unregister_channels_route, unregister_user_route, update_token_route,
     },
     webpush::{delete_notification_route, webpush_route},
 };
@@ -32,21 +43,84 @@ pub struct AppState {
     pub metrics: Arc<StatsdClient>,
     pub settings: Settings,
     pub fernet: MultiFernet,
+    /// The individual keys in `fernet`, used to tell which key decrypted a
+    /// token
+    pub fernet_keys: Vec<Fernet>,
     pub db: Box<dyn DbClient>,
     pub http: reqwest::Client,
-    pub fcm_router: Arc<FcmRouter>,
-    pub apns_router: Arc<ApnsRouter>,
-    pub adm_router: Arc<AdmRouter>,
+    pub routers: Arc<RouterRegistry>,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// The outcome of the last bridge credential reloads
+    pub credential_reloads: Arc<ReloadStatus>,
 }
 
 pub struct Server;
 
 impl Server {
     pub async fn with_settings(settings: Settings) -> ApiResult<dev::Server> {
-        let metrics = Arc::new(metrics::metrics_from_settings(&settings)?);
+        Self::builder(settings).start().await
+    }
+
+    /// Run the server on an already bound listener, with the given database
+    /// instead of the one in the settings. The integration tests use this to
+    /// run on an ephemeral port with an in-process database.
+    pub async fn with_listener(
+        settings: Settings,
+        listener: TcpListener,
+        db: Box<dyn DbClient>,
+    ) -> ApiResult<dev::Server> {
+        Self::builder(settings).listener(listener, db).start().await
+    }
+
+    /// Configure a server beyond its settings, e.g. with extra routers
+    pub fn builder(settings: Settings) -> ServerBuilder {
+        ServerBuilder {
+            settings,
+            listener: None,
+            routers: Vec::new(),
+        }
+    }
+}
+
+/// Builds a server from its settings, plus any options which can't be given
+/// in the settings
+pub struct ServerBuilder {
+    settings: Settings,
+    listener: Option<(TcpListener, Box<dyn DbClient>)>,
+    routers: Vec<(String, Arc<dyn Router>)>,
+}
+
+impl ServerBuilder {
+    /// Run on an already bound listener, with the given database instead of
+    /// the one in the settings
+    pub fn listener(mut self, listener: TcpListener, db: Box<dyn DbClient>) -> Self {
+        self.listener = Some((listener, db));
+        self
+    }
+
+    /// Add a router for users with the `router_type` name, alongside the
+    /// built-in routers. A built-in router registered under the same name is
+    /// replaced.
+    pub fn router(mut self, router_type: &str, router: Box<dyn Router>) -> Self {
+        self.routers
+            .push((router_type.to_owned(), Arc::from(router)));
+        self
+    }
+
+    pub async fn start(self) -> ApiResult<dev::Server> {
+        let ServerBuilder {
+            settings,
+            listener,
+            routers: extra_routers,
+        } = self;
+        let (metrics, prometheus) = metrics::metrics_from_settings(&settings)?;
+        let metrics = Arc::new(metrics);
+        if let Some(prometheus) = prometheus {
+            actix_rt::spawn(prometheus.serve((settings.host.as_str(), settings.metrics_port))?);
+        }
         let bind_address = format!("{}:{}", settings.host, settings.port);
         let fernet = settings.make_fernet();
-        let endpoint_url = settings.endpoint_url();
+        let fernet_keys = settings.fernet_keys();
         let db_settings = DbSettings {
             dsn: settings.db_dsn.clone(),
             db_settings: if settings.db_settings.is_empty() {
@@ -62,70 +136,105 @@ impl Server {
         // rely on either the environment variable `AWS_LOCAL_DYNAMODB` or fall back to the
         // rusoto_core::Region::default(), which complicates things.
         // `StorageType::from_dsn` is very preferential toward DynamoDB.
-        let db: Box<dyn DbClient> = match StorageType::from_dsn(&db_settings.dsn) {
-            StorageType::DynamoDb => Box::new(DdbClientImpl::new(metrics.clone(), &db_settings)?),
-            StorageType::INVALID => {
-                return Err(ApiErrorKind::General("Invalid DSN specified".to_owned()).into())
-            }
+        let (listener, db): (_, Box<dyn DbClient>) = match listener {
+            Some((listener, db)) => (Some(listener), db),
+            None => match StorageType::from_dsn(&db_settings.dsn) {
+                StorageType::DynamoDb => (
+                    None,
+                    Box::new(DdbClientImpl::new(metrics.clone(), &db_settings)?),
+                ),
+                StorageType::INVALID => {
+                    return Err(ApiErrorKind::General("Invalid DSN specified".to_owned()).into())
+                }
+            },
         };
         let http = reqwest::ClientBuilder::new()
             .connect_timeout(Duration::from_millis(settings.connection_timeout_millis))
             .timeout(Duration::from_millis(settings.request_timeout_millis))
             .build()
             .expect("Could not generate request client");
-        let fcm_router = Arc::new(
-            FcmRouter::new(
-                settings.fcm.clone(),
-                endpoint_url.clone(),
-                http.clone(),
-                metrics.clone(),
-                db.clone(),
-            )
-            .await?,
-        );
-        let apns_router = Arc::new(
-            ApnsRouter::new(
-                settings.apns.clone(),
-                endpoint_url.clone(),
+        let mut routers =
+            RouterRegistry::from_settings(&settings, http.clone(), metrics.clone(), db.clone())
+                .await?;
+        for (router_type, router) in extra_routers {
+            routers.register(&router_type, router);
+        }
+        let routers = Arc::new(routers);
+        let rate_limiter = Arc::new(RateLimiter::new(settings.rate_limit.clone())?);
+        let credential_reloads = Arc::new(ReloadStatus::default());
+        actix_rt::spawn(
+            CredentialReloader::new(
+                settings.clone(),
+                routers.clone(),
+                credential_reloads.clone(),
                 metrics.clone(),
-                db.clone(),
             )
-            .await?,
+            .run(),
         );
-        let adm_router = Arc::new(AdmRouter::new(
-            settings.adm.clone(),
-            endpoint_url,
-            http.clone(),
-            metrics.clone(),
-            db.clone(),
-        )?);
         let app_state = AppState {
             metrics: metrics.clone(),
             settings,
             fernet,
+            fernet_keys,
             db,
             http,
-            fcm_router,
-            apns_router,
-            adm_router,
+            routers,
+            rate_limiter,
+            credential_reloads,
         };
 
+        if !app_state.settings.admin_keys().is_empty() {
+            let admin_state = app_state.clone();
+            let admin_address = (
+                app_state.settings.admin_host.clone(),
+                app_state.settings.admin_port,
+            );
+            actix_rt::spawn(
+                HttpServer::new(move || {
+                    App::new()
+                        .app_data(Data::new(admin_state.clone()))
+                        .app_data(Data::from(admin_state.metrics.clone()))
+                        .wrap(
+                            ErrorHandlers::new()
+                                .handler(StatusCode::NOT_FOUND, ApiError::render_404),
+                        )
+                        .wrap(TelemetryWrapper)
+                        .configure(admin_routes)
+                })
+                .workers(1)
+                .bind(admin_address)?
+                .run(),
+            );
+        }
+
         let server = HttpServer::new(move || {
             App::new()
                 // Actix 4 recommends wrapping structures wtih web::Data (internally an Arc)
                 .app_data(Data::new(app_state.clone()))
+                // For the `Metrics` extractor
+                .app_data(Data::from(app_state.metrics.clone()))
                 // Extractor configuration
                 .app_data(web::PayloadConfig::new(app_state.settings.max_data_bytes))
                 .app_data(web::JsonConfig::default().limit(app_state.settings.max_data_bytes))
                 // Middleware
                 .wrap(ErrorHandlers::new().handler(StatusCode::NOT_FOUND, ApiError::render_404))
                 // Our modified Sentry wrapper which does some blocking of non-reportable errors.
-                .wrap(crate::middleware::sentry::SentryWrapper::new(
+                .wrap(SentryWrapper::<ApiError>::new(
                     metrics.clone(),
                     "api_error".to_owned(),
                 ))
                 .wrap(Cors::default())
+                // Outermost, so the request span covers the other middleware
+                .wrap(TelemetryWrapper)
                 // Endpoints
+                // Must be registered before `/wpush/{token}`, which also matches
+                .service(
+                    web::resource("/wpush/batch")
+                        .app_data(
+                            web::JsonConfig::default().limit(app_state.settings.batch_max_bytes),
+                        )
+                        .route(web::post().to(webpush_batch_route)),
+                )
                 .service(
                     web::resource(["/wpush/{api_version}/{token}", "/wpush/{token}"])
                         .route(web::post().to(webpush_route)),
@@ -146,7 +255,16 @@ impl Server {
                 )
                 .service(
                     web::resource("/v1/{router_type}/{app_id}/registration/{uaid}/subscription")
-                        .route(web::post().to(new_channel_route)),
+                        .route(web::post().to(new_channel_route))
+                        .route(web::patch().to(replace_channels_route)),
+                )
+                // Registered before `/subscription/{chid}`, which would take
+                // the path otherwise
+                .service(
+                    web::resource(
+                        "/v1/{router_type}/{app_id}/registration/{uaid}/subscription/delete",
+                    )
+                    .route(web::post().to(unregister_channels_route)),
                 )
                 .service(
                     web::resource(
@@ -154,6 +272,11 @@ impl Server {
                     )
                     .route(web::delete().to(unregister_channel_route)),
                 )
+                .service(
+                    web::resource("/v1/{router_type}/{app_id}/registration/{uaid}/secret")
+                        .route(web::post().to(new_secret_route))
+                        .route(web::delete().to(revoke_secrets_route)),
+                )
                 // Health checks
                 .service(web::resource("/status").route(web::get().to(status_route)))
                 .service(web::resource("/health").route(web::get().to(health_route)))
@@ -165,10 +288,38 @@ impl Server {
                 .service(web::resource("/__heartbeat__").route(web::get().to(health_route)))
                 .service(web::resource("/__lbheartbeat__").route(web::get().to(lb_heartbeat_route)))
                 .service(web::resource("/__version__").route(web::get().to(version_route)))
-        })
-        .bind(bind_address)?
-        .run();
+        });
+        let server = match listener {
+            Some(listener) => server.listen(listener)?,
+            None => server.bind(bind_address)?,
+        };
 
-        Ok(server)
+        Ok(server.run())
     }
 }
+
+/// The admin API, which is served on its own (private) address rather than
+/// alongside the public API
+fn admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/__admin__/dead_letters").route(web::get().to(list_dead_letters_route)),
+    )
+    .service(
+        web::resource("/__admin__/dead_letters/{id}/replay")
+            .route(web::post().to(replay_dead_letter_route)),
+    )
+    .service(
+        web::resource("/__admin__/uaid/{uaid}")
+            .route(web::get().to(get_uaid_route))
+            .route(web::delete().to(drop_uaid_route)),
+    )
+    .service(
+        web::resource("/__admin__/uaid/{uaid}/disconnect")
+            .route(web::post().to(disconnect_uaid_route)),
+    )
+    .service(
+        web::resource("/__admin__/uaid/{uaid}/messages")
+            .route(web::get().to(list_messages_route))
+            .route(web::delete().to(purge_messages_route)),
+    );
+}
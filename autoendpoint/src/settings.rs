@@ -1,13 +1,17 @@
 //! Application settings
 
+use autopush_common::metrics::MetricsBackend;
+use autopush_common::util::sec_since_epoch;
 use config::{Config, ConfigError, Environment, File};
 use fernet::{Fernet, MultiFernet};
 use serde::Deserialize;
 use url::Url;
 
+use crate::rate_limit::settings::RateLimitSettings;
 use crate::routers::adm::settings::AdmSettings;
 use crate::routers::apns::settings::ApnsSettings;
 use crate::routers::fcm::settings::FcmSettings;
+use crate::routers::webhook::settings::WebhookSettings;
 
 pub const ENV_PREFIX: &str = "autoend";
 
@@ -29,8 +33,40 @@ pub struct Settings {
     pub message_table_name: String,
 
     pub max_data_bytes: usize,
+    /// The maximum number of notifications in a single batch request
+    pub batch_max_items: usize,
+    /// The maximum size of a batch request body in bytes
+    pub batch_max_bytes: usize,
+    /// The maximum number of batch notifications routed concurrently
+    pub batch_concurrency: usize,
+    /// The maximum lifetime of a new subscription endpoint in seconds. Endpoints
+    /// never expire when this is 0.
+    pub max_subscription_lifetime: u64,
     pub crypto_keys: String,
+    /// Send a `Link` header with an endpoint re-encrypted under the primary
+    /// crypto key when a notification's endpoint uses an older key
+    pub reissue_endpoints: bool,
+    /// Record the time of the last successful delivery to each bridged
+    /// channel (reported by the registration API). This costs a database
+    /// write per notification.
+    pub record_channel_deliveries: bool,
     pub auth_keys: String,
+    /// Issue scoped registration secrets (signed claims listing the UAID, the
+    /// allowed operations and an expiry) instead of legacy HMAC secrets at
+    /// registration. Revoking secrets always issues a scoped secret.
+    pub issue_scoped_secrets: bool,
+    /// Accept legacy HMAC registration secrets, which allow every operation
+    pub accept_legacy_secrets: bool,
+    /// The lifetime of a scoped registration secret in seconds. Secrets never
+    /// expire when this is 0.
+    pub scoped_secret_ttl: u64,
+    /// A JSON list of bearer tokens which may use the admin API. The admin
+    /// API is disabled when this is empty.
+    pub admin_keys: String,
+    /// The address to serve the admin API on, separately from the public
+    /// API. This shouldn't be exposed publicly.
+    pub admin_host: String,
+    pub admin_port: u16,
     pub human_logs: bool,
 
     pub connection_timeout_millis: u64,
@@ -39,10 +75,39 @@ pub struct Settings {
     pub statsd_host: Option<String>,
     pub statsd_port: u16,
     pub statsd_label: String,
+    /// Where to send metrics: `statsd`, `prometheus` (served on
+    /// `/__metrics__` at `metrics_port`) or `both`
+    pub metrics_backend: MetricsBackend,
+    /// The port to serve Prometheus metrics on. This shouldn't be exposed
+    /// publicly.
+    pub metrics_port: u16,
+
+    /// Where to export trace spans (as JSON): `stdout`, `stderr`, or the path
+    /// of a file. Tracing is disabled when this is empty.
+    pub tracing_exporter: String,
+    /// The fraction of new traces to record (0.0 to 1.0). Requests with a
+    /// `traceparent` header follow the caller's decision.
+    pub tracing_sample_rate: f64,
+    /// The fraction of notifications (0.0 to 1.0) recorded in the audit log,
+    /// as they're received and routed. Auditing is disabled when this is 0.
+    pub audit_sample_rate: f64,
+    /// How long each `/__heartbeat__` check (e.g. the database round trip)
+    /// may take before it fails
+    pub health_check_timeout_millis: u64,
 
     pub fcm: FcmSettings,
     pub apns: ApnsSettings,
     pub adm: AdmSettings,
+    pub webhook: WebhookSettings,
+    pub rate_limit: RateLimitSettings,
+
+    /// How often to check the config file and credential files for changes,
+    /// in seconds. Changed bridge credentials are reloaded without a restart
+    /// (as they are on SIGHUP). Files aren't checked when this is 0.
+    pub credential_reload_interval: u64,
+    /// The config file these settings were read from
+    #[serde(skip)]
+    pub config_file: Option<String>,
 }
 
 impl Default for Settings {
@@ -61,17 +126,39 @@ impl Default for Settings {
             /// 4216 byte data block. Since we're going to be receiving this, we have to
             /// presume base64 encoding, so we can bump things up to 5630 bytes max.
             max_data_bytes: 5630,
+            batch_max_items: 1000,
+            batch_max_bytes: 8 * 1024 * 1024,
+            batch_concurrency: 32,
+            max_subscription_lifetime: 0,
             crypto_keys: format!("[{}]", Fernet::generate_key()),
+            reissue_endpoints: false,
+            record_channel_deliveries: false,
             auth_keys: r#"["AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAB="]"#.to_string(),
+            issue_scoped_secrets: false,
+            accept_legacy_secrets: true,
+            scoped_secret_ttl: 0,
+            admin_keys: "[]".to_string(),
+            admin_host: "127.0.0.1".to_string(),
+            admin_port: 8002,
             human_logs: false,
             connection_timeout_millis: 1000,
             request_timeout_millis: 3000,
             statsd_host: None,
             statsd_port: 8125,
             statsd_label: "autoendpoint".to_string(),
+            metrics_backend: MetricsBackend::Statsd,
+            metrics_port: 8001,
+            tracing_exporter: "".to_string(),
+            tracing_sample_rate: 1.0,
+            audit_sample_rate: 0.0,
+            health_check_timeout_millis: 2000,
             fcm: FcmSettings::default(),
             apns: ApnsSettings::default(),
             adm: AdmSettings::default(),
+            webhook: WebhookSettings::default(),
+            rate_limit: RateLimitSettings::default(),
+            credential_reload_interval: 0,
+            config_file: None,
         }
     }
 }
@@ -95,6 +182,10 @@ impl Settings {
 
         built
             .try_deserialize::<Self>()
+            .map(|settings| Settings {
+                config_file: filename.clone(),
+                ..settings
+            })
             .map_err(|error| match error {
                 // Configuration errors are not very sysop friendly, Try to make them
                 // a bit more 3AM useful.
@@ -131,14 +222,18 @@ impl Settings {
 
     /// Initialize the fernet encryption instance
     pub fn make_fernet(&self) -> MultiFernet {
+        MultiFernet::new(self.fernet_keys())
+    }
+
+    /// Initialize the individual fernet keys, primary key first
+    pub fn fernet_keys(&self) -> Vec<Fernet> {
         let keys = &self.crypto_keys.replace(['"', ' '], "");
-        let fernets = Self::read_list_from_str(keys, "Invalid AUTOEND_CRYPTO_KEYS")
+        Self::read_list_from_str(keys, "Invalid AUTOEND_CRYPTO_KEYS")
             .map(|key| {
                 debug!("Fernet keys: {:?}", &key);
                 Fernet::new(key).expect("Invalid AUTOEND_CRYPTO_KEYS")
             })
-            .collect();
-        MultiFernet::new(fernets)
+            .collect()
     }
 
     /// Get the list of auth hash keys
@@ -149,6 +244,25 @@ impl Settings {
             .collect()
     }
 
+    /// Get the list of admin API tokens
+    pub fn admin_keys(&self) -> Vec<String> {
+        let keys = &self.admin_keys.replace(['"', ' '], "");
+        Self::read_list_from_str(keys, "Invalid AUTOEND_ADMIN_KEYS")
+            .filter(|key| !key.is_empty())
+            .map(|v| v.to_owned())
+            .collect()
+    }
+
+    /// The expiry (UNIX timestamp in seconds) to embed in a new subscription
+    /// endpoint, if endpoints should expire
+    pub fn subscription_expiry(&self) -> Option<u64> {
+        if self.max_subscription_lifetime == 0 {
+            return None;
+        }
+
+        Some(sec_since_epoch() + self.max_subscription_lifetime)
+    }
+
     /// Get the URL for this endpoint server
     pub fn endpoint_url(&self) -> Url {
         let endpoint = if self.endpoint_url.is_empty() {
@@ -189,6 +303,19 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_admin_keys() {
+        assert!(Settings::default().admin_keys().is_empty());
+        let settings = Settings {
+            admin_keys: r#"["admin-key-1", "admin-key-2"]"#.to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.admin_keys(),
+            vec!["admin-key-1".to_owned(), "admin-key-2".to_owned()]
+        );
+    }
+
     #[test]
     fn test_endpoint_url() -> ApiResult<()> {
         let example = "https://example.org/";
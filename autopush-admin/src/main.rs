@@ -0,0 +1,217 @@
+//! Operator tools for an Autopush deployment, working on the database
+//! directly rather than through the admin API.
+#![warn(rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+#[macro_use]
+extern crate slog_scope;
+
+use std::env;
+use std::error::Error;
+use std::sync::Arc;
+
+use cadence::{NopMetricSink, StatsdClient};
+use docopt::Docopt;
+use fernet::{Fernet, MultiFernet};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use autopush_common::db::client::DbClient;
+use autopush_common::db::dynamodb::DdbClientImpl;
+use autopush_common::db::{
+    channels_json, drop_user, fetch_stored_messages, DbSettings, StorageType,
+};
+use autopush_common::endpoint::{decode_endpoint, make_endpoint_with_expiry};
+use autopush_common::logging;
+
+const USAGE: &str = "
+Usage:
+    autopush-admin decode <endpoint> [options]
+    autopush-admin make-endpoint <uaid> <chid> [--key=KEY] [--expiry=TIMESTAMP] [options]
+    autopush-admin gen-keys [options]
+    autopush-admin user show <uaid> [options]
+    autopush-admin user drop <uaid> [options]
+    autopush-admin messages <uaid> [--limit=LIMIT] [options]
+    autopush-admin create-tables [options]
+    autopush-admin (-h | --help)
+
+Commands:
+    decode          Decode an endpoint into its UAID, channel ID and key hash
+    make-endpoint   Create an endpoint for a UAID and channel ID
+    gen-keys        Generate a new fernet (crypto) key and auth key
+    user show       Show a user's router record and channels
+    user drop       Delete a user, with their channels and stored messages
+    messages        Dump a user's stored messages, one JSON object per line
+    create-tables   Create the router and message tables, if they don't exist
+
+Options:
+    -h, --help              Show this message
+    --crypto-keys=KEYS      The endpoint fernet keys, primary key first (defaults
+                            to AUTOEND_CRYPTO_KEYS)
+    --endpoint-url=URL      The endpoint server's URL (defaults to
+                            AUTOEND_ENDPOINT_URL)
+    --db-dsn=DSN            The database DSN (defaults to AUTOEND_DB_DSN)
+    --db-settings=JSON      The database settings (defaults to
+                            AUTOEND_DB_SETTINGS)
+    --key=KEY               Restrict the endpoint to this VAPID public key
+    --expiry=TIMESTAMP      Make a v3 endpoint which expires at this UNIX time
+    --limit=LIMIT           The number of messages of each kind (topic and
+                            timestamped) to dump [default: 100]
+    --json-logs             Use JSON (MozLog) logging
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    cmd_decode: bool,
+    cmd_make_endpoint: bool,
+    cmd_gen_keys: bool,
+    cmd_user: bool,
+    cmd_show: bool,
+    cmd_drop: bool,
+    cmd_messages: bool,
+    cmd_create_tables: bool,
+    arg_endpoint: String,
+    arg_uaid: String,
+    arg_chid: String,
+    flag_crypto_keys: Option<String>,
+    flag_endpoint_url: Option<String>,
+    flag_db_dsn: Option<String>,
+    flag_db_settings: Option<String>,
+    flag_key: Option<String>,
+    flag_expiry: Option<u64>,
+    flag_limit: usize,
+    flag_json_logs: bool,
+}
+
+impl Args {
+    /// Read an option, falling back to the autoendpoint environment variable
+    fn option(flag: &Option<String>, var: &str) -> Option<String> {
+        flag.clone().or_else(|| env::var(var).ok())
+    }
+
+    /// Read the fernet keys, as either a single key or a list in the
+    /// `AUTOEND_CRYPTO_KEYS` format
+    fn fernet(&self) -> Result<MultiFernet, Box<dyn Error>> {
+        let keys = Self::option(&self.flag_crypto_keys, "AUTOEND_CRYPTO_KEYS")
+            .ok_or("No crypto keys given, set --crypto-keys or AUTOEND_CRYPTO_KEYS")?;
+        let keys = keys
+            .trim_matches(|c| c == '[' || c == ']')
+            .split(',')
+            .map(|key| key.trim_matches(|c| c == '"' || c == ' '))
+            .filter(|key| !key.is_empty())
+            .map(|key| Fernet::new(key).ok_or("Invalid crypto key"))
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err("No crypto keys given".into());
+        }
+        Ok(MultiFernet::new(keys))
+    }
+
+    fn db(&self) -> Result<DdbClientImpl, Box<dyn Error>> {
+        let db_settings = DbSettings {
+            dsn: Self::option(&self.flag_db_dsn, "AUTOEND_DB_DSN"),
+            db_settings: Self::option(&self.flag_db_settings, "AUTOEND_DB_SETTINGS")
+                .unwrap_or_default(),
+        };
+        if StorageType::from_dsn(&db_settings.dsn) == StorageType::INVALID {
+            return Err("Invalid DSN specified".into());
+        }
+        let metrics = Arc::new(StatsdClient::builder("autopush-admin", NopMetricSink).build());
+        Ok(DdbClientImpl::new(metrics, &db_settings)?)
+    }
+}
+
+#[actix_rt::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    logging::init_logging(args.flag_json_logs).expect("Logging failed to initialize");
+
+    let result = run(&args).await;
+
+    logging::reset_logging();
+    result
+}
+
+async fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    if args.cmd_decode {
+        let info = decode_endpoint(&args.arg_endpoint, &args.fernet()?).map_err(|e| e.kind)?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "version": info.version,
+                "uaid": info.uaid.simple().to_string(),
+                "chid": info.chid.to_string(),
+                "key_hash": info.key_hash.map(hex::encode),
+                "expiry": info.expiry,
+            }))?
+        );
+    } else if args.cmd_make_endpoint {
+        let endpoint_url = Args::option(&args.flag_endpoint_url, "AUTOEND_ENDPOINT_URL")
+            .ok_or("No endpoint URL given, set --endpoint-url or AUTOEND_ENDPOINT_URL")?;
+        let endpoint = make_endpoint_with_expiry(
+            &Uuid::parse_str(&args.arg_uaid)?,
+            &Uuid::parse_str(&args.arg_chid)?,
+            args.flag_key.as_deref(),
+            args.flag_expiry,
+            &endpoint_url,
+            &args.fernet()?,
+        )
+        .map_err(|e| e.kind)?;
+        println!("{endpoint}");
+    } else if args.cmd_gen_keys {
+        // Auth keys use the same format as fernet keys: 32 random bytes,
+        // base64 encoded
+        println!("CRYPTO_KEY=\"{}\"", Fernet::generate_key());
+        println!("AUTH_KEY=\"{}\"", Fernet::generate_key());
+    } else if args.cmd_user {
+        let uaid = Uuid::parse_str(&args.arg_uaid)?;
+        let db = args.db()?;
+        if args.cmd_show {
+            let user = db.get_user(&uaid).await?.ok_or("User not found")?;
+            let channels = channels_json(db.get_channels_with_meta(&uaid).await?);
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&serde_json::json!({
+                    "user": user,
+                    "channels": channels,
+                }))?
+            );
+        } else if args.cmd_drop {
+            db.get_user(&uaid).await?.ok_or("User not found")?;
+            let removed = drop_user(&db, &uaid).await?;
+            info!(
+                "Dropped user {} and {} stored messages",
+                uaid.simple(),
+                removed
+            );
+        }
+    } else if args.cmd_messages {
+        let uaid = Uuid::parse_str(&args.arg_uaid)?;
+        for message in fetch_stored_messages(&args.db()?, &uaid, args.flag_limit).await? {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "sortKey": message.sort_key(),
+                    "channelID": message.channel_id,
+                    "version": message.version,
+                    "topic": message.topic,
+                    "timestamp": message.timestamp,
+                    "ttl": message.ttl,
+                    "data": message.data,
+                    "headers": message.headers,
+                })
+            );
+        }
+    } else if args.cmd_create_tables {
+        let created = args.db()?.create_tables().await?;
+        if created.is_empty() {
+            info!("The tables already exist");
+        }
+        for table in created {
+            info!("Created table {}", table);
+        }
+    }
+    Ok(())
+}
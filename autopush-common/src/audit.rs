@@ -0,0 +1,201 @@
+//! A structured audit log of each notification's lifecycle, to answer "did
+//! you get my push?".
+//!
+//! Events are logged at info level, via the global logger (the MozLog JSON
+//! drain set up by `logging::init_logging` in production), for a sample of
+//! notifications: whether a notification is sampled depends only on its
+//! `message_id`, so every service logs the same notifications. The
+//! `message_id` correlates the events of a notification. UAIDs are hashed.
+//!
+//! Auditing is disabled until `init` is called with a non-zero sample rate.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use openssl::sha::sha256;
+use uuid::Uuid;
+
+/// The sample rate, as `f64` bits
+static SAMPLE_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// Enable auditing of the given fraction (0.0 to 1.0) of notifications
+pub fn init(sample_rate: f64) {
+    SAMPLE_RATE.store(sample_rate.to_bits(), Ordering::Relaxed);
+}
+
+/// If the notification's events are logged
+pub fn sampled(message_id: &str) -> bool {
+    is_sampled(
+        message_id,
+        f64::from_bits(SAMPLE_RATE.load(Ordering::Relaxed)),
+    )
+}
+
+fn is_sampled(message_id: &str, sample_rate: f64) -> bool {
+    if sample_rate <= 0.0 {
+        return false;
+    }
+    if sample_rate >= 1.0 {
+        return true;
+    }
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&sha256(message_id.as_bytes())[..8]);
+    (u64::from_be_bytes(bytes) as f64) < sample_rate * u64::MAX as f64
+}
+
+/// Hash a UAID, so the log can be searched for a UAID without recording it
+pub fn hash_uaid(uaid: &Uuid) -> String {
+    hex::encode(&sha256(uaid.as_bytes())[..16])
+}
+
+/// A step in a notification's lifecycle
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuditEvent {
+    /// Accepted by the endpoint
+    Received,
+    /// Sent to a connection node (`Direct`), stored (`Stored`), or sent to a
+    /// bridge (the router type)
+    Routed,
+    /// Sent to the client by the connection server
+    Delivered,
+    /// Acknowledged by the client
+    Acked,
+    /// Discarded because its TTL passed before it was delivered
+    Expired,
+    /// Discarded for another reason
+    Dropped,
+}
+
+impl AuditEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditEvent::Received => "received",
+            AuditEvent::Routed => "routed",
+            AuditEvent::Delivered => "delivered",
+            AuditEvent::Acked => "acked",
+            AuditEvent::Expired => "expired",
+            AuditEvent::Dropped => "dropped",
+        }
+    }
+}
+
+/// An audit log event. Fields which don't apply to the event are left unset
+/// (and logged as null).
+#[derive(Debug)]
+pub struct AuditRecord<'a> {
+    event: AuditEvent,
+    message_id: &'a str,
+    uaid: &'a Uuid,
+    channel_id: Option<&'a Uuid>,
+    vapid_sub: Option<&'a str>,
+    size: Option<u64>,
+    ttl: Option<u64>,
+    topic: Option<&'a str>,
+    destination: Option<&'a str>,
+    reason: Option<&'a str>,
+}
+
+impl<'a> AuditRecord<'a> {
+    pub fn new(event: AuditEvent, message_id: &'a str, uaid: &'a Uuid) -> Self {
+        AuditRecord {
+            event,
+            message_id,
+            uaid,
+            channel_id: None,
+            vapid_sub: None,
+            size: None,
+            ttl: None,
+            topic: None,
+            destination: None,
+            reason: None,
+        }
+    }
+
+    pub fn channel_id(mut self, channel_id: &'a Uuid) -> Self {
+        self.channel_id = Some(channel_id);
+        self
+    }
+
+    /// The `sub` claim of the sender's VAPID token
+    pub fn vapid_sub(mut self, vapid_sub: Option<&'a str>) -> Self {
+        self.vapid_sub = vapid_sub;
+        self
+    }
+
+    /// The size of the encrypted payload in bytes
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u64) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn topic(mut self, topic: Option<&'a str>) -> Self {
+        self.topic = topic;
+        self
+    }
+
+    /// Where the notification was routed or delivered from (`Direct`,
+    /// `Stored` or a bridge)
+    pub fn destination(mut self, destination: &'a str) -> Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Why the notification was dropped
+    pub fn reason(mut self, reason: &'a str) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
+    /// Log the event, if the notification is sampled
+    pub fn log(self) {
+        if !sampled(self.message_id) {
+            return;
+        }
+        info!(
+            "Notification audit";
+            "audit_event" => self.event.as_str(),
+            "message_id" => self.message_id,
+            "uaid_hash" => hash_uaid(self.uaid),
+            "channel_id" => self.channel_id.map(Uuid::to_string),
+            "vapid_sub" => self.vapid_sub,
+            "size" => self.size,
+            "ttl" => self.ttl,
+            "topic" => self.topic,
+            "destination" => self.destination,
+            "reason" => self.reason,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{hash_uaid, is_sampled};
+
+    /// Sampling is deterministic, so each service logs the same notifications
+    #[test]
+    fn sampling() {
+        let ids: Vec<String> = (0..1000).map(|i| format!("message-{i}")).collect();
+        assert!(ids.iter().all(|id| !is_sampled(id, 0.0)));
+        assert!(ids.iter().all(|id| is_sampled(id, 1.0)));
+
+        let sampled: Vec<_> = ids.iter().filter(|id| is_sampled(id, 0.25)).collect();
+        assert!((150..350).contains(&sampled.len()), "{}", sampled.len());
+        assert!(sampled.iter().all(|id| is_sampled(id, 0.25)));
+        // A higher rate samples a superset
+        assert!(sampled.iter().all(|id| is_sampled(id, 0.5)));
+    }
+
+    #[test]
+    fn uaid_hash() {
+        let uaid = Uuid::parse_str("deadbeef-0000-0000-deca-fbad00000000").unwrap();
+        let hash = hash_uaid(&uaid);
+        assert_eq!(hash.len(), 32);
+        assert_eq!(hash, hash_uaid(&uaid));
+        assert!(!hash.contains("deadbeef"));
+    }
+}
@@ -1,11 +1,12 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use async_trait::async_trait;
 use uuid::Uuid;
 
-use crate::db::error::DbResult;
-use crate::db::User;
+use crate::db::error::{DbError, DbResult};
+use crate::db::{ChannelMeta, DeadLetter, User};
 use crate::notification::Notification;
+use crate::util::sec_since_epoch;
 
 use super::HelloResponse;
 
@@ -30,6 +31,12 @@ pub trait DbClient: Send + Sync {
     /// `connected_at` timestamp.
     async fn update_user(&self, user: &User) -> DbResult<()>;
 
+    /// Move a user to a different router, replacing their `router_type` and
+    /// `router_data` with the given user's. An error will occur if the user
+    /// does not exist or is registered with a router type other than
+    /// `old_router_type` or the new one.
+    async fn migrate_user_router(&self, user: &User, old_router_type: &str) -> DbResult<()>;
+
     /// Read a user from the database
     async fn get_user(&self, uaid: &Uuid) -> DbResult<Option<User>>;
 
@@ -39,7 +46,16 @@ pub trait DbClient: Send + Sync {
     /// Add a channel to a user
     async fn add_channel(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<()>;
 
-    /// Replace the current channel list
+    /// Add a channel to a user, recording its metadata
+    async fn add_channel_with_meta(
+        &self,
+        uaid: &Uuid,
+        channel_id: &Uuid,
+        meta: &ChannelMeta,
+    ) -> DbResult<()>;
+
+    /// Replace the current channel list. The metadata of removed channels is
+    /// dropped, and added channels are given new metadata.
     async fn save_channels(
         &self,
         uaid: &Uuid,
@@ -50,9 +66,24 @@ pub trait DbClient: Send + Sync {
     /// Get the set of channel IDs for a user
     async fn get_channels(&self, uaid: &Uuid) -> DbResult<HashSet<Uuid>>;
 
+    /// Get a user's channel IDs with their metadata
+    async fn get_channels_with_meta(&self, uaid: &Uuid) -> DbResult<HashMap<Uuid, ChannelMeta>>;
+
     /// Remove a channel from a user. Returns if the removed channel did exist.
     async fn remove_channel(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<bool>;
 
+    /// Remove several channels from a user. Returns the removed channels
+    /// which did exist.
+    async fn remove_channels(
+        &self,
+        uaid: &Uuid,
+        channel_ids: &HashSet<Uuid>,
+    ) -> DbResult<HashSet<Uuid>>;
+
+    /// Record that a notification was delivered to one of a user's channels.
+    /// Nothing is recorded if the user no longer has the channel.
+    async fn record_delivery(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<()>;
+
     /// Remove the node ID from a user in the router table.
     /// The node ID will only be cleared if `connected_at` matches up with the
     /// item's `connected_at`.
@@ -75,6 +106,18 @@ pub trait DbClient: Send + Sync {
     /// Delete a notification
     async fn remove_message(&self, uaid: &Uuid, sort_key: &str) -> DbResult<()>;
 
+    /// Store a notification which could not be delivered by a bridge
+    async fn save_dead_letter(&self, dead_letter: &DeadLetter) -> DbResult<()>;
+
+    /// Fetch stored dead letters, up to `limit`
+    async fn fetch_dead_letters(&self, limit: usize) -> DbResult<Vec<DeadLetter>>;
+
+    /// Read a dead letter by ID
+    async fn get_dead_letter(&self, id: &str) -> DbResult<Option<DeadLetter>>;
+
+    /// Delete a dead letter
+    async fn remove_dead_letter(&self, id: &str) -> DbResult<()>;
+
     /// record a Hello record
     /// Each data store can handle this differently, thus it's best to hand things off to the engine.
     async fn hello(
@@ -91,6 +134,32 @@ pub trait DbClient: Send + Sync {
     /// Check if the message table exists
     async fn message_table_exists(&self) -> DbResult<bool>;
 
+    /// Check that the database can be written to and read from, by storing a
+    /// short lived message under the nil UAID (which is never issued to a
+    /// user) and reading it back. The message always has the same sort key,
+    /// so repeated checks overwrite rather than accumulate records.
+    async fn health_check(&self) -> DbResult<()> {
+        let uaid = Uuid::nil();
+        let probe = Notification {
+            channel_id: Uuid::nil(),
+            version: Uuid::new_v4().as_simple().to_string(),
+            ttl: 60,
+            timestamp: sec_since_epoch(),
+            sortkey_timestamp: Some(1),
+            ..Default::default()
+        };
+        let sort_key = probe.sort_key();
+        let version = probe.version.clone();
+        self.save_message(&uaid, probe).await?;
+        let response = self.fetch_timestamp_messages(&uaid, None, 1).await?;
+        if !response.messages.iter().any(|m| m.version == version) {
+            return Err(DbError::General(
+                "Health check message not found".to_owned(),
+            ));
+        }
+        self.remove_message(&uaid, &sort_key).await
+    }
+
     /// Get the message table name
     fn message_table(&self) -> &str;
 
@@ -1,8 +1,9 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt::{Debug, Display};
 use std::result::Result as StdResult;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::db::client::DbClient;
 use crate::db::dynamodb::retry::{
@@ -11,7 +12,8 @@ use crate::db::dynamodb::retry::{
 };
 use crate::db::error::{DbError, DbResult};
 use crate::db::{
-    client::FetchMessageResponse, DbSettings, NotificationRecord, User, MAX_CHANNEL_TTL, MAX_EXPIRY,
+    client::FetchMessageResponse, ChannelMeta, DbSettings, DeadLetter, NotificationRecord, User,
+    MAX_CHANNEL_TTL, MAX_EXPIRY,
 };
 use crate::notification::Notification;
 use crate::util::sec_since_epoch;
@@ -23,8 +25,10 @@ use chrono::Utc;
 use rusoto_core::credential::StaticProvider;
 use rusoto_core::{HttpClient, Region, RusotoError};
 use rusoto_dynamodb::{
-    AttributeValue, DeleteItemInput, DescribeTableError, DescribeTableInput, DynamoDb,
-    DynamoDbClient, GetItemInput, PutItemInput, QueryInput, UpdateItemInput,
+    AttributeDefinition, AttributeValue, CreateTableInput, DeleteItemInput, DescribeTableError,
+    DescribeTableInput, DynamoDb, DynamoDbClient, GetItemInput, GlobalSecondaryIndex,
+    KeySchemaElement, Projection, PutItemInput, QueryInput, TimeToLiveSpecification,
+    UpdateItemError, UpdateItemInput, UpdateTimeToLiveInput,
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -48,6 +52,94 @@ pub struct DynamoDbSettings {
     pub current_message_month: String,
 }
 
+/// The prefix of the message table partitions holding dead letters. UAIDs are
+/// stored as simple UUIDs, so these can't collide with a user's messages.
+const DEAD_LETTER_PARTITION: &str = "dead_letters";
+/// How many partitions dead letters are spread over, so a burst of them
+/// doesn't all land on one partition
+const DEAD_LETTER_SHARDS: u32 = 16;
+
+/// How many times to try saving a user's channels while the channel record
+/// keeps changing underneath
+const SAVE_CHANNELS_ATTEMPTS: u32 = 3;
+/// How many times to check whether a new table is active, a second apart
+const TABLE_ACTIVE_ATTEMPTS: u32 = 60;
+
+/// A dead letter as stored in the message table. The dead letter itself is
+/// kept as JSON so its fields don't clash with the table's keys.
+#[derive(Deserialize, Serialize)]
+struct DeadLetterRecord {
+    uaid: String,
+    chidmessageid: String,
+    expiry: u64,
+    dead_letter: String,
+}
+
+impl DeadLetterRecord {
+    /// The partition holding the dead letter with this ID, derived from the
+    /// ID so it can be found again without a scan
+    fn partition(id: &str) -> String {
+        let hash = id
+            .bytes()
+            .fold(0u32, |hash, b| hash.wrapping_mul(31).wrapping_add(b as u32));
+        Self::shard_partition(hash % DEAD_LETTER_SHARDS)
+    }
+
+    fn shard_partition(shard: u32) -> String {
+        format!("{DEAD_LETTER_PARTITION}#{shard}")
+    }
+
+    fn into_dead_letter(self) -> DbResult<DeadLetter> {
+        serde_json::from_str(&self.dead_letter).map_err(|e| DbError::Serialization(e.to_string()))
+    }
+}
+
+/// The attribute of the user's channel record holding a channel's metadata
+/// (as JSON)
+fn channel_meta_attr(channel_id: &Uuid) -> String {
+    format!("chmeta_{}", channel_id.simple())
+}
+
+/// The attribute of the user's channel record holding the time of a
+/// channel's last delivery. This is kept apart from the rest of the metadata
+/// so it can be updated on its own.
+fn channel_delivered_attr(channel_id: &Uuid) -> String {
+    format!("chdelivered_{}", channel_id.simple())
+}
+
+/// Get the channel ID of a channel metadata attribute (`chmeta_` or
+/// `chdelivered_`)
+fn channel_attr_id(attr: &str) -> Option<Uuid> {
+    let channel_id = attr
+        .strip_prefix("chmeta_")
+        .or_else(|| attr.strip_prefix("chdelivered_"))?;
+    Uuid::parse_str(channel_id).ok()
+}
+
+/// Start a span for a database operation, ended when it's dropped. Nothing
+/// is traced within the operation, so the span isn't entered.
+fn db_span(operation: &'static str) -> tracing::Span {
+    tracing::info_span!(
+        "db",
+        otel.name = %format!("db.{operation}"),
+        otel.kind = "client",
+        db.system = "dynamodb",
+        db.operation = operation,
+    )
+}
+
+/// Get the channel IDs in the user's channel record, both as stored and
+/// parsed (they may be stored in either simple or hyphenated form)
+fn stored_channel_ids(record: &HashMap<String, AttributeValue>) -> Vec<(String, Uuid)> {
+    record
+        .get("chids")
+        .and_then(|chids| chids.ss.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|chid| Some((chid.clone(), Uuid::parse_str(chid).ok()?)))
+        .collect()
+}
+
 impl Default for DynamoDbSettings {
     fn default() -> Self {
         Self {
@@ -128,6 +220,192 @@ impl DdbClientImpl {
 
         Ok(["CREATING", "UPDATING", "ACTIVE"].contains(&status.as_str()))
     }
+
+    /// Create the router and message tables, skipping any which already
+    /// exist. Returns the names of the created tables.
+    pub async fn create_tables(&self) -> DbResult<Vec<String>> {
+        let mut created = Vec::new();
+
+        let router_table = self.settings.router_table.clone();
+        if !self.table_exists(router_table.clone()).await? {
+            debug!("Creating router table {}", router_table);
+            self.db_client
+                .create_table(CreateTableInput {
+                    table_name: router_table.clone(),
+                    attribute_definitions: attributes!("uaid" => "S", "last_connect" => "N"),
+                    key_schema: key_schema!("uaid" => "HASH"),
+                    global_secondary_indexes: Some(vec![GlobalSecondaryIndex {
+                        index_name: "AccessIndex".to_owned(),
+                        key_schema: key_schema!("last_connect" => "HASH"),
+                        projection: Projection {
+                            projection_type: Some("KEYS_ONLY".to_owned()),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    }]),
+                    billing_mode: Some("PAY_PER_REQUEST".to_owned()),
+                    ..Default::default()
+                })
+                .await?;
+            created.push(router_table);
+        }
+
+        let message_table = self.settings.message_table.clone();
+        if !self.table_exists(message_table.clone()).await? {
+            debug!("Creating message table {}", message_table);
+            self.db_client
+                .create_table(CreateTableInput {
+                    table_name: message_table.clone(),
+                    attribute_definitions: attributes!("uaid" => "S", "chidmessageid" => "S"),
+                    key_schema: key_schema!("uaid" => "HASH", "chidmessageid" => "RANGE"),
+                    billing_mode: Some("PAY_PER_REQUEST".to_owned()),
+                    ..Default::default()
+                })
+                .await?;
+            // Stored messages (and dead letters) are dropped by DynamoDB once
+            // they expire. TTL can't be enabled until the table is active.
+            self.wait_until_active(&message_table).await?;
+            self.db_client
+                .update_time_to_live(UpdateTimeToLiveInput {
+                    table_name: message_table.clone(),
+                    time_to_live_specification: TimeToLiveSpecification {
+                        attribute_name: "expiry".to_owned(),
+                        enabled: true,
+                    },
+                })
+                .await?;
+            created.push(message_table);
+        }
+
+        Ok(created)
+    }
+
+    /// Wait for a newly created table to become active, which takes a few
+    /// seconds
+    async fn wait_until_active(&self, table_name: &str) -> DbResult<()> {
+        for _ in 0..TABLE_ACTIVE_ATTEMPTS {
+            let output = self
+                .db_client
+                .describe_table(DescribeTableInput {
+                    table_name: table_name.to_owned(),
+                })
+                .await?;
+            let status = output.table.and_then(|table| table.table_status);
+            if matches!(status.as_deref(), Some("ACTIVE")) {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        Err(DbError::General(format!(
+            "Table {table_name} did not become active"
+        )))
+    }
+
+    /// Read the user's channel record: the message table item holding the
+    /// channel IDs and metadata (empty if there is none)
+    async fn get_channel_record(&self, uaid: &Uuid) -> DbResult<HashMap<String, AttributeValue>> {
+        let input = GetItemInput {
+            table_name: self.settings.message_table.clone(),
+            consistent_read: Some(true),
+            key: ddb_item! {
+                uaid: s => uaid.simple().to_string(),
+                chidmessageid: s => " ".to_string()
+            },
+            ..Default::default()
+        };
+
+        Ok(retry_policy()
+            .retry_if(
+                || self.db_client.get_item(input.clone()),
+                retryable_getitem_error(self.metrics.clone()),
+            )
+            .await?
+            .item
+            .unwrap_or_default())
+    }
+
+    /// Build the update replacing the channels in the user's channel record.
+    /// Channel IDs are written in simple form. The metadata of every other
+    /// channel is dropped, including any left behind by earlier removals.
+    fn save_channels_input(
+        &self,
+        uaid: &Uuid,
+        record: &HashMap<String, AttributeValue>,
+        channel_list: &HashSet<&Uuid>,
+    ) -> DbResult<UpdateItemInput> {
+        let stored = stored_channel_ids(record);
+        let mut attr_names = HashMap::new();
+        let mut attr_values = hashmap! {
+            ":expiry".to_string() => val!(N => sec_since_epoch() + 2 * MAX_EXPIRY),
+        };
+        let mut sets = vec!["expiry = :expiry".to_string()];
+        let mut removes = Vec::new();
+
+        // Drop the metadata of channels which aren't in the list
+        for (i, attr) in record
+            .keys()
+            .filter(|attr| {
+                channel_attr_id(attr)
+                    .map_or(false, |channel_id| !channel_list.contains(&&channel_id))
+            })
+            .enumerate()
+        {
+            attr_names.insert(format!("#r{i}"), attr.clone());
+            removes.push(format!("#r{i}"));
+        }
+
+        // Add the new channels with new metadata
+        let meta = serde_json::to_string(&ChannelMeta::new(None))
+            .map_err(|e| DbError::Serialization(e.to_string()))?;
+        for (i, channel_id) in channel_list
+            .iter()
+            .copied()
+            .filter(|channel_id| !stored.iter().any(|(_, stored_id)| stored_id == *channel_id))
+            .enumerate()
+        {
+            attr_names.insert(format!("#n{i}"), channel_meta_attr(channel_id));
+            attr_values.insert(format!(":n{i}"), val!(S => meta));
+            sets.push(format!("#n{i} = :n{i}"));
+        }
+
+        // Empty sets can't be stored
+        if channel_list.is_empty() {
+            removes.push("chids".to_string());
+        } else {
+            let chids: Vec<String> = channel_list
+                .iter()
+                .map(|channel_id| channel_id.simple().to_string())
+                .collect();
+            attr_values.insert(":chids".to_string(), val!(SS => chids));
+            sets.push("chids = :chids".to_string());
+        }
+
+        // Only apply the update to the channel IDs which were read
+        let condition = match record.get("chids").and_then(|chids| chids.ss.as_ref()) {
+            Some(old_chids) => {
+                attr_values.insert(":old_chids".to_string(), val!(SS => old_chids));
+                "chids = :old_chids"
+            }
+            None => "attribute_not_exists(chids)",
+        };
+
+        let mut update_expression = format!("SET {}", sets.join(", "));
+        if !removes.is_empty() {
+            update_expression.push_str(&format!(" REMOVE {}", removes.join(", ")));
+        }
+        Ok(UpdateItemInput {
+            key: ddb_item! {
+                uaid: s => uaid.simple().to_string(),
+                chidmessageid: s => " ".to_string()
+            },
+            update_expression: Some(update_expression),
+            condition_expression: Some(condition.to_string()),
+            expression_attribute_names: (!attr_names.is_empty()).then_some(attr_names),
+            expression_attribute_values: Some(attr_values),
+            table_name: self.settings.message_table.clone(),
+            ..Default::default()
+        })
+    }
 }
 
 /// Like Result::ok, convert from Result<T, E> to Option<T> but applying a
@@ -162,6 +440,7 @@ where
 #[async_trait]
 impl DbClient for DdbClientImpl {
     async fn add_user(&self, user: &User) -> DbResult<()> {
+        let _span = db_span("add_user");
         let input = PutItemInput {
             table_name: self.settings.router_table.clone(),
             item: serde_dynamodb::to_hashmap(user)?,
@@ -179,6 +458,7 @@ impl DbClient for DdbClientImpl {
     }
 
     async fn update_user(&self, user: &User) -> DbResult<()> {
+        let _span = db_span("update_user");
         let mut user_map = serde_dynamodb::to_hashmap(&user)?;
         user_map.remove("uaid");
         let input = UpdateItemInput {
@@ -220,7 +500,42 @@ impl DbClient for DdbClientImpl {
         Ok(())
     }
 
+    async fn migrate_user_router(&self, user: &User, old_router_type: &str) -> DbResult<()> {
+        let _span = db_span("migrate_user_router");
+        let mut user_map = serde_dynamodb::to_hashmap(&user)?;
+        let attr_values = hashmap! {
+            ":router_type".to_string() => user_map.remove("router_type").unwrap_or_default(),
+            ":router_data".to_string() => user_map.remove("router_data").unwrap_or_default(),
+            ":old_router_type".to_string() => val!(S => old_router_type)
+        };
+        let input = UpdateItemInput {
+            table_name: self.settings.router_table.clone(),
+            key: ddb_item! { uaid: s => user.uaid.simple().to_string() },
+            update_expression: Some(
+                "SET router_type=:router_type, router_data=:router_data".to_string(),
+            ),
+            expression_attribute_values: Some(attr_values),
+            condition_expression: Some(
+                "attribute_exists(uaid) and (
+                    router_type = :old_router_type or
+                    router_type = :router_type
+                )"
+                .to_string(),
+            ),
+            ..Default::default()
+        };
+
+        retry_policy()
+            .retry_if(
+                || self.db_client.update_item(input.clone()),
+                retryable_updateitem_error(self.metrics.clone()),
+            )
+            .await?;
+        Ok(())
+    }
+
     async fn get_user(&self, uaid: &Uuid) -> DbResult<Option<User>> {
+        let _span = db_span("get_user");
         let input = GetItemInput {
             table_name: self.settings.router_table.clone(),
             consistent_read: Some(true),
@@ -244,6 +559,7 @@ impl DbClient for DdbClientImpl {
     }
 
     async fn remove_user(&self, uaid: &Uuid) -> DbResult<()> {
+        let _span = db_span("remove_user");
         let input = DeleteItemInput {
             table_name: self.settings.router_table.clone(),
             key: ddb_item! { uaid: s => uaid.simple().to_string() },
@@ -260,6 +576,7 @@ impl DbClient for DdbClientImpl {
     }
 
     async fn add_channel(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<()> {
+        let _span = db_span("add_channel");
         let input = UpdateItemInput {
             table_name: self.settings.message_table.clone(),
             key: ddb_item! {
@@ -283,37 +600,77 @@ impl DbClient for DdbClientImpl {
         Ok(())
     }
 
-    async fn save_channels(
+    async fn add_channel_with_meta(
         &self,
         uaid: &Uuid,
-        channel_list: HashSet<&Uuid>,
-        _message_month: &str,
+        channel_id: &Uuid,
+        meta: &ChannelMeta,
     ) -> DbResult<()> {
-        let chids: Vec<String> = channel_list
-            .into_iter()
-            .map(|v| v.simple().to_string())
-            .collect();
-        let expiry = sec_since_epoch() + 2 * MAX_EXPIRY;
-        let attr_values = hashmap! {
-            ":chids".to_string() => val!(SS => chids),
-            ":expiry".to_string() => val!(N => expiry),
-        };
-        let update_item = UpdateItemInput {
+        let _span = db_span("add_channel_with_meta");
+        let meta =
+            serde_json::to_string(meta).map_err(|e| DbError::Serialization(e.to_string()))?;
+        let input = UpdateItemInput {
+            table_name: self.settings.message_table.clone(),
             key: ddb_item! {
                 uaid: s => uaid.simple().to_string(),
                 chidmessageid: s => " ".to_string()
             },
-            update_expression: Some("ADD chids :chids SET expiry=:expiry".to_string()),
-            expression_attribute_values: Some(attr_values),
-            table_name: self.settings.message_table.clone(),
+            update_expression: Some(
+                "ADD chids :channel_id SET expiry = :expiry, #meta = :meta".to_string(),
+            ),
+            expression_attribute_names: Some(hashmap! {
+                "#meta".to_string() => channel_meta_attr(channel_id)
+            }),
+            expression_attribute_values: Some(hashmap! {
+                ":channel_id".to_string() => val!(SS => Some(channel_id.simple())),
+                ":expiry".to_string() => val!(N => sec_since_epoch() + MAX_CHANNEL_TTL),
+                ":meta".to_string() => val!(S => meta)
+            }),
             ..Default::default()
         };
 
-        self.db_client.update_item(update_item.clone()).await?;
+        retry_policy()
+            .retry_if(
+                || self.db_client.update_item(input.clone()),
+                retryable_updateitem_error(self.metrics.clone()),
+            )
+            .await?;
         Ok(())
     }
 
+    async fn save_channels(
+        &self,
+        uaid: &Uuid,
+        channel_list: HashSet<&Uuid>,
+        _message_month: &str,
+    ) -> DbResult<()> {
+        let _span = db_span("save_channels");
+        // The update only applies if the channel IDs are still the ones read,
+        // so a concurrent change is retried against the new record
+        let mut attempts = 1;
+        loop {
+            let record = self.get_channel_record(uaid).await?;
+            let input = self.save_channels_input(uaid, &record, &channel_list)?;
+            match retry_policy()
+                .retry_if(
+                    || self.db_client.update_item(input.clone()),
+                    retryable_updateitem_error(self.metrics.clone()),
+                )
+                .await
+            {
+                Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_)))
+                    if attempts < SAVE_CHANNELS_ATTEMPTS =>
+                {
+                    debug!("Channels of {} changed while saving, retrying", uaid);
+                    attempts += 1;
+                }
+                result => return result.map(|_| ()).map_err(DbError::from),
+            }
+        }
+    }
+
     async fn get_channels(&self, uaid: &Uuid) -> DbResult<HashSet<Uuid>> {
+        let _span = db_span("get_channels");
         // Channel IDs are stored in a special row in the message table, where
         // chidmessageid = " "
         let input = GetItemInput {
@@ -352,16 +709,46 @@ impl DbClient for DdbClientImpl {
         Ok(channels)
     }
 
+    async fn get_channels_with_meta(&self, uaid: &Uuid) -> DbResult<HashMap<Uuid, ChannelMeta>> {
+        let _span = db_span("get_channels_with_meta");
+        let record = self.get_channel_record(uaid).await?;
+
+        Ok(stored_channel_ids(&record)
+            .into_iter()
+            .map(|(_, channel_id)| {
+                let mut meta: ChannelMeta = record
+                    .get(&channel_meta_attr(&channel_id))
+                    .and_then(|meta| meta.s.as_deref())
+                    .and_then(|meta| serde_json::from_str(meta).ok())
+                    .unwrap_or_default();
+                meta.last_delivered = record
+                    .get(&channel_delivered_attr(&channel_id))
+                    .and_then(|delivered| delivered.n.as_deref())
+                    .and_then(|delivered| delivered.parse().ok());
+                (channel_id, meta)
+            })
+            .collect())
+    }
+
     async fn remove_channel(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<bool> {
+        let _span = db_span("remove_channel");
+        // The channel ID may be stored in hyphenated or simple form
         let input = UpdateItemInput {
             table_name: self.settings.message_table.clone(),
             key: ddb_item! {
                 uaid: s => uaid.simple().to_string(),
                 chidmessageid: s => " ".to_string()
             },
-            update_expression: Some("DELETE chids :channel_id SET expiry = :expiry".to_string()),
+            update_expression: Some(
+                "DELETE chids :channel_id SET expiry = :expiry REMOVE #meta, #delivered"
+                    .to_string(),
+            ),
+            expression_attribute_names: Some(hashmap! {
+                "#meta".to_string() => channel_meta_attr(channel_id),
+                "#delivered".to_string() => channel_delivered_attr(channel_id)
+            }),
             expression_attribute_values: Some(hashmap! {
-                ":channel_id".to_string() => val!(SS => Some(channel_id)),
+                ":channel_id".to_string() => val!(SS => [channel_id.to_string(), channel_id.simple().to_string()]),
                 ":expiry".to_string() => val!(N => sec_since_epoch() + MAX_CHANNEL_TTL)
             }),
             return_values: Some("UPDATED_OLD".to_string()),
@@ -381,11 +768,105 @@ impl DbClient for DdbClientImpl {
             .as_ref()
             .and_then(|map| map.get("chids"))
             .and_then(|item| item.ss.as_ref())
-            .map(|channel_ids| channel_ids.contains(&channel_id.to_string()))
+            .map(|channel_ids| {
+                channel_ids.contains(&channel_id.to_string())
+                    || channel_ids.contains(&channel_id.simple().to_string())
+            })
             .unwrap_or(false))
     }
 
+    async fn remove_channels(
+        &self,
+        uaid: &Uuid,
+        channel_ids: &HashSet<Uuid>,
+    ) -> DbResult<HashSet<Uuid>> {
+        let _span = db_span("remove_channels");
+        // Delete the channel IDs as they are stored
+        let removed: Vec<(String, Uuid)> =
+            stored_channel_ids(&self.get_channel_record(uaid).await?)
+                .into_iter()
+                .filter(|(_, channel_id)| channel_ids.contains(channel_id))
+                .collect();
+        if removed.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let mut attr_names = HashMap::new();
+        let mut removes = Vec::new();
+        for (i, (_, channel_id)) in removed.iter().enumerate() {
+            attr_names.insert(format!("#m{i}"), channel_meta_attr(channel_id));
+            attr_names.insert(format!("#d{i}"), channel_delivered_attr(channel_id));
+            removes.push(format!("#m{i}, #d{i}"));
+        }
+        let chids: Vec<&String> = removed.iter().map(|(chid, _)| chid).collect();
+        let input = UpdateItemInput {
+            table_name: self.settings.message_table.clone(),
+            key: ddb_item! {
+                uaid: s => uaid.simple().to_string(),
+                chidmessageid: s => " ".to_string()
+            },
+            update_expression: Some(format!(
+                "DELETE chids :chids SET expiry = :expiry REMOVE {}",
+                removes.join(", ")
+            )),
+            expression_attribute_names: Some(attr_names),
+            expression_attribute_values: Some(hashmap! {
+                ":chids".to_string() => val!(SS => chids),
+                ":expiry".to_string() => val!(N => sec_since_epoch() + MAX_CHANNEL_TTL)
+            }),
+            ..Default::default()
+        };
+
+        retry_policy()
+            .retry_if(
+                || self.db_client.update_item(input.clone()),
+                retryable_updateitem_error(self.metrics.clone()),
+            )
+            .await?;
+        Ok(removed
+            .into_iter()
+            .map(|(_, channel_id)| channel_id)
+            .collect())
+    }
+
+    async fn record_delivery(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<()> {
+        let _span = db_span("record_delivery");
+        let input = UpdateItemInput {
+            table_name: self.settings.message_table.clone(),
+            key: ddb_item! {
+                uaid: s => uaid.simple().to_string(),
+                chidmessageid: s => " ".to_string()
+            },
+            update_expression: Some("SET #delivered = :delivered".to_string()),
+            condition_expression: Some(
+                "contains(chids, :chid) or contains(chids, :simple_chid)".to_string(),
+            ),
+            expression_attribute_names: Some(hashmap! {
+                "#delivered".to_string() => channel_delivered_attr(channel_id)
+            }),
+            expression_attribute_values: Some(hashmap! {
+                ":delivered".to_string() => val!(N => sec_since_epoch()),
+                ":chid".to_string() => val!(S => channel_id),
+                ":simple_chid".to_string() => val!(S => channel_id.simple())
+            }),
+            ..Default::default()
+        };
+
+        match retry_policy()
+            .retry_if(
+                || self.db_client.update_item(input.clone()),
+                retryable_updateitem_error(self.metrics.clone()),
+            )
+            .await
+        {
+            // The channel was removed
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(()),
+            result => result.map(|_| ()).map_err(DbError::from),
+        }
+    }
+
     async fn remove_node_id(&self, uaid: &Uuid, node_id: &str, connected_at: u64) -> DbResult<()> {
+        let _span = db_span("remove_node_id");
         let input = UpdateItemInput {
             key: ddb_item! { uaid: s => uaid.simple().to_string() },
             update_expression: Some("REMOVE node_id".to_string()),
@@ -409,6 +890,7 @@ impl DbClient for DdbClientImpl {
     }
 
     async fn fetch_messages(&self, uaid: &Uuid, limit: usize) -> DbResult<FetchMessageResponse> {
+        let _span = db_span("fetch_messages");
         // from commands::fetch_messages()
         let attr_values = hashmap! {
             ":uaid".to_string() => val!(S => uaid.simple().to_string()),
@@ -466,6 +948,7 @@ impl DbClient for DdbClientImpl {
         timestamp: Option<u64>,
         limit: usize,
     ) -> DbResult<FetchMessageResponse> {
+        let _span = db_span("fetch_timestamp_messages");
         let range_key = if let Some(ts) = timestamp {
             format!("02:{}:z", ts)
         } else {
@@ -511,6 +994,7 @@ impl DbClient for DdbClientImpl {
     }
 
     async fn save_message(&self, uaid: &Uuid, message: Notification) -> DbResult<()> {
+        let _span = db_span("save_message");
         let input = PutItemInput {
             item: serde_dynamodb::to_hashmap(&NotificationRecord::from_notif(uaid, message))?,
             table_name: self.settings.message_table.clone(),
@@ -528,6 +1012,7 @@ impl DbClient for DdbClientImpl {
     }
 
     async fn remove_message(&self, uaid: &Uuid, sort_key: &str) -> DbResult<()> {
+        let _span = db_span("remove_message");
         let input = DeleteItemInput {
             table_name: self.settings.message_table.clone(),
             key: ddb_item! {
@@ -546,6 +1031,104 @@ impl DbClient for DdbClientImpl {
         Ok(())
     }
 
+    async fn save_dead_letter(&self, dead_letter: &DeadLetter) -> DbResult<()> {
+        let _span = db_span("save_dead_letter");
+        let record = DeadLetterRecord {
+            uaid: DeadLetterRecord::partition(&dead_letter.id),
+            chidmessageid: dead_letter.id.clone(),
+            expiry: dead_letter.timestamp + dead_letter.ttl,
+            dead_letter: serde_json::to_string(dead_letter)
+                .map_err(|e| DbError::Serialization(e.to_string()))?,
+        };
+        let input = PutItemInput {
+            table_name: self.settings.message_table.clone(),
+            item: serde_dynamodb::to_hashmap(&record)?,
+            ..Default::default()
+        };
+
+        retry_policy()
+            .retry_if(
+                || self.db_client.put_item(input.clone()),
+                retryable_putitem_error(self.metrics.clone()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn fetch_dead_letters(&self, limit: usize) -> DbResult<Vec<DeadLetter>> {
+        let _span = db_span("fetch_dead_letters");
+        let mut dead_letters = Vec::new();
+        for shard in 0..DEAD_LETTER_SHARDS {
+            if dead_letters.len() >= limit {
+                break;
+            }
+            let input = QueryInput {
+                key_condition_expression: Some("uaid = :uaid".to_string()),
+                expression_attribute_values: Some(hashmap! {
+                    ":uaid".to_string() => val!(S => DeadLetterRecord::shard_partition(shard))
+                }),
+                table_name: self.settings.message_table.clone(),
+                consistent_read: Some(true),
+                limit: Some((limit - dead_letters.len()) as i64),
+                ..Default::default()
+            };
+
+            let output = self.db_client.query(input).await?;
+            for item in output.items.unwrap_or_default() {
+                dead_letters.push(
+                    serde_dynamodb::from_hashmap::<DeadLetterRecord, _>(item)?
+                        .into_dead_letter()?,
+                );
+            }
+        }
+        Ok(dead_letters)
+    }
+
+    async fn get_dead_letter(&self, id: &str) -> DbResult<Option<DeadLetter>> {
+        let _span = db_span("get_dead_letter");
+        let input = GetItemInput {
+            table_name: self.settings.message_table.clone(),
+            consistent_read: Some(true),
+            key: ddb_item! {
+                uaid: s => DeadLetterRecord::partition(id),
+                chidmessageid: s => id.to_owned()
+            },
+            ..Default::default()
+        };
+
+        retry_policy()
+            .retry_if(
+                || self.db_client.get_item(input.clone()),
+                retryable_getitem_error(self.metrics.clone()),
+            )
+            .await?
+            .item
+            .map(|item| {
+                serde_dynamodb::from_hashmap::<DeadLetterRecord, _>(item)?.into_dead_letter()
+            })
+            .transpose()
+    }
+
+    async fn remove_dead_letter(&self, id: &str) -> DbResult<()> {
+        let _span = db_span("remove_dead_letter");
+        let input = DeleteItemInput {
+            table_name: self.settings.message_table.clone(),
+            key: ddb_item! {
+               uaid: s => DeadLetterRecord::partition(id),
+               chidmessageid: s => id.to_owned()
+            },
+            ..Default::default()
+        };
+
+        retry_policy()
+            .retry_if(
+                || self.db_client.delete_item(input.clone()),
+                retryable_delete_error(self.metrics.clone()),
+            )
+            .await?;
+        Ok(())
+    }
+
     /// Perform the "hello" registration process.
     /// Each storage engine can be different, so the 'hello' function needs to be
     /// specific to the engine, unfortunately.
@@ -557,6 +1140,7 @@ impl DbClient for DdbClientImpl {
         router_url: &str,
         mut defer_registration: bool,
     ) -> DbResult<HelloResponse> {
+        let _span = db_span("hello");
         let cur_month = self.settings.current_message_month.clone();
         // lookup_user
         let mut response = HelloResponse {
@@ -650,10 +1234,12 @@ impl DbClient for DdbClientImpl {
     }
 
     async fn router_table_exists(&self) -> DbResult<bool> {
+        let _span = db_span("router_table_exists");
         self.table_exists(self.settings.router_table.clone()).await
     }
 
     async fn message_table_exists(&self) -> DbResult<bool> {
+        let _span = db_span("message_table_exists");
         self.table_exists(self.settings.message_table.clone()).await
     }
 
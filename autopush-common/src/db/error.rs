@@ -1,6 +1,7 @@
 use rusoto_core::RusotoError;
 use rusoto_dynamodb::{
-    DeleteItemError, DescribeTableError, GetItemError, PutItemError, QueryError, UpdateItemError,
+    CreateTableError, DeleteItemError, DescribeTableError, GetItemError, PutItemError, QueryError,
+    UpdateItemError, UpdateTimeToLiveError,
 };
 use thiserror::Error;
 
@@ -23,6 +24,12 @@ pub enum DbError {
     #[error("Database error while performing DescribeTable")]
     DdbDescribeTable(#[from] RusotoError<DescribeTableError>),
 
+    #[error("Database error while performing CreateTable")]
+    DdbCreateTable(#[from] RusotoError<CreateTableError>),
+
+    #[error("Database error while performing UpdateTimeToLive")]
+    DdbUpdateTimeToLive(#[from] RusotoError<UpdateTimeToLiveError>),
+
     #[error("Database error while performing Query")]
     DdbQuery(#[from] RusotoError<QueryError>),
 
@@ -0,0 +1,481 @@
+//! An in-process data store, for the integration tests and local development
+//! without DynamoDB.
+//!
+//! Clones share their data, so a single store may back both an endpoint and
+//! a connection server running in the same process. The data is lost when
+//! the last clone is dropped. Only built with the `memory` feature.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::db::client::{DbClient, FetchMessageResponse};
+use crate::db::error::{DbError, DbResult};
+use crate::db::{ChannelMeta, DeadLetter, HelloResponse, User, USER_RECORD_VERSION};
+use crate::notification::Notification;
+use crate::util::sec_since_epoch;
+
+#[derive(Default)]
+struct Tables {
+    users: HashMap<Uuid, User>,
+    channels: HashMap<Uuid, HashMap<Uuid, ChannelMeta>>,
+    /// Each user's stored messages, by sort key
+    messages: HashMap<Uuid, BTreeMap<String, Notification>>,
+    dead_letters: BTreeMap<String, DeadLetter>,
+}
+
+#[derive(Clone)]
+pub struct MemoryDbClient {
+    tables: Arc<Mutex<Tables>>,
+    /// The message table name users are registered on (their `current_month`)
+    message_table: String,
+}
+
+impl Default for MemoryDbClient {
+    fn default() -> Self {
+        Self::new("message")
+    }
+}
+
+impl MemoryDbClient {
+    pub fn new(message_table: &str) -> Self {
+        Self {
+            tables: Default::default(),
+            message_table: message_table.to_owned(),
+        }
+    }
+
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().expect("Memory database lock poisoned")
+    }
+}
+
+/// The error DynamoDB would return for a failed condition expression
+fn conditional_check_failed(operation: &str) -> DbError {
+    DbError::General(format!("Conditional check failed during {operation}"))
+}
+
+#[async_trait]
+impl DbClient for MemoryDbClient {
+    async fn add_user(&self, user: &User) -> DbResult<()> {
+        let mut tables = self.tables();
+        if tables.users.contains_key(&user.uaid) {
+            return Err(conditional_check_failed("add_user"));
+        }
+        tables.users.insert(user.uaid, user.clone());
+        Ok(())
+    }
+
+    async fn update_user(&self, user: &User) -> DbResult<()> {
+        let mut tables = self.tables();
+        let existing = tables
+            .users
+            .get_mut(&user.uaid)
+            .filter(|existing| existing.router_type == user.router_type)
+            .filter(|existing| {
+                existing.node_id.is_none() || existing.connected_at < user.connected_at
+            })
+            .ok_or_else(|| conditional_check_failed("update_user"))?;
+
+        // Like DynamoDB's `SET`, unset optional fields keep their value
+        let mut updated = user.clone();
+        updated.last_connect = user.last_connect.or(existing.last_connect);
+        updated.node_id = user.node_id.clone().or_else(|| existing.node_id.take());
+        updated.record_version = user.record_version.or(existing.record_version);
+        updated.current_month = user
+            .current_month
+            .clone()
+            .or_else(|| existing.current_month.take());
+        updated.secrets_not_before = user.secrets_not_before.or(existing.secrets_not_before);
+        *existing = updated;
+        Ok(())
+    }
+
+    async fn migrate_user_router(&self, user: &User, old_router_type: &str) -> DbResult<()> {
+        let mut tables = self.tables();
+        let existing = tables
+            .users
+            .get_mut(&user.uaid)
+            .filter(|existing| {
+                existing.router_type == old_router_type || existing.router_type == user.router_type
+            })
+            .ok_or_else(|| conditional_check_failed("migrate_user_router"))?;
+        existing.router_type = user.router_type.clone();
+        existing.router_data = user.router_data.clone();
+        Ok(())
+    }
+
+    async fn get_user(&self, uaid: &Uuid) -> DbResult<Option<User>> {
+        Ok(self.tables().users.get(uaid).cloned())
+    }
+
+    async fn remove_user(&self, uaid: &Uuid) -> DbResult<()> {
+        self.tables().users.remove(uaid);
+        Ok(())
+    }
+
+    async fn add_channel(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<()> {
+        self.tables()
+            .channels
+            .entry(*uaid)
+            .or_default()
+            .entry(*channel_id)
+            .or_default();
+        Ok(())
+    }
+
+    async fn add_channel_with_meta(
+        &self,
+        uaid: &Uuid,
+        channel_id: &Uuid,
+        meta: &ChannelMeta,
+    ) -> DbResult<()> {
+        let mut tables = self.tables();
+        let channel = tables
+            .channels
+            .entry(*uaid)
+            .or_default()
+            .entry(*channel_id)
+            .or_default();
+        // The last delivery is stored apart from the rest of the metadata
+        *channel = ChannelMeta {
+            last_delivered: channel.last_delivered,
+            ..meta.clone()
+        };
+        Ok(())
+    }
+
+    async fn save_channels(
+        &self,
+        uaid: &Uuid,
+        channel_list: HashSet<&Uuid>,
+        _message_month: &str,
+    ) -> DbResult<()> {
+        let mut tables = self.tables();
+        let channels = tables.channels.entry(*uaid).or_default();
+        channels.retain(|channel_id, _| channel_list.contains(channel_id));
+        for channel_id in channel_list {
+            channels
+                .entry(*channel_id)
+                .or_insert_with(|| ChannelMeta::new(None));
+        }
+        Ok(())
+    }
+
+    async fn get_channels(&self, uaid: &Uuid) -> DbResult<HashSet<Uuid>> {
+        Ok(self
+            .tables()
+            .channels
+            .get(uaid)
+            .map(|channels| channels.keys().copied().collect())
+            .unwrap_or_default())
+    }
+
+    async fn get_channels_with_meta(&self, uaid: &Uuid) -> DbResult<HashMap<Uuid, ChannelMeta>> {
+        Ok(self
+            .tables()
+            .channels
+            .get(uaid)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn remove_channel(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<bool> {
+        Ok(self
+            .tables()
+            .channels
+            .get_mut(uaid)
+            .and_then(|channels| channels.remove(channel_id))
+            .is_some())
+    }
+
+    async fn remove_channels(
+        &self,
+        uaid: &Uuid,
+        channel_ids: &HashSet<Uuid>,
+    ) -> DbResult<HashSet<Uuid>> {
+        let mut tables = self.tables();
+        let channels = match tables.channels.get_mut(uaid) {
+            Some(channels) => channels,
+            None => return Ok(HashSet::new()),
+        };
+        Ok(channel_ids
+            .iter()
+            .filter(|channel_id| channels.remove(*channel_id).is_some())
+            .copied()
+            .collect())
+    }
+
+    async fn record_delivery(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<()> {
+        if let Some(meta) = self
+            .tables()
+            .channels
+            .get_mut(uaid)
+            .and_then(|channels| channels.get_mut(channel_id))
+        {
+            meta.last_delivered = Some(sec_since_epoch());
+        }
+        Ok(())
+    }
+
+    async fn remove_node_id(&self, uaid: &Uuid, node_id: &str, connected_at: u64) -> DbResult<()> {
+        let mut tables = self.tables();
+        let user = tables
+            .users
+            .get_mut(uaid)
+            .filter(|user| {
+                user.node_id.as_deref() == Some(node_id) && user.connected_at == connected_at
+            })
+            .ok_or_else(|| conditional_check_failed("remove_node_id"))?;
+        user.node_id = None;
+        Ok(())
+    }
+
+    async fn save_message(&self, uaid: &Uuid, message: Notification) -> DbResult<()> {
+        self.tables()
+            .messages
+            .entry(*uaid)
+            .or_default()
+            .insert(message.sort_key(), message);
+        Ok(())
+    }
+
+    async fn fetch_messages(&self, uaid: &Uuid, limit: usize) -> DbResult<FetchMessageResponse> {
+        // Topic messages sort before timestamped ("02:...") messages
+        let messages = self
+            .tables()
+            .messages
+            .get(uaid)
+            .map(|messages| {
+                messages
+                    .range::<str, _>((Bound::Unbounded, Bound::Excluded("02")))
+                    .take(limit)
+                    .map(|(_, message)| message.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(FetchMessageResponse {
+            timestamp: None,
+            messages,
+        })
+    }
+
+    async fn fetch_timestamp_messages(
+        &self,
+        uaid: &Uuid,
+        timestamp: Option<u64>,
+        limit: usize,
+    ) -> DbResult<FetchMessageResponse> {
+        let range_key = if let Some(ts) = timestamp {
+            format!("02:{}:z", ts)
+        } else {
+            "01;".to_string()
+        };
+        let messages: Vec<Notification> = self
+            .tables()
+            .messages
+            .get(uaid)
+            .map(|messages| {
+                messages
+                    .range::<str, _>((Bound::Excluded(range_key.as_str()), Bound::Unbounded))
+                    .take(limit)
+                    .map(|(_, message)| message.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let timestamp = messages.iter().filter_map(|m| m.sortkey_timestamp).max();
+        Ok(FetchMessageResponse {
+            timestamp,
+            messages,
+        })
+    }
+
+    async fn remove_message(&self, uaid: &Uuid, sort_key: &str) -> DbResult<()> {
+        if let Some(messages) = self.tables().messages.get_mut(uaid) {
+            messages.remove(sort_key);
+        }
+        Ok(())
+    }
+
+    async fn save_dead_letter(&self, dead_letter: &DeadLetter) -> DbResult<()> {
+        self.tables()
+            .dead_letters
+            .insert(dead_letter.id.clone(), dead_letter.clone());
+        Ok(())
+    }
+
+    async fn fetch_dead_letters(&self, limit: usize) -> DbResult<Vec<DeadLetter>> {
+        Ok(self
+            .tables()
+            .dead_letters
+            .values()
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_dead_letter(&self, id: &str) -> DbResult<Option<DeadLetter>> {
+        Ok(self.tables().dead_letters.get(id).cloned())
+    }
+
+    async fn remove_dead_letter(&self, id: &str) -> DbResult<()> {
+        self.tables().dead_letters.remove(id);
+        Ok(())
+    }
+
+    async fn hello(
+        &self,
+        connected_at: u64,
+        uaid: Option<&Uuid>,
+        router_url: &str,
+        defer_registration: bool,
+    ) -> DbResult<HelloResponse> {
+        let mut response = HelloResponse {
+            message_month: self.message_table.clone(),
+            connected_at,
+            ..Default::default()
+        };
+        if let Some(uaid) = uaid {
+            if let Some(mut user) = self.get_user(uaid).await? {
+                if user.current_month.as_deref() == Some(self.message_table.as_str()) {
+                    response.uaid = Some(user.uaid);
+                    response.check_storage = true;
+                    response.reset_uaid = user
+                        .record_version
+                        .map_or(true, |rec_ver| rec_ver < USER_RECORD_VERSION);
+                    user.node_id = Some(router_url.to_owned());
+                    user.connected_at = connected_at;
+                    self.update_user(&user).await?;
+                    return Ok(response);
+                }
+                // The user's message table has been retired
+                self.remove_user(uaid).await?;
+            }
+        }
+
+        let user = User {
+            uaid: uaid.copied().unwrap_or_else(Uuid::new_v4),
+            connected_at,
+            node_id: Some(router_url.to_owned()),
+            current_month: Some(self.message_table.clone()),
+            ..Default::default()
+        };
+        response.uaid = Some(user.uaid);
+        if defer_registration {
+            response.deferred_user_registration = Some(user);
+        } else {
+            self.add_user(&user).await?;
+        }
+        Ok(response)
+    }
+
+    async fn router_table_exists(&self) -> DbResult<bool> {
+        Ok(true)
+    }
+
+    async fn message_table_exists(&self) -> DbResult<bool> {
+        Ok(true)
+    }
+
+    fn message_table(&self) -> &str {
+        &self.message_table
+    }
+
+    fn box_clone(&self) -> Box<dyn DbClient> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use uuid::Uuid;
+
+    use super::MemoryDbClient;
+    use crate::db::client::DbClient;
+    use crate::db::User;
+    use crate::notification::Notification;
+
+    fn notification(channel_id: Uuid, topic: Option<&str>, sortkey_timestamp: u64) -> Notification {
+        Notification {
+            channel_id,
+            version: Uuid::new_v4().simple().to_string(),
+            ttl: 60,
+            topic: topic.map(str::to_owned),
+            timestamp: 0,
+            data: None,
+            sortkey_timestamp: topic.is_none().then_some(sortkey_timestamp),
+            headers: None,
+        }
+    }
+
+    /// Clones share their data, and conditions are checked like DynamoDB's
+    #[test]
+    fn users() {
+        let db = MemoryDbClient::default();
+        let other = db.clone();
+        let user = User {
+            node_id: Some("http://node".to_owned()),
+            ..Default::default()
+        };
+        block_on(db.add_user(&user)).unwrap();
+        assert!(block_on(other.add_user(&user)).is_err());
+        assert_eq!(
+            block_on(other.get_user(&user.uaid)).unwrap(),
+            Some(user.clone())
+        );
+
+        // A connected user is only updated by a newer connection
+        assert!(block_on(db.update_user(&user)).is_err());
+        let newer = User {
+            connected_at: user.connected_at + 1,
+            node_id: None,
+            ..user.clone()
+        };
+        block_on(db.update_user(&newer)).unwrap();
+        let updated = block_on(db.get_user(&user.uaid)).unwrap().unwrap();
+        assert_eq!(updated.connected_at, newer.connected_at);
+        assert_eq!(updated.node_id, user.node_id);
+
+        block_on(db.remove_node_id(&user.uaid, "http://node", newer.connected_at)).unwrap();
+        block_on(db.update_user(&user)).unwrap();
+    }
+
+    /// Topic and timestamped messages are fetched separately, in order
+    #[test]
+    fn messages() {
+        let db = MemoryDbClient::default();
+        let uaid = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+        for message in [
+            notification(channel_id, None, 20),
+            notification(channel_id, Some("a"), 0),
+            notification(channel_id, None, 10),
+        ] {
+            block_on(db.save_message(&uaid, message)).unwrap();
+        }
+
+        let topic = block_on(db.fetch_messages(&uaid, 10)).unwrap();
+        assert_eq!(topic.messages.len(), 1);
+        assert_eq!(topic.messages[0].topic.as_deref(), Some("a"));
+
+        let timestamped = block_on(db.fetch_timestamp_messages(&uaid, None, 10)).unwrap();
+        let timestamps: Vec<_> = timestamped
+            .messages
+            .iter()
+            .map(|m| m.sortkey_timestamp)
+            .collect();
+        assert_eq!(timestamps, [Some(10), Some(20)]);
+        assert_eq!(timestamped.timestamp, Some(20));
+        let later = block_on(db.fetch_timestamp_messages(&uaid, Some(10), 10)).unwrap();
+        assert_eq!(later.messages.len(), 1);
+
+        block_on(db.remove_message(&uaid, &topic.messages[0].sort_key())).unwrap();
+        assert!(block_on(db.fetch_messages(&uaid, 10))
+            .unwrap()
+            .messages
+            .is_empty());
+    }
+}
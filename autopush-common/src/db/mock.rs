@@ -4,10 +4,10 @@
 
 use crate::db::client::DbClient;
 use crate::db::error::DbResult;
-use crate::db::User;
+use crate::db::{ChannelMeta, DeadLetter, User};
 use crate::notification::Notification;
 use async_trait::async_trait;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -22,18 +22,28 @@ mockall::mock! {
 
         fn update_user(&self, user: &User) -> DbResult<()>;
 
+        fn migrate_user_router(&self, user: &User, old_router_type: &str) -> DbResult<()>;
+
         fn get_user(&self, uaid: &Uuid) -> DbResult<Option<User>>;
 
         fn remove_user(&self, uaid: &Uuid) -> DbResult<()>;
 
         fn add_channel(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<()>;
 
+        fn add_channel_with_meta(&self, uaid: &Uuid, channel_id: &Uuid, meta: &ChannelMeta) -> DbResult<()>;
+
         fn save_channels(&self, uaid: &Uuid, channel_list: HashSet<Uuid>, message_month: &str) -> DbResult<()>;
 
         fn get_channels(&self, uaid: &Uuid) -> DbResult<HashSet<Uuid>>;
 
+        fn get_channels_with_meta(&self, uaid: &Uuid) -> DbResult<HashMap<Uuid, ChannelMeta>>;
+
         fn remove_channel(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<bool>;
 
+        fn remove_channels(&self, uaid: &Uuid, channel_ids: &HashSet<Uuid>) -> DbResult<HashSet<Uuid>>;
+
+        fn record_delivery(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<()>;
+
         fn remove_node_id(&self, uaid: &Uuid, node_id: &str, connected_at: u64) -> DbResult<()>;
 
         fn save_message(&self, uaid: &Uuid, message: Notification) -> DbResult<()>;
@@ -49,6 +59,14 @@ mockall::mock! {
 
         fn remove_message(&self, uaid: &Uuid, sort_key: &str) -> DbResult<()>;
 
+        fn save_dead_letter(&self, dead_letter: &DeadLetter) -> DbResult<()>;
+
+        fn fetch_dead_letters(&self, limit: usize) -> DbResult<Vec<DeadLetter>>;
+
+        fn get_dead_letter(&self, id: &str) -> DbResult<Option<DeadLetter>>;
+
+        fn remove_dead_letter(&self, id: &str) -> DbResult<()>;
+
         fn hello(&self, connected_at: u64, uaid: Option<Uuid>, router_url: &str, defer_registration: bool) -> DbResult<HelloResponse>;
 
         fn router_table_exists(&self) -> DbResult<bool>;
@@ -71,6 +89,10 @@ impl DbClient for Arc<MockDbClient> {
         Arc::as_ref(self).update_user(user)
     }
 
+    async fn migrate_user_router(&self, user: &User, old_router_type: &str) -> DbResult<()> {
+        Arc::as_ref(self).migrate_user_router(user, old_router_type)
+    }
+
     async fn get_user(&self, uaid: &Uuid) -> DbResult<Option<User>> {
         Arc::as_ref(self).get_user(uaid)
     }
@@ -83,6 +105,15 @@ impl DbClient for Arc<MockDbClient> {
         Arc::as_ref(self).add_channel(uaid, channel_id)
     }
 
+    async fn add_channel_with_meta(
+        &self,
+        uaid: &Uuid,
+        channel_id: &Uuid,
+        meta: &ChannelMeta,
+    ) -> DbResult<()> {
+        Arc::as_ref(self).add_channel_with_meta(uaid, channel_id, meta)
+    }
+
     async fn save_channels(
         &self,
         uaid: &Uuid,
@@ -101,10 +132,26 @@ impl DbClient for Arc<MockDbClient> {
         Arc::as_ref(self).get_channels(uaid)
     }
 
+    async fn get_channels_with_meta(&self, uaid: &Uuid) -> DbResult<HashMap<Uuid, ChannelMeta>> {
+        Arc::as_ref(self).get_channels_with_meta(uaid)
+    }
+
     async fn remove_channel(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<bool> {
         Arc::as_ref(self).remove_channel(uaid, channel_id)
     }
 
+    async fn remove_channels(
+        &self,
+        uaid: &Uuid,
+        channel_ids: &HashSet<Uuid>,
+    ) -> DbResult<HashSet<Uuid>> {
+        Arc::as_ref(self).remove_channels(uaid, channel_ids)
+    }
+
+    async fn record_delivery(&self, uaid: &Uuid, channel_id: &Uuid) -> DbResult<()> {
+        Arc::as_ref(self).record_delivery(uaid, channel_id)
+    }
+
     async fn remove_node_id(&self, uaid: &Uuid, node_id: &str, connected_at: u64) -> DbResult<()> {
         Arc::as_ref(self).remove_node_id(uaid, node_id, connected_at)
     }
@@ -130,6 +177,22 @@ impl DbClient for Arc<MockDbClient> {
         Arc::as_ref(self).remove_message(uaid, sort_key)
     }
 
+    async fn save_dead_letter(&self, dead_letter: &DeadLetter) -> DbResult<()> {
+        Arc::as_ref(self).save_dead_letter(dead_letter)
+    }
+
+    async fn fetch_dead_letters(&self, limit: usize) -> DbResult<Vec<DeadLetter>> {
+        Arc::as_ref(self).fetch_dead_letters(limit)
+    }
+
+    async fn get_dead_letter(&self, id: &str) -> DbResult<Option<DeadLetter>> {
+        Arc::as_ref(self).get_dead_letter(id)
+    }
+
+    async fn remove_dead_letter(&self, id: &str) -> DbResult<()> {
+        Arc::as_ref(self).remove_dead_letter(id)
+    }
+
     async fn hello(
         &self,
         connected_at: u64,
@@ -19,11 +19,15 @@ use serde::Serializer;
 use serde_derive::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::db::client::DbClient;
+use crate::db::error::DbResult;
 use crate::db::util::generate_last_connect;
 
 pub mod client;
 pub mod dynamodb;
 pub mod error;
+#[cfg(any(test, feature = "memory"))]
+pub mod memory;
 pub mod models;
 //pub mod bigtable;
 //pub mod postgres;
@@ -38,6 +42,8 @@ use crate::util::timing::{ms_since_epoch, sec_since_epoch};
 use models::{NotificationHeaders, RangeKey};
 
 const MAX_EXPIRY: u64 = 2_592_000;
+/// How many stored messages to fetch at a time when purging them
+const PURGE_PAGE_SIZE: usize = 1000;
 const USER_RECORD_VERSION: u8 = 1;
 /// The maximum TTL for channels, 30 days
 pub const MAX_CHANNEL_TTL: u64 = 30 * 24 * 60 * 60;
@@ -268,6 +274,10 @@ pub struct User {
     /// LEGACY: Current month table in the database the user is on
     #[serde(skip_serializing_if = "Option::is_none")]
     pub current_month: Option<String>,
+    /// Time in milliseconds before which the user's registration secrets
+    /// were revoked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets_not_before: Option<u64>,
 }
 
 impl Default for User {
@@ -283,7 +293,166 @@ impl Default for User {
             node_id: None,
             record_version: Some(USER_RECORD_VERSION),
             current_month: None,
+            secrets_not_before: None,
+        }
+    }
+}
+
+/// Metadata about one of a user's channels. Channels created before metadata
+/// was recorded have none.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ChannelMeta {
+    /// When the channel was created, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// The base64url encoded SHA-256 hash of the channel's VAPID public key
+    /// (for v2 endpoints)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_hash: Option<String>,
+    /// When a notification was last delivered to the channel, in seconds.
+    /// This is stored separately from the rest of the metadata.
+    #[serde(skip)]
+    pub last_delivered: Option<u64>,
+}
+
+impl ChannelMeta {
+    /// Metadata for a channel created now
+    pub fn new(key_hash: Option<String>) -> Self {
+        ChannelMeta {
+            created_at: Some(sec_since_epoch()),
+            key_hash,
+            last_delivered: None,
+        }
+    }
+}
+
+/// List a user's channels and their metadata, ordered by channel ID, as the
+/// registration API, the admin API and `autopush-admin` report them. Times
+/// are in milliseconds, like a subscription's `expirationTime`.
+pub fn channels_json(channels: HashMap<Uuid, ChannelMeta>) -> Vec<serde_json::Value> {
+    let mut channels: Vec<_> = channels.into_iter().collect();
+    channels.sort_by_key(|(channel_id, _)| *channel_id);
+    channels
+        .into_iter()
+        .map(|(channel_id, meta)| {
+            serde_json::json!({
+                "channelID": channel_id,
+                "createdAt": meta.created_at.map(|time| time * 1000),
+                "keyHash": meta.key_hash,
+                "lastDelivered": meta.last_delivered.map(|time| time * 1000),
+            })
+        })
+        .collect()
+}
+
+/// Fetch up to `limit` each of a user's topic and timestamped messages, as
+/// the admin API and `autopush-admin` list them
+pub async fn fetch_stored_messages(
+    db: &dyn DbClient,
+    uaid: &Uuid,
+    limit: usize,
+) -> DbResult<Vec<Notification>> {
+    let mut messages = db.fetch_messages(uaid, limit).await?.messages;
+    messages.extend(
+        db.fetch_timestamp_messages(uaid, None, limit)
+            .await?
+            .messages,
+    );
+    Ok(messages)
+}
+
+/// Remove all of a user's stored messages, returning how many were removed
+pub async fn purge_stored_messages(db: &dyn DbClient, uaid: &Uuid) -> DbResult<usize> {
+    let mut removed = 0;
+    // Removed topic messages no longer match the query, so it's repeated
+    // until there are none left
+    loop {
+        let messages = db.fetch_messages(uaid, PURGE_PAGE_SIZE).await?.messages;
+        if messages.is_empty() {
+            break;
+        }
+        removed += remove_messages(db, uaid, messages).await?;
+    }
+    // Timestamped messages are paged through by their timestamp. A page may
+    // hold fewer messages than the limit while there are more to come.
+    let mut timestamp = None;
+    loop {
+        let response = db
+            .fetch_timestamp_messages(uaid, timestamp, PURGE_PAGE_SIZE)
+            .await?;
+        if response.messages.is_empty() {
+            break;
         }
+        removed += remove_messages(db, uaid, response.messages).await?;
+        timestamp = match response.timestamp {
+            Some(next) => Some(next),
+            None => break,
+        };
+    }
+    Ok(removed)
+}
+
+async fn remove_messages(
+    db: &dyn DbClient,
+    uaid: &Uuid,
+    messages: Vec<Notification>,
+) -> DbResult<usize> {
+    let count = messages.len();
+    for message in messages {
+        db.remove_message(uaid, &message.sort_key()).await?;
+    }
+    Ok(count)
+}
+
+/// Delete a user along with their channels and stored messages, returning
+/// how many messages were removed
+pub async fn drop_user(db: &dyn DbClient, uaid: &Uuid) -> DbResult<usize> {
+    let removed = purge_stored_messages(db, uaid).await?;
+    let channel_ids = db.get_channels(uaid).await?;
+    if !channel_ids.is_empty() {
+        db.remove_channels(uaid, &channel_ids).await?;
+    }
+    db.remove_user(uaid).await?;
+    Ok(removed)
+}
+
+/// A bridged notification which could not be delivered after retrying. It is
+/// kept until its TTL runs out so that it may be replayed.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct DeadLetter {
+    /// Unique ID of the dead letter
+    pub id: String,
+    /// The UAID the notification was sent to
+    pub uaid: Uuid,
+    /// The channel the notification was sent to
+    pub channel_id: Uuid,
+    /// The notification's message ID
+    pub message_id: String,
+    /// The router which failed to deliver the notification
+    pub router_type: String,
+    /// Time in seconds from epoch when the notification was received
+    pub timestamp: u64,
+    /// TTL value provided by the application server for the notification
+    pub ttl: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub urgency: Option<String>,
+    /// The notification's encryption headers
+    pub headers: HashMap<String, String>,
+    /// The encrypted notification body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// Time in seconds from epoch when the last delivery attempt failed
+    pub failed_at: u64,
+    /// The error from the last delivery attempt
+    pub reason: String,
+}
+
+impl DeadLetter {
+    /// The number of seconds left until the notification expires
+    pub fn ttl_remaining(&self, at_sec: u64) -> u64 {
+        (self.timestamp + self.ttl).saturating_sub(at_sec)
     }
 }
 
@@ -433,3 +602,152 @@ impl NotificationRecord {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        channels_json, drop_user, fetch_stored_messages, purge_stored_messages, ChannelMeta,
+    };
+    use crate::db::client::FetchMessageResponse;
+    use crate::db::mock::MockDbClient;
+    use crate::notification::Notification;
+    use futures::executor::block_on;
+    use std::collections::{HashMap, HashSet};
+    use uuid::Uuid;
+
+    fn message(topic: Option<&str>, sortkey_timestamp: Option<u64>) -> Notification {
+        Notification {
+            channel_id: Uuid::new_v4(),
+            version: Uuid::new_v4().to_string(),
+            ttl: 60,
+            topic: topic.map(str::to_owned),
+            timestamp: 0,
+            data: None,
+            sortkey_timestamp,
+            headers: None,
+        }
+    }
+
+    /// Channels are listed in order, with their times in milliseconds
+    #[test]
+    fn channels_json_in_millis() {
+        let first = Uuid::from_u128(1);
+        let second = Uuid::from_u128(2);
+        let channels = HashMap::from([
+            (second, ChannelMeta::default()),
+            (
+                first,
+                ChannelMeta {
+                    created_at: Some(10),
+                    key_hash: Some("test-hash".to_owned()),
+                    last_delivered: Some(20),
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            channels_json(channels),
+            vec![
+                serde_json::json!({
+                    "channelID": first,
+                    "createdAt": 10_000,
+                    "keyHash": "test-hash",
+                    "lastDelivered": 20_000,
+                }),
+                serde_json::json!({
+                    "channelID": second,
+                    "createdAt": null,
+                    "keyHash": null,
+                    "lastDelivered": null,
+                }),
+            ]
+        );
+    }
+
+    /// Both topic and timestamped messages are listed
+    #[test]
+    fn fetches_both_kinds_of_message() {
+        let mut db = MockDbClient::new();
+        db.expect_fetch_messages().times(1).return_once(|_, _| {
+            Ok(FetchMessageResponse {
+                timestamp: None,
+                messages: vec![message(Some("topic"), None)],
+            })
+        });
+        db.expect_fetch_timestamp_messages()
+            .withf(|_, timestamp, limit| timestamp.is_none() && *limit == 10)
+            .times(1)
+            .return_once(|_, _, _| {
+                Ok(FetchMessageResponse {
+                    timestamp: Some(1),
+                    messages: vec![message(None, Some(1))],
+                })
+            });
+
+        let db = db.into_boxed_arc();
+        let messages = block_on(fetch_stored_messages(&*db, &Uuid::new_v4(), 10)).unwrap();
+        let sort_keys: Vec<String> = messages.iter().map(Notification::sort_key).collect();
+        assert_eq!(sort_keys.len(), 2);
+        assert!(sort_keys[0].starts_with("01:") && sort_keys[0].ends_with(":topic"));
+        assert!(sort_keys[1].starts_with("02:1:"));
+    }
+
+    /// Purging pages through the timestamped messages by their timestamp,
+    /// even when a page isn't full
+    #[test]
+    fn purges_all_messages() {
+        let mut db = MockDbClient::new();
+        let mut topic_fetches = 0;
+        db.expect_fetch_messages().times(2).returning(move |_, _| {
+            topic_fetches += 1;
+            Ok(FetchMessageResponse {
+                timestamp: None,
+                messages: if topic_fetches == 1 {
+                    vec![message(Some("topic"), None)]
+                } else {
+                    vec![]
+                },
+            })
+        });
+        db.expect_fetch_timestamp_messages()
+            .times(3)
+            .returning(|_, timestamp, _| {
+                let messages = match timestamp {
+                    None => vec![message(None, Some(1)), message(None, Some(2))],
+                    Some(2) => vec![message(None, Some(3))],
+                    _ => vec![],
+                };
+                Ok(FetchMessageResponse {
+                    timestamp: messages.iter().filter_map(|m| m.sortkey_timestamp).max(),
+                    messages,
+                })
+            });
+        db.expect_remove_message().times(4).returning(|_, _| Ok(()));
+
+        let db = db.into_boxed_arc();
+        let removed = block_on(purge_stored_messages(&*db, &Uuid::new_v4())).unwrap();
+        assert_eq!(removed, 4);
+    }
+
+    /// Dropping a user also removes their channels and stored messages
+    #[test]
+    fn drops_channels_and_messages() {
+        let channel_id = Uuid::new_v4();
+        let mut db = MockDbClient::new();
+        db.expect_fetch_messages()
+            .returning(|_, _| Ok(FetchMessageResponse::default()));
+        db.expect_fetch_timestamp_messages()
+            .returning(|_, _, _| Ok(FetchMessageResponse::default()));
+        db.expect_get_channels()
+            .times(1)
+            .return_once(move |_| Ok(HashSet::from([channel_id])));
+        db.expect_remove_channels()
+            .withf(move |_, channel_ids| channel_ids == &HashSet::from([channel_id]))
+            .times(1)
+            .return_once(|_, channel_ids| Ok(channel_ids.clone()));
+        db.expect_remove_user().times(1).return_once(|_| Ok(()));
+
+        let db = db.into_boxed_arc();
+        assert_eq!(block_on(drop_user(&*db, &Uuid::new_v4())).unwrap(), 0);
+    }
+}
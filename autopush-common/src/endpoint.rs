@@ -1,7 +1,7 @@
 use crate::errors::{ApcErrorKind, Result};
 use crate::util::b64_decode_url;
 
-use fernet::MultiFernet;
+use fernet::{Fernet, MultiFernet};
 use openssl::hash;
 use url::Url;
 use uuid::Uuid;
@@ -18,27 +18,287 @@ pub fn make_endpoint(
     endpoint_url: &str,
     fernet: &MultiFernet,
 ) -> Result<String> {
-    let root = Url::parse(endpoint_url)?.join("wpush/")?;
+    make_endpoint_with_expiry(uaid, chid, key, None, endpoint_url, fernet)
+}
+
+/// Create a WebPush endpoint that stops accepting notifications after
+/// `expiry` (UNIX timestamp in seconds). Without an expiry this is the same
+/// as `make_endpoint`.
+//  v3 is the uaid + chid + expiry (u64, big endian) [+ sha256(key).bytes]
+pub fn make_endpoint_with_expiry(
+    uaid: &Uuid,
+    chid: &Uuid,
+    key: Option<&str>,
+    expiry: Option<u64>,
+    endpoint_url: &str,
+    fernet: &MultiFernet,
+) -> Result<String> {
+    let (version, base) = endpoint_token(uaid, chid, key, expiry)?;
+    endpoint_from_encrypted(version, &fernet.encrypt(&base), endpoint_url)
+}
+
+/// Create a WebPush endpoint encrypted with a specific key, rather than the
+/// primary key of a `MultiFernet`
+pub fn make_endpoint_with_key(
+    uaid: &Uuid,
+    chid: &Uuid,
+    key: Option<&str>,
+    expiry: Option<u64>,
+    endpoint_url: &str,
+    fernet: &Fernet,
+) -> Result<String> {
+    let (version, base) = endpoint_token(uaid, chid, key, expiry)?;
+    endpoint_from_encrypted(version, &fernet.encrypt(&base), endpoint_url)
+}
+
+/// Re-encrypt an already decrypted endpoint token with the given key. This is
+/// used to migrate endpoints away from retired keys.
+pub fn reissue_endpoint(
+    version: &str,
+    token: &[u8],
+    endpoint_url: &str,
+    fernet: &Fernet,
+) -> Result<String> {
+    endpoint_from_encrypted(version, &fernet.encrypt(token), endpoint_url)
+}
+
+/// Build the unencrypted token for an endpoint, returning the endpoint version
+/// and the token bytes
+fn endpoint_token(
+    uaid: &Uuid,
+    chid: &Uuid,
+    key: Option<&str>,
+    expiry: Option<u64>,
+) -> Result<(&'static str, Vec<u8>)> {
     let mut base = uaid.as_bytes().to_vec();
     base.extend(chid.as_bytes());
 
-    if let Some(k) = key {
-        let raw_key = b64_decode_url(k)
-            .map_err(|_e| ApcErrorKind::PayloadError("Error encrypting payload".to_owned()))?;
-        let key_digest = hash::hash(hash::MessageDigest::sha256(), &raw_key).map_err(|_e| {
-            ApcErrorKind::PayloadError("Error creating message digest for key".to_owned())
-        })?;
-        base.extend(key_digest.iter());
-        let encrypted = fernet.encrypt(&base).trim_matches('=').to_string();
-        let final_url = root.join(&format!("v2/{encrypted}")).map_err(|_e| {
-            ApcErrorKind::PayloadError("Encrypted data is not URL-safe".to_owned())
-        })?;
-        Ok(final_url.to_string())
+    let version = if let Some(expiry) = expiry {
+        base.extend(expiry.to_be_bytes());
+        "v3"
+    } else if key.is_some() {
+        "v2"
     } else {
-        let encrypted = fernet.encrypt(&base).trim_matches('=').to_string();
-        let final_url = root.join(&format!("v1/{encrypted}")).map_err(|_e| {
-            ApcErrorKind::PayloadError("Encrypted data is not URL-safe".to_owned())
-        })?;
-        Ok(final_url.to_string())
+        "v1"
+    };
+
+    if let Some(k) = key {
+        base.extend(key_digest(k)?);
+    }
+
+    Ok((version, base))
+}
+
+/// Get the SHA-256 digest of a base64url encoded VAPID public key, as
+/// embedded in v2 endpoints
+pub fn key_digest(key: &str) -> Result<Vec<u8>> {
+    let raw_key = b64_decode_url(key)
+        .map_err(|_e| ApcErrorKind::PayloadError("Error encrypting payload".to_owned()))?;
+    let key_digest = hash::hash(hash::MessageDigest::sha256(), &raw_key).map_err(|_e| {
+        ApcErrorKind::PayloadError("Error creating message digest for key".to_owned())
+    })?;
+    Ok(key_digest.to_vec())
+}
+
+/// The identifiers carried by a WebPush endpoint
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EndpointInfo {
+    pub version: String,
+    pub uaid: Uuid,
+    pub chid: Uuid,
+    /// The SHA-256 digest of the VAPID public key the endpoint is restricted
+    /// to (v2 and v3)
+    pub key_hash: Option<Vec<u8>>,
+    /// When the endpoint stops accepting notifications (v3)
+    pub expiry: Option<u64>,
+}
+
+/// Decode a WebPush endpoint, the reverse of `make_endpoint`. Either the
+/// full endpoint URL or its `{version}/{token}` path may be given.
+pub fn decode_endpoint(endpoint: &str, fernet: &MultiFernet) -> Result<EndpointInfo> {
+    let path = endpoint
+        .trim()
+        .trim_end_matches('/')
+        .rsplit("/wpush/")
+        .next()
+        .unwrap_or_default();
+    let (version, token) = path
+        .split_once('/')
+        .ok_or_else(|| ApcErrorKind::PayloadError("Invalid endpoint".to_owned()))?;
+    let padding = "=".repeat((4 - token.len() % 4) % 4);
+    let token = fernet
+        .decrypt(&format!("{token}{padding}"))
+        .map_err(|_e| ApcErrorKind::PayloadError("Could not decrypt endpoint".to_owned()))?;
+
+    let (key_hash, expiry) = match (version, token.len()) {
+        ("v1", 32) => (None, None),
+        ("v2", 64) => (Some(token[32..].to_vec()), None),
+        ("v3", 40) | ("v3", 72) => {
+            let mut expiry = [0; 8];
+            expiry.copy_from_slice(&token[32..40]);
+            let key_hash = (token.len() == 72).then(|| token[40..].to_vec());
+            (key_hash, Some(u64::from_be_bytes(expiry)))
+        }
+        _ => {
+            return Err(ApcErrorKind::PayloadError(format!(
+                "Invalid {version} endpoint token length: {}",
+                token.len()
+            ))
+            .into())
+        }
+    };
+
+    Ok(EndpointInfo {
+        version: version.to_owned(),
+        uaid: Uuid::from_slice(&token[..16]).expect("Token is long enough"),
+        chid: Uuid::from_slice(&token[16..32]).expect("Token is long enough"),
+        key_hash,
+        expiry,
+    })
+}
+
+/// Build the endpoint URL from an encrypted token
+fn endpoint_from_encrypted(version: &str, encrypted: &str, endpoint_url: &str) -> Result<String> {
+    let root = Url::parse(endpoint_url)?.join("wpush/")?;
+    let encrypted = encrypted.trim_matches('=');
+    let final_url = root
+        .join(&format!("{version}/{encrypted}"))
+        .map_err(|_e| ApcErrorKind::PayloadError("Encrypted data is not URL-safe".to_owned()))?;
+    Ok(final_url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_endpoint, key_digest, make_endpoint, make_endpoint_with_expiry,
+        make_endpoint_with_key, reissue_endpoint,
+    };
+    use fernet::{Fernet, MultiFernet};
+    use uuid::Uuid;
+
+    const ENDPOINT_URL: &str = "https://push.example.com";
+    const PUBLIC_KEY: &str =
+        "BM3bVjW_wuZC54alIbqjTbaBNtthriVtdZlchOyOSdbVYeYQu2i5inJdft7jUWIAy4O9xHBbY196Gf-1odb8hds";
+
+    /// Decrypt the token from an endpoint
+    fn decrypt(fernet: &MultiFernet, endpoint: &str, version: &str) -> Vec<u8> {
+        let prefix = format!("{ENDPOINT_URL}/wpush/{version}/");
+        let token = endpoint.strip_prefix(&prefix).expect("Unexpected endpoint");
+        let padding = "=".repeat((4 - token.len() % 4) % 4);
+        fernet.decrypt(&format!("{token}{padding}")).unwrap()
+    }
+
+    #[test]
+    fn test_endpoint_versions() {
+        let fernet = MultiFernet::new(vec![Fernet::new(&Fernet::generate_key()).unwrap()]);
+        let uaid = Uuid::new_v4();
+        let chid = Uuid::new_v4();
+
+        let v1 = make_endpoint(&uaid, &chid, None, ENDPOINT_URL, &fernet).unwrap();
+        assert_eq!(decrypt(&fernet, &v1, "v1").len(), 32);
+
+        let v2 = make_endpoint(&uaid, &chid, Some(PUBLIC_KEY), ENDPOINT_URL, &fernet).unwrap();
+        assert_eq!(decrypt(&fernet, &v2, "v2").len(), 64);
+
+        let v3 = make_endpoint_with_expiry(&uaid, &chid, None, Some(1234), ENDPOINT_URL, &fernet)
+            .unwrap();
+        let token = decrypt(&fernet, &v3, "v3");
+        assert_eq!(token.len(), 40);
+        assert_eq!(&token[..16], uaid.as_bytes());
+        assert_eq!(&token[32..40], &1234u64.to_be_bytes());
+
+        let v3_key = make_endpoint_with_expiry(
+            &uaid,
+            &chid,
+            Some(PUBLIC_KEY),
+            Some(1234),
+            ENDPOINT_URL,
+            &fernet,
+        )
+        .unwrap();
+        assert_eq!(decrypt(&fernet, &v3_key, "v3").len(), 72);
+    }
+
+    #[test]
+    fn test_endpoint_with_key() {
+        let old_key = Fernet::new(&Fernet::generate_key()).unwrap();
+        let new_key = Fernet::new(&Fernet::generate_key()).unwrap();
+        let fernet = MultiFernet::new(vec![
+            Fernet::new(&Fernet::generate_key()).unwrap(),
+            old_key.clone(),
+        ]);
+        let uaid = Uuid::new_v4();
+        let chid = Uuid::new_v4();
+
+        // Endpoints made with a secondary key are still readable
+        let endpoint =
+            make_endpoint_with_key(&uaid, &chid, None, None, ENDPOINT_URL, &old_key).unwrap();
+        let token = decrypt(&fernet, &endpoint, "v1");
+
+        // Reissuing keeps the token contents but changes the key
+        let reissued = reissue_endpoint("v1", &token, ENDPOINT_URL, &new_key).unwrap();
+        assert_ne!(reissued, endpoint);
+        let new_fernet = MultiFernet::new(vec![new_key]);
+        assert_eq!(decrypt(&new_fernet, &reissued, "v1"), token);
+    }
+
+    /// v2 endpoints end with the key digest
+    #[test]
+    fn test_key_digest() {
+        let fernet = MultiFernet::new(vec![Fernet::new(&Fernet::generate_key()).unwrap()]);
+        let endpoint = make_endpoint(
+            &Uuid::new_v4(),
+            &Uuid::new_v4(),
+            Some(PUBLIC_KEY),
+            ENDPOINT_URL,
+            &fernet,
+        )
+        .unwrap();
+
+        let digest = key_digest(PUBLIC_KEY).unwrap();
+        assert_eq!(digest.len(), 32);
+        assert!(decrypt(&fernet, &endpoint, "v2").ends_with(&digest));
+        assert!(key_digest("not base64!").is_err());
+    }
+
+    /// Decoding recovers the identifiers of each endpoint version
+    #[test]
+    fn test_decode_endpoint() {
+        let fernet = MultiFernet::new(vec![Fernet::new(&Fernet::generate_key()).unwrap()]);
+        let uaid = Uuid::new_v4();
+        let chid = Uuid::new_v4();
+        let digest = key_digest(PUBLIC_KEY).unwrap();
+
+        let v1 = make_endpoint(&uaid, &chid, None, ENDPOINT_URL, &fernet).unwrap();
+        let info = decode_endpoint(&v1, &fernet).unwrap();
+        assert_eq!(info.version, "v1");
+        assert_eq!((info.uaid, info.chid), (uaid, chid));
+        assert_eq!((info.key_hash, info.expiry), (None, None));
+
+        let v2 = make_endpoint(&uaid, &chid, Some(PUBLIC_KEY), ENDPOINT_URL, &fernet).unwrap();
+        let path = v2.split("/wpush/").nth(1).unwrap();
+        let info = decode_endpoint(path, &fernet).unwrap();
+        assert_eq!(info.version, "v2");
+        assert_eq!(info.key_hash.as_ref(), Some(&digest));
+
+        let v3 = make_endpoint_with_expiry(
+            &uaid,
+            &chid,
+            Some(PUBLIC_KEY),
+            Some(1234),
+            ENDPOINT_URL,
+            &fernet,
+        )
+        .unwrap();
+        let info = decode_endpoint(&v3, &fernet).unwrap();
+        assert_eq!(info.chid, chid);
+        assert_eq!(info.key_hash, Some(digest));
+        assert_eq!(info.expiry, Some(1234));
+
+        // The version must match the token
+        let bad = v1.replace("/v1/", "/v2/");
+        assert!(decode_endpoint(&bad, &fernet).is_err());
+        let other = MultiFernet::new(vec![Fernet::new(&Fernet::generate_key()).unwrap()]);
+        assert!(decode_endpoint(&v1, &other).is_err());
     }
 }
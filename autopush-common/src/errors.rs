@@ -180,17 +180,48 @@ impl ApcErrorKind {
         }
     }
 
-    pub fn metric_label(&self) -> Option<String> {
+    pub fn metric_label(&self) -> Option<&'static str> {
         // TODO: add labels for skipped stuff
-        let resp = match self {
-            Self::PongTimeout => "pong_timeout",
-            Self::ExcessivePing => "excessive_ping",
-            _ => "",
-        };
-        if !resp.is_empty() {
-            return Some(resp.to_owned());
+        match self {
+            Self::PongTimeout => Some("pong_timeout"),
+            Self::ExcessivePing => Some("excessive_ping"),
+            _ => None,
         }
-        None
+    }
+}
+
+/// An error which the `SentryWrapper` middleware may report to Sentry
+pub trait ReportableError: std::fmt::Debug {
+    /// The error to report: the error without its backtrace, which is
+    /// reported separately
+    fn reportable_source(&self) -> &(dyn std::error::Error + 'static);
+
+    /// Where the error was created
+    fn backtrace(&self) -> Option<&Backtrace>;
+
+    /// If the error should be reported. Errors which aren't are only
+    /// recorded as metrics.
+    fn is_sentry_event(&self) -> bool;
+
+    /// The label of the metric recorded for errors which aren't reported
+    fn metric_label(&self) -> Option<&'static str>;
+}
+
+impl ReportableError for ApcError {
+    fn reportable_source(&self) -> &(dyn std::error::Error + 'static) {
+        &self.kind
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        Some(&self.backtrace)
+    }
+
+    fn is_sentry_event(&self) -> bool {
+        self.kind.is_sentry_event()
+    }
+
+    fn metric_label(&self) -> Option<&'static str> {
+        self.kind.metric_label()
     }
 }
 
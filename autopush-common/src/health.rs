@@ -0,0 +1,251 @@
+//! Dockerflow compliant `/__heartbeat__` reports.
+//!
+//! Each component (the database, a bridge, the Megaphone poller...) reports
+//! `ok`, `warning` or `error`, and the report's status is the worst of them.
+//! Only errors fail the heartbeat: a warning means the server is degraded
+//! (e.g. a bridge is unavailable) but should stay in the load balancer.
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use actix_http::StatusCode;
+use serde::Serialize;
+
+use crate::util::sec_since_epoch;
+
+/// The health of a component, ordered from best to worst
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ComponentHealth {
+    pub fn ok() -> Self {
+        ComponentHealth {
+            status: HealthStatus::Ok,
+            detail: None,
+        }
+    }
+
+    pub fn warning(detail: impl Into<String>) -> Self {
+        ComponentHealth {
+            status: HealthStatus::Warning,
+            detail: Some(detail.into()),
+        }
+    }
+
+    pub fn error(detail: impl Into<String>) -> Self {
+        ComponentHealth {
+            status: HealthStatus::Error,
+            detail: Some(detail.into()),
+        }
+    }
+
+    /// Add a detail without changing the status
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Downgrade an error to a warning, for components the server can run
+    /// without
+    pub fn degraded(mut self) -> Self {
+        if self.status == HealthStatus::Error {
+            self.status = HealthStatus::Warning;
+        }
+        self
+    }
+
+    /// Combine the health of several instances of a component (e.g. a
+    /// bridge's clients) into the worst of them. The details are prefixed
+    /// with the instance name.
+    pub fn worst<I, S>(instances: I) -> Self
+    where
+        I: IntoIterator<Item = (S, ComponentHealth)>,
+        S: AsRef<str>,
+    {
+        let mut status = HealthStatus::Ok;
+        let mut details = Vec::new();
+        for (name, health) in instances {
+            status = status.max(health.status);
+            if let Some(detail) = health.detail {
+                details.push(format!("{}: {}", name.as_ref(), detail));
+            }
+        }
+        ComponentHealth {
+            status,
+            detail: (!details.is_empty()).then(|| details.join("; ")),
+        }
+    }
+}
+
+/// Run a health check, failing it with an error if it takes longer than
+/// `timeout`
+pub async fn probe<F>(timeout: Duration, check: F) -> ComponentHealth
+where
+    F: Future<Output = ComponentHealth>,
+{
+    match actix_web::rt::time::timeout(timeout, check).await {
+        Ok(health) => health,
+        Err(_) => ComponentHealth::error(format!("Timed out after {}ms", timeout.as_millis())),
+    }
+}
+
+/// Tracks when an operation (e.g. a poll or a request over a connection)
+/// last succeeded and failed
+#[derive(Debug, Default)]
+pub struct HealthTracker {
+    /// Seconds since the epoch, 0 if never
+    last_success: AtomicU64,
+    last_failure: AtomicU64,
+}
+
+impl HealthTracker {
+    pub fn record_success(&self) {
+        self.last_success
+            .store(sec_since_epoch(), Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self) {
+        self.last_failure
+            .store(sec_since_epoch(), Ordering::Relaxed);
+    }
+
+    pub fn last_success(&self) -> Option<u64> {
+        Some(self.last_success.load(Ordering::Relaxed)).filter(|&t| t > 0)
+    }
+
+    pub fn last_failure(&self) -> Option<u64> {
+        Some(self.last_failure.load(Ordering::Relaxed)).filter(|&t| t > 0)
+    }
+
+    /// A warning if the latest attempt failed
+    pub fn connection_health(&self) -> ComponentHealth {
+        match self.last_failure() {
+            Some(failure) if Some(failure) > self.last_success() => {
+                ComponentHealth::warning(format!("Last request failed at {failure}"))
+            }
+            _ => ComponentHealth::ok(),
+        }
+    }
+
+    /// A warning if the operation hasn't succeeded within `max_age`
+    pub fn freshness(&self, max_age: Duration) -> ComponentHealth {
+        match self.last_success() {
+            None => ComponentHealth::warning("No successful attempt yet"),
+            Some(success) if sec_since_epoch().saturating_sub(success) > max_age.as_secs() => {
+                ComponentHealth::warning(format!("Last succeeded at {success}"))
+            }
+            Some(success) => {
+                ComponentHealth::ok().with_detail(format!("Last succeeded at {success}"))
+            }
+        }
+    }
+}
+
+/// The health of each of a server's components
+#[derive(Debug, Default)]
+pub struct HealthReport {
+    checks: BTreeMap<String, ComponentHealth>,
+}
+
+impl HealthReport {
+    pub fn add(&mut self, name: impl Into<String>, health: ComponentHealth) {
+        self.checks.insert(name.into(), health);
+    }
+
+    /// The worst status of the components
+    pub fn status(&self) -> HealthStatus {
+        self.checks
+            .values()
+            .map(|health| health.status)
+            .max()
+            .unwrap_or(HealthStatus::Ok)
+    }
+
+    /// The heartbeat response status: only errors fail the heartbeat
+    pub fn http_status(&self) -> StatusCode {
+        match self.status() {
+            HealthStatus::Error => StatusCode::INTERNAL_SERVER_ERROR,
+            HealthStatus::Ok | HealthStatus::Warning => StatusCode::OK,
+        }
+    }
+
+    pub fn to_json(&self, version: &str) -> serde_json::Value {
+        serde_json::json!({
+            "status": self.status(),
+            "version": version,
+            "checks": self.checks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_http::StatusCode;
+
+    use super::{ComponentHealth, HealthReport, HealthStatus, HealthTracker};
+
+    /// Warnings degrade the report without failing the heartbeat
+    #[test]
+    fn report_status() {
+        let mut report = HealthReport::default();
+        report.add("database", ComponentHealth::ok());
+        assert_eq!(report.status(), HealthStatus::Ok);
+
+        report.add("router.fcm", ComponentHealth::error("No token").degraded());
+        assert_eq!(report.status(), HealthStatus::Warning);
+        assert_eq!(report.http_status(), StatusCode::OK);
+
+        report.add("database", ComponentHealth::error("Timed out"));
+        assert_eq!(report.status(), HealthStatus::Error);
+        assert_eq!(report.http_status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(report.to_json("1.0")["status"], "error");
+        assert_eq!(
+            report.to_json("1.0")["checks"]["router.fcm"]["status"],
+            "warning"
+        );
+    }
+
+    #[test]
+    fn worst_instance() {
+        let health = ComponentHealth::worst([
+            ("a", ComponentHealth::ok()),
+            ("b", ComponentHealth::warning("Stale")),
+        ]);
+        assert_eq!(health, ComponentHealth::warning("b: Stale"));
+    }
+
+    #[test]
+    fn tracker() {
+        let tracker = HealthTracker::default();
+        assert_eq!(tracker.connection_health().status, HealthStatus::Ok);
+        assert_eq!(
+            tracker.freshness(Duration::from_secs(60)).status,
+            HealthStatus::Warning
+        );
+
+        tracker.record_failure();
+        assert_eq!(tracker.connection_health().status, HealthStatus::Warning);
+
+        tracker.record_success();
+        assert_eq!(tracker.connection_health().status, HealthStatus::Ok);
+        assert_eq!(
+            tracker.freshness(Duration::from_secs(60)).status,
+            HealthStatus::Ok
+        );
+    }
+}
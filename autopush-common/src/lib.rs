@@ -5,15 +5,19 @@ extern crate slog;
 #[macro_use]
 extern crate slog_scope;
 
+pub mod audit;
 #[macro_use]
 pub mod db;
 pub mod endpoint;
 pub mod errors;
+pub mod health;
 pub mod logging;
 pub mod metrics;
+pub mod middleware;
 pub mod notification;
 // pending actix 4:
 pub mod tags;
+pub mod telemetry;
 
 #[macro_use]
 pub mod util;
@@ -1,18 +1,13 @@
-// TODO: Convert autopush-common::metrics to this?
-
-use std::net::UdpSocket;
+//! The `Metrics` request extractor, shared by the actix servers. The servers
+//! register their `StatsdClient` as app data (`Data<StatsdClient>`).
 use std::sync::Arc;
 use std::time::Instant;
 
-use actix_web::{web::Data, HttpRequest};
-use cadence::{
-    BufferedUdpMetricSink, CountedExt, Metric, MetricError, NopMetricSink, QueuingMetricSink,
-    StatsdClient, Timed,
-};
+use actix_web::{dev::Payload, web::Data, FromRequest, HttpMessage, HttpRequest};
+use cadence::{CountedExt, Metric, NopMetricSink, StatsdClient, Timed};
+use futures::future;
 
-use actix_web::HttpMessage;
-use autoconnect_settings::{options::AppState, Settings};
-use autopush_common::tags::Tags;
+use crate::tags::Tags;
 
 #[derive(Debug, Clone)]
 pub struct MetricTimer {
@@ -21,6 +16,8 @@ pub struct MetricTimer {
     pub tags: Tags,
 }
 
+/// Records metrics with the request's tags. A started timer is recorded when
+/// the `Metrics` are dropped.
 #[derive(Debug, Clone)]
 pub struct Metrics {
     client: Option<Arc<StatsdClient>>,
@@ -62,13 +59,22 @@ impl Drop for Metrics {
     }
 }
 
+impl FromRequest for Metrics {
+    type Error = actix_web::Error;
+    type Future = future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        future::ok(Metrics::from(req))
+    }
+}
+
 impl From<&HttpRequest> for Metrics {
     fn from(req: &HttpRequest) -> Self {
         let exts = req.extensions();
         let def_tags = Tags::from_request_head(req.head());
         let tags = exts.get::<Tags>().unwrap_or(&def_tags);
         Metrics {
-            client: Some(metrics_from_req(req)),
+            client: metrics_from_req(req),
             tags: Some(tags.clone()),
             timer: None,
         }
@@ -77,18 +83,14 @@ impl From<&HttpRequest> for Metrics {
 
 impl From<StatsdClient> for Metrics {
     fn from(client: StatsdClient) -> Self {
-        Metrics {
-            client: Some(Arc::new(client)),
-            tags: None,
-            timer: None,
-        }
+        Metrics::from(Arc::new(client))
     }
 }
 
-impl From<&actix_web::web::Data<AppState>> for Metrics {
-    fn from(state: &actix_web::web::Data<AppState>) -> Self {
+impl From<Arc<StatsdClient>> for Metrics {
+    fn from(client: Arc<StatsdClient>) -> Self {
         Metrics {
-            client: Some(state.metrics.clone()),
+            client: Some(client),
             tags: None,
             timer: None,
         }
@@ -96,18 +98,12 @@ impl From<&actix_web::web::Data<AppState>> for Metrics {
 }
 
 impl Metrics {
-    #![allow(unused)] // TODO: Start using metrics
-
     pub fn sink() -> StatsdClient {
         StatsdClient::builder("", NopMetricSink).build()
     }
 
     pub fn noop() -> Self {
-        Self {
-            client: Some(Arc::new(Self::sink())),
-            timer: None,
-            tags: None,
-        }
+        Self::from(Self::sink())
     }
 
     pub fn start_timer(&mut self, label: &str, tags: Option<Tags>) {
@@ -154,29 +150,43 @@ impl Metrics {
     }
 }
 
-pub fn metrics_from_req(req: &HttpRequest) -> Arc<StatsdClient> {
-    req.app_data::<Data<AppState>>()
-        .expect("Could not get state in metrics_from_req")
-        .metrics
-        .clone()
+/// Get the server's `StatsdClient`, if it was registered as app data
+pub fn metrics_from_req(req: &HttpRequest) -> Option<Arc<StatsdClient>> {
+    req.app_data::<Data<StatsdClient>>()
+        .map(|client| client.clone().into_inner())
 }
 
-/// Create a cadence StatsdClient from the given options
-pub fn metrics_from_settings(settings: &Settings) -> Result<StatsdClient, MetricError> {
-    let builder = if let Some(statsd_host) = settings.statsd_host.as_ref() {
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
-        socket.set_nonblocking(true)?;
-
-        let host = (statsd_host.as_str(), settings.statsd_port);
-        let udp_sink = BufferedUdpMetricSink::from(host, socket)?;
-        let sink = QueuingMetricSink::from(udp_sink);
-        StatsdClient::builder(settings.statsd_label.as_ref(), sink)
-    } else {
-        StatsdClient::builder(settings.statsd_label.as_ref(), NopMetricSink)
-    };
-    Ok(builder
-        .with_error_handler(|err| {
-            warn!("⚠️ Metric send error:  {:?}", err);
-        })
-        .build())
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{test::TestRequest, web::Data};
+    use cadence::StatsdClient;
+
+    use super::Metrics;
+    use crate::metrics::PrometheusSink;
+
+    /// Timers are recorded, with the request's tags, when the metrics are
+    /// dropped
+    #[test]
+    fn timer_recorded_on_drop() {
+        let sink = PrometheusSink::new();
+        let client = Arc::new(StatsdClient::from_sink("autopush", sink.clone()));
+        let req = TestRequest::default()
+            .insert_header((
+                "User-Agent",
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/110.0",
+            ))
+            .app_data(Data::from(client))
+            .to_http_request();
+
+        Metrics::from(&req).incr("notification.received");
+        let mut metrics = Metrics::from(&req);
+        metrics.start_timer("notification.total_request_time", None);
+        drop(metrics);
+
+        let output = sink.render();
+        assert!(output.contains("# TYPE autopush_notification_received_total counter"));
+        assert!(output.contains("# TYPE autopush_notification_total_request_time histogram"));
+    }
 }
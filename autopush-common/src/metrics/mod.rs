@@ -0,0 +1,98 @@
+//! Metrics tie-ins
+
+use std::io;
+use std::net::UdpSocket;
+
+use cadence::{
+    BufferedUdpMetricSink, MetricResult, MetricSink, NopMetricSink, QueuingMetricSink,
+    StatsdClient, StatsdClientBuilder,
+};
+use serde_derive::Deserialize;
+
+use crate::errors::Result;
+
+mod extractor;
+mod prometheus;
+
+pub use self::extractor::{metrics_from_req, MetricTimer, Metrics};
+pub use self::prometheus::PrometheusSink;
+
+/// Where metrics are sent
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsBackend {
+    /// Send metrics to the statsd server
+    #[default]
+    Statsd,
+    /// Aggregate metrics in process, to be scraped by Prometheus
+    Prometheus,
+    /// Both of the above
+    Both,
+}
+
+impl MetricsBackend {
+    pub fn statsd(self) -> bool {
+        matches!(self, MetricsBackend::Statsd | MetricsBackend::Both)
+    }
+
+    pub fn prometheus(self) -> bool {
+        matches!(self, MetricsBackend::Prometheus | MetricsBackend::Both)
+    }
+}
+
+/// Create a cadence StatsdClient from the given options
+pub fn new_metrics(host: Option<String>, port: u16) -> Result<StatsdClient> {
+    let (builder, _) = metrics_builder("autopush", host.as_deref(), port, MetricsBackend::Statsd)?;
+    Ok(builder
+        .with_error_handler(|err| error!("Metrics send error: {}", err))
+        .build())
+}
+
+/// Start building a cadence StatsdClient sending to the configured backends.
+/// Metrics are only sent to statsd if a host is set. The Prometheus sink is
+/// returned when enabled, to serve the aggregated metrics from.
+pub fn metrics_builder(
+    prefix: &str,
+    host: Option<&str>,
+    port: u16,
+    backend: MetricsBackend,
+) -> MetricResult<(StatsdClientBuilder, Option<PrometheusSink>)> {
+    let statsd = match host {
+        Some(statsd_host) if backend.statsd() => {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.set_nonblocking(true)?;
+
+            let udp_sink = BufferedUdpMetricSink::from((statsd_host, port), socket)?;
+            Some(QueuingMetricSink::from(udp_sink))
+        }
+        _ => None,
+    };
+    let prometheus = backend.prometheus().then(PrometheusSink::new);
+
+    let builder = match (statsd, prometheus.clone()) {
+        (Some(statsd), Some(prometheus)) => {
+            StatsdClient::builder(prefix, TeeMetricSink(statsd, prometheus))
+        }
+        (Some(statsd), None) => StatsdClient::builder(prefix, statsd),
+        (None, Some(prometheus)) => StatsdClient::builder(prefix, prometheus),
+        (None, None) => StatsdClient::builder(prefix, NopMetricSink),
+    };
+    Ok((builder, prometheus))
+}
+
+/// Sends metrics to two sinks
+struct TeeMetricSink<A, B>(A, B);
+
+impl<A: MetricSink, B: MetricSink> MetricSink for TeeMetricSink<A, B> {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        let written = self.0.emit(metric);
+        self.1.emit(metric)?;
+        written
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let flushed = self.0.flush();
+        self.1.flush()?;
+        flushed
+    }
+}
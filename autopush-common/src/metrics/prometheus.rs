@@ -0,0 +1,332 @@
+//! Prometheus exposition of the metrics.
+//!
+//! `PrometheusSink` is a cadence sink which parses the statsd lines cadence
+//! formats, and aggregates them in process: counters are summed, gauges keep
+//! their last value, and timers and histograms are bucketed. Metric names are
+//! the statsd names with `.` replaced by `_` (and `_total` appended for
+//! counters), and tags become labels.
+//!
+//! The metrics are served on their own port, which the scraper can reach but
+//! the public can't.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, Mutex};
+
+use actix_web::web::{self, Data};
+use actix_web::{dev::Server, App, HttpResponse, HttpServer};
+use cadence::MetricSink;
+
+/// Upper bounds of the histogram buckets. Timers are in milliseconds.
+const BUCKETS: [f64; 12] = [
+    1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+];
+
+/// Label names and values, sorted by name
+type Labels = Vec<(String, String)>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+/// The series of a metric, one per distinct set of labels
+struct Family {
+    kind: Kind,
+    series: BTreeMap<Labels, Series>,
+}
+
+#[derive(Default)]
+struct Series {
+    /// The counter or gauge value, or the histogram sum
+    value: f64,
+    /// The number of histogram observations in each bucket (not cumulative)
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+}
+
+impl Series {
+    fn record(&mut self, kind: Kind, value: f64) {
+        match kind {
+            Kind::Counter => self.value += value,
+            Kind::Gauge => self.value = value,
+            Kind::Histogram => {
+                self.value += value;
+                self.count += 1;
+                if let Some(bucket) = BUCKETS.iter().position(|bound| value <= *bound) {
+                    self.buckets[bucket] += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Aggregates metrics for Prometheus to scrape. Clones share the metrics.
+#[derive(Clone, Default)]
+pub struct PrometheusSink {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+impl PrometheusSink {
+    /// The content type of the text exposition format
+    pub const CONTENT_TYPE: &'static str = "text/plain; version=0.0.4";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve the metrics on `/__metrics__` at `address`
+    pub fn serve(&self, address: impl ToSocketAddrs) -> io::Result<Server> {
+        let sink = Data::new(self.clone());
+        Ok(HttpServer::new(move || {
+            App::new()
+                .app_data(sink.clone())
+                .service(web::resource("/__metrics__").route(web::get().to(metrics_route)))
+        })
+        .workers(1)
+        .bind(address)?
+        .run())
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut output = String::new();
+        for (name, family) in families.iter() {
+            // Counters are named for their total, by convention
+            let name = match family.kind {
+                Kind::Counter => format!("{name}_total"),
+                _ => name.clone(),
+            };
+            let _ = writeln!(output, "# TYPE {} {}", name, family.kind.as_str());
+            for (labels, series) in &family.series {
+                if family.kind != Kind::Histogram {
+                    let _ = writeln!(
+                        output,
+                        "{}{} {}",
+                        name,
+                        format_labels(labels, None),
+                        series.value
+                    );
+                    continue;
+                }
+
+                let mut cumulative = 0;
+                for (bound, count) in BUCKETS.iter().zip(series.buckets) {
+                    cumulative += count;
+                    let le = bound.to_string();
+                    let _ = writeln!(
+                        output,
+                        "{}_bucket{} {}",
+                        name,
+                        format_labels(labels, Some(&le)),
+                        cumulative
+                    );
+                }
+                let _ = writeln!(
+                    output,
+                    "{}_bucket{} {}",
+                    name,
+                    format_labels(labels, Some("+Inf")),
+                    series.count
+                );
+                let _ = writeln!(
+                    output,
+                    "{}_sum{} {}",
+                    name,
+                    format_labels(labels, None),
+                    series.value
+                );
+                let _ = writeln!(
+                    output,
+                    "{}_count{} {}",
+                    name,
+                    format_labels(labels, None),
+                    series.count
+                );
+            }
+        }
+        output
+    }
+
+    fn record(&self, line: &str) {
+        let (name, kind, value, labels) = match parse_line(line) {
+            Some(metric) => metric,
+            None => {
+                trace!("Not a Prometheus metric: {}", line);
+                return;
+            }
+        };
+
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name).or_insert_with(|| Family {
+            kind,
+            series: BTreeMap::new(),
+        });
+        // Prometheus metrics have a single type
+        if family.kind != kind {
+            trace!("Metric type mismatch: {}", line);
+            return;
+        }
+        family.series.entry(labels).or_default().record(kind, value);
+    }
+}
+
+/// Handle the `/__metrics__` route
+async fn metrics_route(sink: Data<PrometheusSink>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(PrometheusSink::CONTENT_TYPE)
+        .body(sink.render())
+}
+
+impl MetricSink for PrometheusSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        for line in metric.lines() {
+            self.record(line);
+        }
+        Ok(metric.len())
+    }
+}
+
+/// Parse a statsd line: `<name>:<value>|<type>[|@<rate>][|#<tag>:<value>,...]`.
+/// Sets aren't supported.
+fn parse_line(line: &str) -> Option<(String, Kind, f64, Labels)> {
+    let mut parts = line.split('|');
+    let (name, value) = parts.next()?.rsplit_once(':')?;
+    let mut value: f64 = value.parse().ok()?;
+    let kind = match parts.next()? {
+        "c" | "m" => Kind::Counter,
+        "g" => Kind::Gauge,
+        "ms" | "h" | "d" => Kind::Histogram,
+        _ => return None,
+    };
+
+    let mut labels = Labels::new();
+    for part in parts {
+        if let Some(rate) = part.strip_prefix('@') {
+            // Sampled counters represent more events
+            match rate.parse::<f64>() {
+                Ok(rate) if rate > 0.0 && kind == Kind::Counter => value /= rate,
+                _ => (),
+            }
+        } else if let Some(tags) = part.strip_prefix('#') {
+            labels.extend(tags.split(',').filter_map(|tag| {
+                let (key, value) = tag.split_once(':')?;
+                Some((sanitize(key), value.to_owned()))
+            }));
+        }
+    }
+    labels.sort();
+    labels.dedup_by(|a, b| a.0 == b.0);
+    Some((sanitize(name), kind, value, labels))
+}
+
+/// Convert a statsd name to a valid Prometheus name
+fn sanitize(name: &str) -> String {
+    let mut name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use cadence::{CountedExt, Gauged, StatsdClient, Timed};
+
+    use super::PrometheusSink;
+
+    fn client() -> (StatsdClient, PrometheusSink) {
+        let sink = PrometheusSink::new();
+        (StatsdClient::from_sink("autoendpoint", sink.clone()), sink)
+    }
+
+    /// Counters are summed per set of tags
+    #[test]
+    fn counters() {
+        let (client, sink) = client();
+        for platform in ["fcm", "fcm", "apns"] {
+            client
+                .incr_with_tags("notification.bridge.sent")
+                .with_tag("platform", platform)
+                .send();
+        }
+        client.incr("updates.client.host_gone").unwrap();
+
+        let output = sink.render();
+        assert!(output.contains("# TYPE autoendpoint_notification_bridge_sent_total counter\n"));
+        assert!(
+            output.contains("autoendpoint_notification_bridge_sent_total{platform=\"fcm\"} 2\n")
+        );
+        assert!(
+            output.contains("autoendpoint_notification_bridge_sent_total{platform=\"apns\"} 1\n")
+        );
+        assert!(output.contains("autoendpoint_updates_client_host_gone_total 1\n"));
+    }
+
+    /// Gauges keep their last value, timers are bucketed
+    #[test]
+    fn gauges_and_timers() {
+        let (client, sink) = client();
+        client.gauge("connections", 10).unwrap();
+        client.gauge("connections", 4).unwrap();
+        client
+            .time_with_tags("notification.total_request_time", 30)
+            .with_tag("app_id", "a\"b")
+            .send();
+        client.time("notification.total_request_time", 700).unwrap();
+
+        let output = sink.render();
+        assert!(output.contains("# TYPE autoendpoint_connections gauge\n"));
+        assert!(output.contains("autoendpoint_connections 4\n"));
+        assert!(output.contains("# TYPE autoendpoint_notification_total_request_time histogram\n"));
+        assert!(output.contains(
+            "autoendpoint_notification_total_request_time_bucket{app_id=\"a\\\"b\",le=\"50\"} 1\n"
+        ));
+        assert!(
+            output.contains("autoendpoint_notification_total_request_time_bucket{le=\"500\"} 0\n")
+        );
+        assert!(
+            output.contains("autoendpoint_notification_total_request_time_bucket{le=\"+Inf\"} 1\n")
+        );
+        assert!(output.contains("autoendpoint_notification_total_request_time_sum 700\n"));
+    }
+}
@@ -0,0 +1,4 @@
+//! Actix middleware shared by the servers
+
+pub mod sentry;
+pub mod telemetry;
@@ -1,5 +1,6 @@
 use std::{
     cell::RefCell,
+    marker::PhantomData,
     rc::Rc,
     sync::Arc,
     task::{Context, Poll},
@@ -7,62 +8,87 @@ use std::{
 
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage,
+    Error, HttpMessage, ResponseError,
 };
 use cadence::{CountedExt, StatsdClient};
 use futures::{future::LocalBoxFuture, FutureExt};
 use futures_util::future::{ok, Ready};
 use sentry::{protocol::Event, Hub};
 
-use crate::LocalError;
-use autopush_common::tags::Tags;
+use crate::errors::ReportableError;
+use crate::tags::Tags;
 
-#[derive(Clone, Default)]
-pub struct SentryWrapper {
+/// Reports the errors of a server's error type `E` to Sentry. Errors which
+/// aren't reportable are recorded as metrics instead (if they have a metric
+/// label), named `<metric_label>.<error label>`.
+pub struct SentryWrapper<E> {
     metrics: Option<Arc<StatsdClient>>,
     metric_label: String,
+    is_reportable: fn(&E) -> bool,
+    error: PhantomData<fn() -> E>,
 }
 
-impl SentryWrapper {
+impl<E: ReportableError> SentryWrapper<E> {
+    /// Report the errors which are Sentry events (see
+    /// `ReportableError::is_sentry_event`)
     pub fn new(metrics: Arc<StatsdClient>, metric_label: String) -> Self {
         Self {
             metrics: Some(metrics),
             metric_label,
+            is_reportable: E::is_sentry_event,
+            error: PhantomData,
+        }
+    }
+
+    /// Only report the errors matching the predicate
+    pub fn report_if(mut self, is_reportable: fn(&E) -> bool) -> Self {
+        self.is_reportable = is_reportable;
+        self
+    }
+}
+
+// Not derived, which would require `E: Clone`
+impl<E> Clone for SentryWrapper<E> {
+    fn clone(&self) -> Self {
+        Self {
+            metrics: self.metrics.clone(),
+            metric_label: self.metric_label.clone(),
+            is_reportable: self.is_reportable,
+            error: PhantomData,
         }
     }
 }
 
-impl<S, B> Transform<S, ServiceRequest> for SentryWrapper
+impl<S, B, E> Transform<S, ServiceRequest> for SentryWrapper<E>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
+    E: ReportableError + ResponseError + 'static,
 {
     type Response = ServiceResponse<B>;
     type Error = Error;
-    type Transform = SentryWrapperMiddleware<S>;
+    type Transform = SentryWrapperMiddleware<S, E>;
     type InitError = ();
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(SentryWrapperMiddleware {
             service: Rc::new(RefCell::new(service)),
-            metrics: self.metrics.clone(),
-            metric_label: self.metric_label.clone(),
+            wrapper: self.clone(),
         })
     }
 }
 
-#[derive(Debug)]
-pub struct SentryWrapperMiddleware<S> {
+pub struct SentryWrapperMiddleware<S, E> {
     service: Rc<RefCell<S>>,
-    metrics: Option<Arc<StatsdClient>>,
-    metric_label: String,
+    wrapper: SentryWrapper<E>,
 }
 
-impl<S, B> Service<ServiceRequest> for SentryWrapperMiddleware<S>
+impl<S, B, E> Service<ServiceRequest> for SentryWrapperMiddleware<S, E>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
+    E: ReportableError + ResponseError + 'static,
 {
     type Response = ServiceResponse<B>;
     type Error = Error;
@@ -83,8 +109,7 @@ where
 
         // get the tag information
         let mut tags = Tags::from_request_head(sreq.head());
-        let metrics = self.metrics.clone();
-        let metric_label = self.metric_label.clone();
+        let wrapper = self.wrapper.clone();
         if let Some(rtags) = sreq.request().extensions().get::<Tags>() {
             trace!("Sentry: found tags in request: {:?}", &rtags.tags);
             for (k, v) in rtags.tags.clone() {
@@ -99,17 +124,18 @@ where
             let response: Self::Response = match fut.await {
                 Ok(response) => response,
                 Err(error) => {
-                    if let Some(api_err) = error.as_error::<LocalError>() {
+                    if let Some(reportable_err) = error.as_error::<E>() {
                         // if it's not reportable, and we have access to the metrics, record it as a metric.
-                        if !api_err.kind.is_sentry_event() {
+                        if !(wrapper.is_reportable)(reportable_err) {
                             // The error (e.g. VapidErrorKind::InvalidKey(String)) might be too cardinal,
                             // but we may need that information to debug a production issue. We can
                             // add an info here, temporarily turn on info level debugging on a given server,
                             // capture it, and then turn it off before we run out of money.
-                            info!("Sentry: Sending error to metrics: {:?}", api_err.kind);
-                            if let Some(metrics) = metrics {
-                                if let Some(label) = api_err.kind.metric_label() {
-                                    let _ = metrics.incr(&format!("{}.{}", metric_label, label));
+                            info!("Sentry: Sending error to metrics: {:?}", reportable_err);
+                            if let Some(metrics) = wrapper.metrics {
+                                if let Some(label) = reportable_err.metric_label() {
+                                    let _ = metrics
+                                        .incr(&format!("{}.{}", wrapper.metric_label, label));
                                 }
                             }
                         }
@@ -117,7 +143,7 @@ where
                         return Err(error);
                     };
                     debug!("Reporting error to Sentry (service error): {}", error);
-                    let mut event = event_from_actix_error(&error);
+                    let mut event = event_from_actix_error::<E>(&error);
                     event.extra.append(&mut tags.clone().extra_tree());
                     event.tags.append(&mut tags.clone().tag_tree());
                     let event_id = hub.capture_event(event);
@@ -127,14 +153,14 @@ where
             };
             // Check for errors inside the response
             if let Some(error) = response.response().error() {
-                if let Some(api_err) = error.as_error::<LocalError>() {
-                    if !api_err.kind.is_sentry_event() {
+                if let Some(reportable_err) = error.as_error::<E>() {
+                    if !(wrapper.is_reportable)(reportable_err) {
                         debug!("Not reporting error (service error): {:?}", error);
                         return Ok(response);
                     }
                 }
                 debug!("Reporting error to Sentry (response error): {}", error);
-                let mut event = event_from_actix_error(error);
+                let mut event = event_from_actix_error::<E>(error);
                 event.extra.append(&mut tags.clone().extra_tree());
                 event.tags.append(&mut tags.clone().tag_tree());
                 let event_id = hub.capture_event(event);
@@ -183,16 +209,22 @@ fn process_event(
     Some(event)
 }
 
-/// Convert Actix errors into a Sentry event. ApiError is handled explicitly so
-/// the event can include a backtrace and source error information.
-fn event_from_actix_error(error: &actix_web::Error) -> sentry::protocol::Event<'static> {
+/// Convert Actix errors into a Sentry event. The server's error type is
+/// handled explicitly so the event can include a backtrace and source error
+/// information.
+fn event_from_actix_error<E>(error: &actix_web::Error) -> sentry::protocol::Event<'static>
+where
+    E: ReportableError + ResponseError + 'static,
+{
     // Actix errors don't have support source/cause, so to get more information
     // about the error we need to downcast.
-    if let Some(error) = error.as_error::<LocalError>() {
+    if let Some(error) = error.as_error::<E>() {
         // Use our error and associated backtrace for the event
-        let mut event = sentry::event_from_error(&error.kind);
-        event.exception.last_mut().unwrap().stacktrace =
-            sentry::integrations::backtrace::backtrace_to_stacktrace(&error.backtrace);
+        let mut event = sentry::event_from_error(error.reportable_source());
+        if let Some(backtrace) = error.backtrace() {
+            event.exception.last_mut().unwrap().stacktrace =
+                sentry::integrations::backtrace::backtrace_to_stacktrace(backtrace);
+        }
         event
     } else {
         // Fallback to the Actix error
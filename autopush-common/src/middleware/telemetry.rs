@@ -0,0 +1,103 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::{future::LocalBoxFuture, FutureExt};
+use futures_util::future::{ok, Ready};
+use tracing::{field::Empty, Instrument};
+
+use crate::telemetry::{self, TRACEPARENT_HEADER};
+
+/// Records a server span for each request. The span continues the caller's
+/// trace if the request has a `traceparent` header, and is the current span
+/// while the extractors and handler run.
+#[derive(Clone, Default)]
+pub struct TelemetryWrapper;
+
+impl<S, B> Transform<S, ServiceRequest> for TelemetryWrapper
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TelemetryWrapperMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TelemetryWrapperMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct TelemetryWrapperMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service<ServiceRequest> for TelemetryWrapperMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, sreq: ServiceRequest) -> Self::Future {
+        let method = sreq.method().to_string();
+        // The route isn't known until the request is routed, so the span is
+        // renamed once there is a response
+        let span = tracing::info_span!(
+            "http.request",
+            otel.name = %method,
+            otel.kind = "server",
+            otel.status_code = Empty,
+            otel.status_message = Empty,
+            http.method = %method,
+            http.route = Empty,
+            http.status_code = Empty,
+        );
+        let traceparent = sreq
+            .headers()
+            .get(TRACEPARENT_HEADER)
+            .and_then(|value| value.to_str().ok());
+        telemetry::set_parent(&span, traceparent);
+        let fut = self.service.call(sreq).instrument(span.clone());
+
+        async move {
+            let response = fut.await;
+            match &response {
+                Ok(response) => {
+                    if let Some(route) = response.request().match_pattern() {
+                        span.record("otel.name", format!("{method} {route}").as_str());
+                        span.record("http.route", route.as_str());
+                    }
+                    let status = response.status();
+                    span.record("http.status_code", status.as_u16());
+                    if status.is_server_error() {
+                        span.record("otel.status_code", "ERROR");
+                    }
+                }
+                Err(error) => {
+                    span.record("otel.status_code", "ERROR");
+                    span.record("otel.status_message", error.to_string().as_str());
+                }
+            }
+            response
+        }
+        .boxed_local()
+    }
+}
@@ -4,6 +4,7 @@ use std::collections::HashMap;
 use serde_derive::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::audit::{AuditEvent, AuditRecord};
 use crate::util::ms_since_epoch;
 
 #[derive(Serialize, Default, Deserialize, Clone, Debug)]
@@ -15,7 +16,7 @@ pub struct Notification {
     pub ttl: u64,
     #[serde(skip_serializing)]
     pub topic: Option<String>,
-    #[serde(skip_serializing)]
+    #[serde(default, skip_serializing)]
     pub timestamp: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<String>,
@@ -56,6 +57,12 @@ impl Notification {
         }
     }
 
+    /// Start an audit log event for the notification, which was sent to
+    /// `uaid`. The version is the notification's `message_id`.
+    pub fn audit<'a>(&'a self, event: AuditEvent, uaid: &'a Uuid) -> AuditRecord<'a> {
+        AuditRecord::new(event, &self.version, uaid).channel_id(&self.channel_id)
+    }
+
     pub fn expired(&self, at_sec: u64) -> bool {
         at_sec >= self.timestamp + self.ttl
     }
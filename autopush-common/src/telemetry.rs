@@ -0,0 +1,150 @@
+//! Distributed tracing with OpenTelemetry.
+//!
+//! Spans are `tracing` spans, recorded by `tracing-opentelemetry` and written
+//! as JSON to stdout, stderr or a file by the OpenTelemetry stdout exporter,
+//! so no collector is needed. Spans are correlated across services with the
+//! W3C `traceparent` header.
+//!
+//! Tracing is disabled until `init` is called. A future carries its span
+//! with `tracing::Instrument`, so spans started while it runs have the right
+//! parent whichever thread polls it.
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::runtime::TokioCurrentThread;
+use opentelemetry_sdk::trace::{self, Sampler, TracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// The header used to propagate the trace context
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Enable tracing. `exporter` is `stdout`, `stderr` or the path of a file to
+/// append the spans to; tracing stays disabled if it is empty. Spans written
+/// to stdout are interleaved with the logs. New traces are sampled at
+/// `sample_rate` (0.0 to 1.0), traces started by a caller follow the caller's
+/// sampling decision.
+pub fn init(service_name: &str, exporter: &str, sample_rate: f64) -> io::Result<()> {
+    let writer: Box<dyn Write + Send + Sync> = match exporter {
+        "" => return Ok(()),
+        "stdout" => Box::new(io::stdout()),
+        "stderr" => Box::new(io::stderr()),
+        path => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+    };
+    let exporter = opentelemetry_stdout::SpanExporter::builder()
+        .with_writer(writer)
+        .build();
+    // The batch exporter runs on its own thread, so this works outside of a
+    // tokio runtime (the legacy server)
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, TokioCurrentThread)
+        .with_config(
+            trace::config()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    sample_rate,
+                ))))
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    service_name.to_owned(),
+                )])),
+        )
+        .build();
+    let tracer = provider.tracer("autopush");
+    global::set_tracer_provider(provider);
+
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Flush the spans which haven't been exported yet
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Continue the caller's trace in `span`, if `traceparent` is valid (a new
+/// trace is started otherwise). Must be called before `span` is entered.
+pub fn set_parent(span: &tracing::Span, traceparent: Option<&str>) {
+    if let Some(traceparent) = traceparent {
+        let carrier = HashMap::from([(TRACEPARENT_HEADER.to_owned(), traceparent.to_owned())]);
+        span.set_parent(TraceContextPropagator::new().extract(&carrier));
+    }
+}
+
+/// The `traceparent` header for requests made within `span`, if tracing is
+/// enabled
+pub fn traceparent(span: &tracing::Span) -> Option<String> {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&span.context(), &mut carrier);
+    carrier.remove(TRACEPARENT_HEADER)
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{set_parent, traceparent};
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn with_tracing(f: impl FnOnce()) {
+        // The tracer only holds a weak reference to its provider
+        let provider = TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        tracing::subscriber::with_default(subscriber, f);
+    }
+
+    /// A span continues the caller's trace, as a new span
+    #[test]
+    fn continues_trace() {
+        with_tracing(|| {
+            let span = tracing::info_span!("test");
+            set_parent(&span, Some(TRACEPARENT));
+            let header = traceparent(&span).unwrap();
+            assert!(header.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+            assert!(header.ends_with("-01"));
+            assert!(!header.contains("00f067aa0ba902b7"));
+        });
+    }
+
+    /// An invalid traceparent starts a new trace
+    #[test]
+    fn invalid_traceparent() {
+        with_tracing(|| {
+            let span = tracing::info_span!("test");
+            set_parent(&span, Some("00-invalid"));
+            let header = traceparent(&span).unwrap();
+            assert!(!header.contains("4bf92f3577b34da6a3ce929d0e0e4736"));
+        });
+    }
+
+    /// Child spans are in their parent's trace
+    #[test]
+    fn child_span() {
+        with_tracing(|| {
+            let span = tracing::info_span!("parent");
+            set_parent(&span, Some(TRACEPARENT));
+            let child = span.in_scope(|| tracing::info_span!("child"));
+            let header = traceparent(&child).unwrap();
+            assert!(header.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        });
+    }
+
+    /// Nothing is propagated when tracing is disabled
+    #[test]
+    fn disabled() {
+        let span = tracing::info_span!("test");
+        assert_eq!(traceparent(&span), None);
+    }
+}
@@ -0,0 +1,333 @@
+//! A load generator for capacity planning: many websocket clients receiving
+//! notifications sent at a steady rate through the endpoint server.
+#![warn(rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+#[macro_use]
+extern crate slog_scope;
+
+mod stats;
+
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use docopt::Docopt;
+use futures_util::stream::{self, StreamExt};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use autopush_common::logging;
+use autopush_common::util::ms_since_epoch;
+use autopush_test_client::{
+    ClientError, ContentEncoding, PushSender, PushTestClient, Subscription, VapidKey,
+};
+#[cfg(feature = "local")]
+use integration_tests::TestServers;
+
+use crate::stats::{make_payload, Stats};
+
+const USAGE: &str = "
+Usage:
+    autopush-loadtest [options]
+    autopush-loadtest (-h | --help)
+
+Options:
+    -h, --help                  Show this message
+    --connection-url=URL        The connection server's websocket URL
+                                [default: ws://127.0.0.1:8080/]
+    --local                     Run autoendpoint and autoconnect in-process,
+                                with an in-memory database, instead of using
+                                --connection-url. Needs the `local` feature.
+                                Deliveries aren't measured, as autoconnect
+                                doesn't deliver notifications yet.
+    --clients=N                 The number of websocket clients [default: 100]
+    --channels=N                The channels registered by each client
+                                [default: 1]
+    --rate=N                    Notifications sent per second, across all the
+                                channels [default: 50]
+    --duration=SECONDS          How long to send notifications for
+                                [default: 60]
+    --drain=SECONDS             How long to wait for outstanding deliveries
+                                after sending [default: 5]
+    --payload-size=BYTES        The size of each notification's payload
+                                [default: 64]
+    --encoding=ENCODING         The payload encoding, aes128gcm or aesgcm
+                                [default: aes128gcm]
+    --ttl=SECONDS               The notifications' TTL [default: 60]
+    --json                      Print the report as JSON
+    --json-logs                 Use JSON (MozLog) logging
+";
+
+/// The number of clients connecting at once while setting up
+const CONNECT_CONCURRENCY: usize = 50;
+/// How often the clients check whether the test has finished
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How many times a disconnected client tries to reconnect, backing off
+/// exponentially from `RECONNECT_BACKOFF`
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+/// The largest payload the endpoint server accepts (before encryption)
+const MAX_PAYLOAD_SIZE: usize = 4096;
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_connection_url: String,
+    flag_local: bool,
+    flag_clients: usize,
+    flag_channels: usize,
+    flag_rate: f64,
+    flag_duration: u64,
+    flag_drain: u64,
+    flag_payload_size: usize,
+    flag_encoding: String,
+    flag_ttl: u64,
+    flag_json: bool,
+    flag_json_logs: bool,
+}
+
+#[actix_rt::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    logging::init_logging(args.flag_json_logs).expect("Logging failed to initialize");
+
+    let result = run(&args).await;
+
+    logging::reset_logging();
+    result
+}
+
+async fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let encoding = match args.flag_encoding.as_str() {
+        "aes128gcm" => ContentEncoding::Aes128Gcm,
+        "aesgcm" => ContentEncoding::AesGcm,
+        encoding => return Err(format!("Unknown encoding: {encoding}").into()),
+    };
+    if args.flag_payload_size > MAX_PAYLOAD_SIZE {
+        return Err(format!("The payload size is limited to {MAX_PAYLOAD_SIZE} bytes").into());
+    }
+    if args.flag_rate <= 0.0 || args.flag_clients == 0 || args.flag_channels == 0 {
+        return Err("The rate, clients and channels must be positive".into());
+    }
+
+    #[cfg(not(feature = "local"))]
+    if args.flag_local {
+        return Err("--local needs autopush-loadtest built with the `local` feature".into());
+    }
+    // Keep the local servers running until the end of the test
+    #[cfg(feature = "local")]
+    let local = if args.flag_local {
+        let servers = TestServers::start().await;
+        info!(
+            "Started local servers: connection server {}, endpoint server {}",
+            servers.connection_url, servers.endpoint_url
+        );
+        Some(servers)
+    } else {
+        None
+    };
+    #[cfg(feature = "local")]
+    let url = local
+        .as_ref()
+        .map_or(args.flag_connection_url.clone(), |servers| {
+            servers.connection_url.clone()
+        });
+    #[cfg(not(feature = "local"))]
+    let url = args.flag_connection_url.clone();
+    // autoconnect doesn't deliver notifications yet, so waiting for them
+    // would only report them all as lost
+    let deliveries = !args.flag_local;
+
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let done = Arc::new(AtomicBool::new(false));
+    let sender = Arc::new(PushSender::new(VapidKey::generate()?));
+
+    info!("Connecting {} clients to {}", args.flag_clients, url);
+    let connected: Vec<_> = stream::iter(0..args.flag_clients)
+        .map(|_| connect_client(&url, &sender, args.flag_channels))
+        .buffer_unordered(CONNECT_CONCURRENCY)
+        .collect()
+        .await;
+    let mut subscriptions = Vec::new();
+    let mut clients = Vec::new();
+    for result in connected {
+        match result {
+            Ok((client, client_subscriptions)) => {
+                stats.lock().unwrap().clients_connected += 1;
+                subscriptions.extend(client_subscriptions);
+                clients.push(actix_rt::spawn(receive_notifications(
+                    url.clone(),
+                    client,
+                    stats.clone(),
+                    done.clone(),
+                )));
+            }
+            Err(e) => {
+                stats.lock().unwrap().client_failures += 1;
+                warn!("Client failed to connect: {}", e);
+            }
+        }
+    }
+    if subscriptions.is_empty() {
+        return Err("No clients connected".into());
+    }
+
+    info!(
+        "Sending {} notifications/s to {} channels for {}s",
+        args.flag_rate,
+        subscriptions.len(),
+        args.flag_duration
+    );
+    stats.lock().unwrap().start_sending();
+    let start = Instant::now();
+    let send_duration = Duration::from_secs(args.flag_duration);
+    let mut interval = actix_rt::time::interval(Duration::from_secs_f64(1.0 / args.flag_rate));
+    let mut sends = Vec::new();
+    let mut seq = 0;
+    while start.elapsed() < send_duration {
+        interval.tick().await;
+        let subscription = subscriptions[seq as usize % subscriptions.len()].clone();
+        sends.push(actix_rt::spawn(send_notification(
+            sender.clone(),
+            subscription,
+            make_payload(seq, ms_since_epoch(), args.flag_payload_size),
+            encoding,
+            args.flag_ttl,
+            stats.clone(),
+        )));
+        seq += 1;
+    }
+    let send_duration = start.elapsed();
+    for send in sends {
+        send.await?;
+    }
+
+    if deliveries {
+        info!("Waiting {}s for outstanding deliveries", args.flag_drain);
+        actix_rt::time::sleep(Duration::from_secs(args.flag_drain)).await;
+    }
+    done.store(true, Ordering::Relaxed);
+    for client in clients {
+        client.await?;
+    }
+
+    let report = stats.lock().unwrap().report(send_duration, deliveries);
+    if args.flag_json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{report}");
+    }
+    Ok(())
+}
+
+/// Connect a new user and register its channels, restricted to the sender's
+/// VAPID key
+async fn connect_client(
+    url: &str,
+    sender: &PushSender,
+    channels: usize,
+) -> Result<(PushTestClient, Vec<Subscription>), ClientError> {
+    let mut client = PushTestClient::connect(url).await?;
+    client.hello(None, None).await?;
+    let mut subscriptions = Vec::with_capacity(channels);
+    for _ in 0..channels {
+        subscriptions.push(
+            client
+                .register(Uuid::new_v4(), Some(sender.vapid().public_key()))
+                .await?,
+        );
+    }
+    Ok((client, subscriptions))
+}
+
+/// Send a notification, recording the endpoint server's response
+async fn send_notification(
+    sender: Arc<PushSender>,
+    subscription: Subscription,
+    payload: Vec<u8>,
+    encoding: ContentEncoding,
+    ttl: u64,
+    stats: Arc<Mutex<Stats>>,
+) {
+    let result = sender.send(&subscription, &payload, encoding, ttl).await;
+    let mut stats = stats.lock().unwrap();
+    stats.sent += 1;
+    match result {
+        Ok(response) if response.status() == 201 => stats.accepted += 1,
+        Ok(response) => {
+            debug!("Notification rejected: {}", response.status());
+            *stats
+                .rejected
+                .entry(response.status().as_u16())
+                .or_default() += 1;
+        }
+        Err(e) => {
+            debug!("Notification failed: {}", e);
+            stats.send_errors += 1;
+        }
+    }
+}
+
+/// Receive, decrypt and acknowledge notifications until the test is done,
+/// reconnecting if the connection drops
+async fn receive_notifications(
+    url: String,
+    mut client: PushTestClient,
+    stats: Arc<Mutex<Stats>>,
+    done: Arc<AtomicBool>,
+) {
+    while !done.load(Ordering::Relaxed) {
+        match client.receive_notification(POLL_INTERVAL).await {
+            Ok(Some(notification)) => {
+                let result = match client.decrypt(&notification) {
+                    Ok(payload) => {
+                        stats
+                            .lock()
+                            .unwrap()
+                            .record_delivery(&payload, ms_since_epoch());
+                        client.ack(&notification).await
+                    }
+                    Err(e) => {
+                        debug!("Could not decrypt a notification: {}", e);
+                        stats.lock().unwrap().decrypt_failures += 1;
+                        client.nack(&notification, None).await
+                    }
+                };
+                // A failure to respond means the connection dropped, which
+                // the next receive reports
+                if let Err(e) = result {
+                    debug!("Could not acknowledge a notification: {}", e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                debug!("Client disconnected: {}", e);
+                stats.lock().unwrap().disconnects += 1;
+                if !reconnect(&url, &mut client, &stats).await {
+                    return;
+                }
+            }
+        }
+    }
+    client.close().await;
+}
+
+/// Reconnect a client, returning false if it can't
+async fn reconnect(url: &str, client: &mut PushTestClient, stats: &Mutex<Stats>) -> bool {
+    for attempt in 0..MAX_RECONNECT_ATTEMPTS {
+        actix_rt::time::sleep(RECONNECT_BACKOFF * 2u32.pow(attempt)).await;
+        match client.reconnect(url).await {
+            Ok(_) => {
+                stats.lock().unwrap().record_reconnect();
+                return true;
+            }
+            Err(e) => debug!("Reconnection failed: {}", e),
+        }
+    }
+    warn!("Client gave up reconnecting");
+    stats.lock().unwrap().reconnect_failures += 1;
+    false
+}
@@ -0,0 +1,309 @@
+//! Load test measurements and the final report
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde_derive::Serialize;
+
+/// Each notification's payload starts with its sequence number and the time
+/// it was sent (in milliseconds since the epoch), padded to the payload size
+pub fn make_payload(seq: u64, sent_at: u64, size: usize) -> Vec<u8> {
+    let mut payload = format!("{seq} {sent_at} ").into_bytes();
+    if payload.len() < size {
+        payload.resize(size, b'x');
+    }
+    payload
+}
+
+/// Parse the sequence number and send time from a payload
+pub fn parse_payload(payload: &[u8]) -> Option<(u64, u64)> {
+    let payload = std::str::from_utf8(payload).ok()?;
+    let mut fields = payload.split(' ');
+    let seq = fields.next()?.parse().ok()?;
+    let sent_at = fields.next()?.parse().ok()?;
+    Some((seq, sent_at))
+}
+
+/// The value below which `percent` of the sorted `values` fall (by the
+/// nearest rank)
+fn percentile(sorted: &[u64], percent: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+pub struct Stats {
+    start: Instant,
+    pub clients_connected: u64,
+    pub client_failures: u64,
+    /// Notifications sent to the endpoint server
+    pub sent: u64,
+    /// Notifications accepted (with a 201) by the endpoint server
+    pub accepted: u64,
+    /// Notifications rejected by the endpoint server, by response status
+    pub rejected: BTreeMap<u16, u64>,
+    /// Requests to the endpoint server which failed without a response
+    pub send_errors: u64,
+    /// The sequence numbers of the delivered notifications
+    delivered: HashSet<u64>,
+    pub duplicates: u64,
+    pub decrypt_failures: u64,
+    /// The delivery latencies, in milliseconds
+    latencies: Vec<u64>,
+    last_delivery: Option<Instant>,
+    pub disconnects: u64,
+    pub reconnects: u64,
+    pub reconnect_failures: u64,
+    /// The reconnections in each second of the test
+    reconnects_per_sec: BTreeMap<u64, u64>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            start: Instant::now(),
+            clients_connected: 0,
+            client_failures: 0,
+            sent: 0,
+            accepted: 0,
+            rejected: BTreeMap::new(),
+            send_errors: 0,
+            delivered: HashSet::new(),
+            duplicates: 0,
+            decrypt_failures: 0,
+            latencies: Vec::new(),
+            last_delivery: None,
+            disconnects: 0,
+            reconnects: 0,
+            reconnect_failures: 0,
+            reconnects_per_sec: BTreeMap::new(),
+        }
+    }
+}
+
+impl Stats {
+    /// Restart the clock when the notifications start being sent: the
+    /// delivery throughput and reconnection rate are measured from then
+    pub fn start_sending(&mut self) {
+        self.start = Instant::now();
+    }
+
+    /// Record a delivered notification's payload, received at `now` (in
+    /// milliseconds since the epoch)
+    pub fn record_delivery(&mut self, payload: &[u8], now: u64) {
+        let Some((seq, sent_at)) = parse_payload(payload) else {
+            self.decrypt_failures += 1;
+            return;
+        };
+        if !self.delivered.insert(seq) {
+            self.duplicates += 1;
+            return;
+        }
+        self.latencies.push(now.saturating_sub(sent_at));
+        self.last_delivery = Some(Instant::now());
+    }
+
+    pub fn record_reconnect(&mut self) {
+        self.reconnects += 1;
+        *self
+            .reconnects_per_sec
+            .entry(self.start.elapsed().as_secs())
+            .or_default() += 1;
+    }
+
+    /// Summarize the test, which sent notifications for `send_duration`.
+    /// The deliveries are left out when they weren't measured.
+    pub fn report(&self, send_duration: Duration, deliveries: bool) -> Report {
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        let delivered = self.delivered.len() as u64;
+        let delivery_duration = self
+            .last_delivery
+            .map(|last| last.duration_since(self.start))
+            .unwrap_or_default();
+        let rate = |count: u64, duration: Duration| {
+            if duration.is_zero() {
+                0.0
+            } else {
+                count as f64 / duration.as_secs_f64()
+            }
+        };
+
+        Report {
+            clients_connected: self.clients_connected,
+            client_failures: self.client_failures,
+            sent: self.sent,
+            accepted: self.accepted,
+            rejected: self.rejected.clone(),
+            send_errors: self.send_errors,
+            sent_per_sec: rate(self.sent, send_duration),
+            delivery: deliveries.then(|| DeliveryReport {
+                delivered,
+                lost: self.accepted.saturating_sub(delivered),
+                duplicates: self.duplicates,
+                decrypt_failures: self.decrypt_failures,
+                delivered_per_sec: rate(delivered, delivery_duration),
+                latency_ms: LatencyReport {
+                    p50: percentile(&latencies, 50.0),
+                    p90: percentile(&latencies, 90.0),
+                    p99: percentile(&latencies, 99.0),
+                    max: latencies.last().copied(),
+                },
+            }),
+            disconnects: self.disconnects,
+            reconnects: self.reconnects,
+            reconnect_failures: self.reconnect_failures,
+            peak_reconnects_per_sec: self.reconnects_per_sec.values().max().copied().unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LatencyReport {
+    pub p50: Option<u64>,
+    pub p90: Option<u64>,
+    pub p99: Option<u64>,
+    pub max: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryReport {
+    pub delivered: u64,
+    /// Accepted notifications which were never delivered
+    pub lost: u64,
+    pub duplicates: u64,
+    pub decrypt_failures: u64,
+    pub delivered_per_sec: f64,
+    pub latency_ms: LatencyReport,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub clients_connected: u64,
+    pub client_failures: u64,
+    pub sent: u64,
+    pub accepted: u64,
+    /// The rejected notifications, by response status
+    pub rejected: BTreeMap<u16, u64>,
+    pub send_errors: u64,
+    pub sent_per_sec: f64,
+    /// How the accepted notifications were delivered, if that was measured
+    pub delivery: Option<DeliveryReport>,
+    pub disconnects: u64,
+    pub reconnects: u64,
+    pub reconnect_failures: u64,
+    pub peak_reconnects_per_sec: u64,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |value: Option<u64>| value.map_or("-".to_owned(), |v| v.to_string());
+        writeln!(
+            f,
+            "Clients:       {} connected, {} failed",
+            self.clients_connected, self.client_failures
+        )?;
+        writeln!(
+            f,
+            "Notifications: {} sent, {} accepted, {} rejected, {} errors",
+            self.sent,
+            self.accepted,
+            self.rejected.values().sum::<u64>(),
+            self.send_errors
+        )?;
+        for (status, count) in &self.rejected {
+            writeln!(f, "               {count} rejected with status {status}")?;
+        }
+        match &self.delivery {
+            Some(delivery) => {
+                writeln!(
+                    f,
+                    "Delivery:      {} delivered, {} lost, {} duplicates, {} undecryptable",
+                    delivery.delivered,
+                    delivery.lost,
+                    delivery.duplicates,
+                    delivery.decrypt_failures
+                )?;
+                writeln!(
+                    f,
+                    "Throughput:    {:.1} sent/s, {:.1} delivered/s",
+                    self.sent_per_sec, delivery.delivered_per_sec
+                )?;
+                writeln!(
+                    f,
+                    "Latency (ms):  p50 {}, p90 {}, p99 {}, max {}",
+                    ms(delivery.latency_ms.p50),
+                    ms(delivery.latency_ms.p90),
+                    ms(delivery.latency_ms.p99),
+                    ms(delivery.latency_ms.max)
+                )?;
+            }
+            None => {
+                writeln!(f, "Delivery:      not measured")?;
+                writeln!(f, "Throughput:    {:.1} sent/s", self.sent_per_sec)?;
+            }
+        }
+        write!(
+            f,
+            "Reconnects:    {} disconnects, {} reconnects (peak {}/s), {} failed",
+            self.disconnects,
+            self.reconnects,
+            self.peak_reconnects_per_sec,
+            self.reconnect_failures
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{make_payload, parse_payload, percentile, Stats};
+
+    #[test]
+    fn payload() {
+        let payload = make_payload(42, 1_000, 64);
+        assert_eq!(payload.len(), 64);
+        assert_eq!(parse_payload(&payload), Some((42, 1_000)));
+        assert_eq!(parse_payload(b"garbage"), None);
+    }
+
+    #[test]
+    fn percentiles() {
+        let values: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&values, 50.0), Some(50));
+        assert_eq!(percentile(&values, 99.0), Some(99));
+        assert_eq!(percentile(&[7], 90.0), Some(7));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn report() {
+        let mut stats = Stats::default();
+        stats.sent = 3;
+        stats.accepted = 3;
+        stats.record_delivery(&make_payload(0, 1_000, 16), 1_010);
+        stats.record_delivery(&make_payload(1, 1_000, 16), 1_030);
+        stats.record_delivery(&make_payload(1, 1_000, 16), 1_040);
+        stats.record_reconnect();
+        stats.record_reconnect();
+
+        let report = stats.report(Duration::from_secs(1), true);
+        let delivery = report.delivery.unwrap();
+        assert_eq!(delivery.delivered, 2);
+        assert_eq!(delivery.lost, 1);
+        assert_eq!(delivery.duplicates, 1);
+        assert_eq!(delivery.latency_ms.p50, Some(10));
+        assert_eq!(delivery.latency_ms.max, Some(30));
+        assert_eq!(report.sent_per_sec, 3.0);
+        assert_eq!(report.peak_reconnects_per_sec, 2);
+
+        // Unmeasured deliveries aren't reported as lost
+        assert!(stats
+            .report(Duration::from_secs(1), false)
+            .delivery
+            .is_none());
+    }
+}
@@ -0,0 +1,313 @@
+//! A user agent speaking the WebPush websocket protocol
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::time::Duration;
+
+use actix_codec::Framed;
+use actix_rt::time::Instant;
+use awc::ws::{Codec, Frame, Message};
+use awc::BoxedSocket;
+use bytestring::ByteString;
+use futures_util::{SinkExt, StreamExt};
+use uuid::Uuid;
+
+use autoconnect_common::protocol::{BroadcastValue, ClientAck, ClientMessage, ServerMessage};
+use autopush_common::notification::Notification;
+
+use crate::crypto::SubscriptionKeys;
+use crate::error::{ClientError, Result};
+
+/// How long to wait for a response from the server
+pub const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A registered channel: what an application server needs to send it
+/// notifications
+#[derive(Clone, Debug)]
+pub struct Subscription {
+    pub channel_id: Uuid,
+    pub endpoint: String,
+    /// When the endpoint expires, in milliseconds since the epoch
+    pub expiration_time: Option<u64>,
+    /// The user agent's public key
+    pub p256dh: Vec<u8>,
+    pub auth: Vec<u8>,
+}
+
+pub struct PushTestClient {
+    framed: Framed<BoxedSocket, Codec>,
+    /// The message encryption keys, shared by all the client's channels
+    keys: SubscriptionKeys,
+    /// The UAID assigned by the server's `hello` response
+    pub uaid: Option<String>,
+    /// Messages received while waiting for another kind (e.g. notifications
+    /// which arrived before a `register` response)
+    pending: VecDeque<ServerMessage>,
+}
+
+impl PushTestClient {
+    /// Connect to a connection server's websocket URL
+    pub async fn connect(url: &str) -> Result<Self> {
+        Self::connect_with_keys(url, SubscriptionKeys::generate()?).await
+    }
+
+    /// Connect with existing encryption keys, e.g. to reconnect as a user
+    /// with registered channels
+    pub async fn connect_with_keys(url: &str, keys: SubscriptionKeys) -> Result<Self> {
+        let (_, framed) = awc::Client::new().ws(url).connect().await?;
+        Ok(PushTestClient {
+            framed,
+            keys,
+            uaid: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    pub fn keys(&self) -> &SubscriptionKeys {
+        &self.keys
+    }
+
+    /// Close the connection, returning the keys for a later reconnection
+    pub async fn close(mut self) -> SubscriptionKeys {
+        // The server may already have closed the connection
+        let _ = self.framed.send(Message::Close(None)).await;
+        self.keys
+    }
+
+    /// Open a new connection (e.g. after the server dropped this one) and say
+    /// hello as the same user, returning the server's broadcast versions.
+    /// Messages received on the old connection but not yet read are
+    /// discarded.
+    pub async fn reconnect(&mut self, url: &str) -> Result<HashMap<String, BroadcastValue>> {
+        let (_, framed) = awc::Client::new().ws(url).connect().await?;
+        // The server may already have closed the old connection
+        let _ = self.framed.send(Message::Close(None)).await;
+        self.framed = framed;
+        self.pending.clear();
+        let uaid = self.uaid.clone();
+        self.hello(uaid.as_deref(), None).await
+    }
+
+    pub async fn send(&mut self, message: &ClientMessage) -> Result<()> {
+        let json = serde_json::to_string(message)?;
+        self.framed
+            .send(Message::Text(ByteString::from(json)))
+            .await?;
+        Ok(())
+    }
+
+    /// Receive the next message from the server, replying to pings.
+    /// Returns `None` if nothing arrives within `timeout`.
+    pub async fn receive_timeout(&mut self, timeout: Duration) -> Result<Option<ServerMessage>> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+        match actix_rt::time::timeout(timeout, self.read_message()).await {
+            Ok(message) => message.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Receive the next message from the server
+    pub async fn receive(&mut self) -> Result<ServerMessage> {
+        self.receive_timeout(RECEIVE_TIMEOUT)
+            .await?
+            .ok_or(ClientError::Timeout)
+    }
+
+    async fn read_message(&mut self) -> Result<ServerMessage> {
+        while let Some(frame) = self.framed.next().await {
+            match frame? {
+                Frame::Text(text) => {
+                    return Ok(ServerMessage::from_str(&String::from_utf8_lossy(&text))?)
+                }
+                Frame::Ping(data) => self.framed.send(Message::Pong(data)).await?,
+                Frame::Close(_) => return Err(ClientError::Closed),
+                _ => {}
+            }
+        }
+        Err(ClientError::Closed)
+    }
+
+    /// Wait for the first message `select` accepts, keeping the others for
+    /// later
+    async fn receive_matching<T>(
+        &mut self,
+        timeout: Duration,
+        mut select: impl FnMut(ServerMessage) -> std::result::Result<T, ServerMessage>,
+    ) -> Result<Option<T>> {
+        let mut skipped = VecDeque::new();
+        let mut found = None;
+        for message in self.pending.drain(..) {
+            match found {
+                None => match select(message) {
+                    Ok(value) => found = Some(value),
+                    Err(message) => skipped.push_back(message),
+                },
+                Some(_) => skipped.push_back(message),
+            }
+        }
+        self.pending = skipped;
+        if found.is_some() {
+            return Ok(found);
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let message = match actix_rt::time::timeout(remaining, self.read_message()).await {
+                Ok(message) => message?,
+                Err(_) => return Ok(None),
+            };
+            match select(message) {
+                Ok(value) => return Ok(Some(value)),
+                Err(message) => self.pending.push_back(message),
+            }
+        }
+    }
+
+    /// Say hello, as a new user if `uaid` is `None`, returning the server's
+    /// broadcast versions
+    pub async fn hello(
+        &mut self,
+        uaid: Option<&str>,
+        broadcasts: Option<HashMap<String, String>>,
+    ) -> Result<HashMap<String, BroadcastValue>> {
+        self.send(&ClientMessage::Hello {
+            uaid: uaid.map(str::to_owned),
+            channel_ids: None,
+            use_webpush: Some(true),
+            broadcasts,
+        })
+        .await?;
+
+        let (uaid, status, broadcasts) = self
+            .receive_matching(RECEIVE_TIMEOUT, |message| match message {
+                ServerMessage::Hello {
+                    uaid,
+                    status,
+                    broadcasts,
+                    ..
+                } => Ok((uaid, status, broadcasts)),
+                message => Err(message),
+            })
+            .await?
+            .ok_or(ClientError::Timeout)?;
+        if status != 200 {
+            return Err(ClientError::Status("hello", status));
+        }
+        self.uaid = Some(uaid);
+        Ok(broadcasts)
+    }
+
+    /// Register a channel, optionally restricted to a VAPID public key
+    pub async fn register(&mut self, channel_id: Uuid, key: Option<&str>) -> Result<Subscription> {
+        self.send(&ClientMessage::Register {
+            channel_id: channel_id.as_hyphenated().to_string(),
+            key: key.map(str::to_owned),
+        })
+        .await?;
+
+        let (status, endpoint, expiration_time) = self
+            .receive_matching(RECEIVE_TIMEOUT, |message| match message {
+                ServerMessage::Register {
+                    channel_id: id,
+                    status,
+                    push_endpoint,
+                    expiration_time,
+                } if id == channel_id => Ok((status, push_endpoint, expiration_time)),
+                message => Err(message),
+            })
+            .await?
+            .ok_or(ClientError::Timeout)?;
+        if status != 200 {
+            return Err(ClientError::Status("register", status));
+        }
+        Ok(Subscription {
+            channel_id,
+            endpoint,
+            expiration_time,
+            p256dh: self.keys.p256dh().to_vec(),
+            auth: self.keys.auth().to_vec(),
+        })
+    }
+
+    pub async fn unregister(&mut self, channel_id: Uuid) -> Result<()> {
+        self.send(&ClientMessage::Unregister {
+            channel_id,
+            code: None,
+        })
+        .await?;
+
+        let status = self
+            .receive_matching(RECEIVE_TIMEOUT, |message| match message {
+                ServerMessage::Unregister {
+                    channel_id: id,
+                    status,
+                } if id == channel_id => Ok(status),
+                message => Err(message),
+            })
+            .await?
+            .ok_or(ClientError::Timeout)?;
+        if status != 200 {
+            return Err(ClientError::Status("unregister", status));
+        }
+        Ok(())
+    }
+
+    /// Wait for the next notification. Returns `None` if none arrives within
+    /// `timeout`.
+    pub async fn receive_notification(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<Notification>> {
+        self.receive_matching(timeout, |message| match message {
+            ServerMessage::Notification(notification) => Ok(notification),
+            message => Err(message),
+        })
+        .await
+    }
+
+    /// Wait for the next broadcast update. Returns `None` if none arrives
+    /// within `timeout`.
+    pub async fn receive_broadcast(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<HashMap<String, BroadcastValue>>> {
+        self.receive_matching(timeout, |message| match message {
+            ServerMessage::Broadcast { broadcasts } => Ok(broadcasts),
+            message => Err(message),
+        })
+        .await
+    }
+
+    /// Decrypt a notification's payload
+    pub fn decrypt(&self, notification: &Notification) -> Result<Vec<u8>> {
+        self.keys.decrypt(notification)
+    }
+
+    /// Acknowledge a notification, so it's removed from storage
+    pub async fn ack(&mut self, notification: &Notification) -> Result<()> {
+        self.send(&ClientMessage::Ack {
+            updates: vec![ClientAck {
+                channel_id: notification.channel_id,
+                version: notification.version.clone(),
+            }],
+        })
+        .await
+    }
+
+    /// Report a notification which couldn't be processed (e.g. decrypted)
+    pub async fn nack(&mut self, notification: &Notification, code: Option<i32>) -> Result<()> {
+        self.send(&ClientMessage::Nack {
+            code,
+            version: notification.version.clone(),
+        })
+        .await
+    }
+
+    /// Subscribe to more broadcasts
+    pub async fn broadcast_subscribe(&mut self, broadcasts: HashMap<String, String>) -> Result<()> {
+        self.send(&ClientMessage::BroadcastSubscribe { broadcasts })
+            .await
+    }
+}
@@ -0,0 +1,197 @@
+//! Message encryption (RFC 8291), in the current `aes128gcm` and the legacy
+//! `aesgcm` content encodings
+use ece::legacy::{decrypt_aesgcm, encrypt_aesgcm, AesGcmEncryptedBlock};
+use ece::EcKeyComponents;
+
+use autopush_common::notification::Notification;
+use autopush_common::util::b64_decode_url;
+
+use crate::error::{ClientError, Result};
+
+/// The record size `aesgcm` payloads use unless the `Encryption` header says
+/// otherwise
+const DEFAULT_AESGCM_RS: u32 = 4096;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ContentEncoding {
+    Aes128Gcm,
+    AesGcm,
+}
+
+impl ContentEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Aes128Gcm => "aes128gcm",
+            ContentEncoding::AesGcm => "aesgcm",
+        }
+    }
+}
+
+/// An encrypted notification body and the headers describing it
+pub struct EncryptedPayload {
+    /// The `Content-Encoding`, `Crypto-Key` and `Encryption` headers, as
+    /// needed by the encoding
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl EncryptedPayload {
+    /// Encrypt `data` for a subscription's public key (`p256dh`) and
+    /// authentication secret
+    pub fn encrypt(
+        encoding: ContentEncoding,
+        p256dh: &[u8],
+        auth: &[u8],
+        data: &[u8],
+    ) -> Result<Self> {
+        let mut headers = vec![("Content-Encoding", encoding.as_str().to_owned())];
+        let body = match encoding {
+            ContentEncoding::Aes128Gcm => ece::encrypt(p256dh, auth, data)?,
+            ContentEncoding::AesGcm => {
+                let block = encrypt_aesgcm(p256dh, auth, data)?;
+                headers.extend(block.headers(None));
+                b64_decode_url(&block.body()).map_err(|e| {
+                    ClientError::InvalidNotification(format!("Invalid aesgcm body: {e}"))
+                })?
+            }
+        };
+        Ok(EncryptedPayload { headers, body })
+    }
+}
+
+/// A user agent's message encryption keys: the key pair whose public half is
+/// a subscription's `p256dh`, and its authentication secret
+pub struct SubscriptionKeys {
+    components: EcKeyComponents,
+    auth: [u8; 16],
+}
+
+impl SubscriptionKeys {
+    pub fn generate() -> Result<Self> {
+        let (key_pair, auth) = ece::generate_keypair_and_auth_secret()?;
+        Ok(SubscriptionKeys {
+            components: key_pair.raw_components()?,
+            auth,
+        })
+    }
+
+    /// The raw, uncompressed public key
+    pub fn p256dh(&self) -> &[u8] {
+        self.components.public_key()
+    }
+
+    pub fn auth(&self) -> &[u8] {
+        &self.auth
+    }
+
+    /// Decrypt a notification's data, as delivered by the connection server
+    /// (base64url encoded, with its encryption headers)
+    pub fn decrypt(&self, notification: &Notification) -> Result<Vec<u8>> {
+        let data = notification
+            .data
+            .as_deref()
+            .ok_or_else(|| ClientError::InvalidNotification("No data".to_owned()))?;
+        let body = b64_decode_url(data)
+            .map_err(|e| ClientError::InvalidNotification(format!("Invalid data: {e}")))?;
+        let header = |name: &str| {
+            notification
+                .headers
+                .as_ref()
+                .and_then(|headers| headers.get(name))
+                .map(String::as_str)
+        };
+
+        match header("encoding") {
+            Some("aes128gcm") => Ok(ece::decrypt(&self.components, &self.auth, &body)?),
+            Some("aesgcm") => {
+                let dh = header_param(header("crypto_key"), "dh")
+                    .ok_or_else(|| ClientError::InvalidNotification("No dh".to_owned()))?;
+                let salt = header_param(header("encryption"), "salt")
+                    .ok_or_else(|| ClientError::InvalidNotification("No salt".to_owned()))?;
+                let rs = header_param(header("encryption"), "rs")
+                    .map(|rs| {
+                        rs.parse().map_err(|_| {
+                            ClientError::InvalidNotification(format!("Invalid rs: {rs}"))
+                        })
+                    })
+                    .transpose()?
+                    .unwrap_or(DEFAULT_AESGCM_RS);
+                let decode = |value: &str| {
+                    b64_decode_url(value).map_err(|e| {
+                        ClientError::InvalidNotification(format!("Invalid header value: {e}"))
+                    })
+                };
+                let block = AesGcmEncryptedBlock::new(&decode(dh)?, &decode(salt)?, rs, body)?;
+                Ok(decrypt_aesgcm(&self.components, &self.auth, &block)?)
+            }
+            encoding => Err(ClientError::InvalidNotification(format!(
+                "Unsupported encoding: {encoding:?}"
+            ))),
+        }
+    }
+}
+
+/// Find a parameter (e.g. `dh`) in a header of `key=value` pairs, separated
+/// by `;` or `,`
+fn header_param<'a>(header: Option<&'a str>, name: &str) -> Option<&'a str> {
+    header?
+        .split(|c| c == ';' || c == ',')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| key.trim() == name)
+        .map(|(_, value)| value.trim().trim_matches('"'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use autopush_common::notification::Notification;
+    use autopush_common::util::b64_encode_url;
+
+    use super::{header_param, ContentEncoding, EncryptedPayload, SubscriptionKeys};
+
+    /// The notification the connection server would deliver for a payload
+    fn delivered(payload: EncryptedPayload) -> Notification {
+        let headers: HashMap<String, String> = payload
+            .headers
+            .into_iter()
+            .map(|(name, value)| {
+                let name = match name {
+                    "Content-Encoding" => "encoding",
+                    "Crypto-Key" => "crypto_key",
+                    "Encryption" => "encryption",
+                    name => name,
+                };
+                (name.to_owned(), value)
+            })
+            .collect();
+        Notification {
+            data: Some(b64_encode_url(&payload.body)),
+            headers: Some(headers),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let keys = SubscriptionKeys::generate().unwrap();
+        for encoding in [ContentEncoding::Aes128Gcm, ContentEncoding::AesGcm] {
+            let payload =
+                EncryptedPayload::encrypt(encoding, keys.p256dh(), keys.auth(), b"Hello, world")
+                    .unwrap();
+            assert_eq!(
+                keys.decrypt(&delivered(payload)).unwrap(),
+                b"Hello, world",
+                "{encoding:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn header_params() {
+        let header = Some("dh=BAbc; p256ecdsa=BDef,rs=\"4096\"");
+        assert_eq!(header_param(header, "dh"), Some("BAbc"));
+        assert_eq!(header_param(header, "rs"), Some("4096"));
+        assert_eq!(header_param(header, "salt"), None);
+    }
+}
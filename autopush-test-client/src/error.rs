@@ -0,0 +1,42 @@
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Could not connect: {0}")]
+    Connect(#[from] awc::error::WsClientError),
+
+    #[error("Websocket error: {0}")]
+    Websocket(#[from] awc::error::WsProtocolError),
+
+    #[error("Invalid message: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Timed out waiting for a message")]
+    Timeout,
+
+    #[error("The server closed the connection")]
+    Closed,
+
+    #[error("The server responded to {0} with status {1}")]
+    Status(&'static str, u32),
+
+    #[error("Invalid notification: {0}")]
+    InvalidNotification(String),
+
+    #[error("Encryption error: {0}")]
+    Crypto(#[from] ece::Error),
+
+    #[error("Could not sign the VAPID token: {0}")]
+    Vapid(#[from] jsonwebtoken::errors::Error),
+
+    #[error("OpenSSL error: {0}")]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+
+    #[error("Invalid endpoint: {0}")]
+    InvalidEndpoint(#[from] url::ParseError),
+
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+}
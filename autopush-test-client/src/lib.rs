@@ -0,0 +1,19 @@
+//! A WebPush user agent and application server for testing Autopush.
+//!
+//! [`PushTestClient`] speaks the websocket protocol to a connection server
+//! (using the `autoconnect_common` protocol messages) and decrypts the
+//! notifications it receives. [`PushSender`] signs notifications with a
+//! VAPID key and encrypts them for a [`Subscription`], as an application
+//! server would. Both the integration tests and the load generator use them.
+#![warn(rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+mod client;
+mod crypto;
+mod error;
+mod sender;
+
+pub use crate::client::{PushTestClient, Subscription, RECEIVE_TIMEOUT};
+pub use crate::crypto::{ContentEncoding, EncryptedPayload, SubscriptionKeys};
+pub use crate::error::{ClientError, Result};
+pub use crate::sender::{PushSender, VapidKey};
@@ -0,0 +1,162 @@
+//! Sending notifications, as an application server would
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use openssl::bn::BigNumContext;
+use openssl::ec::{EcGroup, EcKey, PointConversionForm};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use serde_json::json;
+use url::Url;
+
+use autopush_common::util::{b64_encode_url, sec_since_epoch};
+
+use crate::client::Subscription;
+use crate::crypto::{ContentEncoding, EncryptedPayload};
+use crate::error::Result;
+
+/// How long the VAPID tokens are valid for, in seconds
+const VAPID_TOKEN_LIFETIME: u64 = 12 * 60 * 60;
+
+/// An application server's VAPID key pair
+pub struct VapidKey {
+    key: EcKey<Private>,
+    /// The raw, uncompressed public key, base64url encoded
+    public_key: String,
+}
+
+impl VapidKey {
+    pub fn generate() -> Result<Self> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let key = EcKey::generate(&group)?;
+        let mut ctx = BigNumContext::new()?;
+        let public_key =
+            key.public_key()
+                .to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut ctx)?;
+        Ok(VapidKey {
+            key,
+            public_key: b64_encode_url(&public_key),
+        })
+    }
+
+    /// The public key, as given when registering a restricted channel
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// The `Authorization` header for a notification sent to `endpoint`:
+    /// a token for the endpoint's origin, signed by the key
+    pub fn authorization(&self, endpoint: &str, subject: &str) -> Result<String> {
+        let endpoint = Url::parse(endpoint)?;
+        let claims = json!({
+            "aud": endpoint.origin().ascii_serialization(),
+            "exp": sec_since_epoch() + VAPID_TOKEN_LIFETIME,
+            "sub": subject,
+        });
+        let pem = PKey::from_ec_key(self.key.clone())?.private_key_to_pem_pkcs8()?;
+        let token = jsonwebtoken::encode(
+            &Header::new(Algorithm::ES256),
+            &claims,
+            &EncodingKey::from_ec_pem(&pem)?,
+        )?;
+        Ok(format!("vapid t={},k={}", token, self.public_key))
+    }
+}
+
+/// Sends notifications to subscriptions, signed by a VAPID key
+pub struct PushSender {
+    http: reqwest::Client,
+    vapid: VapidKey,
+    /// The VAPID `sub` claim, a contact for the application server
+    subject: String,
+}
+
+impl PushSender {
+    pub fn new(vapid: VapidKey) -> Self {
+        PushSender {
+            http: reqwest::Client::new(),
+            vapid,
+            subject: "mailto:push-tests@example.com".to_owned(),
+        }
+    }
+
+    pub fn vapid(&self) -> &VapidKey {
+        &self.vapid
+    }
+
+    /// Build the request for a notification with an encrypted payload
+    pub fn request(
+        &self,
+        subscription: &Subscription,
+        data: &[u8],
+        encoding: ContentEncoding,
+        ttl: u64,
+    ) -> Result<reqwest::RequestBuilder> {
+        let payload =
+            EncryptedPayload::encrypt(encoding, &subscription.p256dh, &subscription.auth, data)?;
+        let mut request = self
+            .http
+            .post(&subscription.endpoint)
+            .header("TTL", ttl.to_string())
+            .header(
+                "Authorization",
+                self.vapid
+                    .authorization(&subscription.endpoint, &self.subject)?,
+            );
+        for (name, value) in payload.headers {
+            request = request.header(name, value);
+        }
+        Ok(request.body(payload.body))
+    }
+
+    /// Send a notification with an encrypted payload, returning the endpoint
+    /// server's response
+    pub async fn send(
+        &self,
+        subscription: &Subscription,
+        data: &[u8],
+        encoding: ContentEncoding,
+        ttl: u64,
+    ) -> Result<reqwest::Response> {
+        Ok(self
+            .request(subscription, data, encoding, ttl)?
+            .send()
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+    use serde_json::Value;
+
+    use autopush_common::util::b64_decode_url;
+
+    use super::VapidKey;
+
+    /// The token verifies against the public key in the header, as the
+    /// endpoint server checks it
+    #[test]
+    fn authorization() {
+        let vapid = VapidKey::generate().unwrap();
+        let header = vapid
+            .authorization(
+                "http://127.0.0.1:8082/wpush/v2/gAAAA",
+                "mailto:admin@example.com",
+            )
+            .unwrap();
+        let (token, key) = header
+            .strip_prefix("vapid t=")
+            .and_then(|data| data.split_once(",k="))
+            .unwrap();
+        assert_eq!(key, vapid.public_key());
+
+        let claims = jsonwebtoken::decode::<Value>(
+            token,
+            &DecodingKey::from_ec_der(&b64_decode_url(key).unwrap()),
+            &Validation::new(Algorithm::ES256),
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(claims["aud"], "http://127.0.0.1:8082");
+        assert_eq!(claims["sub"], "mailto:admin@example.com");
+    }
+}
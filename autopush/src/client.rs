@@ -19,6 +19,7 @@ use tokio_core::reactor::Timeout;
 use uuid::Uuid;
 
 use crate::db::{CheckStorageResponse, DynamoDbUser, HelloResponse, RegisterResponse};
+use autopush_common::audit::AuditEvent;
 use autopush_common::endpoint::make_endpoint;
 use autopush_common::errors::{ApcError, ApcErrorKind};
 use autopush_common::notification::Notification;
@@ -1085,7 +1086,10 @@ where
                         v.channel_id == notif.channel_id && v.version == notif.version
                     }) {
                         webpush.stats.direct_acked += 1;
-                        webpush.unacked_direct_notifs.remove(pos);
+                        let n = webpush.unacked_direct_notifs.remove(pos);
+                        n.audit(AuditEvent::Acked, &webpush.uaid)
+                            .destination("Direct")
+                            .log();
                         continue;
                     };
                     if let Some(pos) = webpush.unacked_stored_notifs.iter().position(|v| {
@@ -1094,6 +1098,9 @@ where
                         webpush.stats.stored_acked += 1;
                         let message_month = webpush.message_month.clone();
                         let n = webpush.unacked_stored_notifs.remove(pos);
+                        n.audit(AuditEvent::Acked, &webpush.uaid)
+                            .destination("Stored")
+                            .log();
                         // Topic/legacy messages have no sortkey_timestamp
                         if n.sortkey_timestamp.is_none() {
                             fut = if let Some(call) = fut {
@@ -1143,6 +1150,10 @@ where
                 }
                 debug!("Got a notification to send, sending!");
                 emit_metrics_for_send(&data.srv.metrics, &notif, "Direct", &webpush.ua_info);
+                notif
+                    .audit(AuditEvent::Delivered, &webpush.uaid)
+                    .destination("Direct")
+                    .log();
                 transition!(Send {
                     smessages: vec![ServerMessage::Notification(notif)],
                     data,
@@ -1239,6 +1250,7 @@ where
             if !n.expired(now) {
                 return true;
             }
+            n.audit(AuditEvent::Expired, &webpush.uaid).ttl(n.ttl).log();
             if n.sortkey_timestamp.is_none() {
                 srv.handle.spawn(
                     srv.ddb
@@ -1260,7 +1272,10 @@ where
             let smessages: Vec<_> = messages
                 .into_iter()
                 .inspect(|msg| {
-                    emit_metrics_for_send(&data.srv.metrics, msg, "Stored", &webpush.ua_info)
+                    emit_metrics_for_send(&data.srv.metrics, msg, "Stored", &webpush.ua_info);
+                    msg.audit(AuditEvent::Delivered, &webpush.uaid)
+                        .destination("Stored")
+                        .log();
                 })
                 .map(ServerMessage::Notification)
                 .collect();
@@ -4,16 +4,19 @@
 //! a client to check storage.
 //!
 //! Valid URL's:
-//!     PUT /push/UAID      - Deliver notification to a client
-//!     PUT /notify/UAID    - Tell a client to check storage
+//!     PUT /push/UAID       - Deliver notification to a client
+//!     PUT /notify/UAID     - Tell a client to check storage
+//!     PUT /disconnect/UAID - Disconnect a client (requested by an operator)
 
 use std::{str, sync::Arc};
 
 use futures::future::Either;
 
+use autopush_common::telemetry::{self, TRACEPARENT_HEADER};
 use futures::future::ok;
 use futures::{Future, Stream};
 use hyper::{self, service::Service, Body, Method, StatusCode};
+use tracing::field::Empty;
 use uuid::Uuid;
 
 use crate::server::registry::ClientRegistry;
@@ -44,26 +47,27 @@ impl Service for Push {
             }
         };
         let clients = Arc::clone(&self.0);
+        let traceparent = req
+            .headers()
+            .get(TRACEPARENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
         // Handle incoming routed push notifications from endpoints.
         match (req.method(), method_name, uaid) {
             (&Method::PUT, "push", uaid) => {
                 trace!("⏩ PUT /push/ {}", uaid);
+                let span = server_span("/push/{uaid}", traceparent.as_deref());
                 // Due to consumption of body as a future we must return here
                 let body = req.into_body().concat2();
                 return Box::new(body.and_then(move |body| {
                     let s = String::from_utf8(body.to_vec()).unwrap();
                     if let Ok(msg) = serde_json::from_str(&s) {
                         Either::A(clients.notify(uaid, msg).then(move |result| {
-                            let body = if result.is_ok() {
-                                response.status(StatusCode::OK);
-                                Body::empty()
-                            } else {
-                                response.status(StatusCode::NOT_FOUND);
-                                Body::from("Client not available.")
-                            };
-                            Ok(response.body(body).unwrap())
+                            span.record("push.delivered", result.is_ok());
+                            Ok(client_response(result.is_ok(), span))
                         }))
                     } else {
+                        span.record("http.status_code", 400u16);
                         Either::B(ok(response
                             .status(hyper::StatusCode::BAD_REQUEST)
                             .body("Unable to decode body payload".into())
@@ -73,18 +77,23 @@ impl Service for Push {
             }
             (&Method::PUT, "notif", uaid) => {
                 trace!("⏩ PUT /notif/ {}", uaid);
-                return Box::new(clients.check_storage(uaid).then(move |result| {
-                    let body = if result.is_ok() {
-                        response.status(StatusCode::OK);
-                        Body::empty()
-                    } else {
-                        response.status(StatusCode::NOT_FOUND);
-                        Body::from("Client not available.")
-                    };
-                    Ok(response.body(body).unwrap())
-                }));
+                let span = server_span("/notif/{uaid}", traceparent.as_deref());
+                return Box::new(
+                    clients
+                        .check_storage(uaid)
+                        .then(move |result| Ok(client_response(result.is_ok(), span))),
+                );
             }
-            (_, "push", _) | (_, "notif", _) => {
+            (&Method::PUT, "disconnect", uaid) => {
+                trace!("⏩ PUT /disconnect/ {}", uaid);
+                let span = server_span("/disconnect/{uaid}", traceparent.as_deref());
+                return Box::new(
+                    clients
+                        .force_disconnect(uaid)
+                        .then(move |result| Ok(client_response(result.is_ok(), span))),
+                );
+            }
+            (_, "push", _) | (_, "notif", _) | (_, "disconnect", _) => {
                 response.status(StatusCode::METHOD_NOT_ALLOWED);
             }
             _ => {
@@ -94,3 +103,40 @@ impl Service for Push {
         Box::new(ok(response.body(Body::empty()).unwrap()))
     }
 }
+
+/// Start the server span for a request to `route`, continuing the caller's
+/// trace if there's a `traceparent`
+fn server_span(route: &str, traceparent: Option<&str>) -> tracing::Span {
+    let span = tracing::info_span!(
+        "http.request",
+        otel.name = %format!("PUT {route}"),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        otel.status_message = Empty,
+        http.method = "PUT",
+        http.route = route,
+        http.status_code = Empty,
+        push.delivered = Empty,
+    );
+    telemetry::set_parent(&span, traceparent);
+    span
+}
+
+/// Respond to a request for a client, ending the request's span
+fn client_response(client_available: bool, span: tracing::Span) -> hyper::Response<Body> {
+    let mut response = hyper::Response::builder();
+    let body = if client_available {
+        response.status(StatusCode::OK);
+        Body::empty()
+    } else {
+        response.status(StatusCode::NOT_FOUND);
+        span.record("otel.status_code", "ERROR");
+        span.record("otel.status_message", "Client not available");
+        Body::from("Client not available.")
+    };
+    span.record(
+        "http.status_code",
+        if client_available { 200u16 } else { 404 },
+    );
+    response.body(body).unwrap()
+}
@@ -58,13 +58,19 @@ fn main() -> Result<()> {
     if let Some(ref ddb_local) = settings.aws_ddb_endpoint {
         env::set_var("AWS_LOCAL_DYNAMODB", ddb_local);
     }
+    autopush_common::telemetry::init(
+        "autopush",
+        &settings.tracing_exporter,
+        settings.tracing_sample_rate,
+    )?;
+    autopush_common::audit::init(settings.audit_sample_rate);
     let app_state = AppState::from_settings(settings)?;
     let server = AutopushServer::new(app_state);
     server.start();
     signal.recv().unwrap();
-    server
-        .stop()
-        .map_err(|_e| ApcErrorKind::GeneralError("Failed to shutdown properly".into()).into())
+    let result = server.stop();
+    autopush_common::telemetry::shutdown();
+    result.map_err(|_e| ApcErrorKind::GeneralError("Failed to shutdown properly".into()).into())
 }
 
 /// Create a new channel subscribed to the given signals
@@ -1,3 +0,0 @@
-//! Actix middleware
-
-pub mod sentry2;
@@ -27,6 +27,7 @@ use tungstenite::handshake::server::Request;
 use tungstenite::{self, Message};
 
 use autopush_common::errors::{ApcError, ApcErrorKind, Result};
+use autopush_common::health::{HealthReport, HealthTracker};
 use autopush_common::logging;
 use autopush_common::notification::Notification;
 
@@ -53,6 +54,8 @@ mod tls;
 mod webpush_io;
 
 const UAHEADER: &str = "User-Agent";
+/// How many Megaphone polls may fail before `/__heartbeat__` warns
+const MEGAPHONE_MAX_MISSED_POLLS: u32 = 3;
 
 fn ito_dur(seconds: u32) -> Option<Duration> {
     if seconds == 0 {
@@ -228,6 +231,8 @@ pub struct Server {
     pub handle: Handle,
     /// analytics reporting
     pub metrics: Arc<StatsdClient>,
+    /// When the Megaphone poller last succeeded, reported by `/__heartbeat__`
+    megaphone: HealthTracker,
 }
 
 impl Server {
@@ -313,6 +318,7 @@ impl Server {
             handle: core.handle(),
             tls_acceptor: tls::configure(app_state),
             metrics,
+            megaphone: HealthTracker::default(),
         });
         let addr = SocketAddr::from(([0, 0, 0, 0], srv.app_state.port));
         debug!("Starting server: {:?}", &addr);
@@ -367,7 +373,7 @@ impl Server {
 
                     let client = request.and_then(move |(socket, request)| -> MyFuture<_> {
                         match request {
-                            RequestType::Status => write_status(socket),
+                            RequestType::Status => write_status(socket, &srv2),
                             RequestType::LBHeartBeat => {
                                 write_json(socket, StatusCode::OK, serde_json::Value::from(""))
                             }
@@ -567,6 +573,7 @@ impl Future for MegaphoneUpdater {
                     match response.poll() {
                         Ok(Async::Ready(MegaphoneAPIResponse { broadcasts })) => {
                             trace!("📢Fetched broadcasts: {:?}", broadcasts);
+                            self.srv.megaphone.record_success();
                             let mut broadcaster = self.srv.broadcaster.borrow_mut();
                             for srv in Broadcast::from_hashmap(broadcasts) {
                                 let vv = broadcaster.add_broadcast(srv);
@@ -576,6 +583,7 @@ impl Future for MegaphoneUpdater {
                         }
                         Ok(Async::NotReady) => return Ok(Async::NotReady),
                         Err(error) => {
+                            self.srv.megaphone.record_failure();
                             error!("📢Failed to get response, queue again {error:?}");
                             capture_message(
                                 &format!("Failed to get response, queue again {error:?}"),
@@ -931,15 +939,17 @@ where
     }
 }
 
-fn write_status(socket: WebpushIo) -> MyFuture<()> {
-    write_json(
-        socket,
-        StatusCode::OK,
-        json!({
-                "status": "OK",
-                "version": env!("CARGO_PKG_VERSION"),
-        }),
-    )
+fn write_status(socket: WebpushIo, srv: &Server) -> MyFuture<()> {
+    let mut report = HealthReport::default();
+    if srv.app_state.megaphone_api_url.is_some() {
+        // Broadcasts go stale without the poller, but clients can still
+        // connect, so this is only a warning
+        let max_age = srv.app_state.megaphone_poll_interval * MEGAPHONE_MAX_MISSED_POLLS;
+        report.add("megaphone", srv.megaphone.freshness(max_age));
+    }
+    let status = StatusCode::from_u16(report.http_status().as_u16())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    write_json(socket, status, report.to_json(env!("CARGO_PKG_VERSION")))
 }
 
 /// Return a static copy of `version.json` from compile time.
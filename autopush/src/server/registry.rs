@@ -92,6 +92,25 @@ impl ClientRegistry {
         Box::new(fut)
     }
 
+    /// An operator asked for the client to be disconnected
+    pub fn force_disconnect(&self, uaid: Uuid) -> MySendFuture<()> {
+        let fut = self
+            .clients
+            .read()
+            .and_then(move |clients| {
+                if let Some(client) = clients.get(&uaid) {
+                    let result = client.tx.unbounded_send(ServerNotification::Disconnect);
+                    if result.is_ok() {
+                        debug!("Told client to disconnect");
+                        return ok(());
+                    }
+                }
+                err(())
+            })
+            .map_err(|_| ApcErrorKind::GeneralError("User not connected".into()).into());
+        Box::new(fut)
+    }
+
     /// The client specified by `uaid` has disconnected.
     #[allow(clippy::clone_on_copy)]
     pub fn disconnect(&self, uaid: &Uuid, uid: &Uuid) -> MySendFuture<()> {
@@ -59,6 +59,14 @@ pub struct Settings {
     pub megaphone_poll_interval: u32,
     pub human_logs: bool,
     pub msg_limit: u32,
+    /// Where to export trace spans (as JSON): `stdout`, `stderr`, or the path
+    /// of a file. Tracing is disabled when this is empty.
+    pub tracing_exporter: String,
+    /// The fraction of new traces to record (0.0 to 1.0)
+    pub tracing_sample_rate: f64,
+    /// The fraction of notifications (0.0 to 1.0) whose deliveries and acks
+    /// are recorded in the audit log. Auditing is disabled when this is 0.
+    pub audit_sample_rate: f64,
 }
 
 impl Default for Settings {
@@ -90,6 +98,9 @@ impl Default for Settings {
             megaphone_poll_interval: 30,
             human_logs: false,
             msg_limit: 100,
+            tracing_exporter: "".to_owned(),
+            tracing_sample_rate: 1.0,
+            audit_sample_rate: 0.0,
         }
     }
 }
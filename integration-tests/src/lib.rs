@@ -0,0 +1,13 @@
+//! An in-process Autopush deployment for the end to end tests.
+//!
+//! [`TestServers`] runs autoendpoint and autoconnect on ephemeral ports,
+//! sharing an in-memory database, with a mock Megaphone serving broadcasts.
+//! The tests drive it with the `autopush_test_client` user agent and
+//! application server.
+#![warn(rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+pub mod megaphone;
+pub mod servers;
+
+pub use servers::TestServers;
@@ -0,0 +1,108 @@
+//! A mock of the Megaphone broadcast service
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::dev::ServerHandle;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+
+#[derive(Default)]
+struct MegaphoneState {
+    token: String,
+    broadcasts: Mutex<HashMap<String, String>>,
+    polls: AtomicUsize,
+}
+
+/// Serves the broadcast versions set by the test on `/v1/broadcasts`, as
+/// Megaphone does, to clients presenting the expected `Authorization` header
+pub struct MockMegaphone {
+    url: String,
+    state: Arc<MegaphoneState>,
+    handle: ServerHandle,
+}
+
+impl MockMegaphone {
+    pub fn start(token: &str) -> Self {
+        let state = Arc::new(MegaphoneState {
+            token: token.to_owned(),
+            ..Default::default()
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind the mock Megaphone");
+        let url = format!(
+            "http://{}/v1/broadcasts",
+            listener.local_addr().expect("No local address")
+        );
+
+        let app_state = web::Data::from(state.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .service(web::resource("/v1/broadcasts").route(web::get().to(broadcasts_route)))
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .expect("Could not start the mock Megaphone")
+        .run();
+        let handle = server.handle();
+        actix_rt::spawn(server);
+
+        MockMegaphone { url, state, handle }
+    }
+
+    /// The URL of the broadcasts API
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Set the current version of a broadcast
+    pub fn set_broadcast(&self, broadcast_id: &str, version: &str) {
+        self.state
+            .broadcasts
+            .lock()
+            .unwrap()
+            .insert(broadcast_id.to_owned(), version.to_owned());
+    }
+
+    /// The number of successful polls so far
+    pub fn polls(&self) -> usize {
+        self.state.polls.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the next successful poll, returning false if there's none
+    /// within `timeout`
+    pub async fn wait_for_poll(&self, timeout: Duration) -> bool {
+        let start = self.polls();
+        let poll = async {
+            while self.polls() == start {
+                actix_rt::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        actix_rt::time::timeout(timeout, poll).await.is_ok()
+    }
+}
+
+impl Drop for MockMegaphone {
+    fn drop(&mut self) {
+        // The stop command is sent immediately, there's no need to wait for
+        // the server to finish
+        let _ = self.handle.stop(false);
+    }
+}
+
+async fn broadcasts_route(req: HttpRequest, state: web::Data<MegaphoneState>) -> HttpResponse {
+    let authorized = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        == Some(state.token.as_str());
+    if !authorized {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    state.polls.fetch_add(1, Ordering::Relaxed);
+    let broadcasts = state.broadcasts.lock().unwrap().clone();
+    HttpResponse::Ok().json(serde_json::json!({ "broadcasts": broadcasts }))
+}
@@ -0,0 +1,126 @@
+//! Autoendpoint and autoconnect, running in-process
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::{Arc, RwLock};
+
+use actix_web::dev::ServerHandle;
+use actix_web::{web, App, HttpServer};
+use fernet::Fernet;
+use uuid::Uuid;
+
+use autoconnect_settings::{options::AppState, Settings as ConnectionSettings};
+use autoconnect_web::client::ClientChannels;
+use autoendpoint::server::Server as EndpointServer;
+use autoendpoint::settings::Settings as EndpointSettings;
+use autopush_common::db::memory::MemoryDbClient;
+
+use crate::megaphone::MockMegaphone;
+
+/// An Autopush deployment on ephemeral ports. The servers are stopped when
+/// this is dropped.
+pub struct TestServers {
+    /// The endpoint server's URL, e.g. `http://127.0.0.1:1234`
+    pub endpoint_url: String,
+    /// The connection server's websocket URL
+    pub connection_url: String,
+    /// The database shared by both servers
+    pub db: MemoryDbClient,
+    pub megaphone: MockMegaphone,
+    handles: Vec<ServerHandle>,
+}
+
+impl TestServers {
+    /// Start the servers. This must be called within an actix runtime (e.g.
+    /// from an `#[actix_rt::test]`).
+    pub async fn start() -> Self {
+        let db = MemoryDbClient::default();
+        let crypto_key = format!("[{}]", Fernet::generate_key());
+        let megaphone_token = format!("Bearer {}", Uuid::new_v4().simple());
+        let megaphone = MockMegaphone::start(&megaphone_token);
+
+        let endpoint_listener = bind();
+        let endpoint_port = endpoint_listener
+            .local_addr()
+            .expect("No local address")
+            .port();
+        let endpoint_url = format!("http://127.0.0.1:{endpoint_port}");
+        let endpoint_settings = EndpointSettings {
+            port: endpoint_port,
+            endpoint_url: endpoint_url.clone(),
+            crypto_keys: crypto_key.clone(),
+            ..Default::default()
+        };
+        let endpoint = EndpointServer::with_listener(
+            endpoint_settings,
+            endpoint_listener,
+            Box::new(db.clone()),
+        )
+        .await
+        .expect("Could not start autoendpoint");
+        let mut handles = vec![endpoint.handle()];
+        actix_rt::spawn(endpoint);
+
+        let connection_listener = bind();
+        let connection_port = connection_listener
+            .local_addr()
+            .expect("No local address")
+            .port();
+        // autoconnect has no separate router listener: node IDs point at the
+        // websocket port
+        let connection_settings = ConnectionSettings {
+            port: connection_port,
+            hostname: Some("127.0.0.1".to_owned()),
+            router_port: connection_port,
+            endpoint_hostname: "127.0.0.1".to_owned(),
+            endpoint_port,
+            crypto_key,
+            statsd_host: None,
+            megaphone_api_url: Some(megaphone.url().to_owned()),
+            megaphone_api_token: Some(megaphone_token),
+            megaphone_poll_interval: 1,
+            ..Default::default()
+        };
+        let app_state = AppState {
+            db_client: Box::new(db.clone()),
+            ..AppState::from_settings(&connection_settings).expect("Invalid autoconnect settings")
+        };
+        let client_channels: ClientChannels = Arc::new(RwLock::new(HashMap::new()));
+        let connection = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(app_state.clone()))
+                .app_data(web::Data::new(client_channels.clone()))
+                .app_data(web::Data::from(app_state.metrics.clone()))
+                .configure(autoconnect_web::config)
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(connection_listener)
+        .expect("Could not start autoconnect")
+        .run();
+        handles.push(connection.handle());
+        actix_rt::spawn(connection);
+
+        TestServers {
+            endpoint_url,
+            connection_url: format!("ws://127.0.0.1:{connection_port}/ws/"),
+            db,
+            megaphone,
+            handles,
+        }
+    }
+}
+
+impl Drop for TestServers {
+    fn drop(&mut self) {
+        // The stop commands are sent immediately, there's no need to wait for
+        // the servers to finish
+        for handle in &self.handles {
+            let _ = handle.stop(false);
+        }
+    }
+}
+
+/// Bind an ephemeral port on the loopback interface
+fn bind() -> TcpListener {
+    TcpListener::bind("127.0.0.1:0").expect("Could not bind an ephemeral port")
+}
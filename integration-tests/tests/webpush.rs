@@ -0,0 +1,288 @@
+//! End to end WebPush scenarios against in-process autoendpoint and
+//! autoconnect servers
+use std::collections::HashMap;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use autoconnect_common::protocol::BroadcastValue;
+use autopush_common::db::client::DbClient;
+use autopush_test_client::{
+    ContentEncoding, PushSender, PushTestClient, Subscription, SubscriptionKeys, VapidKey,
+};
+use integration_tests::TestServers;
+
+/// Connect a new user and register a channel restricted to the sender's
+/// VAPID key
+async fn registered_client(
+    servers: &TestServers,
+    sender: &PushSender,
+) -> (PushTestClient, Subscription) {
+    let mut client = PushTestClient::connect(&servers.connection_url)
+        .await
+        .unwrap();
+    client.hello(None, None).await.unwrap();
+    let subscription = client
+        .register(Uuid::new_v4(), Some(sender.vapid().public_key()))
+        .await
+        .unwrap();
+    (client, subscription)
+}
+
+fn sender() -> PushSender {
+    PushSender::new(VapidKey::generate().unwrap())
+}
+
+#[actix_rt::test]
+async fn hello_and_register() {
+    let servers = TestServers::start().await;
+    let sender = sender();
+
+    let mut client = PushTestClient::connect(&servers.connection_url)
+        .await
+        .unwrap();
+    client.hello(None, None).await.unwrap();
+    let uaid = client.uaid.clone().unwrap();
+    assert_eq!(uaid.len(), 32);
+
+    let channel_id = Uuid::new_v4();
+    let subscription = client
+        .register(channel_id, Some(sender.vapid().public_key()))
+        .await
+        .unwrap();
+    assert!(subscription
+        .endpoint
+        .starts_with(&format!("{}/wpush/v2/", servers.endpoint_url)));
+
+    // Registering saved the (deferred) user and the channel
+    let uaid = Uuid::parse_str(&uaid).unwrap();
+    assert!(servers.db.get_user(&uaid).await.unwrap().is_some());
+    assert!(servers
+        .db
+        .get_channels(&uaid)
+        .await
+        .unwrap()
+        .contains(&channel_id));
+
+    // Reconnecting keeps the UAID
+    let keys = client.close().await;
+    let mut client = PushTestClient::connect_with_keys(&servers.connection_url, keys)
+        .await
+        .unwrap();
+    let simple_uaid = uaid.simple().to_string();
+    client.hello(Some(&simple_uaid), None).await.unwrap();
+    assert_eq!(client.uaid, Some(simple_uaid));
+}
+
+#[actix_rt::test]
+async fn push_is_stored() {
+    let servers = TestServers::start().await;
+    let sender = sender();
+    let (client, subscription) = registered_client(&servers, &sender).await;
+
+    for encoding in [ContentEncoding::Aes128Gcm, ContentEncoding::AesGcm] {
+        let response = sender
+            .send(&subscription, b"Hello, world", encoding, 60)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        assert_eq!(response.headers()["TTL"], "60");
+    }
+
+    // autoconnect doesn't record a node ID for its clients, so the
+    // notifications are stored for the next connection
+    let uaid = Uuid::parse_str(client.uaid.as_ref().unwrap()).unwrap();
+    let stored = servers
+        .db
+        .fetch_timestamp_messages(&uaid, None, 10)
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(stored.len(), 2);
+    for notification in &stored {
+        assert_eq!(client.decrypt(notification).unwrap(), b"Hello, world");
+    }
+}
+
+#[actix_rt::test]
+async fn push_with_another_vapid_key() {
+    let servers = TestServers::start().await;
+    let (_client, subscription) = registered_client(&servers, &sender()).await;
+
+    let response = sender()
+        .send(
+            &subscription,
+            b"Hello, world",
+            ContentEncoding::Aes128Gcm,
+            60,
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+}
+
+#[actix_rt::test]
+async fn unregister() {
+    let servers = TestServers::start().await;
+    let sender = sender();
+    let (mut client, subscription) = registered_client(&servers, &sender).await;
+
+    client.unregister(subscription.channel_id).await.unwrap();
+    // Unregistering an unknown channel succeeds
+    client.unregister(subscription.channel_id).await.unwrap();
+
+    let response = sender
+        .send(
+            &subscription,
+            b"Hello, world",
+            ContentEncoding::Aes128Gcm,
+            60,
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 410);
+}
+
+#[actix_rt::test]
+#[ignore = "autoconnect doesn't deliver notifications yet"]
+async fn push_is_delivered_and_acked() {
+    let servers = TestServers::start().await;
+    let sender = sender();
+    let (mut client, subscription) = registered_client(&servers, &sender).await;
+
+    let response = sender
+        .send(
+            &subscription,
+            b"Hello, world",
+            ContentEncoding::Aes128Gcm,
+            60,
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+
+    let notification = client
+        .receive_notification(Duration::from_secs(5))
+        .await
+        .unwrap()
+        .expect("No notification delivered");
+    assert_eq!(notification.channel_id, subscription.channel_id);
+    assert_eq!(client.decrypt(&notification).unwrap(), b"Hello, world");
+    client.ack(&notification).await.unwrap();
+
+    // Nothing is redelivered once acked
+    let uaid = client.uaid.clone().unwrap();
+    let keys = client.close().await;
+    let mut client = PushTestClient::connect_with_keys(&servers.connection_url, keys)
+        .await
+        .unwrap();
+    client.hello(Some(&uaid), None).await.unwrap();
+    assert!(client
+        .receive_notification(Duration::from_millis(500))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[actix_rt::test]
+#[ignore = "autoconnect doesn't deliver notifications yet"]
+async fn nacked_push_is_redelivered() {
+    let servers = TestServers::start().await;
+    let sender = sender();
+    let (mut client, subscription) = registered_client(&servers, &sender).await;
+
+    // The user agent can't decrypt a payload for other keys
+    let other_keys = SubscriptionKeys::generate().unwrap();
+    let other_subscription = Subscription {
+        p256dh: other_keys.p256dh().to_vec(),
+        auth: other_keys.auth().to_vec(),
+        ..subscription
+    };
+    sender
+        .send(
+            &other_subscription,
+            b"Hello, world",
+            ContentEncoding::AesGcm,
+            60,
+        )
+        .await
+        .unwrap();
+    let notification = client
+        .receive_notification(Duration::from_secs(5))
+        .await
+        .unwrap()
+        .expect("No notification delivered");
+    assert!(client.decrypt(&notification).is_err());
+    client.nack(&notification, Some(301)).await.unwrap();
+
+    // An unacknowledged notification is delivered again on reconnecting
+    let uaid = client.uaid.clone().unwrap();
+    let keys = client.close().await;
+    let mut client = PushTestClient::connect_with_keys(&servers.connection_url, keys)
+        .await
+        .unwrap();
+    client.hello(Some(&uaid), None).await.unwrap();
+    let redelivered = client
+        .receive_notification(Duration::from_secs(5))
+        .await
+        .unwrap()
+        .expect("No notification redelivered");
+    assert_eq!(redelivered.version, notification.version);
+}
+
+#[actix_rt::test]
+#[ignore = "autoconnect doesn't poll Megaphone yet"]
+async fn broadcasts() {
+    let servers = TestServers::start().await;
+    servers.megaphone.set_broadcast("kinto:123", "ver1");
+    assert!(
+        servers
+            .megaphone
+            .wait_for_poll(Duration::from_secs(5))
+            .await
+    );
+
+    // The client is told about the newer version on connecting
+    let mut client = PushTestClient::connect(&servers.connection_url)
+        .await
+        .unwrap();
+    let broadcasts = client
+        .hello(
+            None,
+            Some(HashMap::from([
+                ("kinto:123".to_owned(), "ver0".to_owned()),
+                ("kinto:456".to_owned(), "ver1".to_owned()),
+            ])),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        broadcasts["kinto:123"],
+        BroadcastValue::Value("ver1".to_owned())
+    );
+    assert_eq!(
+        broadcasts["errors"],
+        BroadcastValue::Nested(HashMap::from([(
+            "kinto:456".to_owned(),
+            BroadcastValue::Value("Broadcast not found".to_owned())
+        )]))
+    );
+
+    // ...and about later versions while connected
+    servers.megaphone.set_broadcast("kinto:123", "ver2");
+    assert!(
+        servers
+            .megaphone
+            .wait_for_poll(Duration::from_secs(5))
+            .await
+    );
+    let broadcasts = client
+        .receive_broadcast(Duration::from_secs(5))
+        .await
+        .unwrap()
+        .expect("No broadcast received");
+    assert_eq!(
+        broadcasts["kinto:123"],
+        BroadcastValue::Value("ver2".to_owned())
+    );
+}
@@ -0,0 +1,133 @@
+//! The APNs provider API. The server speaks HTTP/2 without TLS (h2c) as well
+//! as HTTP/1.1, so point a token-authenticated channel's `base_url` at it.
+
+use crate::script::{self, Bridge, MockState, Received};
+use actix_web::http::StatusCode;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{HttpRequest, HttpResponse};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// The APNs request headers recorded with each notification
+const RECORDED_HEADERS: [&str; 5] = [
+    "apns-topic",
+    "apns-priority",
+    "apns-expiration",
+    "apns-collapse-id",
+    "apns-push-type",
+];
+
+/// Handle the `POST /3/device/{device_token}` route
+pub async fn send_route(
+    device_token: Path<String>,
+    payload: Json<serde_json::Value>,
+    req: HttpRequest,
+    state: Data<MockState>,
+) -> HttpResponse {
+    if req.headers().get("Authorization").is_none() {
+        return error_response(StatusCode::FORBIDDEN, None);
+    }
+    let device_token = device_token.into_inner();
+
+    if let Some(failure) = state.next_failure(Bridge::Apns, &device_token) {
+        if let Some(status) = failure.apply().await {
+            return error_response(status, failure.retry_after);
+        }
+    }
+
+    let headers = RECORDED_HEADERS
+        .iter()
+        .filter_map(|&name| {
+            let value = req.headers().get(name)?.to_str().ok()?;
+            Some((name.to_owned(), value.to_owned()))
+        })
+        .collect();
+    let apns_id = req
+        .headers()
+        .get("apns-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    state.record(Received {
+        bridge: Bridge::Apns,
+        token: device_token,
+        headers,
+        body: payload.into_inner(),
+    });
+    HttpResponse::Ok()
+        .insert_header(("apns-id", apns_id))
+        .finish()
+}
+
+/// Build an APNs error response. Unregistered devices include the time they
+/// were last known to be valid.
+fn error_response(status: StatusCode, retry_after: Option<u64>) -> HttpResponse {
+    let reason = match status.as_u16() {
+        403 => "ExpiredProviderToken",
+        404 | 410 => "Unregistered",
+        413 => "PayloadTooLarge",
+        429 => "TooManyRequests",
+        503 => "ServiceUnavailable",
+        status if status >= 500 => "InternalServerError",
+        _ => "BadDeviceToken",
+    };
+    let mut body = serde_json::json!({ "reason": reason });
+    if reason == "Unregistered" {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        body["timestamp"] = now.into();
+    }
+    script::error_response(status, retry_after, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::{Bridge, Failure, MockState};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+
+    /// Notifications are recorded with their APNs headers, and scripted
+    /// failures use the APNs error reasons
+    #[actix_rt::test]
+    async fn send() {
+        let state = web::Data::new(MockState::default());
+        state.add_failure(Failure {
+            bridge: Bridge::Apns,
+            token: Some("gone-token".to_owned()),
+            status: Some(410),
+            retry_after: None,
+            delay_millis: 0,
+            times: 0,
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(crate::configure),
+        )
+        .await;
+        let send_request = |token: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/3/device/{token}"))
+                .insert_header(("Authorization", "bearer test-provider-token"))
+                .insert_header(("apns-topic", "com.example.app"))
+                .set_json(serde_json::json!({"aps": {"mutable-content": 1}}))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, send_request("test-token")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key("apns-id"));
+
+        let response = test::call_service(&app, send_request("gone-token")).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["reason"], "Unregistered");
+
+        let received = state.received(Some(Bridge::Apns));
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].headers["apns-topic"], "com.example.app");
+    }
+}
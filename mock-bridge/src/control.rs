@@ -0,0 +1,37 @@
+//! Routes for scripting failures and inspecting accepted notifications
+
+use crate::script::{Bridge, Failure, MockState};
+use actix_web::web::{Data, Json, Query};
+use actix_web::HttpResponse;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct ReceivedQuery {
+    pub bridge: Option<Bridge>,
+}
+
+/// Handle the `POST /__mock__/failures` route
+pub async fn add_failure_route(failure: Json<Failure>, state: Data<MockState>) -> HttpResponse {
+    debug!("Scripting failure: {:?}", failure);
+    state.add_failure(failure.into_inner());
+    HttpResponse::Created().finish()
+}
+
+/// Handle the `DELETE /__mock__/failures` route
+pub async fn clear_failures_route(state: Data<MockState>) -> HttpResponse {
+    state.clear_failures();
+    HttpResponse::NoContent().finish()
+}
+
+/// Handle the `GET /__mock__/received` route
+pub async fn received_route(query: Query<ReceivedQuery>, state: Data<MockState>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "received": state.received(query.bridge)
+    }))
+}
+
+/// Handle the `DELETE /__mock__/received` route
+pub async fn clear_received_route(state: Data<MockState>) -> HttpResponse {
+    state.clear_received();
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,48 @@
+//! A local server emulating the FCM, APNs and ADM push bridges, for running
+//! integration and load tests offline. Failures (error statuses,
+//! `Retry-After` and timeouts) are scripted through the `/__mock__/failures`
+//! route, and accepted notifications are listed at `/__mock__/received`.
+
+#![warn(rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+#[macro_use]
+extern crate slog_scope;
+
+use actix_web::web;
+
+pub mod adm;
+pub mod apns;
+pub mod control;
+pub mod fcm;
+pub mod script;
+
+/// Add the mock bridge routes to an app. The app needs a `Data<MockState>`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg
+        // FCM v1
+        .service(web::resource("/token").route(web::post().to(fcm::token_route)))
+        .service(
+            web::resource("/v1/projects/{project_id}/messages:send")
+                .route(web::post().to(fcm::send_route)),
+        )
+        // ADM
+        .service(web::resource("/auth/O2/token").route(web::post().to(adm::token_route)))
+        .service(
+            web::resource("/messaging/registrations/{registration_id}/messages")
+                .route(web::post().to(adm::send_route)),
+        )
+        // APNs
+        .service(web::resource("/3/device/{device_token}").route(web::post().to(apns::send_route)))
+        // Scripting
+        .service(
+            web::resource("/__mock__/failures")
+                .route(web::post().to(control::add_failure_route))
+                .route(web::delete().to(control::clear_failures_route)),
+        )
+        .service(
+            web::resource("/__mock__/received")
+                .route(web::get().to(control::received_route))
+                .route(web::delete().to(control::clear_received_route)),
+        );
+}
@@ -0,0 +1,66 @@
+#![warn(rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+#[macro_use]
+extern crate slog_scope;
+
+use actix_web::{web, App, HttpServer};
+use docopt::Docopt;
+use serde::Deserialize;
+use std::error::Error;
+
+use autopush_common::logging;
+use mock_bridge::script::{Failure, MockState};
+
+const USAGE: &str = "
+Usage: mock_bridge [options]
+
+Options:
+    -h, --help              Show this message
+    --host=HOST             The host to listen on [default: 127.0.0.1]
+    --port=PORT             The port to listen on [default: 8099]
+    --script=SCRIPTFILE     A JSON file listing failures to script at startup
+    --human-logs            Use human-readable logging
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_host: String,
+    flag_port: u16,
+    flag_script: Option<String>,
+    flag_human_logs: bool,
+}
+
+#[actix_rt::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    logging::init_logging(!args.flag_human_logs).expect("Logging failed to initialize");
+
+    let state = web::Data::new(MockState::default());
+    if let Some(script) = &args.flag_script {
+        let failures: Vec<Failure> = serde_json::from_str(&std::fs::read_to_string(script)?)?;
+        debug!("Scripting {} failures from {}", failures.len(), script);
+        for failure in failures {
+            state.add_failure(failure);
+        }
+    }
+
+    info!(
+        "Mock bridge listening on {}:{}",
+        args.flag_host, args.flag_port
+    );
+    HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .configure(mock_bridge::configure)
+    })
+    // APNs requires HTTP/2, the other bridges use HTTP/1.1
+    .bind_auto_h2c((args.flag_host.as_str(), args.flag_port))?
+    .run()
+    .await?;
+
+    logging::reset_logging();
+    Ok(())
+}
@@ -0,0 +1,186 @@
+//! Scripted failures, and the notifications the mock bridges accepted
+
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The bridges the mock server emulates
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bridge {
+    Fcm,
+    Apns,
+    Adm,
+}
+
+/// A failure to respond with instead of accepting a notification
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Failure {
+    pub bridge: Bridge,
+    /// Only fail requests to this device token (any token if unset)
+    #[serde(default)]
+    pub token: Option<String>,
+    /// The HTTP status to respond with. If unset, the notification is
+    /// accepted after `delay_millis`.
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// The `Retry-After` header to send with the failure, in seconds
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+    /// How long to wait before responding. Use a delay longer than the
+    /// client's timeout to script a timeout.
+    #[serde(default)]
+    pub delay_millis: u64,
+    /// How many requests to fail. 0 fails every matching request until the
+    /// failures are cleared.
+    #[serde(default)]
+    pub times: usize,
+}
+
+impl Failure {
+    /// Wait out the failure's delay, then get the status to fail with (if
+    /// any)
+    pub async fn apply(&self) -> Option<StatusCode> {
+        if self.delay_millis > 0 {
+            tokio::time::sleep(Duration::from_millis(self.delay_millis)).await;
+        }
+        self.status
+            .map(|status| StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_REQUEST))
+    }
+}
+
+/// Build an error response with a bridge-specific body
+pub fn error_response(
+    status: StatusCode,
+    retry_after: Option<u64>,
+    body: serde_json::Value,
+) -> HttpResponse {
+    let mut builder = HttpResponse::build(status);
+    if let Some(retry_after) = retry_after {
+        builder.insert_header(("Retry-After", retry_after.to_string()));
+    }
+    builder.json(body)
+}
+
+/// A notification accepted by one of the mock bridges
+#[derive(Clone, Debug, Serialize)]
+pub struct Received {
+    pub bridge: Bridge,
+    pub token: String,
+    /// Bridge-specific request headers (e.g. `apns-topic`)
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    pub body: serde_json::Value,
+}
+
+/// The state shared by the mock bridges
+#[derive(Default)]
+pub struct MockState {
+    failures: Mutex<Vec<Failure>>,
+    received: Mutex<Vec<Received>>,
+}
+
+impl MockState {
+    /// Script a failure. Failures are matched in the order they were added.
+    pub fn add_failure(&self, failure: Failure) {
+        self.failures.lock().unwrap().push(failure);
+    }
+
+    /// Remove all scripted failures
+    pub fn clear_failures(&self) {
+        self.failures.lock().unwrap().clear();
+    }
+
+    /// Get the next failure scripted for a request to the bridge, using up
+    /// one of its `times`
+    pub fn next_failure(&self, bridge: Bridge, token: &str) -> Option<Failure> {
+        let mut failures = self.failures.lock().unwrap();
+        let index = failures.iter().position(|failure| {
+            failure.bridge == bridge && failure.token.as_deref().map_or(true, |t| t == token)
+        })?;
+
+        match failures[index].times {
+            0 => Some(failures[index].clone()),
+            1 => Some(failures.remove(index)),
+            _ => {
+                failures[index].times -= 1;
+                Some(failures[index].clone())
+            }
+        }
+    }
+
+    /// Record an accepted notification
+    pub fn record(&self, received: Received) {
+        debug!(
+            "Accepted {:?} notification for {}",
+            received.bridge, received.token
+        );
+        self.received.lock().unwrap().push(received);
+    }
+
+    /// Get the accepted notifications, optionally only for one bridge
+    pub fn received(&self, bridge: Option<Bridge>) -> Vec<Received> {
+        self.received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|received| bridge.map_or(true, |bridge| received.bridge == bridge))
+            .cloned()
+            .collect()
+    }
+
+    /// Forget the accepted notifications
+    pub fn clear_received(&self) {
+        self.received.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bridge, Failure, MockState};
+
+    fn failure(bridge: Bridge, token: Option<&str>, times: usize) -> Failure {
+        Failure {
+            bridge,
+            token: token.map(str::to_owned),
+            status: Some(503),
+            retry_after: None,
+            delay_millis: 0,
+            times,
+        }
+    }
+
+    /// Failures are used up, and only match their bridge and token
+    #[test]
+    fn next_failure() {
+        let state = MockState::default();
+        state.add_failure(failure(Bridge::Fcm, Some("token-1"), 2));
+        state.add_failure(failure(Bridge::Apns, None, 1));
+
+        assert!(state.next_failure(Bridge::Fcm, "token-2").is_none());
+        assert!(state.next_failure(Bridge::Fcm, "token-1").is_some());
+        assert!(state.next_failure(Bridge::Fcm, "token-1").is_some());
+        assert!(state.next_failure(Bridge::Fcm, "token-1").is_none());
+
+        assert!(state.next_failure(Bridge::Adm, "token-1").is_none());
+        assert!(state.next_failure(Bridge::Apns, "token-1").is_some());
+        assert!(state.next_failure(Bridge::Apns, "token-1").is_none());
+    }
+
+    /// A failure with no limit fails until it is cleared
+    #[test]
+    fn unlimited_failure() {
+        let state = MockState::default();
+        state.add_failure(failure(Bridge::Adm, None, 0));
+
+        for _ in 0..5 {
+            assert!(state.next_failure(Bridge::Adm, "token").is_some());
+        }
+        state.clear_failures();
+        assert!(state.next_failure(Bridge::Adm, "token").is_none());
+    }
+}